@@ -199,11 +199,19 @@ impl ISORoot {
 pub struct Initrd(PathBuf);
 
 impl Initrd {
-    pub fn create(files: Vec<impl Into<PathBuf>>, out_file: PathBuf) -> Initrd {
+    /// packs `files`, which are stored relative to `strip_prefix`.
+    pub fn create(
+        files: Vec<impl Into<PathBuf>>,
+        strip_prefix: impl AsRef<Path>,
+        out_file: PathBuf,
+    ) -> Initrd {
         assert!(
             tool("initrd")
+                .arg("create")
                 .arg("--output")
                 .arg(&out_file)
+                .arg("--strip-prefix")
+                .arg(strip_prefix.as_ref())
                 .args(files.into_iter().map(|e| Into::<PathBuf>::into(e)))
                 .status()
                 .expect("failed to start initrd tool")
@@ -300,16 +308,22 @@ fn run_qemu(image: Image) {
 
 #[test]
 fn test() {
-    fn cargo_test(dir: &str) {
+    fn cargo_test(dir: &str, args: &[&str]) {
         let cmd = cargo()
             .arg("test")
+            .args(args)
             .current_dir(dir)
             .status()
             .expect(&format!("failed to test '{dir}'"));
         assert!(cmd.success(), "failed test '{dir}'",);
     }
-    cargo_test("tools");
-    cargo_test("dep");
+    cargo_test("tools", &[]);
+    cargo_test("dep", &[]);
+    // the kernel builds for its own target by default, its tests run on the host.
+    cargo_test(
+        "kernel",
+        &["-Z", "build-std", "--target", "x86_64-unknown-linux-gnu"],
+    );
 }
 
 fn main() {
@@ -323,6 +337,7 @@ fn main() {
     );
     let initrd = Initrd::create(
         vec!["build/usrspc/ps2"],
+        "build/usrspc",
         format!("{}/initrd", path::build().display()).into(),
     );
     iso_root.put_module(initrd.output());
@@ -0,0 +1,97 @@
+use core::fmt::{self, Display};
+
+macro_rules! errno {
+    ($($ident:ident = $val:literal: $desc:literal,)*) => {
+        /// Error codes returned by syscalls, numbered like their Linux counterparts.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        #[repr(u16)]
+        pub enum Errno {
+            $($ident = $val,)*
+        }
+
+        impl Errno {
+            pub const fn from_raw(raw: u16) -> Option<Self> {
+                match raw {
+                    $($val => Some(Self::$ident),)*
+                    _ => None,
+                }
+            }
+
+            pub const fn description(self) -> &'static str {
+                match self {
+                    $(Self::$ident => $desc,)*
+                }
+            }
+        }
+    };
+}
+
+errno! {
+    EPERM = 1: "operation not permitted",
+    ENOENT = 2: "no such file or directory",
+    ESRCH = 3: "no such process",
+    EINTR = 4: "interrupted system call",
+    EIO = 5: "input/output error",
+    ENXIO = 6: "no such device or address",
+    E2BIG = 7: "argument list too long",
+    ENOEXEC = 8: "exec format error",
+    EBADF = 9: "bad file descriptor",
+    ECHILD = 10: "no child processes",
+    EAGAIN = 11: "resource temporarily unavailable",
+    ENOMEM = 12: "cannot allocate memory",
+    EACCES = 13: "permission denied",
+    EFAULT = 14: "bad address",
+    EBUSY = 16: "device or resource busy",
+    EEXIST = 17: "file exists",
+    EXDEV = 18: "invalid cross-device link",
+    ENODEV = 19: "no such device",
+    ENOTDIR = 20: "not a directory",
+    EISDIR = 21: "is a directory",
+    EINVAL = 22: "invalid argument",
+    ENFILE = 23: "too many open files in system",
+    EMFILE = 24: "too many open files",
+    ENOTTY = 25: "inappropriate ioctl for device",
+    EFBIG = 27: "file too large",
+    ENOSPC = 28: "no space left on device",
+    ESPIPE = 29: "illegal seek",
+    EROFS = 30: "read-only file system",
+    EMLINK = 31: "too many links",
+    EPIPE = 32: "broken pipe",
+    ERANGE = 34: "numerical result out of range",
+    ENAMETOOLONG = 36: "file name too long",
+    ENOSYS = 38: "function not implemented",
+    ENOTEMPTY = 39: "directory not empty",
+    ELOOP = 40: "too many levels of symbolic links",
+    EBADMSG = 74: "bad message",
+    EMSGSIZE = 90: "message too long",
+}
+
+impl Display for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.description())
+    }
+}
+
+/// Largest error code that can be encoded in a syscall return value.
+pub const MAX_ERRNO: u64 = 4095;
+
+/// Encodes a syscall result the way it is returned in `rax`: values in
+/// `-MAX_ERRNO..=-1` are negated error codes, everything else is a success.
+pub const fn encode(result: Result<u64, Errno>) -> u64 {
+    match result {
+        Ok(val) => val,
+        Err(errno) => (-(errno as i64)) as u64,
+    }
+}
+
+/// Inverse of `encode`.
+pub const fn decode(raw: u64) -> Result<u64, Errno> {
+    if raw > u64::MAX - MAX_ERRNO {
+        match Errno::from_raw((raw as i64).unsigned_abs() as u16) {
+            Some(errno) => Err(errno),
+            None => Err(Errno::EINVAL),
+        }
+    } else {
+        Ok(raw)
+    }
+}
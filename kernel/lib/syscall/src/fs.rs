@@ -0,0 +1,97 @@
+//! Types shared by the file syscalls.
+
+use core::mem::size_of;
+
+/// A file descriptor, an index into the calling process' file table.
+pub type Fd = usize;
+
+pub const STDIN: Fd = 0;
+pub const STDOUT: Fd = 1;
+pub const STDERR: Fd = 2;
+
+// `open` flags, only the access mode is supported so far.
+pub const O_RDONLY: u32 = 0;
+pub const O_WRONLY: u32 = 1;
+pub const O_RDWR: u32 = 2;
+pub const O_ACCMODE: u32 = 3;
+
+// `lseek` origins.
+pub const SEEK_SET: u32 = 0;
+pub const SEEK_CUR: u32 = 1;
+pub const SEEK_END: u32 = 2;
+
+// file types in `Stat::ty` and `Dirent::ty`.
+pub const FT_REGULAR: u8 = 1;
+pub const FT_DIRECTORY: u8 = 2;
+pub const FT_SYMLINK: u8 = 3;
+pub const FT_CHAR_DEVICE: u8 = 4;
+pub const FT_BLOCK_DEVICE: u8 = 5;
+pub const FT_PIPE: u8 = 6;
+
+/// Filled in by `fstat`.
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct Stat {
+    pub ino: u64,
+    pub size: u64,
+    /// permission bits, like the lower 12 bits of a unix mode.
+    pub mode: u16,
+    /// one of the `FT_*` constants.
+    pub ty: u8,
+    /// zeroed, so no uninitialized padding crosses the syscall boundary.
+    pub _pad: [u8; 5],
+}
+
+/// Header of a directory entry written by `getdents`, followed by `name_len`
+/// bytes of name and padding up to `rec_len`.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct Dirent {
+    pub ino: u64,
+    /// size of the whole record, a multiple of `DIRENT_ALIGN`.
+    pub rec_len: u16,
+    pub name_len: u8,
+    /// one of the `FT_*` constants.
+    pub ty: u8,
+    /// zeroed, like `Stat::_pad`.
+    pub _pad: u32,
+}
+
+pub const DIRENT_ALIGN: usize = 8;
+
+impl Dirent {
+    /// size of the record for a name of `name_len` bytes.
+    pub const fn rec_len(name_len: usize) -> usize {
+        (size_of::<Self>() + name_len + DIRENT_ALIGN - 1) & !(DIRENT_ALIGN - 1)
+    }
+}
+
+/// Iterates the `(entry, name)` pairs in a buffer filled by `getdents`.
+pub struct Dirents<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Dirents<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+}
+
+impl<'a> Iterator for Dirents<'a> {
+    type Item = (Dirent, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buf.len() < size_of::<Dirent>() {
+            return None;
+        }
+        let dirent = unsafe { (self.buf.as_ptr() as *const Dirent).read_unaligned() };
+        let rec_len = dirent.rec_len as usize;
+        let name_end = size_of::<Dirent>() + dirent.name_len as usize;
+        if rec_len < name_end || rec_len > self.buf.len() {
+            return None;
+        }
+        let name = &self.buf[size_of::<Dirent>()..name_end];
+        self.buf = &self.buf[rec_len..];
+        Some((dirent, name))
+    }
+}
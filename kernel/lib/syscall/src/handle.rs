@@ -0,0 +1,54 @@
+//! Types shared by the handle syscalls.
+//!
+//! A handle is a reference to a kernel object which the calling process
+//! holds, along with the rights it has on the object. Syscalls acting on an
+//! object take a handle to it, so a process can only touch what it was
+//! given.
+
+/// A handle, an index into the calling process' handle table.
+pub type Handle = usize;
+
+/// the process itself, which every process holds with all rights.
+pub const HANDLE_SELF: Handle = 0;
+/// the main thread of a process the kernel spawned.
+pub const HANDLE_MAIN_THREAD: Handle = 1;
+/// the first handle the kernel grants a process it spawns, like the IRQs of
+/// a driver. further ones follow in the order the driver expects them.
+pub const HANDLE_GRANTED: Handle = 2;
+/// no handle, where one is optional.
+pub const HANDLE_NONE: Handle = Handle::MAX;
+
+// rights a handle can have.
+/// the handle can be duplicated.
+pub const RIGHT_DUPLICATE: u32 = 1 << 0;
+/// the handle can be sent to another process.
+pub const RIGHT_TRANSFER: u32 = 1 << 1;
+pub const RIGHT_READ: u32 = 1 << 2;
+pub const RIGHT_WRITE: u32 = 1 << 3;
+/// memory can be mapped into a process, or a memory object can be mapped.
+pub const RIGHT_MAP: u32 = 1 << 4;
+/// messages can be sent to an endpoint.
+pub const RIGHT_SEND: u32 = 1 << 5;
+/// messages can be received on an endpoint.
+pub const RIGHT_RECEIVE: u32 = 1 << 6;
+/// the object's hardware can be driven: an IRQ bound or IO ports accessed.
+pub const RIGHT_MANAGE: u32 = 1 << 7;
+pub const RIGHTS_ALL: u32 = (1 << 8) - 1;
+
+// the types of objects.
+pub const OBJECT_PROCESS: u32 = 1;
+pub const OBJECT_THREAD: u32 = 2;
+pub const OBJECT_MEMORY: u32 = 3;
+pub const OBJECT_ENDPOINT: u32 = 4;
+pub const OBJECT_IRQ: u32 = 5;
+pub const OBJECT_IO_PORTS: u32 = 6;
+
+/// What a handle refers to, as returned by `handle_info`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HandleInfo {
+    /// one of the `OBJECT_*` types.
+    pub ty: u32,
+    /// the `RIGHT_*` flags the handle has.
+    pub rights: u32,
+}
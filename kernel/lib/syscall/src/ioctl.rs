@@ -0,0 +1,11 @@
+//! requests for the `ioctl` syscall and the structures they pass.
+
+/// writes the framebuffer's size in character cells as an `FbSize`.
+pub const FB_GET_SIZE: u64 = 0x4601;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FbSize {
+    pub width: u32,
+    pub height: u32,
+}
@@ -0,0 +1,65 @@
+//! Types shared by the IPC syscalls.
+
+use crate::handle::{Handle, HANDLE_NONE};
+
+/// words a message carries besides its payload.
+pub const MSG_WORDS: usize = 4;
+
+// how `Message::payload` is passed.
+pub const PAYLOAD_NONE: u32 = 0;
+/// copied into the receiver's buffer.
+pub const PAYLOAD_COPY: u32 = 1;
+/// the pages holding it are mapped into the receiver as well, read-only.
+pub const PAYLOAD_GRANT: u32 = 2;
+/// like `PAYLOAD_GRANT`, but the receiver can write to them.
+pub const PAYLOAD_GRANT_RW: u32 = 3;
+
+/// the token `ipc_receive` returns for notifications, which aren't replied
+/// to. the first word of the message holds the bits notified since the last
+/// one.
+pub const TOKEN_NOTIFY: u64 = 1 << 63;
+
+/// the largest payload which can be copied.
+pub const MAX_COPY_LEN: usize = 64 * 1024;
+
+/// A message as sent and received.
+///
+/// The sender sets the address and length of the payload in its own memory,
+/// the receiver finds them replaced by where the payload ended up in its
+/// memory: its buffer for copies and the mapped pages for grants.
+///
+/// A message can carry a handle, which needs `RIGHT_TRANSFER`. It is taken
+/// from the sender's handle table once the message is sent, and the receiver
+/// finds the handle it got in its table. If the message isn't delivered, the
+/// handle goes back to the sender under the same number.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Message {
+    pub words: [u64; MSG_WORDS],
+    pub payload_kind: u32,
+    /// zeroed, so no uninitialized padding crosses the syscall boundary.
+    pub _pad: u32,
+    pub payload: u64,
+    pub payload_len: u64,
+    /// `HANDLE_NONE` if the message carries none.
+    pub handle: Handle,
+}
+
+impl Message {
+    pub const fn new(words: [u64; MSG_WORDS]) -> Self {
+        Self {
+            words,
+            payload_kind: PAYLOAD_NONE,
+            _pad: 0,
+            payload: 0,
+            payload_len: 0,
+            handle: HANDLE_NONE,
+        }
+    }
+}
+
+impl Default for Message {
+    fn default() -> Self {
+        Self::new([0; MSG_WORDS])
+    }
+}
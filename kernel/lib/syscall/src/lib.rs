@@ -1,38 +1,452 @@
 #![no_std]
 
+pub mod fs;
+pub mod handle;
+pub mod ioctl;
+pub mod ipc;
+pub mod mm;
+
+mod errno;
+
 use core::arch::asm;
+use core::mem::MaybeUninit;
+use fs::{Fd, Stat};
+use handle::{Handle, HandleInfo};
+use ipc::Message;
+
+pub use errno::{decode, encode, Errno, MAX_ERRNO};
+
+pub type Result<T> = core::result::Result<T, Errno>;
 
 pub const SYSCALL_KPRINT: u64 = 0x0;
 pub const SYSCALL_MALLOC: u64 = 0x1;
 pub const SYSCALL_FREE: u64 = 0x2;
+pub const SYSCALL_OPEN: u64 = 0x3;
+pub const SYSCALL_CLOSE: u64 = 0x4;
+pub const SYSCALL_READ: u64 = 0x5;
+pub const SYSCALL_WRITE: u64 = 0x6;
+pub const SYSCALL_LSEEK: u64 = 0x7;
+pub const SYSCALL_FSTAT: u64 = 0x8;
+pub const SYSCALL_DUP: u64 = 0x9;
+pub const SYSCALL_DUP2: u64 = 0xa;
+pub const SYSCALL_GETDENTS: u64 = 0xb;
+pub const SYSCALL_IOCTL: u64 = 0xc;
+pub const SYSCALL_MMAP: u64 = 0xd;
+pub const SYSCALL_SHM_OPEN: u64 = 0xe;
+pub const SYSCALL_PIPE: u64 = 0xf;
+pub const SYSCALL_HANDLE_CLOSE: u64 = 0x10;
+pub const SYSCALL_ENDPOINT_CREATE: u64 = 0x11;
+pub const SYSCALL_ENDPOINT_OPEN: u64 = 0x12;
+pub const SYSCALL_IPC_SEND: u64 = 0x13;
+pub const SYSCALL_IPC_CALL: u64 = 0x14;
+pub const SYSCALL_IPC_RECEIVE: u64 = 0x15;
+pub const SYSCALL_IPC_REPLY: u64 = 0x16;
+pub const SYSCALL_HANDLE_DUPLICATE: u64 = 0x17;
+pub const SYSCALL_HANDLE_INFO: u64 = 0x18;
+pub const SYSCALL_MEMORY_CREATE: u64 = 0x19;
+pub const SYSCALL_MEMORY_MAP: u64 = 0x1a;
+pub const SYSCALL_IRQ_BIND: u64 = 0x1b;
+pub const SYSCALL_IRQ_ACK: u64 = 0x1c;
+pub const SYSCALL_IOPORT_GRANT: u64 = 0x1d;
 
+/// Performs a raw syscall.
+///
+/// The number goes in `rax` and the arguments in `rdi`, `rsi`, `rdx`, `r10`,
+/// `r8` and `r9`, following the Linux x86-64 convention. Every other register
+/// except `rcx` and `r11` is preserved by the kernel.
+///
+/// # Safety
+/// the kernel may read from or write to memory given as arguments.
 #[inline]
-fn syscall(syscall: u64, param0: u64, param1: u64) -> u64 {
+pub unsafe fn syscall(number: u64, args: [u64; 6]) -> u64 {
     let mut out: u64;
+    asm!(
+        "syscall",
+        inlateout("rax") number => out,
+        in("rdi") args[0],
+        in("rsi") args[1],
+        in("rdx") args[2],
+        in("r10") args[3],
+        in("r8") args[4],
+        in("r9") args[5],
+        lateout("rcx") _,
+        lateout("r11") _,
+        options(nostack),
+    );
+    out
+}
+
+#[inline]
+unsafe fn call(number: u64, args: [u64; 6]) -> Result<u64> {
+    decode(syscall(number, args))
+}
+
+pub fn kprint(msg: impl AsRef<str>) -> Result<()> {
+    let msg = msg.as_ref();
     unsafe {
-        asm!(
-            "syscall",
-            in("rdi") syscall,
-            in("rsi") param0,
-            in("rdx") param1,
-            out("rax") out,
-        );
+        call(
+            SYSCALL_KPRINT,
+            [msg.as_ptr() as u64, msg.len() as u64, 0, 0, 0, 0],
+        )
     }
-    out
+    .map(|_| ())
 }
 
-pub fn kprint(msg: impl AsRef<str>) {
-    syscall(
-        SYSCALL_KPRINT,
-        msg.as_ref().as_ptr() as u64,
-        msg.as_ref().len() as u64,
-    );
+/// maps `count` bytes of fresh memory into `process`, which needs
+/// `RIGHT_MAP`.
+pub fn malloc(process: Handle, count: usize) -> Result<*mut u8> {
+    unsafe { call(SYSCALL_MALLOC, [process as u64, count as u64, 0, 0, 0, 0]) }
+        .map(|adr| adr as *mut _)
+}
+
+pub fn free(ptr: *mut u8, len: usize) -> Result<()> {
+    unsafe { call(SYSCALL_FREE, [ptr as u64, len as u64, 0, 0, 0, 0]) }.map(|_| ())
+}
+
+/// opens the absolute path `path` with the `fs::O_*` flags in `flags`.
+pub fn open(path: impl AsRef<str>, flags: u32) -> Result<Fd> {
+    let path = path.as_ref();
+    unsafe {
+        call(
+            SYSCALL_OPEN,
+            [
+                path.as_ptr() as u64,
+                path.len() as u64,
+                flags as u64,
+                0,
+                0,
+                0,
+            ],
+        )
+    }
+    .map(|fd| fd as Fd)
+}
+
+pub fn close(fd: Fd) -> Result<()> {
+    unsafe { call(SYSCALL_CLOSE, [fd as u64, 0, 0, 0, 0, 0]) }.map(|_| ())
+}
+
+/// returns the number of bytes read, which is zero at the end of the file.
+pub fn read(fd: Fd, buf: &mut [u8]) -> Result<usize> {
+    unsafe {
+        call(
+            SYSCALL_READ,
+            [
+                fd as u64,
+                buf.as_mut_ptr() as u64,
+                buf.len() as u64,
+                0,
+                0,
+                0,
+            ],
+        )
+    }
+    .map(|len| len as usize)
+}
+
+/// returns the number of bytes written, which may be less than `buf.len()`.
+pub fn write(fd: Fd, buf: &[u8]) -> Result<usize> {
+    unsafe {
+        call(
+            SYSCALL_WRITE,
+            [fd as u64, buf.as_ptr() as u64, buf.len() as u64, 0, 0, 0],
+        )
+    }
+    .map(|len| len as usize)
+}
+
+/// moves the offset of `fd` relative to the `fs::SEEK_*` origin `whence` and
+/// returns the new offset.
+pub fn lseek(fd: Fd, offset: i64, whence: u32) -> Result<u64> {
+    unsafe {
+        call(
+            SYSCALL_LSEEK,
+            [fd as u64, offset as u64, whence as u64, 0, 0, 0],
+        )
+    }
+}
+
+pub fn fstat(fd: Fd) -> Result<Stat> {
+    let mut stat = MaybeUninit::<Stat>::uninit();
+    unsafe {
+        call(
+            SYSCALL_FSTAT,
+            [fd as u64, stat.as_mut_ptr() as u64, 0, 0, 0, 0],
+        )?;
+        Ok(stat.assume_init())
+    }
 }
 
-pub fn malloc(count: usize) -> *mut u8 {
-    syscall(SYSCALL_MALLOC, count as u64, 0) as *mut _
+/// returns the lowest free descriptor, which refers to the same open file as
+/// `fd`.
+pub fn dup(fd: Fd) -> Result<Fd> {
+    unsafe { call(SYSCALL_DUP, [fd as u64, 0, 0, 0, 0, 0]) }.map(|fd| fd as Fd)
 }
 
-pub fn free(ptr: *mut u8, len: usize) {
-    syscall(SYSCALL_FREE, ptr as u64, len as u64);
+/// makes `new` refer to the same open file as `old`, closing it first if it
+/// was open.
+pub fn dup2(old: Fd, new: Fd) -> Result<Fd> {
+    unsafe { call(SYSCALL_DUP2, [old as u64, new as u64, 0, 0, 0, 0]) }.map(|fd| fd as Fd)
+}
+
+/// fills `buf` with the next entries of the directory `fd`, see `fs::Dirents`.
+/// returns the number of bytes used, which is zero after the last entry.
+pub fn getdents(fd: Fd, buf: &mut [u8]) -> Result<usize> {
+    unsafe {
+        call(
+            SYSCALL_GETDENTS,
+            [
+                fd as u64,
+                buf.as_mut_ptr() as u64,
+                buf.len() as u64,
+                0,
+                0,
+                0,
+            ],
+        )
+    }
+    .map(|len| len as usize)
+}
+
+/// sends the device specific `request` to `fd`, the device reads from and
+/// writes back into `buf`.
+pub fn ioctl(fd: Fd, request: u64, buf: &mut [u8]) -> Result<u64> {
+    unsafe {
+        call(
+            SYSCALL_IOCTL,
+            [
+                fd as u64,
+                request,
+                buf.as_mut_ptr() as u64,
+                buf.len() as u64,
+                0,
+                0,
+            ],
+        )
+    }
+}
+
+/// maps `len` bytes of `fd` from `offset`, which is page aligned, with the
+/// `mm::PROT_*` flags in `prot`. writes to the mapping end up in the file.
+/// shared memory objects are mapped from their start, `offset` has to be 0.
+/// the mapping goes into `process`, which needs `RIGHT_MAP`.
+pub fn mmap(process: Handle, fd: Fd, offset: u64, len: usize, prot: u32) -> Result<*mut u8> {
+    unsafe {
+        call(
+            SYSCALL_MMAP,
+            [
+                process as u64,
+                fd as u64,
+                offset,
+                len as u64,
+                prot as u64,
+                0,
+            ],
+        )
+    }
+    .map(|adr| adr as *mut u8)
+}
+
+/// opens the shared memory object `name`, which is created with `len` bytes
+/// if it doesn't exist. every process mapping it sees the same memory.
+pub fn shm_open(name: impl AsRef<str>, len: usize) -> Result<Fd> {
+    let name = name.as_ref();
+    unsafe {
+        call(
+            SYSCALL_SHM_OPEN,
+            [name.as_ptr() as u64, name.len() as u64, len as u64, 0, 0, 0],
+        )
+    }
+    .map(|fd| fd as Fd)
+}
+
+/// creates a pipe and returns its read and write end. reads block until
+/// something was written and return 0 once every write end is closed,
+/// writes block while the pipe is full and fail with `EPIPE` once every read
+/// end is closed.
+pub fn pipe() -> Result<(Fd, Fd)> {
+    let mut fds = MaybeUninit::<[Fd; 2]>::uninit();
+    unsafe {
+        call(SYSCALL_PIPE, [fds.as_mut_ptr() as u64, 0, 0, 0, 0, 0])?;
+        let [reader, writer] = fds.assume_init();
+        Ok((reader, writer))
+    }
+}
+
+pub fn handle_close(handle: Handle) -> Result<()> {
+    unsafe { call(SYSCALL_HANDLE_CLOSE, [handle as u64, 0, 0, 0, 0, 0]) }.map(|_| ())
+}
+
+/// creates the endpoint `name` and returns a handle to receive on it. the
+/// endpoint is closed once that handle is.
+pub fn endpoint_create(name: impl AsRef<str>) -> Result<Handle> {
+    let name = name.as_ref();
+    unsafe {
+        call(
+            SYSCALL_ENDPOINT_CREATE,
+            [name.as_ptr() as u64, name.len() as u64, 0, 0, 0, 0],
+        )
+    }
+    .map(|handle| handle as Handle)
+}
+
+/// opens the endpoint `name` to send to it.
+pub fn endpoint_open(name: impl AsRef<str>) -> Result<Handle> {
+    let name = name.as_ref();
+    unsafe {
+        call(
+            SYSCALL_ENDPOINT_OPEN,
+            [name.as_ptr() as u64, name.len() as u64, 0, 0, 0, 0],
+        )
+    }
+    .map(|handle| handle as Handle)
+}
+
+/// sends `msg` to `endpoint` and blocks until it was received. no reply is
+/// expected.
+pub fn ipc_send(endpoint: Handle, msg: &Message) -> Result<()> {
+    unsafe {
+        call(
+            SYSCALL_IPC_SEND,
+            [endpoint as u64, msg as *const _ as u64, 0, 0, 0, 0],
+        )
+    }
+    .map(|_| ())
+}
+
+/// sends `msg` to `endpoint` and blocks until the receiver replied, the reply
+/// is stored in `msg`. a copied reply payload goes to `buf`.
+pub fn ipc_call(endpoint: Handle, msg: &mut Message, buf: &mut [u8]) -> Result<()> {
+    unsafe {
+        call(
+            SYSCALL_IPC_CALL,
+            [
+                endpoint as u64,
+                msg as *mut _ as u64,
+                buf.as_mut_ptr() as u64,
+                buf.len() as u64,
+                0,
+                0,
+            ],
+        )
+    }
+    .map(|_| ())
+}
+
+/// blocks until a message arrives at `endpoint`, which is stored in `msg`. a
+/// copied payload goes to `buf`. returns the token to reply with, which is 0
+/// for messages that were sent without waiting for a reply and
+/// `ipc::TOKEN_NOTIFY` for notifications.
+pub fn ipc_receive(endpoint: Handle, msg: &mut Message, buf: &mut [u8]) -> Result<u64> {
+    unsafe {
+        call(
+            SYSCALL_IPC_RECEIVE,
+            [
+                endpoint as u64,
+                msg as *mut _ as u64,
+                buf.as_mut_ptr() as u64,
+                buf.len() as u64,
+                0,
+                0,
+            ],
+        )
+    }
+}
+
+/// answers the call received with `token` with `reply`, or makes it fail
+/// with `reply`'s error.
+pub fn ipc_reply(endpoint: Handle, token: u64, reply: Result<&Message>) -> Result<()> {
+    let (msg, errno) = match reply {
+        Ok(msg) => (msg as *const _ as u64, 0),
+        Err(errno) => (0, errno as u64),
+    };
+    unsafe {
+        call(
+            SYSCALL_IPC_REPLY,
+            [endpoint as u64, token, msg, errno, 0, 0],
+        )
+    }
+    .map(|_| ())
+}
+
+/// returns a new handle to the object `handle` refers to, with `rights`,
+/// which can't be more than `handle` has. `handle` needs `RIGHT_DUPLICATE`.
+pub fn handle_duplicate(handle: Handle, rights: u32) -> Result<Handle> {
+    unsafe {
+        call(
+            SYSCALL_HANDLE_DUPLICATE,
+            [handle as u64, rights as u64, 0, 0, 0, 0],
+        )
+    }
+    .map(|handle| handle as Handle)
+}
+
+pub fn handle_info(handle: Handle) -> Result<HandleInfo> {
+    let mut info = MaybeUninit::<HandleInfo>::uninit();
+    unsafe {
+        call(
+            SYSCALL_HANDLE_INFO,
+            [handle as u64, info.as_mut_ptr() as u64, 0, 0, 0, 0],
+        )?;
+        Ok(info.assume_init())
+    }
+}
+
+/// creates a memory object of `len` bytes, which isn't mapped anywhere yet.
+pub fn memory_create(len: usize) -> Result<Handle> {
+    unsafe { call(SYSCALL_MEMORY_CREATE, [len as u64, 0, 0, 0, 0, 0]) }
+        .map(|handle| handle as Handle)
+}
+
+/// maps all of `memory` into `process` with the `mm::PROT_*` flags in
+/// `prot`. `process` needs `RIGHT_MAP`, `memory` needs `RIGHT_MAP` and
+/// `RIGHT_READ`, and `RIGHT_WRITE` to be mapped writable.
+pub fn memory_map(process: Handle, memory: Handle, prot: u32) -> Result<*mut u8> {
+    unsafe {
+        call(
+            SYSCALL_MEMORY_MAP,
+            [process as u64, memory as u64, prot as u64, 0, 0, 0],
+        )
+    }
+    .map(|adr| adr as *mut u8)
+}
+
+/// binds the interrupt line `irq` refers to to `endpoint`, which is notified
+/// with `bits` whenever the line is raised. the line is masked until the
+/// notification was acknowledged with `irq_ack`. `irq` needs `RIGHT_MANAGE`
+/// and `endpoint` needs `RIGHT_RECEIVE`, a line stays bound until its
+/// endpoint is closed.
+pub fn irq_bind(irq: Handle, endpoint: Handle, bits: u64) -> Result<()> {
+    unsafe {
+        call(
+            SYSCALL_IRQ_BIND,
+            [irq as u64, endpoint as u64, bits, 0, 0, 0],
+        )
+    }
+    .map(|_| ())
+}
+
+/// unmasks the interrupt line `irq` refers to once its device was serviced.
+pub fn irq_ack(irq: Handle) -> Result<()> {
+    unsafe { call(SYSCALL_IRQ_ACK, [irq as u64, 0, 0, 0, 0, 0]) }.map(|_| ())
+}
+
+/// lets `process` use `count` ports from `first` with `in` and `out`. they
+/// have to lie in the range `ports` refers to, which needs `RIGHT_MANAGE`,
+/// as does `process`.
+pub fn ioport_grant(process: Handle, ports: Handle, first: u16, count: u16) -> Result<()> {
+    unsafe {
+        call(
+            SYSCALL_IOPORT_GRANT,
+            [
+                process as u64,
+                ports as u64,
+                first as u64,
+                count as u64,
+                0,
+                0,
+            ],
+        )
+    }
+    .map(|_| ())
 }
@@ -0,0 +1,7 @@
+//! Types shared by the memory syscalls.
+
+// `mmap` protection, a mapping can always be read.
+pub const PROT_READ: u32 = 0;
+pub const PROT_WRITE: u32 = 1 << 0;
+pub const PROT_EXEC: u32 = 1 << 1;
+pub const PROT_MASK: u32 = PROT_WRITE | PROT_EXEC;
@@ -1,4 +1,19 @@
+//! the IO-APIC, which routes the interrupt lines of devices to vectors.
+//!
+//! The MADT lists every IO-APIC and the global system interrupt (GSI) each
+//! of them starts at, along with overrides for ISA lines which aren't wired
+//! to the GSI of the same number. The ISA lines are routed to consecutive
+//! vectors from `VECTOR_BASE`, masked until a driver binds them.
+
+use super::super::sdt::{self, Rsdt};
+use super::lapic;
+use crate::mm::pmm;
+use crate::util::adr::{PhysAdr, VirtAdr};
+use crate::util::locked::Locked;
+use core::mem::size_of;
+
 bitfield! {
+    #[derive(Clone, Copy)]
     struct Redirection(u64) {
         vec: u8 @ 0..=7,
         deiliver_mode: u8 @ 8..=10,
@@ -8,7 +23,7 @@ bitfield! {
         level_triggered: bool @ 14,
         trigger_mode: bool @ 15,
         mask: bool @ 16,
-        dest: u8 @ 56..63,
+        dest: u8 @ 56..=63,
     }
 }
 
@@ -25,10 +40,204 @@ impl Redirection {
     const TRIGGER_MODE_LEVEL_SENSITIVE: bool = true;
 }
 
-struct IoApicPtr(u64);
+/// ISA lines which can be bound, their vectors follow `VECTOR_BASE`.
+pub const LINES: u8 = 16;
+/// the vector of ISA line 0.
+pub const VECTOR_BASE: u8 = 48;
+
+const MAX_IOAPICS: usize = 8;
+
+const REG_SELECT: usize = 0x00;
+const REG_WINDOW: usize = 0x10;
+const REG_VERSION: u32 = 0x01;
+const REG_REDIRECTION: u32 = 0x10;
+
+// MADT entry types.
+const MADT_IOAPIC: u8 = 1;
+const MADT_SOURCE_OVERRIDE: u8 = 2;
+
+/// the MADT's fields past the header: the local APIC address and flags.
+const MADT_ENTRIES_OFFSET: usize = size_of::<Rsdt>() + 8;
+
+#[derive(Clone, Copy)]
+struct IoApicPtr {
+    base: VirtAdr,
+    /// the first GSI it serves.
+    gsi_base: u32,
+    count: u32,
+}
+
+impl IoApicPtr {
+    unsafe fn read(&self, reg: u32) -> u32 {
+        let base = self.base.ptr();
+        (base.add(REG_SELECT) as *mut u32).write_volatile(reg);
+        (base.add(REG_WINDOW) as *const u32).read_volatile()
+    }
+
+    unsafe fn write(&self, reg: u32, val: u32) {
+        let base = self.base.ptr();
+        (base.add(REG_SELECT) as *mut u32).write_volatile(reg);
+        (base.add(REG_WINDOW) as *mut u32).write_volatile(val)
+    }
+
+    fn serves(&self, gsi: u32) -> bool {
+        (self.gsi_base..self.gsi_base + self.count).contains(&gsi)
+    }
+
+    unsafe fn redirection(&self, gsi: u32) -> Redirection {
+        let reg = REG_REDIRECTION + (gsi - self.gsi_base) * 2;
+        Redirection((self.read(reg + 1) as u64) << 32 | self.read(reg) as u64)
+    }
+
+    unsafe fn set_redirection(&self, gsi: u32, redirection: Redirection) {
+        let reg = REG_REDIRECTION + (gsi - self.gsi_base) * 2;
+        self.write(reg, redirection.0 as u32);
+        self.write(reg + 1, (redirection.0 >> 32) as u32);
+    }
+}
+
+/// How an ISA line reaches an IO-APIC.
+#[derive(Clone, Copy)]
+struct Route {
+    gsi: u32,
+    active_low: bool,
+    level: bool,
+}
+
+struct IoApics {
+    apics: [Option<IoApicPtr>; MAX_IOAPICS],
+    /// by ISA line, identity mapped and edge triggered unless overridden.
+    /// `None` for lines whose GSI another line was moved to.
+    routes: [Option<Route>; LINES as usize],
+}
+
+impl IoApics {
+    /// the IO-APIC serving ISA `line` and the GSI it is wired to.
+    fn find(&self, line: u8) -> Option<(IoApicPtr, u32)> {
+        let gsi = (*self.routes.get(line as usize)?)?.gsi;
+        let apic = self.apics.iter().flatten().find(|apic| apic.serves(gsi))?;
+        Some((*apic, gsi))
+    }
+}
 
-impl IoApicPtr {}
+static IOAPICS: Locked<IoApics> = Locked::new(IoApics {
+    apics: [None; MAX_IOAPICS],
+    routes: {
+        let mut routes = [None; LINES as usize];
+        let mut line = 0;
+        while line < LINES as usize {
+            routes[line] = Some(Route {
+                gsi: line as u32,
+                active_low: false,
+                level: false,
+            });
+            line += 1;
+        }
+        routes
+    },
+});
 
-pub unsafe fn init() {
+/// reads the IO-APICs and ISA overrides from the MADT.
+unsafe fn parse_madt(madt: &Rsdt, ioapics: &mut IoApics) {
+    let start = madt as *const Rsdt as *const u8;
+    let end = start.add(madt.length as usize);
+    let mut entry = start.add(MADT_ENTRIES_OFFSET);
+    let mut count = 0;
+    while entry.add(2) <= end {
+        let (ty, len) = (*entry, *entry.add(1) as usize);
+        if len < 2 || entry.add(len) > end {
+            break;
+        }
+        match ty {
+            MADT_IOAPIC if count < MAX_IOAPICS => {
+                let adr = (entry.add(4) as *const u32).read_unaligned();
+                let base = pmm::phys_to_hhdm(PhysAdr::new(adr as u64));
+                let mut apic = IoApicPtr {
+                    base,
+                    gsi_base: (entry.add(8) as *const u32).read_unaligned(),
+                    count: 0,
+                };
+                apic.count = ((apic.read(REG_VERSION) >> 16) & 0xff) + 1;
+                ioapics.apics[count] = Some(apic);
+                count += 1;
+            }
+            MADT_SOURCE_OVERRIDE => {
+                let line = *entry.add(3) as usize;
+                let gsi = (entry.add(4) as *const u32).read_unaligned();
+                let flags = (entry.add(8) as *const u16).read_unaligned();
+                if line < LINES as usize {
+                    ioapics.routes[line] = Some(Route {
+                        gsi,
+                        active_low: flags & 0b11 == 0b11,
+                        level: (flags >> 2) & 0b11 == 0b11,
+                    });
+                }
+                // the line identity mapped to that GSI isn't wired to it,
+                // like the cascade line 2 which ISA line 0 usually takes.
+                let other = ioapics.routes.get_mut(gsi as usize);
+                if let Some(other) = other.filter(|_| gsi as usize != line) {
+                    if other.map_or(false, |route| route.gsi == gsi) {
+                        *other = None;
+                    }
+                }
+            }
+            _ => {}
+        }
+        entry = entry.add(len);
+    }
+}
+
+/// masks or unmasks ISA `line`, which does nothing for lines without an
+/// IO-APIC.
+pub fn set_masked(line: u8, masked: bool) {
+    let ioapics = IOAPICS.lock();
+    if let Some((apic, gsi)) = ioapics.find(line) {
+        unsafe {
+            let mut redirection = apic.redirection(gsi);
+            redirection.set_mask(masked);
+            apic.set_redirection(gsi, redirection);
+        }
+    }
+}
+
+pub unsafe fn init(rsdt: &'static Rsdt) {
     trace!("initializing the IO-APIC");
+    let Some(madt) = sdt::find(rsdt, b"APIC") else {
+        warn!("no MADT, device interrupts are unavailable");
+        return;
+    };
+    let mut ioapics = IOAPICS.lock();
+    parse_madt(madt, &mut ioapics);
+    for apic in ioapics.apics.iter().flatten() {
+        for gsi in apic.gsi_base..apic.gsi_base + apic.count {
+            let mut redirection = Redirection(0);
+            redirection.set_mask(true);
+            apic.set_redirection(gsi, redirection);
+        }
+    }
+    let dest = lapic::local_id();
+    for line in 0..LINES {
+        let Some((apic, gsi)) = ioapics.find(line) else {
+            continue;
+        };
+        let Some(route) = ioapics.routes[line as usize] else {
+            continue;
+        };
+        let mut redirection = Redirection(0);
+        redirection.set_vec(VECTOR_BASE + line);
+        redirection.set_deiliver_mode(Redirection::DELIVERY_MODE_NORMAL);
+        redirection.set_polarity(if route.active_low {
+            Redirection::POLARITY_LOW
+        } else {
+            Redirection::POLARITY_HIGH
+        });
+        redirection.set_trigger_mode(if route.level {
+            Redirection::TRIGGER_MODE_LEVEL_SENSITIVE
+        } else {
+            Redirection::TRIGGER_MODE_EDGE_SENSITIVE
+        });
+        redirection.set_mask(true);
+        redirection.set_dest(dest);
+        apic.set_redirection(gsi, redirection);
+    }
 }
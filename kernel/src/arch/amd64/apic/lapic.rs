@@ -62,6 +62,12 @@ impl LApicPtr {
     }
 }
 
+/// the APIC ID of the current core.
+pub unsafe fn local_id() -> u8 {
+    let ptr = LApicPtr(pmm::phys_to_hhdm(get_local_apic_base_adr()));
+    (ptr.read_reg(LApicPtr::ID) >> 24) as u8
+}
+
 #[must_use]
 pub unsafe fn create_local() -> LApicPtr {
     let phys_adr = get_local_apic_base_adr();
@@ -9,7 +9,7 @@ stack_end:
 .extern kernel_early
 .global _start 
 _start:
-    mov rsp, offset stack_end 
+    lea rsp, [rip + stack_end]
     push 0
     mov rbp, 0
     push rbp
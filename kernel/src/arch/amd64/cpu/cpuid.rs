@@ -0,0 +1,40 @@
+use core::arch::x86_64::__cpuid_count;
+
+/// CPU features the kernel makes use of.
+#[derive(Debug, Clone, Copy)]
+pub struct Features {
+    pub smep: bool,
+    pub smap: bool,
+    pub umip: bool,
+    pub nx: bool,
+    pub pcid: bool,
+    pub rdrand: bool,
+    pub rdseed: bool,
+}
+
+fn max_leaf(leaf: u32) -> u32 {
+    unsafe { __cpuid_count(leaf, 0).eax }
+}
+
+pub fn features() -> Features {
+    let basic = unsafe { __cpuid_count(1, 0) };
+    let extended = if max_leaf(0) >= 7 {
+        unsafe { __cpuid_count(7, 0) }
+    } else {
+        unsafe { core::mem::zeroed() }
+    };
+    let ext_info = if max_leaf(0x80000000) >= 0x80000001 {
+        unsafe { __cpuid_count(0x80000001, 0) }
+    } else {
+        unsafe { core::mem::zeroed() }
+    };
+    Features {
+        smep: extended.ebx & (1 << 7) != 0,
+        smap: extended.ebx & (1 << 20) != 0,
+        umip: extended.ecx & (1 << 2) != 0,
+        nx: ext_info.edx & (1 << 20) != 0,
+        pcid: basic.ecx & (1 << 17) != 0,
+        rdrand: basic.ecx & (1 << 30) != 0,
+        rdseed: extended.ebx & (1 << 18) != 0,
+    }
+}
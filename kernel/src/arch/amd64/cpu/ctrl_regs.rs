@@ -22,9 +22,29 @@ pub mod cr0 {
     }
 }
 
+pub mod cr3 {
+    use core::arch::asm;
+
+    /// the bits holding the PCID, or flags without PCIDs.
+    pub const FLAGS_MASK: u64 = 0xfff;
+
+    pub fn get() -> u64 {
+        unsafe {
+            let out: u64;
+            asm!("mov rax, cr3", out("rax") out);
+            out
+        }
+    }
+}
+
 pub mod cr4 {
     use core::arch::asm;
 
+    pub const UMIP: u64 = 1 << 11;
+    pub const PCIDE: u64 = 1 << 17;
+    pub const SMEP: u64 = 1 << 20;
+    pub const SMAP: u64 = 1 << 21;
+
     pub fn get() -> u64 {
         unsafe {
             let out: u64;
@@ -32,4 +52,8 @@ pub mod cr4 {
             out
         }
     }
+
+    pub unsafe fn set(v: u64) {
+        asm!("mov cr4, rax", in("rax") v);
+    }
 }
@@ -1,10 +1,13 @@
+pub mod cpuid;
 pub mod ctrl_regs;
 
 use super::apic::lapic;
 use super::apic::LApicPtr;
+use super::{msr, uaccess};
 use crate::mm::pmm;
 use core::mem::size_of;
 use core::ptr::null_mut;
+use ctrl_regs::cr4;
 
 static mut CUR_CORE: *mut Core = null_mut();
 
@@ -29,3 +32,31 @@ pub(super) unsafe fn init_core() {
 pub(super) fn get_core() -> *mut Core {
     unsafe { CUR_CORE }
 }
+
+/// enables every memory protection the CPU supports: NX, SMEP, SMAP and UMIP.
+pub(super) unsafe fn init_protections() {
+    let features = cpuid::features();
+    if features.nx {
+        msr::set(msr::IA32_EFER, msr::get(msr::IA32_EFER) | msr::EFER_NXE);
+    }
+    let mut cr4_val = cr4::get();
+    if features.smep {
+        cr4_val |= cr4::SMEP;
+    }
+    if features.smap {
+        cr4_val |= cr4::SMAP;
+    }
+    if features.umip {
+        cr4_val |= cr4::UMIP;
+    }
+    cr4::set(cr4_val);
+    uaccess::set_smap(features.smap);
+    let on_off = |enabled: bool| if enabled { "on" } else { "off" };
+    info!(
+        "cpu protections: NX {}, SMEP {}, SMAP {}, UMIP {}",
+        on_off(features.nx),
+        on_off(features.smep),
+        on_off(features.smap),
+        on_off(features.umip)
+    );
+}
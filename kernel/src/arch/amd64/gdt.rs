@@ -1,4 +1,5 @@
 use crate::mm::pmm;
+use crate::util::adr::VirtAdr;
 
 use super::interrupt;
 use core::arch::{asm, global_asm};
@@ -21,6 +22,11 @@ const USRSPC_DATA_FLAGS: u8 = 0xa;
 const TSS_ACCESS: u8 = 0x89;
 const TSS_FLAGS: u8 = 0;
 
+const INTERRUPT_STACK_PAGES: usize = 16;
+
+/// bytes of an IO permission bitmap, a bit for each port.
+pub const IO_BITMAP_LEN: usize = 0x10000 / 8;
+
 #[allow(unused)]
 const ENTRY_SIZE: u16 = core::mem::size_of::<Entry>() as u16;
 const_assert_eq!(ENTRY_SIZE, 8);
@@ -102,6 +108,20 @@ impl Tss {
     }
 }
 
+/// The TSS followed by the IO permission bitmap of the running process.
+#[repr(C, packed)]
+struct TaskState {
+    tss: Tss,
+    io_bitmap: [u8; IO_BITMAP_LEN],
+    /// the CPU reads two bytes of the bitmap at once, so a byte of set bits
+    /// has to follow it.
+    end: u8,
+}
+
+/// an `iopb` past the TSS limit denies userspace every port.
+const IOPB_NONE: u16 = size_of::<TaskState>() as u16;
+const IOPB: u16 = offset_of!(TaskState, io_bitmap) as u16;
+
 #[repr(C, packed)]
 struct Gdtr {
     size: u16,
@@ -144,7 +164,7 @@ pub const USRSPC_DATA_SELECTOR: u16 = offset_of!(Gdt, usrspc_data) as u16;
 pub const USRSPC_CODE_SELECTOR: u16 = offset_of!(Gdt, usrspc_code) as u16;
 
 impl Gdt {
-    pub fn new(tss: NonNull<Tss>) -> Self {
+    fn new(tss: NonNull<TaskState>) -> Self {
         Self {
             null: Entry::null(),
             kernel_code: Entry::new(0, 0xffffff, KERNEL_CODE_FLAGS, KERNEL_CODE_ACCESS),
@@ -154,7 +174,7 @@ impl Gdt {
             usrspc_code: Entry::new(0, 0xffffff, USRSPC_CODE_FLAGS, USRSPC_CODE_ACCESS),
             tss: SegmentEntry::new(
                 tss.addr().get() as u64,
-                size_of::<Tss>() as u32,
+                size_of::<TaskState>() as u32 - 1,
                 TSS_FLAGS,
                 TSS_ACCESS,
             ),
@@ -187,6 +207,9 @@ impl Gdt {
     }
 }
 
+// the CPU reads the GDT and TSS on every interrupt, so they have to stay
+// mapped in the user page maps when the kernel is isolated.
+#[cfg_attr(feature = "kpti", link_section = ".data.trampoline")]
 static mut GDT: Gdt = Gdt {
     null: Entry::null(),
     kernel_code: Entry::null(),
@@ -197,46 +220,74 @@ static mut GDT: Gdt = Gdt {
     usrspc_code: Entry::null(),
 };
 
-static mut TSS: Tss = Tss {
-    _resv0: 0,
-    rsp0: 0,
-    rsp1: 0,
-    rsp2: 0,
-    _resv1: 0,
-    ist1: 0,
-    ist2: 0,
-    ist3: 0,
-    ist4: 0,
-    ist5: 0,
-    ist6: 0,
-    ist7: 0,
-    _resv2: 0,
-    _resv3: 0,
-    iopb: 0,
+#[cfg_attr(feature = "kpti", link_section = ".data.trampoline")]
+static mut TASK_STATE: TaskState = TaskState {
+    tss: Tss {
+        _resv0: 0,
+        rsp0: 0,
+        rsp1: 0,
+        rsp2: 0,
+        _resv1: 0,
+        ist1: 0,
+        ist2: 0,
+        ist3: 0,
+        ist4: 0,
+        ist5: 0,
+        ist6: 0,
+        ist7: 0,
+        _resv2: 0,
+        _resv3: 0,
+        iopb: IOPB_NONE,
+    },
+    io_bitmap: [0xff; IO_BITMAP_LEN],
+    end: 0xff,
 };
 
+static mut INTERRUPT_STACKS: [VirtAdr; 2] = [VirtAdr::null(); 2];
+
+/// base addresses and page count of the stacks the CPU switches to on
+/// interrupts, the privilege level 0 stack and IST1.
+pub fn interrupt_stacks() -> ([VirtAdr; 2], usize) {
+    unsafe { (INTERRUPT_STACKS, INTERRUPT_STACK_PAGES) }
+}
+
 pub unsafe fn init() {
     trace!("initializing GDT");
-    let rsp_stack_alloc = pmm::alloc_pages(16);
-    let ist1_stack_alloc = pmm::alloc_pages(16);
-    TSS = Tss {
-        rsp0: rsp_stack_alloc.virt().adr(),
-        rsp1: rsp_stack_alloc.virt().adr(),
-        rsp2: rsp_stack_alloc.virt().adr(),
-        ist1: ist1_stack_alloc.virt().adr(),
+    let rsp_stack = pmm::alloc_pages(INTERRUPT_STACK_PAGES).virt();
+    let ist1_stack = pmm::alloc_pages(INTERRUPT_STACK_PAGES).virt();
+    INTERRUPT_STACKS = [rsp_stack, ist1_stack];
+    // stacks grow down, so the TSS points at their tops.
+    let rsp_stack_top = rsp_stack.add(INTERRUPT_STACK_PAGES * pmm::PAGE_SIZE).adr();
+    let ist1_stack_top = ist1_stack.add(INTERRUPT_STACK_PAGES * pmm::PAGE_SIZE).adr();
+    TASK_STATE.tss = Tss {
+        rsp0: rsp_stack_top,
+        rsp1: rsp_stack_top,
+        rsp2: rsp_stack_top,
+        ist1: ist1_stack_top,
         ist2: 0,
         ist3: 0,
         ist4: 0,
         ist5: 0,
         ist6: 0,
         ist7: 0,
-        iopb: 0,
+        iopb: IOPB_NONE,
         ..Tss::default()
     };
-    GDT = Gdt::new(NonNull::new_unchecked(&mut TSS as *mut _));
+    GDT = Gdt::new(NonNull::new_unchecked(&mut TASK_STATE as *mut _));
 }
 
 pub unsafe fn install() {
     GDT.install();
     GDT.use_tss(TSS_SELECTOR);
 }
+
+/// copies `bitmap` into the TSS and lets userspace use the ports it allows.
+pub(super) unsafe fn copy_io_bitmap(bitmap: &[u8; IO_BITMAP_LEN]) {
+    TASK_STATE.io_bitmap = *bitmap;
+    TASK_STATE.tss.iopb = IOPB;
+}
+
+/// makes userspace use the bitmap in the TSS, or no ports at all.
+pub(super) unsafe fn use_io_bitmap(enabled: bool) {
+    TASK_STATE.tss.iopb = if enabled { IOPB } else { IOPB_NONE };
+}
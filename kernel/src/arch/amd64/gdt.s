@@ -4,7 +4,7 @@
 .global set_segments
 set_segments:
     push 0x08
-    lea rax, reload_cs
+    lea rax, [rip + reload_cs]
     push rax  
     retfq
 reload_cs: 
@@ -1,4 +1,8 @@
+use super::super::uaccess;
 use super::StackFrame;
+use crate::mm::vmm;
+use crate::process::{self, thread};
+use crate::util::adr::VirtAdr;
 use core::arch::asm;
 
 #[no_mangle]
@@ -74,13 +78,25 @@ unsafe extern "C" fn excpt_general_protection_fault(
 }
 
 #[no_mangle]
-unsafe extern "C" fn excpt_page_fault(_stackframe: *mut StackFrame) {
+unsafe extern "C" fn excpt_page_fault(stackframe: *mut StackFrame) {
     let adr: u64;
     asm!(
         "mov {adr}, cr2",
         adr = out(reg) adr
     );
+    // lazily populated mappings fault on their first access, from userspace
+    // or from the kernel copying to or from them.
+    if adr < vmm::USERSPACE_END && process::populate_fault(VirtAdr::new(adr)) {
+        return;
+    }
+    if let Some(fixup) = uaccess::search_ex_table((*stackframe).rip) {
+        (*stackframe).rip = fixup;
+        return;
+    }
     //debug_assert!(adr != 0, "nullptr");
+    if let Some(id) = thread::find_guard_page_owner(VirtAdr::new(adr)) {
+        panic!("stack overflow in thread {id} (page fault at adr {adr:016x})")
+    }
     panic!("page fault at adr {adr:016x}")
     //if let Some(mut entry) = vm::get_page_entry(vm::get_cur(), adr) {
     //    let entry = entry.as_mut();
@@ -88,6 +88,7 @@ impl Idt {
     }
 }
 
+#[cfg_attr(feature = "kpti", link_section = ".data.trampoline")]
 static mut IDT: Idt = Idt {
     entries: [Entry(0); 256],
 };
@@ -7,3 +7,10 @@ unsafe extern "C" fn irq_timer(stackframe: *mut StackFrame) {
     sched::step(stackframe);
     lapic::eoi();
 }
+
+/// ISA `line` was raised, see `crate::irq`.
+#[no_mangle]
+unsafe extern "C" fn irq_line(_stackframe: *mut StackFrame, line: u64) {
+    crate::irq::raise(line as u8);
+    lapic::eoi();
+}
@@ -4,12 +4,17 @@ mod except;
 mod irq;
 mod syscall;
 
+pub use self::syscall::restart_syscall;
+
 use super::cpu::ctrl_regs::{cr0, cr4};
 use super::gdt;
 use super::vm::PageMapPtr;
 use core::arch::{asm, global_asm};
 
-global_asm!(include_str!("stubs.s"));
+/// whether the entry stubs switch between the kernel and shadow page maps.
+const KPTI: u8 = cfg!(feature = "kpti") as u8;
+
+global_asm!(include_str!("stubs.s"), kpti = const KPTI);
 
 #[derive(Clone, Debug)]
 #[repr(C, packed)]
@@ -166,12 +171,19 @@ static ISR_META_TBL: [IsrMeta; 256] = const {
 
 #[inline]
 pub fn disable() {
-    unsafe { asm!("cli", options(nostack)) };
+    // host test builds run in userspace, where cli and sti fault.
+    #[cfg(not(test))]
+    unsafe {
+        asm!("cli", options(nostack))
+    };
 }
 
 #[inline]
 pub fn enable() {
-    unsafe { asm!("sti", options(nostack)) }
+    #[cfg(not(test))]
+    unsafe {
+        asm!("sti", options(nostack))
+    }
 }
 
 #[inline]
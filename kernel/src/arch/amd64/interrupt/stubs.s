@@ -1,7 +1,74 @@
+.set KPTI, {kpti}
+
+.if KPTI
+.section .data.trampoline, "aw"
+
+# PCID of the user page maps, zero if PCIDs aren't supported.
+.global kpti_user_pcid
+kpti_user_pcid: .quad 0
+# bit 63 if PCIDs are supported, which keeps the TLB when written to cr3.
+.global kpti_cr3_noflush
+kpti_cr3_noflush: .quad 0
+# set whenever a different page map is installed, so the user PCID is flushed
+# on the next return to userspace.
+.global kpti_user_stale
+kpti_user_stale: .byte 0
+
+.align 16
+kpti_rax_save: .quad 0
+    .space 64, 0
+kpti_stack:
+.endif
+
 .code64
-.section .text
+.section .text.trampoline, "ax"
 
 .set EXCEPTION_DUMMY_ERROR, 0xFFFFFFFFFFFFFFFF
+.set KPTI_SHADOW_OFFSET, 0x1000
+.set KPTI_PCID_MASK, 0xFFF
+
+# switches from the shadow page map to the kernel page map.
+.macro KPTI_ENTER_KERNEL
+	push rax
+	mov rax, cr3
+	and rax, ~KPTI_PCID_MASK
+	sub rax, KPTI_SHADOW_OFFSET
+	or rax, [rip + kpti_cr3_noflush]
+	mov cr3, rax
+	pop rax
+.endm
+
+# switches to the shadow page map and returns to userspace. the interrupt frame
+# is moved to the trampoline stack first, as the stack it is on may not be
+# mapped in the shadow page map.
+.macro KPTI_EXIT_TO_USER
+	mov [rip + kpti_rax_save], rax
+	mov rax, [rsp + 40]
+	mov [rip + kpti_stack - 8], rax
+	mov rax, [rsp + 32]
+	mov [rip + kpti_stack - 16], rax
+	mov rax, [rsp + 24]
+	mov [rip + kpti_stack - 24], rax
+	mov rax, [rsp + 16]
+	mov [rip + kpti_stack - 32], rax
+	mov rax, [rsp + 8]
+	mov [rip + kpti_stack - 40], rax
+	lea rsp, [rip + kpti_stack - 40]
+
+	mov rax, cr3
+	add rax, KPTI_SHADOW_OFFSET
+	or rax, [rip + kpti_user_pcid]
+	cmp byte ptr [rip + kpti_user_stale], 0
+	jne 2f
+	or rax, [rip + kpti_cr3_noflush]
+	2:
+	mov byte ptr [rip + kpti_user_stale], 0
+	mov cr3, rax
+	mov rax, [rip + kpti_rax_save]
+
+	swapgs
+	iretq
+.endm
 
 .macro PUSH_REGS
 	push rax
@@ -35,7 +102,14 @@
 	mov cr4, rax
 
 	pop rax
+.if KPTI
+	mov rbx, cr3
+	cmp rax, rbx
+	je 2f
+	mov byte ptr [rip + kpti_user_stale], 1
+.endif
 	mov cr3, rax
+	2:
 
 	pop rax
 	mov cr0, rax
@@ -57,17 +131,13 @@
 	pop rax
 .endm
 
-.macro HANDLER n, handler 
-	test qword ptr [rsp + 16], 0x3
-	jz 1f
-	
-	swapgs
-
-	1:
-
+# calls `handler` with the stack frame and `arg` once the kernel was entered,
+# and returns to where the frame points.
+.macro HANDLE handler, arg=0
 	PUSH_REGS
 	
 	mov rdi, rsp 
+	mov rsi, \arg
 	.extern \handler
 	call \handler
 	
@@ -76,7 +146,11 @@
 	test qword ptr [rsp + 16], 0x3
 	jz 1f
 	
+.if KPTI
+	KPTI_EXIT_TO_USER
+.else
 	swapgs
+.endif
 
 	1:
 
@@ -84,6 +158,21 @@
 	iretq
 .endm 
 
+# calls `handler` with the stack frame and `arg`.
+.macro HANDLER n, handler, arg=0
+	test qword ptr [rsp + 16], 0x3
+	jz 1f
+	
+	swapgs
+.if KPTI
+	KPTI_ENTER_KERNEL
+.endif
+
+	1:
+
+	HANDLE \handler \arg
+.endm
+
 .macro EXCPT n, handler
 	.align 8
 	irq_handler_\n:
@@ -104,6 +193,20 @@
 		HANDLER \n \handler
 .endm 
 
+# an ISA line routed through the IO-APIC.
+.macro IRQ_LINE n, line
+	.align 8
+	irq_handler_\n:
+        push EXCEPTION_DUMMY_ERROR
+		HANDLER \n irq_line \line
+.endm
+
+# entered from `syscall_enter` with a frame returning to the syscall
+# instruction of a syscall which runs again, the other threads run first.
+.global syscall_yield
+syscall_yield:
+	HANDLE syscall_switch
+
 EXCPT_DUMMY 0 division_error
 EXCPT_DUMMY 1 debug
 EXCPT_DUMMY 2 non_maskable_interrupt
@@ -155,22 +258,22 @@ IRQ 45 unimp
 IRQ 46 unimp
 IRQ 47 unimp
 
-IRQ 48 unimp
-IRQ 49 unimp
-IRQ 50 unimp
-IRQ 51 unimp
-IRQ 52 unimp
-IRQ 53 unimp
-IRQ 54 unimp
-IRQ 55 unimp
-IRQ 56 unimp
-IRQ 57 unimp
-IRQ 58 unimp
-IRQ 59 unimp
-IRQ 60 unimp
-IRQ 61 unimp
-IRQ 62 unimp
-IRQ 63 unimp
+IRQ_LINE 48 0
+IRQ_LINE 49 1
+IRQ_LINE 50 2
+IRQ_LINE 51 3
+IRQ_LINE 52 4
+IRQ_LINE 53 5
+IRQ_LINE 54 6
+IRQ_LINE 55 7
+IRQ_LINE 56 8
+IRQ_LINE 57 9
+IRQ_LINE 58 10
+IRQ_LINE 59 11
+IRQ_LINE 60 12
+IRQ_LINE 61 13
+IRQ_LINE 62 14
+IRQ_LINE 63 15
 IRQ 64 unimp
 IRQ 65 unimp
 IRQ 66 unimp
@@ -364,7 +467,8 @@ IRQ 253 unimp
 IRQ 254 unimp
 IRQ 255 unimp
 
-.section .rodata
+# holds absolute addresses which are relocated when loading the kernel.
+.section .data.rel.ro
 
 .global irq_routines
 irq_routines:
@@ -1,25 +1,78 @@
+use super::StackFrame;
 use crate::arch::imp::gdt;
 use crate::arch::imp::msr;
+use crate::process::thread::{self, sched};
 use core::arch::global_asm;
 
 pub fn init() {
     trace!("initializing syscalls");
-    msr::set(msr::IA32_EFER, msr::get(msr::IA32_EFER) | 1);
+    msr::set(msr::IA32_EFER, msr::get(msr::IA32_EFER) | msr::EFER_SCE);
     msr::set(
         msr::IA32_STAR,
         ((gdt::KERNEL_CODE_SELECTOR as u64) << 32) | ((gdt::USRSPC_CODE_32_SELECTOR as u64) << 48),
     );
     msr::set(msr::IA32_LSTAR, syscall_enter as u64);
-    msr::set(msr::IA32_FMASK, 1 << 10 /* clears direction bit */);
+    // clears the interrupt, direction and alignment check flags. syscalls
+    // run with interrupts disabled: they share a single kernel stack, which
+    // the entry stub only switches to after a few instructions, and under
+    // KPTI the shadow page map is still installed until then.
+    msr::set(msr::IA32_FMASK, (1 << 9) | (1 << 10) | (1 << 18));
 }
 
 extern "C" {
     fn syscall_enter();
 }
 
-global_asm!(include_str!("syscall.s"));
+/// makes the current thread's syscall run again from the start once it
+/// returns, after the scheduler ran the other threads. the arguments are
+/// still in their registers, so it sees the same ones.
+pub fn restart_syscall() {
+    unsafe {
+        thread::cur_thread()
+            .get_mut()
+            .arch_mut()
+            .set_restart_syscall()
+    };
+}
+
+global_asm!(
+    include_str!("syscall.s"),
+    kpti = const super::KPTI,
+    user_cs = const gdt::USRSPC_CODE_SELECTOR as u64 | 3,
+    user_ss = const gdt::USRSPC_DATA_SELECTOR as u64 | 3,
+);
+
+/// returned in rax and rdx.
+#[repr(C)]
+struct SyscallReturn {
+    value: u64,
+    restart: u64,
+}
+
+#[no_mangle]
+unsafe extern "C" fn syscall_handler(
+    arg0: u64,
+    arg1: u64,
+    arg2: u64,
+    arg3: u64,
+    arg4: u64,
+    arg5: u64,
+    syscall: u64,
+) -> SyscallReturn {
+    let value = crate::syscall::syscall(syscall, [arg0, arg1, arg2, arg3, arg4, arg5]);
+    let restart = thread::cur_thread()
+        .get_mut()
+        .arch_mut()
+        .take_restart_syscall();
+    SyscallReturn {
+        value,
+        restart: restart as u64,
+    }
+}
 
+/// switches to the next thread from a syscall which restarts, `stackframe`
+/// returns to its syscall instruction.
 #[no_mangle]
-unsafe extern "C" fn syscall_handler(syscall: u64, param0: u64, param1: u64) -> u64 {
-    crate::syscall::syscall(syscall, param0, param1)
+unsafe extern "C" fn syscall_switch(stackframe: *mut StackFrame) {
+    sched::switch(stackframe);
 }
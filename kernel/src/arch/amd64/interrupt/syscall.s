@@ -1,34 +1,104 @@
+.set KPTI, {kpti}
+.set KPTI_SHADOW_OFFSET, 0x1000
+.set KPTI_PCID_MASK, 0xFFF
+.set USER_CS, {user_cs}
+.set USER_SS, {user_ss}
+
 .section .bss
 .align 16
     .space (1 << 12), 0
 kernel_stack:
 
-.section .data
+.section .data.trampoline, "aw"
 userspace_stack_save: .quad 0
 kernel_stack_save: .quad kernel_stack
 
 .code64
-.section .text
+.section .text.trampoline, "ax"
 .extern syscall_handler
 .global syscall_enter
 syscall_enter:
     swapgs 
 
-    mov [userspace_stack_save], rsp
-    mov rsp, [kernel_stack_save]
+    mov [rip + userspace_stack_save], rsp
+.if KPTI
+    # rsp is the only free register until the kernel stack is loaded.
+    mov rsp, cr3
+    and rsp, ~KPTI_PCID_MASK
+    sub rsp, KPTI_SHADOW_OFFSET
+    or rsp, [rip + kpti_cr3_noflush]
+    mov cr3, rsp
+.endif
+    mov rsp, [rip + kernel_stack_save]
 
     push rcx 
     push r11
     push rbp 
+    push rdi
+    push rsi
+    push rdx
+    push r10
+    push r8
+    push r9
 
+    # the syscall number is passed on the stack as the 7th argument, and
+    # r10 takes the place of rcx which holds the return address.
+    push rax
+    mov rcx, r10
     call syscall_handler
-    
+    # rdx is set if the syscall has to run again.
+    test rdx, rdx
+    jnz 2f
+    add rsp, 8
+
+    pop r9
+    pop r8
+    pop r10
+    pop rdx
+    pop rsi
+    pop rdi
     pop rbp 
     pop r11 
     pop rcx
 
-    mov rsp, [userspace_stack_save]
+.if KPTI
+    mov rsp, cr3
+    add rsp, KPTI_SHADOW_OFFSET
+    or rsp, [rip + kpti_user_pcid]
+    cmp byte ptr [rip + kpti_user_stale], 0
+    jne 1f
+    or rsp, [rip + kpti_cr3_noflush]
+1:
+    mov byte ptr [rip + kpti_user_stale], 0
+    mov cr3, rsp
+.endif
+    mov rsp, [rip + userspace_stack_save]
     
     swapgs 
 
-    sysretq
\ No newline at end of file
+    sysretq
+
+2:
+    # the syscall number goes back into rax for the syscall to run again.
+    pop rax
+    pop r9
+    pop r8
+    pop r10
+    pop rdx
+    pop rsi
+    pop rdi
+    pop rbp
+    pop r11
+    pop rcx
+
+    # an interrupt frame returning to the 2 byte syscall instruction, with
+    # the flags sysret would restore and no error code.
+    push USER_SS
+    push qword ptr [rip + userspace_stack_save]
+    push r11
+    push USER_CS
+    sub rcx, 2
+    push rcx
+    push 0
+.extern syscall_yield
+    jmp syscall_yield
@@ -0,0 +1,77 @@
+//! IO permission bitmaps, which let a process use the ports it was granted
+//! with `in` and `out`.
+//!
+//! The TSS holds the bitmap of the running process. It's only copied there
+//! when a process with another bitmap runs, most processes have none.
+
+use super::gdt::{self, IO_BITMAP_LEN};
+use alloc::boxed::Box;
+use core::fmt::{self, Debug};
+use core::ops::Range;
+use core::ptr;
+
+/// the bitmap the TSS holds a copy of.
+static mut LOADED: *const [u8; IO_BITMAP_LEN] = ptr::null();
+
+/// The ports a process may use, a set bit denies its port.
+pub struct IoBitmap(Box<[u8; IO_BITMAP_LEN]>);
+
+impl IoBitmap {
+    /// a bitmap denying every port.
+    pub fn new() -> Self {
+        let bits = vec![0xff; IO_BITMAP_LEN].into_boxed_slice();
+        Self(bits.try_into().unwrap())
+    }
+
+    pub fn allow(&mut self, ports: Range<u16>) {
+        for port in ports {
+            self.0[port as usize / 8] &= !(1 << (port % 8));
+        }
+        self.forget();
+    }
+
+    pub fn allows(&self, port: u16) -> bool {
+        self.0[port as usize / 8] & (1 << (port % 8)) == 0
+    }
+
+    /// makes the next `load` copy the bitmap again.
+    fn forget(&self) {
+        unsafe {
+            if ptr::eq(LOADED, &*self.0) {
+                LOADED = ptr::null();
+            }
+        }
+    }
+}
+
+impl Default for IoBitmap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for IoBitmap {
+    fn drop(&mut self) {
+        self.forget();
+    }
+}
+
+impl Debug for IoBitmap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let allowed = (0..=u16::MAX).filter(|&port| self.allows(port)).count();
+        write!(f, "IoBitmap {{ allowed: {allowed} }}")
+    }
+}
+
+/// makes userspace run with `bitmap`, or without any ports if it's `None`.
+/// interrupts have to be disabled.
+pub unsafe fn load(bitmap: Option<&IoBitmap>) {
+    match bitmap {
+        Some(bitmap) if ptr::eq(LOADED, &*bitmap.0) => gdt::use_io_bitmap(true),
+        Some(bitmap) => {
+            gdt::copy_io_bitmap(&bitmap.0);
+            LOADED = &*bitmap.0;
+        }
+        None => gdt::use_io_bitmap(false),
+    }
+}
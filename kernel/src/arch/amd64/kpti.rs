@@ -0,0 +1,128 @@
+//! Kernel page-table isolation.
+//!
+//! Every userspace page map is followed by a shadow page map which is
+//! installed while userspace runs. Its lower half mirrors the userspace
+//! mappings, but its upper half only maps the trampoline: the entry stubs, the
+//! data they touch, the GDT, IDT and TSS, and the interrupt stacks. The entry
+//! stubs switch between the two, see `interrupt/stubs.s`.
+
+use super::cpu::cpuid;
+use super::cpu::ctrl_regs::cr4;
+use super::gdt;
+use super::vm::{self, PageMapPtr, VMFlags, LARGE_PAGE_SIZE, MEDIUM_PAGE_SIZE, PAGE_SIZE};
+use crate::mm::pmm;
+use crate::symbols;
+use crate::util::adr::VirtAdr;
+
+/// PCID of the shadow page maps. the kernel page maps use PCID 0.
+const USER_PCID: u64 = 1;
+
+/// number of PML4 entries covering userspace.
+const USER_ENTRIES: usize = 256;
+
+extern "C" {
+    static mut kpti_user_pcid: u64;
+    static mut kpti_cr3_noflush: u64;
+    static mut kpti_user_stale: u8;
+}
+
+static mut TRAMPOLINE_PAGE_MAP: PageMapPtr = unsafe { PageMapPtr::nullptr() };
+
+pub(super) unsafe fn init() {
+    let pcid = cpuid::features().pcid;
+    if pcid {
+        cr4::set(cr4::get() | cr4::PCIDE);
+        kpti_user_pcid = USER_PCID;
+        kpti_cr3_noflush = 1 << 63;
+    }
+    info!(
+        "kernel page-table isolation enabled (PCID {})",
+        if pcid { "on" } else { "off" }
+    );
+}
+
+/// maps the pages at `start..end` with the same physical pages as in the
+/// kernel page map.
+unsafe fn map_kernel_range(map: PageMapPtr, start: u64, end: u64, flags: VMFlags) {
+    let mut virt = VirtAdr::new(start);
+    while virt.adr() < end {
+        let phys = vm::get_page_entry(vm::kernel_page_map(), virt)
+            .expect("trampoline is not mapped in the kernel page map")
+            .as_ref()
+            .adr();
+        map.map(virt, 1, phys, flags);
+        virt = virt.add(PAGE_SIZE);
+    }
+}
+
+/// the trampoline is built on first use, as the kernel image is only mapped
+/// into the kernel page map once the kernel VMM is created.
+unsafe fn trampoline_page_map() -> PageMapPtr {
+    if !TRAMPOLINE_PAGE_MAP.adr().is_null() {
+        return TRAMPOLINE_PAGE_MAP;
+    }
+    let map = PageMapPtr::from_vadr(pmm::alloc_pages_zeroed(1).virt());
+    map_kernel_range(
+        map,
+        symbols::trampoline_text_start(),
+        symbols::trampoline_text_end(),
+        VMFlags::PRESENT,
+    );
+    map_kernel_range(
+        map,
+        symbols::trampoline_data_start(),
+        symbols::trampoline_data_end(),
+        VMFlags::PRESENT | VMFlags::RW | VMFlags::XD,
+    );
+    let (stacks, stack_pages) = gdt::interrupt_stacks();
+    for stack in stacks {
+        map.map(
+            stack,
+            stack_pages,
+            pmm::hhdm_to_phys(stack),
+            VMFlags::PRESENT | VMFlags::RW | VMFlags::XD,
+        );
+    }
+    TRAMPOLINE_PAGE_MAP = map;
+    map
+}
+
+/// makes the next return to userspace flush the user PCID, which still holds
+/// the TLB entries of the page map installed before.
+pub(super) unsafe fn mark_user_stale() {
+    kpti_user_stale = 1;
+}
+
+pub(super) unsafe fn init_shadow_page_map(shadow: PageMapPtr) {
+    let trampoline = trampoline_page_map();
+    for i in USER_ENTRIES..USER_ENTRIES * 2 {
+        *shadow.entry(i) = *trampoline.entry(i);
+    }
+}
+
+/// copies the userspace PML4 entries covering a new mapping of `map` into its
+/// shadow page map, the lower level tables are then shared between the two.
+pub(super) unsafe fn sync_shadow_page_map(
+    map: PageMapPtr,
+    virt: VirtAdr,
+    pages: usize,
+    flags: VMFlags,
+) {
+    // the kernel page map has no shadow.
+    if map.adr().adr() == vm::kernel_page_map().adr().adr() || pages == 0 {
+        return;
+    }
+    let page_size = if flags.has(VMFlags::SIZE_LARGE) {
+        LARGE_PAGE_SIZE
+    } else if flags.has(VMFlags::SIZE_MEDIUM) {
+        MEDIUM_PAGE_SIZE
+    } else {
+        PAGE_SIZE
+    };
+    let first = (virt.adr() >> 39) as usize;
+    let last = ((virt.adr() + (pages * page_size) as u64 - 1) >> 39) as usize;
+    let shadow = map.shadow();
+    for i in first..=last.min(USER_ENTRIES - 1) {
+        *shadow.entry(i) = *map.entry(i);
+    }
+}
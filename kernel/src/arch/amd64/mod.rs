@@ -1,23 +1,32 @@
 pub mod cpu;
 pub mod fb;
 pub mod interrupt;
+pub mod io_bitmap;
 pub mod panic;
+pub mod port;
+pub mod random;
 pub mod serial;
 pub mod stack_unwind;
 pub mod thread;
+pub mod uaccess;
 pub mod vm;
 
 mod apic;
 mod gdt;
+#[cfg(feature = "kpti")]
+mod kpti;
 mod msr;
 mod pic;
-mod port;
 mod sdt;
 
 use crate::boot::BootInfo;
 use apic::ioapic;
+
+pub use apic::ioapic as irq;
 use core::arch::global_asm;
 
+// host test builds link against the C runtime, which brings its own `_start`.
+#[cfg(not(test))]
 global_asm!(include_str!("boot.s"));
 
 #[allow(non_camel_case_types)]
@@ -30,9 +39,11 @@ pub unsafe fn arch_init(boot_info: &mut BootInfo) {
     gdt::init();
     gdt::install();
     interrupt::init();
+    cpu::init_protections();
+    #[cfg(feature = "kpti")]
+    kpti::init();
     vm::init();
     cpu::init_core();
-    let _ = sdt::init(&boot_info);
-    ioapic::init();
+    ioapic::init(sdt::init(boot_info));
     pic::disable();
 }
@@ -7,6 +7,9 @@ pub const IA32_STAR: Msr = 0xc0000081;
 pub const IA32_LSTAR: Msr = 0xc0000082;
 pub const IA32_FMASK: Msr = 0xc0000084;
 
+pub const EFER_SCE: u64 = 1 << 0;
+pub const EFER_NXE: u64 = 1 << 11;
+
 pub const KERNEL_GS_BASE: u32 = 0xc0000102;
 pub const GS_BASE: u32 = 0xc0000101;
 pub const FS_BASE: u32 = 0xC0000100;
@@ -58,7 +58,7 @@ pub fn in16(port: u16) -> u16 {
     let mut out: u16;
     unsafe {
         asm! {
-            "in al, dx",
+            "in ax, dx",
             out("ax") out,
             in("dx") port,
             options(nostack)
@@ -72,7 +72,7 @@ pub fn in32(port: u16) -> u32 {
     let mut out: u32;
     unsafe {
         asm! {
-            "in al, dx",
+            "in eax, dx",
             out("eax") out,
             in("dx") port,
             options(nostack)
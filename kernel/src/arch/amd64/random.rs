@@ -0,0 +1,49 @@
+use super::cpu::cpuid;
+use core::arch::asm;
+use core::arch::x86_64::_rdtsc;
+
+/// rdrand and rdseed may fail transiently while the hardware is busy.
+const RETRIES: usize = 10;
+
+unsafe fn rdrand() -> Option<u64> {
+    for _ in 0..RETRIES {
+        let val: u64;
+        let ok: u8;
+        asm!("rdrand {}", "setc {}", out(reg) val, out(reg_byte) ok, options(nomem, nostack));
+        if ok != 0 {
+            return Some(val);
+        }
+    }
+    None
+}
+
+unsafe fn rdseed() -> Option<u64> {
+    for _ in 0..RETRIES {
+        let val: u64;
+        let ok: u8;
+        asm!("rdseed {}", "setc {}", out(reg) val, out(reg_byte) ok, options(nomem, nostack));
+        if ok != 0 {
+            return Some(val);
+        }
+    }
+    None
+}
+
+/// gathers 64 bits of entropy, from rdseed or rdrand if the CPU has them.
+/// the time stamp counter is always mixed in, and is all there is on CPUs
+/// without a hardware random number generator.
+pub fn entropy() -> u64 {
+    let features = cpuid::features();
+    let mut entropy = unsafe { _rdtsc() }.rotate_left(32);
+    if features.rdseed {
+        if let Some(seed) = unsafe { rdseed() } {
+            return entropy ^ seed;
+        }
+    }
+    if features.rdrand {
+        if let Some(rand) = unsafe { rdrand() } {
+            entropy ^= rand;
+        }
+    }
+    entropy
+}
@@ -1,12 +1,13 @@
 use crate::boot::BootInfo;
 use crate::mm::pmm;
 use crate::util::adr::PhysAdr;
+use core::mem::size_of;
 
 #[repr(C, packed)]
 struct Rsdp {
-    pub signature: [char; 8],
+    pub signature: [u8; 8],
     pub checksum: u8,
-    pub oem_id: [char; 8],
+    pub oem_id: [u8; 6],
     pub revision: u8,
     pub rsdt_adr: u32,
     pub length: u32,
@@ -25,14 +26,15 @@ fn get_rsdp(boot_info: &BootInfo) -> &'static Rsdp {
     }
 }
 
+/// The header of the RSDT, which every other table starts with as well.
 #[repr(C, packed)]
 pub struct Rsdt {
-    pub singature: [char; 4],
+    pub singature: [u8; 4],
     pub length: u32,
     pub revision: u8,
     pub checksum: u8,
-    pub oem_id: [char; 6],
-    pub oem_table_id: [char; 8],
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
     pub oem_revision: u32,
     pub creator_id: u32,
     pub creator_revision: u32,
@@ -49,12 +51,24 @@ unsafe fn validate(rsdt: &Rsdt) -> bool {
     );
     let len = rsdt.length;
     let mut ptr = rsdt as *const _ as *const u8;
-    let mut sum = 0;
+    let mut sum = 0u8;
     for _ in 0..len {
-        sum += *ptr;
+        sum = sum.wrapping_add(*ptr);
         ptr = ptr.add(1);
     }
-    sum % 100 == 0
+    sum == 0
+}
+
+/// the table with `signature` the RSDT points to, if there is a valid one.
+pub unsafe fn find(rsdt: &'static Rsdt, signature: &[u8; 4]) -> Option<&'static Rsdt> {
+    let count = (rsdt.length as usize - size_of::<Rsdt>()) / size_of::<u32>();
+    let entries = (rsdt as *const Rsdt).add(1) as *const u32;
+    (0..count)
+        .map(|i| {
+            let adr = PhysAdr::new(entries.add(i).read_unaligned() as u64);
+            &*(pmm::phys_to_hhdm(adr).ptr() as *const Rsdt)
+        })
+        .find(|table| &table.singature == signature && validate(table))
 }
 
 pub unsafe fn init(boot_info: &BootInfo) -> &'static Rsdt {
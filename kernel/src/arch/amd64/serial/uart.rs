@@ -1,4 +1,9 @@
-use crate::arch::imp::port::out8;
+use crate::arch::imp::port::{in8, out8};
+
+/// line status register, relative to the base port.
+const LINE_STATUS: u16 = 5;
+/// a received byte is waiting in the data register.
+const LINE_STATUS_DATA_READY: u8 = 1 << 0;
 
 pub struct Uart(u16);
 
@@ -6,6 +11,20 @@ impl Uart {
     pub fn putb(&self, b: u8) {
         out8(self.0, b)
     }
+
+    /// whether a received byte is waiting, without taking it.
+    pub fn can_read(&self) -> bool {
+        in8(self.0 + LINE_STATUS) & LINE_STATUS_DATA_READY != 0
+    }
+
+    /// the next received byte, if there is one.
+    pub fn getb(&self) -> Option<u8> {
+        if self.can_read() {
+            Some(in8(self.0))
+        } else {
+            None
+        }
+    }
 }
 
 static UARTS: &[Uart] = &[
@@ -19,6 +38,14 @@ pub fn putb(b: u8) {
     DEFAULT_UART.putb(b);
 }
 
+pub fn can_read() -> bool {
+    DEFAULT_UART.can_read()
+}
+
+pub fn getb() -> Option<u8> {
+    DEFAULT_UART.getb()
+}
+
 pub fn putc(c: char) {
     putb(c as u8)
 }
@@ -10,6 +10,8 @@ use core::ptr::null_mut;
 pub struct ArchThread {
     ptr: u64,
     cur_core: *mut Core,
+    /// set by `restart_syscall`, the thread's syscall runs again.
+    restart_syscall: bool,
 }
 
 impl ArchThread {
@@ -17,6 +19,7 @@ impl ArchThread {
         Self {
             ptr: 0,
             cur_core: null_mut(),
+            restart_syscall: false,
         }
     }
 
@@ -29,6 +32,15 @@ impl ArchThread {
     pub(super) fn core_ptr(&self) -> *mut Core {
         self.cur_core
     }
+
+    pub(super) fn set_restart_syscall(&mut self) {
+        self.restart_syscall = true;
+    }
+
+    /// whether the syscall has to run again, which is cleared for the next.
+    pub(super) fn take_restart_syscall(&mut self) -> bool {
+        core::mem::take(&mut self.restart_syscall)
+    }
 }
 
 pub fn get_gs_base() -> VirtAdr {
@@ -55,6 +67,11 @@ pub fn cur_thread() -> ThreadPtr {
     }
 }
 
+/// returns false until the first thread has been made current.
+pub fn has_thread() -> bool {
+    !get_gs_base().is_null()
+}
+
 pub unsafe fn set_thread(thread: &mut Thread) {
     let core_ptr = super::cpu::get_core();
     debug_assert!(!core_ptr.is_null());
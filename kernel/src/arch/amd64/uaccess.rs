@@ -0,0 +1,68 @@
+//! Exception fixups for kernel accesses to userspace memory.
+
+use crate::symbols;
+use core::arch::global_asm;
+use core::mem::size_of;
+use core::slice;
+
+global_asm!(include_str!("uaccess.s"));
+
+extern "C" {
+    fn copy_user(dst: *mut u8, src: *const u8, len: usize) -> usize;
+
+    /// non-zero if `copy_user` has to lift SMAP with `stac` for the copy.
+    static mut smap_enabled: u8;
+}
+
+pub(super) unsafe fn set_smap(enabled: bool) {
+    smap_enabled = enabled as u8;
+}
+
+/// An instruction which may fault on a userspace address, and where to
+/// continue execution if it does.
+///
+/// both are stored relative to the field itself, so the table needs no
+/// relocations when the kernel is loaded at a random address.
+#[repr(C)]
+struct ExTableEntry {
+    ip: i32,
+    fixup: i32,
+}
+
+impl ExTableEntry {
+    fn resolve(field: &i32) -> u64 {
+        (field as *const i32 as i64 + *field as i64) as u64
+    }
+
+    fn ip(&self) -> u64 {
+        Self::resolve(&self.ip)
+    }
+
+    fn fixup(&self) -> u64 {
+        Self::resolve(&self.fixup)
+    }
+}
+
+fn ex_table() -> &'static [ExTableEntry] {
+    let start = symbols::ex_table_start();
+    let len = (symbols::ex_table_end() - start) as usize / size_of::<ExTableEntry>();
+    unsafe { slice::from_raw_parts(start as *const ExTableEntry, len) }
+}
+
+/// returns the fixup address for a fault at `ip`, if the faulting instruction has one.
+pub(super) fn search_ex_table(ip: u64) -> Option<u64> {
+    ex_table()
+        .iter()
+        .find(|entry| entry.ip() == ip)
+        .map(ExTableEntry::fixup)
+}
+
+/// copies `len` bytes from `src` to `dst`, where either side may be a userspace
+/// address. returns the number of bytes which could not be copied because of a
+/// page fault.
+///
+/// # Safety
+/// the kernel side of the copy must be valid for `len` bytes.
+pub unsafe fn copy(dst: *mut u8, src: *const u8, len: usize) -> usize {
+    copy_user(dst, src, len)
+}
@@ -0,0 +1,31 @@
+.section .data
+.global smap_enabled
+smap_enabled: .byte 0
+
+.code64
+.section .text
+
+# copies rdx bytes from rsi to rdi. returns the number of bytes that could not
+# be copied in rax, which is non-zero when the copy faulted part way through.
+.global copy_user
+copy_user:
+    mov rcx, rdx
+    cmp byte ptr [rip + smap_enabled], 0
+    je 1f
+    stac
+1:
+copy_user_access:
+    rep movsb
+    xor eax, eax
+    jmp copy_user_end
+copy_user_fixup:
+    mov rax, rcx
+copy_user_end:
+    cmp byte ptr [rip + smap_enabled], 0
+    je 1f
+    clac
+1:
+    ret
+
+.section .ex_table, "a"
+.long copy_user_access - ., copy_user_fixup - .
@@ -1,3 +1,5 @@
+#[cfg(feature = "kpti")]
+use super::cpu::ctrl_regs::cr3;
 use super::vadr;
 use crate::mm::pmm;
 use crate::util::adr::{PhysAdr, VirtAdr};
@@ -103,10 +105,17 @@ impl PageMapPtr {
         Self(VirtAdr::new(ptr as vadr))
     }
 
-    const fn from_vadr(adr: VirtAdr) -> Self {
+    pub(super) const fn from_vadr(adr: VirtAdr) -> Self {
         Self(adr)
     }
 
+    /// the shadow page map installed while userspace runs, which directly
+    /// follows the page map of every userspace process.
+    #[cfg(feature = "kpti")]
+    pub(super) fn shadow(self) -> Self {
+        Self(self.0.add(PAGE_SIZE))
+    }
+
     unsafe fn ptr(&self) -> *mut PageMap {
         self.0.ptr() as *mut _
     }
@@ -186,6 +195,12 @@ impl PageMap {
     }
 
     unsafe fn install_ptr(ptr: *const Self) {
+        // every shadow page map shares the user PCID, so switching to another
+        // process has to flush it.
+        #[cfg(feature = "kpti")]
+        if cr3::get() & !cr3::FLAGS_MASK != ptr as u64 & !cr3::FLAGS_MASK {
+            super::kpti::mark_user_stale();
+        }
         unsafe {
             asm!(
                 "mov cr3, rax",
@@ -275,7 +290,14 @@ pub fn kernel_page_map() -> PageMapPtr {
 }
 
 pub unsafe fn new_userland_page_map() -> PageMapPtr {
+    #[cfg(not(feature = "kpti"))]
     let ptr = PageMapPtr::from_vadr(pmm::alloc_pages_zeroed(1).virt());
+    #[cfg(feature = "kpti")]
+    let ptr = {
+        let ptr = PageMapPtr::from_vadr(pmm::alloc_pages_zeroed(2).virt());
+        super::kpti::init_shadow_page_map(ptr.shadow());
+        ptr
+    };
     for i in 256..PAGE_MAP_ENTRIES {
         *ptr.entry(i) = KERNEL_PAGE_MAP_PTR.entry(i).clone();
     }
@@ -291,7 +313,9 @@ pub unsafe fn get_page_entry(map: PageMapPtr, virt: VirtAdr) -> Option<NonNull<P
 }
 
 pub unsafe fn map(map: PageMapPtr, virt: VirtAdr, pages: usize, phys: PhysAdr, flags: VMFlags) {
-    map.map(virt, pages, phys, flags)
+    map.map(virt, pages, phys, flags);
+    #[cfg(feature = "kpti")]
+    super::kpti::sync_shadow_page_map(map, virt, pages, flags);
 }
 
 pub unsafe fn unmap(map: PageMapPtr, _virt: VirtAdr, _pages: usize) {
@@ -35,6 +35,8 @@ pub mod serial {
         use super::imp::serial::uart;
 
         export_assert_fn!(uart::putb: fn(u8));
+        export_assert_fn!(uart::can_read: fn() -> bool);
+        export_assert_fn!(uart::getb: fn() -> Option<u8>);
         export_assert_fn!(uart::putc: fn(char));
         export_assert_fn!(uart::puts: fn(&str));
     }
@@ -90,6 +92,36 @@ pub mod fb {
     export_assert_fn!(fb::putb: fn(pos: Cursor, b: u8));
 }
 
+pub mod io_bitmap {
+    use super::imp::io_bitmap;
+    use core::ops::Range;
+
+    pub use io_bitmap::IoBitmap;
+
+    assert_fn!(IoBitmap::new: fn() -> IoBitmap);
+    assert_fn!(IoBitmap::allow: fn(&mut IoBitmap, Range<u16>));
+    assert_fn!(IoBitmap::allows: fn(&IoBitmap, u16) -> bool);
+
+    export_assert_fn!(io_bitmap::load: unsafe fn(Option<&IoBitmap>));
+}
+
+pub mod irq {
+    use super::imp::irq;
+
+    pub const LINES: u8 = irq::LINES;
+
+    export_assert_fn!(irq::set_masked: fn(u8, bool));
+}
+
+pub mod port {
+    use super::imp::port;
+
+    export_assert_fn!(port::in8: fn(u16) -> u8);
+    export_assert_fn!(port::out8: fn(u16, u8));
+    export_assert_fn!(port::in16: fn(u16) -> u16);
+    export_assert_fn!(port::out16: fn(u16, u16));
+}
+
 pub mod interrupt {
     use super::imp::interrupt;
     use super::imp::vm::PageMapPtr;
@@ -104,6 +136,7 @@ pub mod interrupt {
     export_assert_fn!(interrupt::enable: fn());
     export_assert_fn!(interrupt::disable: fn());
     export_assert_fn!(interrupt::is_enabled: fn() -> bool);
+    export_assert_fn!(interrupt::restart_syscall: fn());
 
     pub fn disable_fn(mut f: impl FnMut()) {
         interrupt::disable();
@@ -129,9 +162,22 @@ pub mod thread {
     pub use thread::ArchThread;
 
     export_assert_fn!(thread::cur_thread: fn() -> ThreadPtr);
+    export_assert_fn!(thread::has_thread: fn() -> bool);
     export_assert_fn!(thread::set_thread: unsafe fn(&mut Thread));
 }
 
+pub mod uaccess {
+    use super::imp::uaccess;
+
+    export_assert_fn!(uaccess::copy: unsafe fn(*mut u8, *const u8, usize) -> usize);
+}
+
+pub mod random {
+    use super::imp::random;
+
+    export_assert_fn!(random::entropy: fn() -> u64);
+}
+
 pub mod panic {
     use super::imp::panic;
 
@@ -0,0 +1,260 @@
+//! ATA disks on the primary IDE bus, driven with polled PIO.
+//!
+//! Interrupts are disabled on the controller, every command busy-waits for
+//! the drive. Slow, but all the boot partition needs.
+
+use crate::arch::port::{in16, in8, out16, out8};
+use crate::fs::block::{self, BlockDevice, BlockDeviceRef};
+use crate::fs::{Error, Result};
+use crate::util::locked::Locked;
+use alloc::sync::Arc;
+
+const SECTOR_SIZE: usize = 512;
+
+const PRIMARY_IO: u16 = 0x1f0;
+const PRIMARY_CTRL: u16 = 0x3f6;
+
+// offsets of the task file registers from the io base.
+const REG_DATA: u16 = 0;
+const REG_ERROR: u16 = 1;
+const REG_SECTOR_COUNT: u16 = 2;
+const REG_LBA_LOW: u16 = 3;
+const REG_LBA_MID: u16 = 4;
+const REG_LBA_HIGH: u16 = 5;
+const REG_DRIVE: u16 = 6;
+const REG_STATUS: u16 = 7;
+const REG_COMMAND: u16 = 7;
+
+const STATUS_ERR: u8 = 1 << 0;
+const STATUS_DRQ: u8 = 1 << 3;
+const STATUS_DF: u8 = 1 << 5;
+const STATUS_BSY: u8 = 1 << 7;
+
+/// disables interrupts from the drive.
+const CTRL_NIEN: u8 = 1 << 1;
+
+/// selects the master drive and LBA addressing.
+const DRIVE_MASTER_LBA: u8 = 0xe0;
+
+const CMD_READ_SECTORS: u8 = 0x20;
+const CMD_READ_SECTORS_EXT: u8 = 0x24;
+const CMD_WRITE_SECTORS: u8 = 0x30;
+const CMD_WRITE_SECTORS_EXT: u8 = 0x34;
+const CMD_FLUSH_CACHE: u8 = 0xe7;
+const CMD_FLUSH_CACHE_EXT: u8 = 0xea;
+const CMD_IDENTIFY: u8 = 0xec;
+
+// words of the IDENTIFY data.
+const ID_LBA28_SECTORS: usize = 60;
+const ID_COMMAND_SETS: usize = 83;
+const ID_LBA48_SECTORS: usize = 100;
+/// `ID_COMMAND_SETS` bit for LBA48 support.
+const ID_LBA48: u16 = 1 << 10;
+
+/// the most sectors one LBA28 command can transfer, a count of 0 means 256.
+const MAX_SECTORS_LBA28: usize = 256;
+const LBA28_LIMIT: u64 = 1 << 28;
+
+/// the master drive of the primary bus.
+pub struct AtaDisk {
+    /// held for the whole of a command, the task file is shared.
+    io: Locked<u16>,
+    sectors: u64,
+    lba48: bool,
+}
+
+/// the disk on the primary bus, if there is one which answers IDENTIFY.
+pub fn primary() -> Option<BlockDeviceRef> {
+    AtaDisk::identify(PRIMARY_IO, PRIMARY_CTRL).map(|disk| Arc::new(disk) as BlockDeviceRef)
+}
+
+impl AtaDisk {
+    fn identify(io: u16, ctrl: u16) -> Option<Self> {
+        out8(ctrl, CTRL_NIEN);
+        out8(io + REG_DRIVE, DRIVE_MASTER_LBA);
+        delay(io);
+        for reg in [REG_SECTOR_COUNT, REG_LBA_LOW, REG_LBA_MID, REG_LBA_HIGH] {
+            out8(io + reg, 0);
+        }
+        out8(io + REG_COMMAND, CMD_IDENTIFY);
+        // a floating bus reads as all ones, a missing drive as zero.
+        let status = in8(io + REG_STATUS);
+        if status == 0 || status == 0xff {
+            return None;
+        }
+        wait_ready(io).ok()?;
+        // ATAPI and SATA devices put their signature here instead.
+        if in8(io + REG_LBA_MID) != 0 || in8(io + REG_LBA_HIGH) != 0 {
+            return None;
+        }
+        wait_drq(io).ok()?;
+        let mut id = [0u16; SECTOR_SIZE / 2];
+        for word in &mut id {
+            *word = in16(io + REG_DATA);
+        }
+
+        let lba48 = id[ID_COMMAND_SETS] & ID_LBA48 != 0;
+        let sectors = if lba48 {
+            (0..4).fold(0, |sectors, i| {
+                sectors | (id[ID_LBA48_SECTORS + i] as u64) << (16 * i)
+            })
+        } else {
+            id[ID_LBA28_SECTORS] as u64 | (id[ID_LBA28_SECTORS + 1] as u64) << 16
+        };
+        if sectors == 0 {
+            return None;
+        }
+        info!("ata: primary master with {sectors} sectors");
+        Some(Self {
+            io: Locked::new(io),
+            sectors,
+            lba48,
+        })
+    }
+
+    /// the most sectors a single command can move.
+    fn max_sectors(&self) -> usize {
+        if self.lba48 {
+            u16::MAX as usize
+        } else {
+            MAX_SECTORS_LBA28
+        }
+    }
+
+    /// loads the task file for `count` sectors at `lba` and issues one of
+    /// the two commands, depending on how far the range reaches.
+    fn command(&self, io: u16, lba: u64, count: usize, cmd: u8, cmd_ext: u8) -> Result<()> {
+        wait_ready(io)?;
+        if lba + count as u64 <= LBA28_LIMIT && count <= MAX_SECTORS_LBA28 {
+            out8(io + REG_DRIVE, DRIVE_MASTER_LBA | (lba >> 24) as u8 & 0xf);
+            out8(io + REG_SECTOR_COUNT, count as u8);
+            out8(io + REG_LBA_LOW, lba as u8);
+            out8(io + REG_LBA_MID, (lba >> 8) as u8);
+            out8(io + REG_LBA_HIGH, (lba >> 16) as u8);
+            out8(io + REG_COMMAND, cmd);
+        } else {
+            if !self.lba48 {
+                return Err(Error::Io(format!("ata: sector {lba} needs lba48")));
+            }
+            // the high bytes go first, each register holds two.
+            out8(io + REG_DRIVE, DRIVE_MASTER_LBA);
+            out8(io + REG_SECTOR_COUNT, (count >> 8) as u8);
+            out8(io + REG_LBA_LOW, (lba >> 24) as u8);
+            out8(io + REG_LBA_MID, (lba >> 32) as u8);
+            out8(io + REG_LBA_HIGH, (lba >> 40) as u8);
+            out8(io + REG_SECTOR_COUNT, count as u8);
+            out8(io + REG_LBA_LOW, lba as u8);
+            out8(io + REG_LBA_MID, (lba >> 8) as u8);
+            out8(io + REG_LBA_HIGH, (lba >> 16) as u8);
+            out8(io + REG_COMMAND, cmd_ext);
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for AtaDisk {
+    fn sector_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<()> {
+        block::check_range(self, lba, buf.len())?;
+        let guard = self.io.lock();
+        let io = *guard;
+        let mut lba = lba;
+        for chunk in buf.chunks_mut(self.max_sectors() * SECTOR_SIZE) {
+            let count = chunk.len() / SECTOR_SIZE;
+            self.command(io, lba, count, CMD_READ_SECTORS, CMD_READ_SECTORS_EXT)?;
+            for sector in chunk.chunks_exact_mut(SECTOR_SIZE) {
+                delay(io);
+                wait_drq(io)?;
+                for word in sector.chunks_exact_mut(2) {
+                    word.copy_from_slice(&in16(io + REG_DATA).to_le_bytes());
+                }
+            }
+            lba += count as u64;
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<()> {
+        block::check_range(self, lba, buf.len())?;
+        let guard = self.io.lock();
+        let io = *guard;
+        let mut lba = lba;
+        for chunk in buf.chunks(self.max_sectors() * SECTOR_SIZE) {
+            let count = chunk.len() / SECTOR_SIZE;
+            self.command(io, lba, count, CMD_WRITE_SECTORS, CMD_WRITE_SECTORS_EXT)?;
+            for sector in chunk.chunks_exact(SECTOR_SIZE) {
+                delay(io);
+                wait_drq(io)?;
+                for word in sector.chunks_exact(2) {
+                    out16(io + REG_DATA, u16::from_le_bytes([word[0], word[1]]));
+                }
+            }
+            lba += count as u64;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        let guard = self.io.lock();
+        let io = *guard;
+        wait_ready(io)?;
+        out8(io + REG_DRIVE, DRIVE_MASTER_LBA);
+        let cmd = if self.lba48 {
+            CMD_FLUSH_CACHE_EXT
+        } else {
+            CMD_FLUSH_CACHE
+        };
+        out8(io + REG_COMMAND, cmd);
+        delay(io);
+        wait_ready(io)
+    }
+}
+
+/// gives the drive the 400ns it needs to update its status after a
+/// command, each read of the status takes about 100ns.
+fn delay(io: u16) {
+    for _ in 0..4 {
+        in8(io + REG_STATUS);
+    }
+}
+
+fn check_error(io: u16, status: u8) -> Result<()> {
+    if status & (STATUS_ERR | STATUS_DF) != 0 {
+        return Err(Error::Io(format!(
+            "ata: status {status:#x}, error {:#x}",
+            in8(io + REG_ERROR)
+        )));
+    }
+    Ok(())
+}
+
+/// waits until the drive is no longer busy.
+fn wait_ready(io: u16) -> Result<()> {
+    loop {
+        let status = in8(io + REG_STATUS);
+        if status & STATUS_BSY == 0 {
+            return check_error(io, status);
+        }
+    }
+}
+
+/// waits until the drive wants to transfer data.
+fn wait_drq(io: u16) -> Result<()> {
+    loop {
+        let status = in8(io + REG_STATUS);
+        if status & STATUS_BSY != 0 {
+            continue;
+        }
+        check_error(io, status)?;
+        if status & STATUS_DRQ != 0 {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,34 @@
+//! console device, which processes get as stdin, stdout and stderr.
+
+use crate::arch::serial::uart;
+use crate::fs::impls::devfs::{self, CharDevice};
+use crate::fs::{Access, FileRef, OpenFile, Result};
+use alloc::sync::Arc;
+
+const NAME: &str = "console";
+
+struct Console;
+
+impl CharDevice for Console {
+    /// there is no input source yet, so reads always hit the end of the file.
+    fn read(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize> {
+        Ok(0)
+    }
+
+    fn write(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        for &b in buf {
+            uart::putb(b);
+        }
+        Ok(buf.len())
+    }
+}
+
+pub fn init() -> Result<()> {
+    devfs::register(NAME, 0o620, Arc::new(Console))
+}
+
+/// opens the console for reading and writing.
+pub fn open() -> FileRef {
+    let console = devfs::get(NAME).expect("console isn't registered");
+    OpenFile::new(console, Access::READ | Access::WRITE)
+}
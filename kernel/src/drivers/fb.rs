@@ -0,0 +1,53 @@
+//! the text mode framebuffer as a device. the file offset is the index of a
+//! character cell, counted row by row.
+
+use crate::arch::fb::{self, Cursor};
+use crate::fs::impls::devfs::{self, CharDevice};
+use crate::fs::{Error, Result};
+use ::syscall::ioctl::{FbSize, FB_GET_SIZE};
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use core::mem::size_of;
+
+const CELLS: usize = fb::WIDTH * fb::HEIGHT;
+
+struct Framebuffer;
+
+impl CharDevice for Framebuffer {
+    fn write(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        if offset >= CELLS && !buf.is_empty() {
+            return Err(Error::NoSpace("framebuffer".to_string()));
+        }
+        let len = buf.len().min(CELLS.saturating_sub(offset));
+        for (i, &b) in buf[..len].iter().enumerate() {
+            let cell = offset + i;
+            let pos = Cursor {
+                x: cell % fb::WIDTH,
+                y: cell / fb::WIDTH,
+            };
+            fb::putb(pos, b);
+        }
+        Ok(len)
+    }
+
+    fn ioctl(&self, request: u64, buf: &mut [u8]) -> Result<u64> {
+        match request {
+            FB_GET_SIZE => {
+                let out = buf
+                    .get_mut(..size_of::<FbSize>())
+                    .ok_or_else(|| Error::InvalidOperation("ioctl buffer".to_string()))?;
+                let size = FbSize {
+                    width: fb::WIDTH as u32,
+                    height: fb::HEIGHT as u32,
+                };
+                unsafe { out.as_mut_ptr().cast::<FbSize>().write_unaligned(size) };
+                Ok(0)
+            }
+            _ => Err(Error::InvalidOperation(format!("ioctl {request:#x}"))),
+        }
+    }
+}
+
+pub fn init() -> Result<()> {
+    devfs::register("fb0", 0o660, Arc::new(Framebuffer))
+}
@@ -1,11 +1,101 @@
-//! keyboard driver
+//! keyboard device, reading scancodes from the userspace PS/2 driver.
+//!
+//! Only the driver touches the controller, the device calls its endpoint for
+//! the scancodes instead. A call is kept in flight between reads, so a read
+//! takes the reply which came in since the last one and never waits for the
+//! driver.
 
-use crate::arch::interrupt;
+use crate::fs::impls::devfs::{self, CharDevice};
+use crate::fs::{Error, Poll, Result};
+use crate::ipc::{CallRef, Endpoint, Message, Payload};
+use crate::util::locked::Locked;
+use ::syscall::ipc::MSG_WORDS;
+use ::syscall::Errno;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
 
-pub extern "C" fn main() -> ! {
-    trace!("running keyboard driver");
-    loop {
-        trace!("keyboard");
-        interrupt::halt();
+/// the endpoint of the PS/2 driver, which replies to each call with a
+/// scancode or fails it with `EAGAIN` if none is queued.
+const ENDPOINT: &str = "ps2";
+
+struct State {
+    /// the call to the driver which wasn't collected yet.
+    call: Option<CallRef>,
+    /// scancodes collected but not read yet.
+    scancodes: VecDeque<u8>,
+}
+
+/// raw set 1 scancodes, as the PS/2 controller hands them out.
+struct Keyboard {
+    state: Locked<State>,
+}
+
+impl Keyboard {
+    /// collects the reply to the call in flight, then calls the driver again
+    /// once it was answered.
+    fn fetch(state: &mut State) -> Result<()> {
+        if let Some(call) = &state.call {
+            match call.finish() {
+                None => return Ok(()),
+                Some(Ok(Some(reply))) => state.scancodes.push_back(reply.words[0] as u8),
+                Some(Ok(None) | Err(Errno::EAGAIN)) => {}
+                Some(Err(errno)) => {
+                    state.call = None;
+                    return Err(driver_error(errno));
+                }
+            }
+        }
+        let message = Message {
+            words: [0; MSG_WORDS],
+            payload: Payload::None,
+            handle: None,
+        };
+        let call = Endpoint::open(ENDPOINT)
+            .and_then(|endpoint| endpoint.send(message, Some(0)))
+            .map_err(driver_error);
+        state.call = call.as_ref().ok().cloned();
+        call.map(|_| ())
+    }
+}
+
+fn driver_error(errno: Errno) -> Error {
+    Error::Io(format!("{ENDPOINT} driver: {errno}"))
+}
+
+impl CharDevice for Keyboard {
+    /// returns the scancodes collected from the driver, which may be none.
+    fn read(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let mut state = self.state.lock();
+        Self::fetch(&mut state)?;
+        let mut read = 0;
+        while read < buf.len() {
+            let Some(scancode) = state.scancodes.pop_front() else {
+                break;
+            };
+            buf[read] = scancode;
+            read += 1;
+        }
+        Ok(read)
     }
+
+    fn poll(&self) -> Poll {
+        let mut state = self.state.lock();
+        // a driver which isn't running has nothing to read either.
+        let _ = Self::fetch(&mut state);
+        if state.scancodes.is_empty() {
+            Poll::NONE
+        } else {
+            Poll::READ
+        }
+    }
+}
+
+pub fn init() -> Result<()> {
+    let keyboard = Keyboard {
+        state: Locked::new(State {
+            call: None,
+            scancodes: VecDeque::new(),
+        }),
+    };
+    devfs::register("kbd", 0o440, Arc::new(keyboard))
 }
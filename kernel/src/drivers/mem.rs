@@ -0,0 +1,54 @@
+//! devices which only exist in memory: `null`, `zero` and `random`.
+
+use crate::fs::impls::devfs::{self, CharDevice};
+use crate::fs::Result;
+use crate::util::random;
+use alloc::sync::Arc;
+
+/// discards writes and is always at the end of the file.
+struct Null;
+
+impl CharDevice for Null {
+    fn read(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize> {
+        Ok(0)
+    }
+
+    fn write(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        Ok(buf.len())
+    }
+}
+
+/// discards writes and reads as an endless run of zeros.
+struct Zero;
+
+impl CharDevice for Zero {
+    fn read(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        buf.fill(0);
+        Ok(buf.len())
+    }
+
+    fn write(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        Ok(buf.len())
+    }
+}
+
+/// reads as random bytes from a generator of its own, not the one the kernel
+/// randomizes its layout with. writes are discarded.
+struct Random;
+
+impl CharDevice for Random {
+    fn read(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        random::fill_bytes(buf);
+        Ok(buf.len())
+    }
+
+    fn write(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        Ok(buf.len())
+    }
+}
+
+pub fn init() -> Result<()> {
+    devfs::register("null", 0o666, Arc::new(Null))?;
+    devfs::register("zero", 0o666, Arc::new(Zero))?;
+    devfs::register("random", 0o444, Arc::new(Random))
+}
@@ -1,2 +1,18 @@
+pub mod ata;
+pub mod console;
 pub mod crsr;
+pub mod fb;
 pub mod kbd;
+pub mod mem;
+pub mod serial;
+
+use crate::fs::Result;
+
+/// publishes the devices of every driver in the devfs.
+pub fn init() -> Result<()> {
+    console::init()?;
+    mem::init()?;
+    serial::init()?;
+    kbd::init()?;
+    fb::init()
+}
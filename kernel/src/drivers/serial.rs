@@ -0,0 +1,43 @@
+//! the default serial port as a device, without the line discipline of the
+//! console.
+
+use crate::arch::serial::uart;
+use crate::fs::impls::devfs::{self, CharDevice};
+use crate::fs::{Poll, Result};
+use alloc::sync::Arc;
+
+struct Serial;
+
+impl CharDevice for Serial {
+    /// returns the bytes received so far, which may be none.
+    fn read(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let mut read = 0;
+        while read < buf.len() {
+            let Some(b) = uart::getb() else {
+                break;
+            };
+            buf[read] = b;
+            read += 1;
+        }
+        Ok(read)
+    }
+
+    fn write(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        for &b in buf {
+            uart::putb(b);
+        }
+        Ok(buf.len())
+    }
+
+    fn poll(&self) -> Poll {
+        if uart::can_read() {
+            Poll::READ | Poll::WRITE
+        } else {
+            Poll::WRITE
+        }
+    }
+}
+
+pub fn init() -> Result<()> {
+    devfs::register("ttyS0", 0o660, Arc::new(Serial))
+}
@@ -0,0 +1,135 @@
+//! devices which are read and written in whole sectors, and the partitions
+//! on them.
+
+#[cfg(test)]
+pub mod testing;
+
+use super::{Error, Result};
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// A disk or anything else filesystems can be stored on.
+///
+/// Buffers always hold a whole number of sectors. Devices have to handle
+/// their own locking, as filesystems share them between their nodes.
+pub trait BlockDevice: Send + Sync {
+    fn sector_size(&self) -> usize;
+
+    fn sector_count(&self) -> u64;
+
+    /// reads the sectors starting at `lba` into `buf`.
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<()>;
+
+    /// writes `buf` to the sectors starting at `lba`.
+    fn write_blocks(&self, _lba: u64, _buf: &[u8]) -> Result<()> {
+        Err(Error::ReadOnly("block device".to_string()))
+    }
+
+    /// waits until everything written so far is stored permanently.
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub type BlockDeviceRef = Arc<dyn BlockDevice>;
+
+/// checks that `len` bytes starting at `lba` are whole sectors of `dev`.
+pub fn check_range(dev: &dyn BlockDevice, lba: u64, len: usize) -> Result<()> {
+    let sectors = len / dev.sector_size();
+    if len % dev.sector_size() != 0 {
+        return Err(Error::Io(format!("{len} bytes aren't whole sectors")));
+    }
+    match lba.checked_add(sectors as u64) {
+        Some(end) if end <= dev.sector_count() => Ok(()),
+        _ => Err(Error::Io(format!("sector {lba} out of range"))),
+    }
+}
+
+/// A range of sectors of another device.
+pub struct Partition {
+    dev: BlockDeviceRef,
+    start: u64,
+    count: u64,
+}
+
+impl Partition {
+    pub fn new(dev: BlockDeviceRef, start: u64, count: u64) -> Result<Self> {
+        if start
+            .checked_add(count)
+            .map_or(true, |end| end > dev.sector_count())
+        {
+            return Err(Error::Io(
+                "partition past the end of the device".to_string(),
+            ));
+        }
+        Ok(Self { dev, start, count })
+    }
+}
+
+impl BlockDevice for Partition {
+    fn sector_size(&self) -> usize {
+        self.dev.sector_size()
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.count
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<()> {
+        check_range(self, lba, buf.len())?;
+        self.dev.read_blocks(self.start + lba, buf)
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<()> {
+        check_range(self, lba, buf.len())?;
+        self.dev.write_blocks(self.start + lba, buf)
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.dev.flush()
+    }
+}
+
+/// An entry of an MBR partition table.
+#[derive(Debug, Clone, Copy)]
+pub struct MbrEntry {
+    pub bootable: bool,
+    /// the system id, `0x83` for Linux filesystems.
+    pub ty: u8,
+    pub start: u64,
+    pub count: u64,
+}
+
+pub const MBR_TYPE_LINUX: u8 = 0x83;
+/// an EFI system partition.
+pub const MBR_TYPE_EFI: u8 = 0xef;
+
+const MBR_SIZE: usize = 512;
+const MBR_TABLE: usize = 446;
+const MBR_ENTRY_SIZE: usize = 16;
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+
+/// the used primary partitions in the MBR of `dev`. extended partitions are
+/// returned as they are, not followed.
+pub fn mbr_partitions(dev: &dyn BlockDevice) -> Result<Vec<MbrEntry>> {
+    let mut buf = vec![0; dev.sector_size()];
+    dev.read_blocks(0, &mut buf)?;
+    if buf.len() < MBR_SIZE || buf[MBR_SIZE - 2..MBR_SIZE] != MBR_SIGNATURE {
+        return Err(Error::InvalidFile("no MBR signature".to_string()));
+    }
+    let entries = buf[MBR_TABLE..MBR_SIZE - 2]
+        .chunks_exact(MBR_ENTRY_SIZE)
+        .map(|entry| {
+            let u32_at = |i: usize| u32::from_le_bytes(entry[i..i + 4].try_into().unwrap());
+            MbrEntry {
+                bootable: entry[0] & 0x80 != 0,
+                ty: entry[4],
+                start: u32_at(8) as u64,
+                count: u32_at(12) as u64,
+            }
+        })
+        .filter(|entry| entry.ty != 0 && entry.count != 0)
+        .collect();
+    Ok(entries)
+}
@@ -0,0 +1,137 @@
+//! images on the host for the filesystem tests, which make them and check
+//! what we wrote with the host's own tools.
+
+extern crate std;
+
+use super::{check_range, BlockDevice, BlockDeviceRef};
+use crate::fs::{Error, Result};
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use std::os::unix::fs::FileExt;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{fs as host, println};
+
+pub const SECTOR_SIZE: usize = 512;
+
+/// an image on the host.
+pub struct FileDevice {
+    file: host::File,
+    sectors: u64,
+    writable: bool,
+}
+
+impl BlockDevice for FileDevice {
+    fn sector_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<()> {
+        check_range(self, lba, buf.len())?;
+        self.file
+            .read_exact_at(buf, lba * SECTOR_SIZE as u64)
+            .map_err(|e| Error::Io(e.to_string()))
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<()> {
+        if !self.writable {
+            return Err(Error::ReadOnly("read-only image".to_string()));
+        }
+        check_range(self, lba, buf.len())?;
+        self.file
+            .write_all_at(buf, lba * SECTOR_SIZE as u64)
+            .map_err(|e| Error::Io(e.to_string()))
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.file.sync_data().map_err(|e| Error::Io(e.to_string()))
+    }
+}
+
+/// a scratch directory holding an image, removed again when the test is
+/// done.
+pub struct Scratch(PathBuf);
+
+impl Scratch {
+    /// `None` if any of `tools` isn't installed, so the test can be skipped.
+    pub fn new(name: &str, tools: &[&str]) -> Option<Self> {
+        if !installed(tools) {
+            return None;
+        }
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "{name}-test-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        host::create_dir_all(&path).unwrap();
+        Some(Self(path))
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.0
+    }
+
+    pub fn image(&self) -> PathBuf {
+        self.0.join("image")
+    }
+
+    /// the image as a device, which may be written to if `writable`.
+    pub fn device(&self, writable: bool) -> BlockDeviceRef {
+        let file = host::OpenOptions::new()
+            .read(true)
+            .write(writable)
+            .open(self.image())
+            .unwrap();
+        let sectors = file.metadata().unwrap().len() / SECTOR_SIZE as u64;
+        Arc::new(FileDevice {
+            file,
+            sectors,
+            writable,
+        })
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = host::remove_dir_all(&self.0);
+    }
+}
+
+/// whether all of `tools` can be found in `PATH`, telling which one is
+/// missing otherwise.
+fn installed(tools: &[&str]) -> bool {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    for tool in tools {
+        if !std::env::split_paths(&path).any(|dir| dir.join(tool).is_file()) {
+            println!("skipping, `{tool}` is not installed");
+            return false;
+        }
+    }
+    true
+}
+
+pub fn run(cmd: &mut Command) {
+    run_allowing(cmd, 0);
+}
+
+/// runs `cmd`, which may exit with any status up to `max_status`.
+pub fn run_allowing(cmd: &mut Command, max_status: i32) {
+    let output = cmd.output().unwrap();
+    if output.status.code().map_or(true, |code| code > max_status) {
+        println!("{}", String::from_utf8_lossy(&output.stdout));
+        println!("{}", String::from_utf8_lossy(&output.stderr));
+        panic!("{cmd:?} failed with {}", output.status);
+    }
+}
+
+/// some bytes which differ from sector to sector.
+pub fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
@@ -0,0 +1,184 @@
+use super::vnode::{DirEntry, FileType, Poll, Stat, VNode};
+use super::{Error, Fs, MountRef, Result};
+use crate::mm::page_cache::{self, CachedInode};
+use crate::process::thread::WaitQueue;
+use crate::util::locked::Locked;
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+bit_flags!(
+    pub struct Access(u8);
+    READ = 0;
+    WRITE = 1;
+);
+
+#[derive(Debug, Clone, Copy)]
+pub enum SeekFrom {
+    Start(usize),
+    Current(isize),
+    End(isize),
+}
+
+/// A node opened through the VFS, with its own offset.
+///
+/// Open files are reference counted, so every handle cloned from the same
+/// `open` shares one offset. Regular files of filesystems which want it are
+/// read and written through the page cache.
+pub struct OpenFile {
+    vnode: VNode,
+    offset: Locked<usize>,
+    access: Access,
+    /// keeps the mount busy while the file is open.
+    mount: Option<MountRef>,
+    cached: bool,
+}
+
+pub type FileRef = Arc<OpenFile>;
+
+impl OpenFile {
+    /// opens a node which doesn't belong to a mounted filesystem.
+    pub fn new(vnode: VNode, access: Access) -> FileRef {
+        Arc::new(Self {
+            vnode,
+            offset: Locked::new(0),
+            access,
+            mount: None,
+            cached: false,
+        })
+    }
+
+    pub(super) fn new_mounted(vnode: VNode, access: Access, mount: MountRef) -> FileRef {
+        let cached = mount.fs.page_cached()
+            && vnode
+                .stat()
+                .map_or(false, |stat| stat.ty == FileType::Regular);
+        Arc::new(Self {
+            vnode,
+            offset: Locked::new(0),
+            access,
+            mount: Some(mount),
+            cached,
+        })
+    }
+
+    /// the filesystem the file is cached for, if it is.
+    fn cache_fs(&self) -> Option<&Arc<dyn Fs>> {
+        self.mount
+            .as_ref()
+            .filter(|_| self.cached)
+            .map(|mount| &mount.fs)
+    }
+
+    pub fn vnode(&self) -> &VNode {
+        &self.vnode
+    }
+
+    pub fn offset(&self) -> usize {
+        *self.offset.lock()
+    }
+
+    pub fn access(&self) -> Access {
+        self.access
+    }
+
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        if !self.access.has(Access::READ) {
+            return Err(Error::InvalidAccess("not open for reading".to_string()));
+        }
+        let mut offset = self.offset.lock();
+        let read = match self.cache_fs() {
+            Some(fs) => page_cache::read(fs, &self.vnode, *offset, buf)?,
+            None => self.vnode.read_at(*offset, buf)?,
+        };
+        *offset += read;
+        Ok(read)
+    }
+
+    /// reads from `offset` without moving the file's own offset.
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        if !self.access.has(Access::READ) {
+            return Err(Error::InvalidAccess("not open for reading".to_string()));
+        }
+        match self.cache_fs() {
+            Some(fs) => page_cache::read(fs, &self.vnode, offset, buf),
+            None => self.vnode.read_at(offset, buf),
+        }
+    }
+
+    pub fn write(&self, buf: &[u8]) -> Result<usize> {
+        if !self.access.has(Access::WRITE) {
+            return Err(Error::InvalidAccess("not open for writing".to_string()));
+        }
+        let mut offset = self.offset.lock();
+        let written = match self.cache_fs() {
+            Some(fs) => page_cache::write(fs, &self.vnode, *offset, buf)?,
+            None => self.vnode.write_at(*offset, buf)?,
+        };
+        *offset += written;
+        Ok(written)
+    }
+
+    /// moves the offset and returns the new one. seeking past the end is
+    /// allowed, seeking before the start is not.
+    pub fn seek(&self, pos: SeekFrom) -> Result<usize> {
+        let mut offset = self.offset.lock();
+        let (base, delta) = match pos {
+            SeekFrom::Start(pos) => (0, pos as isize),
+            SeekFrom::Current(delta) => (*offset, delta),
+            SeekFrom::End(delta) => (self.vnode.stat()?.size, delta),
+        };
+        let new = base
+            .checked_add_signed(delta)
+            .ok_or_else(|| Error::InvalidOperation("seek out of range".to_string()))?;
+        *offset = new;
+        Ok(new)
+    }
+
+    pub fn truncate(&self, size: usize) -> Result<()> {
+        if !self.access.has(Access::WRITE) {
+            return Err(Error::InvalidAccess("not open for writing".to_string()));
+        }
+        self.vnode.truncate(size)?;
+        page_cache::truncate(&self.vnode, size);
+        Ok(())
+    }
+
+    /// the file as the page cache knows it, for mapping it.
+    pub fn cached_inode(&self) -> Result<CachedInode> {
+        let fs = self
+            .cache_fs()
+            .ok_or_else(|| Error::InvalidOperation("map uncached file".to_string()))?;
+        Ok(CachedInode::new(fs.clone(), self.vnode.clone()))
+    }
+
+    pub fn stat(&self) -> Result<Stat> {
+        self.vnode.stat()
+    }
+
+    pub fn readdir(&self) -> Result<Vec<DirEntry>> {
+        self.vnode.readdir()
+    }
+
+    pub fn ioctl(&self, request: u64, buf: &mut [u8]) -> Result<u64> {
+        self.vnode.ioctl(request, buf)
+    }
+
+    /// readiness of the node, limited to the access the file was opened with.
+    pub fn poll(&self) -> Poll {
+        let ready = self.vnode.poll();
+        let mut poll = Poll::NONE;
+        if self.access.has(Access::READ) && ready.has(Poll::READ) {
+            poll |= Poll::READ;
+        }
+        if self.access.has(Access::WRITE) && ready.has(Poll::WRITE) {
+            poll |= Poll::WRITE;
+        }
+        poll
+    }
+
+    /// where to wait until the node is ready for `ready`.
+    pub fn wait_queue(&self, ready: Poll) -> Option<&WaitQueue> {
+        self.vnode.wait_queue(ready)
+    }
+}
@@ -0,0 +1,200 @@
+//! `newc` cpio archives, the format of Linux' initramfs.
+//!
+//! Every member is a 110 byte header of ASCII hex fields, followed by the
+//! NUL terminated name and the data, each padded to 4 bytes. The archive
+//! ends with a member named `TRAILER!!!`.
+
+use super::tree::{Tree, TreeEntry};
+use crate::fs::{Error, FileType, Result};
+use alloc::string::ToString;
+use core::str;
+
+const MAGIC: &[u8] = b"070701";
+/// the same format, with a checksum of the data in the header.
+const MAGIC_CRC: &[u8] = b"070702";
+const HEADER_LEN: usize = 110;
+const TRAILER: &str = "TRAILER!!!";
+
+// index of the 8 digit fields after the magic.
+const F_MODE: usize = 1;
+const F_FILESIZE: usize = 6;
+const F_NAMESIZE: usize = 11;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFREG: u32 = 0o100000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFLNK: u32 = 0o120000;
+
+pub fn is_cpio(image: &[u8]) -> bool {
+    image.starts_with(MAGIC) || image.starts_with(MAGIC_CRC)
+}
+
+fn error(offset: usize, msg: &str) -> Error {
+    Error::InvalidFile(format!("cpio at {offset:#x}: {msg}"))
+}
+
+fn field(header: &[u8], index: usize) -> Option<u32> {
+    let start = MAGIC.len() + index * 8;
+    let digits = str::from_utf8(&header[start..start + 8]).ok()?;
+    u32::from_str_radix(digits, 16).ok()
+}
+
+/// reads every member up to the trailer. devices, fifos and sockets are
+/// skipped.
+pub fn load(image: &'static [u8]) -> Result<Tree> {
+    let mut tree = Tree::new();
+    let mut offset = 0;
+    loop {
+        let header = image
+            .get(offset..offset + HEADER_LEN)
+            .ok_or_else(|| error(offset, "truncated header"))?;
+        if !is_cpio(header) {
+            return Err(error(offset, "invalid magic"));
+        }
+        let (Some(mode), Some(file_size), Some(name_size)) = (
+            field(header, F_MODE),
+            field(header, F_FILESIZE),
+            field(header, F_NAMESIZE),
+        ) else {
+            return Err(error(offset, "invalid header field"));
+        };
+
+        let name_start = offset + HEADER_LEN;
+        let name = (name_size as usize)
+            .checked_sub(1)
+            .and_then(|len| image.get(name_start..name_start + len))
+            .and_then(|name| str::from_utf8(name).ok())
+            .ok_or_else(|| error(offset, "invalid name"))?;
+        let data_start = (name_start + name_size as usize).next_multiple_of(4);
+        let data = image
+            .get(data_start..data_start + file_size as usize)
+            .ok_or_else(|| error(offset, "truncated data"))?;
+
+        if name == TRAILER {
+            return Ok(tree);
+        }
+        let ty = match mode & S_IFMT {
+            S_IFREG => Some(FileType::Regular),
+            S_IFDIR => Some(FileType::Directory),
+            S_IFLNK => Some(FileType::Symlink),
+            _ => None,
+        };
+        if let Some(ty) = ty {
+            tree.insert(TreeEntry {
+                path: name,
+                ty,
+                mode: (mode & 0o7777) as u16,
+                data,
+            })
+            .map_err(|e| error(offset, &e.to_string()))?;
+        }
+        offset = (data_start + data.len()).next_multiple_of(4);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::sync::Arc;
+    use alloc::vec::Vec;
+
+    fn pad(bytes: &mut Vec<u8>) {
+        bytes.resize(bytes.len().next_multiple_of(4), 0);
+    }
+
+    /// a member with the fields we read, the others are zero.
+    fn member(name: &str, mode: u32, data: &[u8]) -> Vec<u8> {
+        let mut fields = [0; 13];
+        fields[F_MODE] = mode;
+        fields[F_FILESIZE] = data.len() as u32;
+        fields[F_NAMESIZE] = name.len() as u32 + 1;
+        let mut member = MAGIC.to_vec();
+        for field in fields {
+            member.extend(format!("{field:08x}").bytes());
+        }
+        member.extend(name.bytes());
+        member.push(0);
+        pad(&mut member);
+        member.extend(data);
+        pad(&mut member);
+        member
+    }
+
+    fn archive(members: &[Vec<u8>]) -> &'static [u8] {
+        let mut image = members.concat();
+        image.extend(member(TRAILER, 0, &[]));
+        image.leak()
+    }
+
+    fn is_invalid(result: Result<Tree>, msg: &str) -> bool {
+        matches!(result, Err(Error::InvalidFile(e)) if e.ends_with(msg))
+    }
+
+    #[test]
+    fn reads_members_up_to_the_trailer() {
+        let image = archive(&[
+            member("a", S_IFDIR | 0o750, &[]),
+            member("a/file", S_IFREG | 0o644, b"hello"),
+            member("link", S_IFLNK | 0o777, b"a/file"),
+            // a fifo, which is skipped.
+            member("fifo", 0o010644, &[]),
+        ]);
+        let root = Arc::new(load(image).unwrap()).root();
+        let names: Vec<_> = root
+            .readdir()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        assert_eq!(names, ["a", "link"]);
+
+        let dir = root.lookup("a").unwrap();
+        assert_eq!(dir.stat().unwrap().mode, 0o750);
+        let file = dir.lookup("file").unwrap();
+        let mut buf = [0; 8];
+        assert_eq!(file.read_at(0, &mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], b"hello");
+        let link = root.lookup("link").unwrap().stat().unwrap();
+        assert_eq!((link.ty, link.size), (FileType::Symlink, 6));
+    }
+
+    #[test]
+    fn truncated_headers_fail() {
+        let image = archive(&[member("file", S_IFREG | 0o644, b"x")]);
+        // cut off in the header of the trailer.
+        let image = &image[..image.len() - 50];
+        assert!(is_invalid(load(image), "truncated header"));
+        assert!(is_invalid(
+            load(&image[..HEADER_LEN - 1]),
+            "truncated header"
+        ));
+    }
+
+    #[test]
+    fn bad_magic_fails() {
+        let mut image = member("file", S_IFREG | 0o644, b"x");
+        image[..MAGIC.len()].copy_from_slice(b"070707");
+        assert!(is_invalid(load(image.leak()), "invalid magic"));
+
+        let mut image = archive(&[member("file", S_IFREG | 0o644, b"x")]).to_vec();
+        // the magic of the trailer, after the first member.
+        let trailer = image.len() - member(TRAILER, 0, &[]).len();
+        image[trailer] = b'1';
+        assert!(is_invalid(load(image.leak()), "invalid magic"));
+    }
+
+    #[test]
+    fn sizes_past_the_end_fail() {
+        let mut image = member("file", S_IFREG | 0o644, b"x");
+        let size = MAGIC.len() + F_FILESIZE * 8;
+        image[size..size + 8].copy_from_slice(b"ffffffff");
+        assert!(is_invalid(load(image.leak()), "truncated data"));
+
+        let name_size = MAGIC.len() + F_NAMESIZE * 8;
+        for digits in [b"ffffffff", b"00000000"] {
+            let mut image = member("file", S_IFREG | 0o644, b"x");
+            image[name_size..name_size + 8].copy_from_slice(digits);
+            assert!(is_invalid(load(image.leak()), "invalid name"));
+        }
+    }
+}
@@ -0,0 +1,158 @@
+//! character devices published by drivers, usually mounted at `/dev`.
+//!
+//! Devices live in one global registry, so drivers can register them before
+//! or after the filesystem is mounted and every mount shows the same set.
+
+use crate::fs::{DirEntry, Error, FileType, Fs, Inode, Poll, Result, Stat, VNode};
+use crate::util::locked::Locked;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+const ROOT_INO: u64 = 0;
+
+/// A driver's side of a device node.
+///
+/// Offsets are passed through from the open file, devices which aren't
+/// seekable can ignore them. Like `Inode`, everything but `poll` fails by
+/// default.
+pub trait CharDevice: Send + Sync {
+    fn read(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize> {
+        Err(Error::InvalidOperation("read".to_string()))
+    }
+
+    fn write(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(Error::InvalidOperation("write".to_string()))
+    }
+
+    fn ioctl(&self, request: u64, _buf: &mut [u8]) -> Result<u64> {
+        Err(Error::InvalidOperation(format!("ioctl {request:#x}")))
+    }
+
+    fn poll(&self) -> Poll {
+        Poll::READ | Poll::WRITE
+    }
+}
+
+struct Registry {
+    next_ino: u64,
+    devices: BTreeMap<String, Arc<DevNode>>,
+}
+
+static REGISTRY: Locked<Registry> = Locked::new(Registry {
+    next_ino: ROOT_INO + 1,
+    devices: BTreeMap::new(),
+});
+
+/// publishes `device` as `name`, with the permission bits `mode`.
+pub fn register(name: &str, mode: u16, device: Arc<dyn CharDevice>) -> Result<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        return Err(Error::InvalidPath(name.to_string()));
+    }
+    let mut registry = REGISTRY.lock();
+    if registry.devices.contains_key(name) {
+        return Err(Error::AlreadyExists(name.to_string()));
+    }
+    let ino = registry.next_ino;
+    registry.next_ino += 1;
+    let node = Arc::new(DevNode {
+        ino,
+        mode: mode & 0o7777,
+        device,
+    });
+    registry.devices.insert(name.to_string(), node);
+    Ok(())
+}
+
+/// removes the device `name`. files already open on it keep working.
+pub fn unregister(name: &str) -> Result<()> {
+    REGISTRY
+        .lock()
+        .devices
+        .remove(name)
+        .map(|_| ())
+        .ok_or_else(|| Error::NoSuchPath(name.to_string()))
+}
+
+/// opens the device `name` without going through a mount.
+pub fn get(name: &str) -> Result<VNode> {
+    REGISTRY
+        .lock()
+        .devices
+        .get(name)
+        .map(|node| node.clone() as VNode)
+        .ok_or_else(|| Error::NoSuchPath(name.to_string()))
+}
+
+pub struct DevFs;
+
+impl Fs for DevFs {
+    fn root(&self) -> Result<VNode> {
+        Ok(Arc::new(DevDir))
+    }
+}
+
+/// the only directory, holding every registered device.
+struct DevDir;
+
+impl Inode for DevDir {
+    fn stat(&self) -> Result<Stat> {
+        Ok(Stat {
+            ino: ROOT_INO,
+            ty: FileType::Directory,
+            mode: 0o755,
+            size: REGISTRY.lock().devices.len(),
+        })
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        Ok(REGISTRY
+            .lock()
+            .devices
+            .iter()
+            .map(|(name, node)| DirEntry {
+                name: name.clone(),
+                ino: node.ino,
+                ty: FileType::CharDevice,
+            })
+            .collect())
+    }
+
+    fn lookup(&self, name: &str) -> Result<VNode> {
+        get(name)
+    }
+}
+
+struct DevNode {
+    ino: u64,
+    mode: u16,
+    device: Arc<dyn CharDevice>,
+}
+
+impl Inode for DevNode {
+    fn stat(&self) -> Result<Stat> {
+        Ok(Stat {
+            ino: self.ino,
+            ty: FileType::CharDevice,
+            mode: self.mode,
+            size: 0,
+        })
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        self.device.read(offset, buf)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        self.device.write(offset, buf)
+    }
+
+    fn ioctl(&self, request: u64, buf: &mut [u8]) -> Result<u64> {
+        self.device.ioctl(request, buf)
+    }
+
+    fn poll(&self) -> Poll {
+        self.device.poll()
+    }
+}
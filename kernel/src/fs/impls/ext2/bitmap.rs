@@ -0,0 +1,245 @@
+//! block and inode allocation through the bitmaps of the block groups.
+
+use super::disk::{self, GroupCounts, RawInode, Superblock};
+use super::Ext2;
+use crate::fs::{Error, Result};
+use alloc::collections::BTreeSet;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+/// Everything which changes when the filesystem is written, behind one lock
+/// which every change holds from start to end.
+///
+/// The counters are written back once the change is done, the bitmaps as
+/// soon as a bit flips.
+pub(super) struct State {
+    pub free_blocks: u32,
+    free_inodes: u32,
+    groups: Vec<GroupCounts>,
+    /// groups whose descriptor has to be written back.
+    dirty: BTreeSet<usize>,
+    /// inodes without links which are still in use by a node, they are freed
+    /// when the last one is dropped.
+    pub orphans: BTreeSet<u32>,
+}
+
+impl State {
+    pub fn new(sb: &Superblock, groups: Vec<GroupCounts>) -> Self {
+        Self {
+            free_blocks: sb.free_blocks_count,
+            free_inodes: sb.free_inodes_count,
+            groups,
+            dirty: BTreeSet::new(),
+            orphans: BTreeSet::new(),
+        }
+    }
+}
+
+/// what a bitmap keeps track of.
+#[derive(Clone, Copy)]
+enum Bitmap {
+    Blocks,
+    Inodes,
+}
+
+impl Ext2 {
+    /// runs `f` with the lock for changes held, and writes the counters it
+    /// changed back afterwards.
+    pub(super) fn modify<T>(&self, f: impl FnOnce(&mut State) -> Result<T>) -> Result<T> {
+        if !self.writable {
+            return Err(Error::ReadOnly("ext2".to_string()));
+        }
+        let mut state = self.state.lock();
+        let result = f(&mut state);
+        let committed = self.commit(&mut state);
+        let value = result?;
+        committed.map(|_| value)
+    }
+
+    fn commit(&self, state: &mut State) -> Result<()> {
+        if state.dirty.is_empty() {
+            return Ok(());
+        }
+        let mut block = vec![0; self.block_size];
+        let per_block = self.block_size / disk::GROUP_DESC_SIZE;
+        let mut loaded = None;
+        for group in core::mem::take(&mut state.dirty) {
+            let index = (group / per_block) as u32;
+            if loaded != Some(index) {
+                if let Some(loaded) = loaded {
+                    self.write_block(self.desc_block(loaded), &block)?;
+                }
+                self.read_block(self.desc_block(index), &mut block)?;
+                loaded = Some(index);
+            }
+            let offset = group % per_block * disk::GROUP_DESC_SIZE;
+            state.groups[group].store(&mut block[offset..]);
+        }
+        if let Some(loaded) = loaded {
+            self.write_block(self.desc_block(loaded), &block)?;
+        }
+        let (free_blocks, free_inodes) = (state.free_blocks, state.free_inodes);
+        self.update_superblock(|sb| Superblock::store_counts(sb, free_blocks, free_inodes))
+    }
+
+    /// the `index`th block of the group descriptor table.
+    pub(super) fn desc_block(&self, index: u32) -> u32 {
+        self.sb.first_data_block + 1 + index
+    }
+
+    /// the group holding inode `ino`, where its data is best placed too.
+    pub(super) fn inode_group(&self, ino: u32) -> usize {
+        ((ino - 1) / self.sb.inodes_per_group) as usize
+    }
+
+    /// allocates a block, preferably in `goal`, and accounts it to `inode`.
+    /// it is zeroed on disk if `zero` is set.
+    pub(super) fn alloc_block(
+        &self,
+        state: &mut State,
+        goal: usize,
+        inode: &mut RawInode,
+        zero: bool,
+    ) -> Result<u32> {
+        if state.free_blocks == 0 {
+            return Err(Error::NoSpace("ext2: no free blocks".to_string()));
+        }
+        let (group, bit) = self.alloc_bit(state, goal, Bitmap::Blocks)?;
+        state.free_blocks -= 1;
+        state.groups[group].free_blocks -= 1;
+        let block = self.sb.first_data_block + group as u32 * self.sb.blocks_per_group + bit;
+        inode.blocks += (self.block_size / 512) as u32;
+        if zero {
+            self.write_block(block, &vec![0; self.block_size])?;
+        }
+        Ok(block)
+    }
+
+    pub(super) fn free_block(
+        &self,
+        state: &mut State,
+        inode: &mut RawInode,
+        block: u32,
+    ) -> Result<()> {
+        if block < self.sb.first_data_block || block >= self.sb.blocks_count {
+            return Err(Error::InvalidFile(format!("ext2: freeing block {block}")));
+        }
+        let index = block - self.sb.first_data_block;
+        let group = (index / self.sb.blocks_per_group) as usize;
+        self.free_bit(
+            state,
+            group,
+            index % self.sb.blocks_per_group,
+            Bitmap::Blocks,
+        )?;
+        state.free_blocks += 1;
+        state.groups[group].free_blocks += 1;
+        inode.blocks = inode.blocks.saturating_sub((self.block_size / 512) as u32);
+        Ok(())
+    }
+
+    /// allocates an inode, preferably in `goal`. the inode itself is left
+    /// for the caller to initialise.
+    pub(super) fn alloc_inode(&self, state: &mut State, goal: usize, dir: bool) -> Result<u32> {
+        if state.free_inodes == 0 {
+            return Err(Error::NoSpace("ext2: no free inodes".to_string()));
+        }
+        let (group, bit) = self.alloc_bit(state, goal, Bitmap::Inodes)?;
+        state.free_inodes -= 1;
+        state.groups[group].free_inodes -= 1;
+        if dir {
+            state.groups[group].used_dirs += 1;
+        }
+        Ok(group as u32 * self.sb.inodes_per_group + bit + 1)
+    }
+
+    pub(super) fn free_inode(&self, state: &mut State, ino: u32, dir: bool) -> Result<()> {
+        if ino < self.sb.first_ino || ino > self.sb.inodes_count {
+            return Err(Error::InvalidFile(format!("ext2: freeing inode {ino}")));
+        }
+        let group = self.inode_group(ino);
+        self.free_bit(
+            state,
+            group,
+            (ino - 1) % self.sb.inodes_per_group,
+            Bitmap::Inodes,
+        )?;
+        state.free_inodes += 1;
+        state.groups[group].free_inodes += 1;
+        if dir {
+            state.groups[group].used_dirs -= 1;
+        }
+        Ok(())
+    }
+
+    /// how many bits of a group's bitmap are in use, the last group of
+    /// blocks may be shorter than the others.
+    fn bitmap_len(&self, group: usize, bitmap: Bitmap) -> u32 {
+        match bitmap {
+            Bitmap::Blocks => {
+                let start = group as u32 * self.sb.blocks_per_group;
+                let blocks = self.sb.blocks_count - self.sb.first_data_block - start;
+                blocks.min(self.sb.blocks_per_group)
+            }
+            Bitmap::Inodes => self.sb.inodes_per_group,
+        }
+    }
+
+    fn bitmap_block(&self, group: usize, bitmap: Bitmap) -> u32 {
+        match bitmap {
+            Bitmap::Blocks => self.groups[group].block_bitmap,
+            Bitmap::Inodes => self.groups[group].inode_bitmap,
+        }
+    }
+
+    /// sets the first clear bit in the first group with free space, starting
+    /// at `goal`.
+    fn alloc_bit(&self, state: &mut State, goal: usize, bitmap: Bitmap) -> Result<(usize, u32)> {
+        let group_count = self.groups.len();
+        let mut buf = vec![0; self.block_size];
+        for group in (0..group_count).map(|i| (goal + i) % group_count) {
+            let counts = &state.groups[group];
+            let free = match bitmap {
+                Bitmap::Blocks => counts.free_blocks,
+                Bitmap::Inodes => counts.free_inodes,
+            };
+            if free == 0 {
+                continue;
+            }
+            // the reserved inodes are never handed out.
+            let first = match bitmap {
+                Bitmap::Inodes if group == 0 => self.sb.first_ino - 1,
+                _ => 0,
+            };
+            let block = self.bitmap_block(group, bitmap);
+            self.read_block(block, &mut buf)?;
+            let found = (first..self.bitmap_len(group, bitmap))
+                .find(|&bit| buf[bit as usize / 8] & 1 << (bit % 8) == 0);
+            if let Some(bit) = found {
+                buf[bit as usize / 8] |= 1 << (bit % 8);
+                self.write_block(block, &buf)?;
+                state.dirty.insert(group);
+                return Ok((group, bit));
+            }
+        }
+        Err(Error::InvalidFile(
+            "ext2: free counts don't match the bitmaps".to_string(),
+        ))
+    }
+
+    fn free_bit(&self, state: &mut State, group: usize, bit: u32, bitmap: Bitmap) -> Result<()> {
+        let block = self.bitmap_block(group, bitmap);
+        let mut buf = vec![0; self.block_size];
+        self.read_block(block, &mut buf)?;
+        let byte = &mut buf[bit as usize / 8];
+        if *byte & 1 << (bit % 8) == 0 {
+            return Err(Error::InvalidFile(format!(
+                "ext2: bit {bit} of group {group} is free"
+            )));
+        }
+        *byte &= !(1 << (bit % 8));
+        self.write_block(block, &buf)?;
+        state.dirty.insert(group);
+        Ok(())
+    }
+}
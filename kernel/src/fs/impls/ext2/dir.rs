@@ -0,0 +1,325 @@
+//! directories, a list of entries in each block, optionally indexed by a
+//! hash tree.
+
+use super::bitmap::State;
+use super::disk::{self, RawDirent, RawInode};
+use super::{hash, Ext2};
+use crate::fs::{DirEntry, Error, FileType, Result};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// where a lookup in an htree directory ended up.
+pub(super) enum HtreeLookup {
+    Found(u32),
+    NotFound,
+    /// the index can't answer this, the directory has to be searched.
+    Unusable,
+}
+
+impl Ext2 {
+    pub(super) fn dir_blocks(&self, dir: &RawInode) -> u64 {
+        dir.size / self.block_size as u64
+    }
+
+    /// calls `f` on every used entry in a directory block until it returns
+    /// something.
+    fn find_in_block<T>(
+        &self,
+        block: &[u8],
+        mut f: impl FnMut(&RawDirent, u8) -> Option<T>,
+    ) -> Result<Option<T>> {
+        let mut offset = 0;
+        while offset < block.len() {
+            let dirent = RawDirent::parse(&block[offset..])?;
+            if dirent.inode != 0 {
+                if let Some(found) = f(&dirent, block[offset + 7]) {
+                    return Ok(Some(found));
+                }
+            }
+            offset += dirent.rec_len;
+        }
+        Ok(None)
+    }
+
+    pub(super) fn readdir(&self, dir: &RawInode) -> Result<Vec<DirEntry>> {
+        let mut entries = Vec::new();
+        let mut block = vec![0; self.block_size];
+        for index in 0..self.dir_blocks(dir) {
+            self.read_file_block(dir, index, &mut block)?;
+            self.find_in_block(&block, |dirent, file_type| -> Option<()> {
+                if dirent.name != b"." && dirent.name != b".." {
+                    let name = String::from_utf8_lossy(dirent.name).into_owned();
+                    entries.push((dirent.inode, name, file_type));
+                }
+                None
+            })?;
+        }
+        entries
+            .into_iter()
+            .map(|(ino, name, file_type)| {
+                let ty = match self.dirent_type(file_type) {
+                    Some(ty) => ty,
+                    None => self.read_inode(ino)?.ty(),
+                };
+                Ok(DirEntry {
+                    name,
+                    ino: ino as u64,
+                    ty,
+                })
+            })
+            .collect()
+    }
+
+    /// the type stored in a directory entry, if the filesystem stores them.
+    fn dirent_type(&self, file_type: u8) -> Option<FileType> {
+        if self.sb.feature_incompat & disk::INCOMPAT_FILETYPE == 0 {
+            return None;
+        }
+        match file_type {
+            1 | 6 => Some(FileType::Regular),
+            2 => Some(FileType::Directory),
+            3 => Some(FileType::CharDevice),
+            4 => Some(FileType::BlockDevice),
+            5 => Some(FileType::Pipe),
+            7 => Some(FileType::Symlink),
+            _ => None,
+        }
+    }
+
+    fn find_in_dir_block(&self, dir: &RawInode, index: u64, name: &[u8]) -> Result<Option<u32>> {
+        let mut block = vec![0; self.block_size];
+        self.read_file_block(dir, index, &mut block)?;
+        self.find_in_block(&block, |dirent, _| {
+            (dirent.name == name).then_some(dirent.inode)
+        })
+    }
+
+    pub(super) fn lookup(&self, dir: &RawInode, name: &str) -> Result<Option<u32>> {
+        let name = name.as_bytes();
+        if dir.flags & disk::INODE_INDEX_FL != 0 {
+            match self.htree_lookup(dir, name)? {
+                HtreeLookup::Found(ino) => return Ok(Some(ino)),
+                HtreeLookup::NotFound => return Ok(None),
+                HtreeLookup::Unusable => {}
+            }
+        }
+        for index in 0..self.dir_blocks(dir) {
+            if let Some(ino) = self.find_in_dir_block(dir, index, name)? {
+                return Ok(Some(ino));
+            }
+        }
+        Ok(None)
+    }
+
+    /// walks the hash index down to the leaf block which holds `name`.
+    pub(super) fn htree_lookup(&self, dir: &RawInode, name: &[u8]) -> Result<HtreeLookup> {
+        // the root block starts with `.` and `..`, whose record covers the
+        // index which follows.
+        const INFO: usize = 24;
+        const NODE_ENTRIES: usize = 8;
+        const MAX_LEVELS: u8 = 2;
+        const BLOCK_MASK: u32 = 0x0fff_ffff;
+
+        let mut block = vec![0; self.block_size];
+        self.read_file_block(dir, 0, &mut block)?;
+        let mut version = block[INFO + 4];
+        let info_len = block[INFO + 5] as usize;
+        let levels = block[INFO + 6];
+        if info_len != 8 || levels >= MAX_LEVELS || version > hash::HASH_TEA {
+            return Ok(HtreeLookup::Unusable);
+        }
+        if self.sb.flags & disk::FLAGS_UNSIGNED_HASH != 0 {
+            version += hash::HASH_UNSIGNED_OFFSET;
+        }
+        let hash = hash::dirhash(name, version, &self.sb.hash_seed);
+
+        let mut entries = INFO + info_len;
+        for level in 0..=levels {
+            // the first entry holds the limit and count instead of a hash.
+            let count = disk::u16_at(&block, entries + 2) as usize;
+            if count == 0 || entries + count * 8 > block.len() {
+                return Ok(HtreeLookup::Unusable);
+            }
+            let entry_hash = |i: usize| disk::u32_at(&block, entries + i * 8);
+            let entry_block = |i: usize| disk::u32_at(&block, entries + i * 8 + 4) & BLOCK_MASK;
+            // the last entry whose hash isn't above ours.
+            let at = (1..count)
+                .take_while(|&i| entry_hash(i) <= hash)
+                .last()
+                .unwrap_or(0);
+            let leaf = entry_block(at) as u64;
+            if level < levels {
+                self.read_file_block(dir, leaf, &mut block)?;
+                entries = NODE_ENTRIES;
+                continue;
+            }
+            if let Some(ino) = self.find_in_dir_block(dir, leaf, name)? {
+                return Ok(HtreeLookup::Found(ino));
+            }
+            // names with the same hash may continue in the next leaves, which
+            // the entries mark with the low bit of their hash.
+            let mut next = at + 1;
+            while next < count && entry_hash(next) == hash | 1 {
+                if let Some(ino) = self.find_in_dir_block(dir, entry_block(next) as u64, name)? {
+                    return Ok(HtreeLookup::Found(ino));
+                }
+                next += 1;
+            }
+            if next == count && levels > 0 {
+                // the collision might go on in the next index node.
+                return Ok(HtreeLookup::Unusable);
+            }
+        }
+        Ok(HtreeLookup::NotFound)
+    }
+
+    /// adds an entry for `ino` to the directory `dir_ino`, in the first gap
+    /// large enough or a new block at the end.
+    ///
+    /// the hash index isn't updated, so it is dropped and the directory
+    /// searched linearly from then on.
+    pub(super) fn add_entry(
+        &self,
+        state: &mut State,
+        dir_ino: u32,
+        name: &str,
+        ino: u32,
+        mode: u16,
+    ) -> Result<()> {
+        let name = name.as_bytes();
+        let file_type = if self.sb.feature_incompat & disk::INCOMPAT_FILETYPE != 0 {
+            disk::dirent_file_type(mode)
+        } else {
+            0
+        };
+        let needed = disk::dirent_len(name.len());
+        let mut dir = self.read_inode(dir_ino)?;
+        if dir.flags & disk::INODE_INDEX_FL != 0 {
+            dir.flags &= !disk::INODE_INDEX_FL;
+            self.write_inode(dir_ino, &dir)?;
+        }
+
+        let mut block = vec![0; self.block_size];
+        for index in 0..self.dir_blocks(&dir) {
+            let lba = self.block_map(&dir, index)?;
+            if lba == 0 {
+                continue;
+            }
+            self.read_block(lba, &mut block)?;
+            let mut offset = 0;
+            while offset < block.len() {
+                let dirent = RawDirent::parse(&block[offset..])?;
+                let used = match dirent.inode {
+                    0 => 0,
+                    _ => disk::dirent_len(dirent.name.len()),
+                };
+                let rec_len = dirent.rec_len;
+                if rec_len - used >= needed {
+                    if used > 0 {
+                        disk::set_u16(&mut block, offset + 4, used as u16);
+                    }
+                    let entry = &mut block[offset + used..];
+                    disk::store_dirent(entry, ino, rec_len - used, name, file_type);
+                    return self.write_block(lba, &block);
+                }
+                offset += rec_len;
+            }
+        }
+
+        let index = self.dir_blocks(&dir);
+        let (lba, _) = self.block_map_alloc(state, dir_ino, &mut dir, index)?;
+        block.fill(0);
+        disk::store_dirent(&mut block, ino, self.block_size, name, file_type);
+        self.write_block(lba, &block)?;
+        dir.size += self.block_size as u64;
+        self.write_inode(dir_ino, &dir)
+    }
+
+    /// removes the entry `name` from the directory `dir_ino` and returns the
+    /// inode it pointed to. the index stays valid, as nothing moves.
+    pub(super) fn remove_entry(&self, dir_ino: u32, name: &str) -> Result<u32> {
+        let dir = self.read_inode(dir_ino)?;
+        let mut block = vec![0; self.block_size];
+        for index in 0..self.dir_blocks(&dir) {
+            let lba = self.block_map(&dir, index)?;
+            if lba == 0 {
+                continue;
+            }
+            self.read_block(lba, &mut block)?;
+            let mut offset = 0;
+            let mut prev = None;
+            while offset < block.len() {
+                let dirent = RawDirent::parse(&block[offset..])?;
+                if dirent.inode != 0 && dirent.name == name.as_bytes() {
+                    let ino = dirent.inode;
+                    // the space goes to the entry before, the first entry of
+                    // a block is only marked unused.
+                    match prev {
+                        Some(prev) => {
+                            let merged = offset - prev + dirent.rec_len;
+                            disk::set_u16(&mut block, prev + 4, merged as u16);
+                        }
+                        None => disk::set_u32(&mut block, offset, 0),
+                    }
+                    self.write_block(lba, &block)?;
+                    return Ok(ino);
+                }
+                prev = Some(offset);
+                offset += dirent.rec_len;
+            }
+        }
+        Err(Error::NoSuchPath(name.to_string()))
+    }
+
+    /// fills the first block of the new directory `ino` with `.` and `..`.
+    pub(super) fn init_dir_block(&self, lba: u32, ino: u32, parent: u32) -> Result<()> {
+        let file_type = match self.sb.feature_incompat & disk::INCOMPAT_FILETYPE {
+            0 => 0,
+            _ => disk::dirent_file_type(disk::S_IFDIR),
+        };
+        let dot_len = disk::dirent_len(1);
+        let mut block = vec![0; self.block_size];
+        disk::store_dirent(&mut block, ino, dot_len, b".", file_type);
+        let rest = self.block_size - dot_len;
+        disk::store_dirent(&mut block[dot_len..], parent, rest, b"..", file_type);
+        self.write_block(lba, &block)
+    }
+
+    /// points `..` of the directory `ino` to `parent`, it is always the
+    /// second entry of the first block.
+    pub(super) fn set_parent(&self, ino: u32, parent: u32) -> Result<()> {
+        let dir = self.read_inode(ino)?;
+        let lba = self.block_map(&dir, 0)?;
+        let mut block = vec![0; self.block_size];
+        self.read_block(lba, &mut block)?;
+        let dot = RawDirent::parse(&block)?;
+        let offset = dot.rec_len;
+        if RawDirent::parse(&block[offset..])?.name != b".." {
+            return Err(Error::InvalidFile(format!(
+                "ext2: directory {ino} has no '..'"
+            )));
+        }
+        disk::set_u32(&mut block, offset, parent);
+        self.write_block(lba, &block)
+    }
+
+    /// the inode `..` of the directory `dir` points to.
+    pub(super) fn parent(&self, dir: &RawInode) -> Result<u32> {
+        self.find_in_dir_block(dir, 0, b"..")?
+            .ok_or_else(|| Error::InvalidFile("ext2: directory without '..'".to_string()))
+    }
+
+    pub(super) fn is_empty(&self, dir: &RawInode) -> Result<bool> {
+        let mut block = vec![0; self.block_size];
+        for index in 0..self.dir_blocks(dir) {
+            self.read_file_block(dir, index, &mut block)?;
+            let found = self.find_in_block(&block, |dirent, _| {
+                (dirent.name != b"." && dirent.name != b"..").then_some(())
+            })?;
+            if found.is_some() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
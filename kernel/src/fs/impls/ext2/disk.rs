@@ -0,0 +1,344 @@
+//! on-disk structures, all little endian.
+
+use crate::fs::{Error, FileType, Result};
+use alloc::string::ToString;
+
+/// the superblock is always 1024 bytes into the filesystem.
+pub const SUPERBLOCK_OFFSET: usize = 1024;
+pub const SUPERBLOCK_SIZE: usize = 1024;
+
+pub const MAGIC: u16 = 0xef53;
+
+pub const ROOT_INO: u32 = 2;
+
+/// inode size of revision 0 filesystems.
+pub const GOOD_OLD_INODE_SIZE: u16 = 128;
+/// first inode which isn't reserved in revision 0 filesystems.
+pub const GOOD_OLD_FIRST_INO: u32 = 11;
+
+pub const GROUP_DESC_SIZE: usize = 32;
+
+/// directory entries carry the type of the inode they point to.
+pub const INCOMPAT_FILETYPE: u32 = 0x2;
+/// features we can read, everything else refuses to mount.
+pub const INCOMPAT_SUPPORTED: u32 = INCOMPAT_FILETYPE;
+
+/// read-only features we keep intact when writing, the filesystem is
+/// mounted read-only if it has any others.
+pub const RO_COMPAT_SPARSE_SUPER: u32 = 0x1;
+pub const RO_COMPAT_LARGE_FILE: u32 = 0x2;
+pub const RO_COMPAT_SUPPORTED: u32 = RO_COMPAT_SPARSE_SUPER | RO_COMPAT_LARGE_FILE;
+
+/// `Superblock::state` of a filesystem which was unmounted cleanly.
+pub const STATE_VALID: u16 = 0x1;
+
+/// directory hashes are computed on unsigned chars.
+pub const FLAGS_UNSIGNED_HASH: u32 = 0x2;
+
+pub const DIRECT_BLOCKS: usize = 12;
+pub const IND_BLOCK: usize = DIRECT_BLOCKS;
+pub const DIND_BLOCK: usize = IND_BLOCK + 1;
+pub const TIND_BLOCK: usize = DIND_BLOCK + 1;
+pub const N_BLOCKS: usize = TIND_BLOCK + 1;
+
+/// the directory has an htree index.
+pub const INODE_INDEX_FL: u32 = 0x1000;
+
+pub const S_IFMT: u16 = 0xf000;
+pub const S_IFSOCK: u16 = 0xc000;
+pub const S_IFLNK: u16 = 0xa000;
+pub const S_IFREG: u16 = 0x8000;
+pub const S_IFBLK: u16 = 0x6000;
+pub const S_IFDIR: u16 = 0x4000;
+pub const S_IFCHR: u16 = 0x2000;
+pub const S_IFIFO: u16 = 0x1000;
+
+pub fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap())
+}
+
+pub fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+pub fn set_u16(buf: &mut [u8], offset: usize, v: u16) {
+    buf[offset..offset + 2].copy_from_slice(&v.to_le_bytes());
+}
+
+pub fn set_u32(buf: &mut [u8], offset: usize, v: u32) {
+    buf[offset..offset + 4].copy_from_slice(&v.to_le_bytes());
+}
+
+pub struct Superblock {
+    pub inodes_count: u32,
+    pub blocks_count: u32,
+    pub free_blocks_count: u32,
+    pub free_inodes_count: u32,
+    pub first_data_block: u32,
+    pub log_block_size: u32,
+    pub blocks_per_group: u32,
+    pub inodes_per_group: u32,
+    pub state: u16,
+    pub rev_level: u32,
+    pub first_ino: u32,
+    pub inode_size: u16,
+    pub feature_compat: u32,
+    pub feature_incompat: u32,
+    pub feature_ro_compat: u32,
+    pub hash_seed: [u32; 4],
+    pub def_hash_version: u8,
+    pub flags: u32,
+}
+
+impl Superblock {
+    pub fn parse(buf: &[u8]) -> Result<Self> {
+        if u16_at(buf, 56) != MAGIC {
+            return Err(Error::InvalidFile("ext2: invalid magic".to_string()));
+        }
+        let rev_level = u32_at(buf, 76);
+        let (first_ino, inode_size) = if rev_level == 0 {
+            (GOOD_OLD_FIRST_INO, GOOD_OLD_INODE_SIZE)
+        } else {
+            (u32_at(buf, 84), u16_at(buf, 88))
+        };
+        let sb = Self {
+            inodes_count: u32_at(buf, 0),
+            blocks_count: u32_at(buf, 4),
+            free_blocks_count: u32_at(buf, 12),
+            free_inodes_count: u32_at(buf, 16),
+            first_data_block: u32_at(buf, 20),
+            log_block_size: u32_at(buf, 24),
+            blocks_per_group: u32_at(buf, 32),
+            inodes_per_group: u32_at(buf, 40),
+            state: u16_at(buf, 58),
+            rev_level,
+            first_ino,
+            inode_size,
+            feature_compat: u32_at(buf, 92),
+            feature_incompat: u32_at(buf, 96),
+            feature_ro_compat: u32_at(buf, 100),
+            hash_seed: [
+                u32_at(buf, 0xec),
+                u32_at(buf, 0xf0),
+                u32_at(buf, 0xf4),
+                u32_at(buf, 0xf8),
+            ],
+            def_hash_version: buf[0xfc],
+            flags: u32_at(buf, 0x160),
+        };
+        if sb.log_block_size > 6
+            || sb.blocks_per_group == 0
+            || sb.inodes_per_group == 0
+            || sb.inode_size < GOOD_OLD_INODE_SIZE
+            || !sb.inode_size.is_power_of_two()
+        {
+            return Err(Error::InvalidFile("ext2: invalid superblock".to_string()));
+        }
+        if sb.feature_incompat & !INCOMPAT_SUPPORTED != 0 {
+            return Err(Error::InvalidFile(format!(
+                "ext2: unsupported features {:#x}",
+                sb.feature_incompat & !INCOMPAT_SUPPORTED
+            )));
+        }
+        Ok(sb)
+    }
+
+    /// patches the free counts into the raw superblock in `buf`.
+    pub fn store_counts(buf: &mut [u8], free_blocks: u32, free_inodes: u32) {
+        set_u32(buf, 12, free_blocks);
+        set_u32(buf, 16, free_inodes);
+    }
+
+    pub fn store_state(buf: &mut [u8], state: u16) {
+        set_u16(buf, 58, state);
+    }
+
+    pub fn block_size(&self) -> usize {
+        1024 << self.log_block_size
+    }
+
+    pub fn group_count(&self) -> usize {
+        let blocks = self.blocks_count - self.first_data_block;
+        blocks.div_ceil(self.blocks_per_group) as usize
+    }
+}
+
+/// where the metadata of a block group lies, which never changes.
+pub struct GroupDesc {
+    pub block_bitmap: u32,
+    pub inode_bitmap: u32,
+    pub inode_table: u32,
+}
+
+impl GroupDesc {
+    pub fn parse(buf: &[u8]) -> Self {
+        Self {
+            block_bitmap: u32_at(buf, 0),
+            inode_bitmap: u32_at(buf, 4),
+            inode_table: u32_at(buf, 8),
+        }
+    }
+}
+
+/// the counters of a group descriptor, which change with every allocation.
+#[derive(Clone, Copy)]
+pub struct GroupCounts {
+    pub free_blocks: u16,
+    pub free_inodes: u16,
+    pub used_dirs: u16,
+}
+
+impl GroupCounts {
+    pub fn parse(buf: &[u8]) -> Self {
+        Self {
+            free_blocks: u16_at(buf, 12),
+            free_inodes: u16_at(buf, 14),
+            used_dirs: u16_at(buf, 16),
+        }
+    }
+
+    pub fn store(&self, buf: &mut [u8]) {
+        set_u16(buf, 12, self.free_blocks);
+        set_u16(buf, 14, self.free_inodes);
+        set_u16(buf, 16, self.used_dirs);
+    }
+}
+
+/// The fields of an inode we use, the rest is left alone on disk.
+#[derive(Clone, Default)]
+pub struct RawInode {
+    pub mode: u16,
+    pub size: u64,
+    pub links_count: u16,
+    /// in 512 byte units, whatever the block size.
+    pub blocks: u32,
+    pub flags: u32,
+    pub block: [u32; N_BLOCKS],
+    /// block holding extended attributes, shared between inodes.
+    pub file_acl: u32,
+}
+
+impl RawInode {
+    pub fn parse(buf: &[u8]) -> Self {
+        let mode = u16_at(buf, 0);
+        let size_high = if mode & S_IFMT == S_IFREG {
+            u32_at(buf, 108)
+        } else {
+            0
+        };
+        Self {
+            mode,
+            size: u32_at(buf, 4) as u64 | (size_high as u64) << 32,
+            links_count: u16_at(buf, 26),
+            blocks: u32_at(buf, 28),
+            flags: u32_at(buf, 32),
+            block: core::array::from_fn(|i| u32_at(buf, 40 + i * 4)),
+            file_acl: u32_at(buf, 104),
+        }
+    }
+
+    /// patches the fields into the raw inode in `buf`.
+    pub fn store(&self, buf: &mut [u8]) {
+        set_u16(buf, 0, self.mode);
+        set_u32(buf, 4, self.size as u32);
+        if self.mode & S_IFMT == S_IFREG {
+            set_u32(buf, 108, (self.size >> 32) as u32);
+        }
+        set_u16(buf, 26, self.links_count);
+        set_u32(buf, 28, self.blocks);
+        set_u32(buf, 32, self.flags);
+        for (i, block) in self.block.iter().enumerate() {
+            set_u32(buf, 40 + i * 4, *block);
+        }
+        set_u32(buf, 104, self.file_acl);
+    }
+
+    pub fn ty(&self) -> FileType {
+        mode_type(self.mode)
+    }
+
+    /// symlinks with short targets keep them in `block` instead of a data
+    /// block.
+    pub fn is_fast_symlink(&self) -> bool {
+        self.mode & S_IFMT == S_IFLNK && self.size < (N_BLOCKS * 4) as u64
+    }
+
+    /// the raw bytes of `block`, which hold a fast symlink's target.
+    pub fn block_bytes(&self) -> [u8; N_BLOCKS * 4] {
+        let mut bytes = [0; N_BLOCKS * 4];
+        for (chunk, block) in bytes.chunks_exact_mut(4).zip(self.block) {
+            chunk.copy_from_slice(&block.to_le_bytes());
+        }
+        bytes
+    }
+}
+
+/// the type byte of directory entries pointing to an inode with `mode`.
+pub fn dirent_file_type(mode: u16) -> u8 {
+    match mode & S_IFMT {
+        S_IFREG => 1,
+        S_IFDIR => 2,
+        S_IFCHR => 3,
+        S_IFBLK => 4,
+        S_IFIFO => 5,
+        S_IFSOCK => 6,
+        S_IFLNK => 7,
+        _ => 0,
+    }
+}
+
+pub fn mode_type(mode: u16) -> FileType {
+    match mode & S_IFMT {
+        S_IFDIR => FileType::Directory,
+        S_IFLNK => FileType::Symlink,
+        S_IFCHR => FileType::CharDevice,
+        S_IFBLK => FileType::BlockDevice,
+        S_IFIFO => FileType::Pipe,
+        // sockets don't have a type of their own.
+        _ => FileType::Regular,
+    }
+}
+
+/// header of a directory entry, followed by the name.
+pub const DIRENT_HEADER: usize = 8;
+pub const MAX_NAME_LEN: usize = 255;
+
+/// the space an entry with a name of `name_len` bytes needs.
+pub fn dirent_len(name_len: usize) -> usize {
+    (DIRENT_HEADER + name_len).next_multiple_of(4)
+}
+
+/// writes an entry at the start of `buf`.
+pub fn store_dirent(buf: &mut [u8], inode: u32, rec_len: usize, name: &[u8], file_type: u8) {
+    set_u32(buf, 0, inode);
+    set_u16(buf, 4, rec_len as u16);
+    buf[6] = name.len() as u8;
+    buf[7] = file_type;
+    buf[DIRENT_HEADER..DIRENT_HEADER + name.len()].copy_from_slice(name);
+}
+
+pub struct RawDirent<'a> {
+    pub inode: u32,
+    pub rec_len: usize,
+    pub name: &'a [u8],
+}
+
+impl<'a> RawDirent<'a> {
+    /// the entry at the start of `buf`, which is the rest of a directory
+    /// block.
+    pub fn parse(buf: &'a [u8]) -> Result<Self> {
+        if buf.len() < DIRENT_HEADER {
+            return Err(Error::InvalidFile("ext2: truncated dirent".to_string()));
+        }
+        let rec_len = u16_at(buf, 4) as usize;
+        let name_len = buf[6] as usize;
+        if rec_len < DIRENT_HEADER || rec_len > buf.len() || DIRENT_HEADER + name_len > rec_len {
+            return Err(Error::InvalidFile("ext2: invalid dirent".to_string()));
+        }
+        Ok(Self {
+            inode: u32_at(buf, 0),
+            rec_len,
+            name: &buf[DIRENT_HEADER..DIRENT_HEADER + name_len],
+        })
+    }
+}
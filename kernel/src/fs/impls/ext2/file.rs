@@ -0,0 +1,290 @@
+//! the data of inodes, found through the direct and indirect blocks.
+
+use super::bitmap::State;
+use super::disk::{self, RawInode};
+use super::Ext2;
+use crate::fs::{Error, Result};
+use alloc::string::ToString;
+
+impl Ext2 {
+    fn per_block(&self) -> u64 {
+        (self.block_size / 4) as u64
+    }
+
+    /// where the `index`th block of a file is found: the slot in the inode,
+    /// the index below it and how many indirect blocks lie on the way.
+    fn block_path(&self, index: u64) -> Result<(usize, u64, u32)> {
+        let per_block = self.per_block();
+        let mut index = index;
+        if index < disk::DIRECT_BLOCKS as u64 {
+            return Ok((index as usize, 0, 0));
+        }
+        index -= disk::DIRECT_BLOCKS as u64;
+        for (slot, depth) in [
+            (disk::IND_BLOCK, 1),
+            (disk::DIND_BLOCK, 2),
+            (disk::TIND_BLOCK, 3),
+        ] {
+            let span = per_block.pow(depth);
+            if index < span {
+                return Ok((slot, index, depth));
+            }
+            index -= span;
+        }
+        Err(Error::NoSpace("ext2: file too large".to_string()))
+    }
+
+    /// the block holding the `index`th block of `inode`'s data, zero for
+    /// holes.
+    pub(super) fn block_map(&self, inode: &RawInode, index: u64) -> Result<u32> {
+        let (slot, mut index, depth) = self.block_path(index)?;
+        let mut block = inode.block[slot];
+        let mut buf = vec![0; self.block_size];
+        for level in (0..depth).rev() {
+            if block == 0 {
+                return Ok(0);
+            }
+            self.read_block(block, &mut buf)?;
+            let span = self.per_block().pow(level);
+            block = disk::u32_at(&buf, (index / span) as usize * 4);
+            index %= span;
+        }
+        Ok(block)
+    }
+
+    /// like `block_map`, but fills holes with new blocks on the way. also
+    /// returns whether the data block is new, its content is undefined then.
+    pub(super) fn block_map_alloc(
+        &self,
+        state: &mut State,
+        ino: u32,
+        inode: &mut RawInode,
+        index: u64,
+    ) -> Result<(u32, bool)> {
+        let (slot, mut index, depth) = self.block_path(index)?;
+        let goal = self.inode_group(ino);
+        let mut new = false;
+        if inode.block[slot] == 0 {
+            inode.block[slot] = self.alloc_block(state, goal, inode, depth > 0)?;
+            new = true;
+        }
+        let mut block = inode.block[slot];
+        let mut buf = vec![0; self.block_size];
+        for level in (0..depth).rev() {
+            self.read_block(block, &mut buf)?;
+            let span = self.per_block().pow(level);
+            let entry = (index / span) as usize * 4;
+            index %= span;
+            let mut next = disk::u32_at(&buf, entry);
+            new = next == 0;
+            if new {
+                next = self.alloc_block(state, goal, inode, level > 0)?;
+                disk::set_u32(&mut buf, entry, next);
+                self.write_block(block, &buf)?;
+            }
+            block = next;
+        }
+        Ok((block, new))
+    }
+
+    /// reads the `index`th block of `inode`'s data, holes read as zeros.
+    pub(super) fn read_file_block(
+        &self,
+        inode: &RawInode,
+        index: u64,
+        buf: &mut [u8],
+    ) -> Result<()> {
+        match self.block_map(inode, index)? {
+            0 => {
+                buf.fill(0);
+                Ok(())
+            }
+            block => self.read_block(block, buf),
+        }
+    }
+
+    pub(super) fn read_data(&self, inode: &RawInode, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        if offset >= inode.size {
+            return Ok(0);
+        }
+        let len = (buf.len() as u64).min(inode.size - offset) as usize;
+        let block_size = self.block_size as u64;
+        let mut block = vec![0; self.block_size];
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done as u64;
+            let in_block = (pos % block_size) as usize;
+            let chunk = (self.block_size - in_block).min(len - done);
+            self.read_file_block(inode, pos / block_size, &mut block)?;
+            buf[done..done + chunk].copy_from_slice(&block[in_block..in_block + chunk]);
+            done += chunk;
+        }
+        Ok(len)
+    }
+
+    /// the largest size files can grow to.
+    fn max_size(&self) -> u64 {
+        if self.sb.feature_ro_compat & disk::RO_COMPAT_LARGE_FILE != 0 {
+            u64::MAX
+        } else {
+            i32::MAX as u64
+        }
+    }
+
+    /// writes `buf` at `offset`, allocating blocks as needed, and stores the
+    /// inode. a write which runs out of space part way is cut short.
+    pub(super) fn write_data(
+        &self,
+        state: &mut State,
+        ino: u32,
+        inode: &mut RawInode,
+        offset: u64,
+        buf: &[u8],
+    ) -> Result<usize> {
+        if offset.saturating_add(buf.len() as u64) > self.max_size() {
+            return Err(Error::NoSpace("ext2: file too large".to_string()));
+        }
+        let block_size = self.block_size as u64;
+        let mut block = vec![0; self.block_size];
+        let mut done = 0;
+        let mut result = Ok(());
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let in_block = (pos % block_size) as usize;
+            let chunk = (self.block_size - in_block).min(buf.len() - done);
+            result = self
+                .block_map_alloc(state, ino, inode, pos / block_size)
+                .and_then(|(lba, new)| {
+                    if chunk == self.block_size {
+                        return self.write_block(lba, &buf[done..done + chunk]);
+                    }
+                    if new {
+                        block.fill(0);
+                    } else {
+                        self.read_block(lba, &mut block)?;
+                    }
+                    block[in_block..in_block + chunk].copy_from_slice(&buf[done..done + chunk]);
+                    self.write_block(lba, &block)
+                });
+            if result.is_err() {
+                break;
+            }
+            done += chunk;
+        }
+        inode.size = inode.size.max(offset + done as u64);
+        // the blocks allocated so far belong to the inode even on errors.
+        self.write_inode(ino, inode)?;
+        match result {
+            Err(e) if done == 0 => Err(e),
+            _ => Ok(done),
+        }
+    }
+
+    /// sets the size of `inode` and stores it, freeing the blocks past the
+    /// end when it shrinks.
+    pub(super) fn truncate(
+        &self,
+        state: &mut State,
+        ino: u32,
+        inode: &mut RawInode,
+        size: u64,
+    ) -> Result<()> {
+        if size > self.max_size() {
+            return Err(Error::NoSpace("ext2: file too large".to_string()));
+        }
+        if size < inode.size {
+            let block_size = self.block_size as u64;
+            self.free_data_from(state, inode, size.div_ceil(block_size))?;
+            // the rest of the last block has to read as zeros if the file
+            // grows again.
+            let tail = (size % block_size) as usize;
+            if tail != 0 {
+                let lba = self.block_map(inode, size / block_size)?;
+                if lba != 0 {
+                    let mut block = vec![0; self.block_size];
+                    self.read_block(lba, &mut block)?;
+                    block[tail..].fill(0);
+                    self.write_block(lba, &block)?;
+                }
+            }
+        }
+        inode.size = size;
+        self.write_inode(ino, inode)
+    }
+
+    /// frees every data block from the `first`th on, and the indirect blocks
+    /// which are left empty.
+    pub(super) fn free_data_from(
+        &self,
+        state: &mut State,
+        inode: &mut RawInode,
+        first: u64,
+    ) -> Result<()> {
+        for slot in (first as usize).min(disk::DIRECT_BLOCKS)..disk::DIRECT_BLOCKS {
+            let block = core::mem::take(&mut inode.block[slot]);
+            if block != 0 {
+                self.free_block(state, inode, block)?;
+            }
+        }
+        let mut base = disk::DIRECT_BLOCKS as u64;
+        for (slot, depth) in [
+            (disk::IND_BLOCK, 1),
+            (disk::DIND_BLOCK, 2),
+            (disk::TIND_BLOCK, 3),
+        ] {
+            let span = self.per_block().pow(depth);
+            let block = inode.block[slot];
+            if block != 0
+                && first < base + span
+                && self.free_tree(state, inode, block, depth, first.saturating_sub(base))?
+            {
+                inode.block[slot] = 0;
+                self.free_block(state, inode, block)?;
+            }
+            base += span;
+        }
+        Ok(())
+    }
+
+    /// frees the blocks from the `first`th on below the indirect `block`,
+    /// returns whether nothing is left below it.
+    fn free_tree(
+        &self,
+        state: &mut State,
+        inode: &mut RawInode,
+        block: u32,
+        depth: u32,
+        first: u64,
+    ) -> Result<bool> {
+        let span = self.per_block().pow(depth - 1);
+        let mut buf = vec![0; self.block_size];
+        self.read_block(block, &mut buf)?;
+        let mut changed = false;
+        let mut empty = true;
+        for (i, entry) in buf.chunks_exact_mut(4).enumerate() {
+            let child = disk::u32_at(entry, 0);
+            if child == 0 {
+                continue;
+            }
+            let start = i as u64 * span;
+            let freed = if start + span <= first {
+                false
+            } else if depth == 1 {
+                true
+            } else {
+                self.free_tree(state, inode, child, depth - 1, first.saturating_sub(start))?
+            };
+            if freed {
+                disk::set_u32(entry, 0, 0);
+                self.free_block(state, inode, child)?;
+                changed = true;
+            } else {
+                empty = false;
+            }
+        }
+        if changed && !empty {
+            self.write_block(block, &buf)?;
+        }
+        Ok(empty)
+    }
+}
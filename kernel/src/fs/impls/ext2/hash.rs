@@ -0,0 +1,160 @@
+//! name hashes of htree directories, as computed by Linux and e2fsprogs.
+
+pub const HASH_LEGACY: u8 = 0;
+pub const HASH_HALF_MD4: u8 = 1;
+pub const HASH_TEA: u8 = 2;
+/// added to the versions above on filesystems with unsigned char hashes.
+pub const HASH_UNSIGNED_OFFSET: u8 = 3;
+
+/// hashes at or above this mark the end of the directory to readdir.
+const HTREE_EOF: u32 = 0x7fff_ffff;
+
+/// the major hash of `name`, with the lowest bit cleared as stored in the
+/// index.
+pub fn dirhash(name: &[u8], version: u8, seed: &[u32; 4]) -> u32 {
+    let mut buf = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+    if seed.iter().any(|&word| word != 0) {
+        buf = *seed;
+    }
+    let unsigned = version >= HASH_UNSIGNED_OFFSET;
+    let hash = match version % HASH_UNSIGNED_OFFSET {
+        HASH_HALF_MD4 => {
+            let mut input = [0; 8];
+            for chunk in chunks(name, 32) {
+                str_to_hash_buf(chunk, &mut input, unsigned);
+                half_md4_transform(&mut buf, &input);
+            }
+            buf[1]
+        }
+        HASH_TEA => {
+            let mut input = [0; 4];
+            for chunk in chunks(name, 16) {
+                str_to_hash_buf(chunk, &mut input, unsigned);
+                tea_transform(&mut buf, &input);
+            }
+            buf[0]
+        }
+        _ => legacy_hash(name, unsigned),
+    };
+    let hash = hash & !1;
+    if hash == HTREE_EOF << 1 {
+        (HTREE_EOF - 1) << 1
+    } else {
+        hash
+    }
+}
+
+/// the name from every `size`th byte on, as the length of the whole rest
+/// goes into the padding.
+fn chunks(name: &[u8], size: usize) -> impl Iterator<Item = &[u8]> {
+    (0..name.len())
+        .step_by(size)
+        .map(move |start| &name[start..])
+}
+
+fn char_value(b: u8, unsigned: bool) -> u32 {
+    if unsigned {
+        b as u32
+    } else {
+        b as i8 as i32 as u32
+    }
+}
+
+fn legacy_hash(name: &[u8], unsigned: bool) -> u32 {
+    let (mut hash0, mut hash1): (u32, u32) = (0x12a3fe2d, 0x37abe8f9);
+    for &b in name {
+        let mut hash = hash1.wrapping_add(hash0 ^ char_value(b, unsigned).wrapping_mul(7152373));
+        if hash & 0x8000_0000 != 0 {
+            hash = hash.wrapping_sub(0x7fff_ffff);
+        }
+        hash1 = hash0;
+        hash0 = hash;
+    }
+    hash0 << 1
+}
+
+/// packs up to `4 * buf.len()` bytes of `msg` into `buf`, padded with its
+/// length.
+fn str_to_hash_buf(msg: &[u8], buf: &mut [u32], unsigned: bool) {
+    let len = msg.len() as u32;
+    let mut pad = len | (len << 8);
+    pad |= pad << 16;
+    let mut val = pad;
+    let mut words = 0;
+    for (i, &b) in msg.iter().take(buf.len() * 4).enumerate() {
+        val = char_value(b, unsigned).wrapping_add(val << 8);
+        if i % 4 == 3 {
+            buf[words] = val;
+            words += 1;
+            val = pad;
+        }
+    }
+    if words < buf.len() {
+        buf[words] = val;
+        words += 1;
+    }
+    buf[words..].fill(pad);
+}
+
+fn half_md4_transform(buf: &mut [u32; 4], input: &[u32; 8]) {
+    const K1: u32 = 0;
+    const K2: u32 = 0o13240474631;
+    const K3: u32 = 0o15666365641;
+    let f = |x: u32, y: u32, z: u32| z ^ (x & (y ^ z));
+    let g = |x: u32, y: u32, z: u32| (x & y).wrapping_add((x ^ y) & z);
+    let h = |x: u32, y: u32, z: u32| x ^ y ^ z;
+    let round = |f: &dyn Fn(u32, u32, u32) -> u32, a: u32, b, c, d, x: u32, s| {
+        a.wrapping_add(f(b, c, d)).wrapping_add(x).rotate_left(s)
+    };
+    let [mut a, mut b, mut c, mut d] = *buf;
+
+    a = round(&f, a, b, c, d, input[0].wrapping_add(K1), 3);
+    d = round(&f, d, a, b, c, input[1].wrapping_add(K1), 7);
+    c = round(&f, c, d, a, b, input[2].wrapping_add(K1), 11);
+    b = round(&f, b, c, d, a, input[3].wrapping_add(K1), 19);
+    a = round(&f, a, b, c, d, input[4].wrapping_add(K1), 3);
+    d = round(&f, d, a, b, c, input[5].wrapping_add(K1), 7);
+    c = round(&f, c, d, a, b, input[6].wrapping_add(K1), 11);
+    b = round(&f, b, c, d, a, input[7].wrapping_add(K1), 19);
+
+    a = round(&g, a, b, c, d, input[1].wrapping_add(K2), 3);
+    d = round(&g, d, a, b, c, input[3].wrapping_add(K2), 5);
+    c = round(&g, c, d, a, b, input[5].wrapping_add(K2), 9);
+    b = round(&g, b, c, d, a, input[7].wrapping_add(K2), 13);
+    a = round(&g, a, b, c, d, input[0].wrapping_add(K2), 3);
+    d = round(&g, d, a, b, c, input[2].wrapping_add(K2), 5);
+    c = round(&g, c, d, a, b, input[4].wrapping_add(K2), 9);
+    b = round(&g, b, c, d, a, input[6].wrapping_add(K2), 13);
+
+    a = round(&h, a, b, c, d, input[3].wrapping_add(K3), 3);
+    d = round(&h, d, a, b, c, input[7].wrapping_add(K3), 9);
+    c = round(&h, c, d, a, b, input[2].wrapping_add(K3), 11);
+    b = round(&h, b, c, d, a, input[6].wrapping_add(K3), 15);
+    a = round(&h, a, b, c, d, input[1].wrapping_add(K3), 3);
+    d = round(&h, d, a, b, c, input[5].wrapping_add(K3), 9);
+    c = round(&h, c, d, a, b, input[0].wrapping_add(K3), 11);
+    b = round(&h, b, c, d, a, input[4].wrapping_add(K3), 15);
+
+    buf[0] = buf[0].wrapping_add(a);
+    buf[1] = buf[1].wrapping_add(b);
+    buf[2] = buf[2].wrapping_add(c);
+    buf[3] = buf[3].wrapping_add(d);
+}
+
+fn tea_transform(buf: &mut [u32; 4], input: &[u32; 4]) {
+    const DELTA: u32 = 0x9e3779b9;
+    let (mut b0, mut b1) = (buf[0], buf[1]);
+    let [a, b, c, d] = *input;
+    let mut sum: u32 = 0;
+    for _ in 0..16 {
+        sum = sum.wrapping_add(DELTA);
+        b0 = b0.wrapping_add(
+            (b1 << 4).wrapping_add(a) ^ b1.wrapping_add(sum) ^ (b1 >> 5).wrapping_add(b),
+        );
+        b1 = b1.wrapping_add(
+            (b0 << 4).wrapping_add(c) ^ b0.wrapping_add(sum) ^ (b0 >> 5).wrapping_add(d),
+        );
+    }
+    buf[0] = buf[0].wrapping_add(b0);
+    buf[1] = buf[1].wrapping_add(b1);
+}
@@ -0,0 +1,592 @@
+//! the second extended filesystem, as made by `mkfs.ext2`.
+//!
+//! Nodes only hold their inode number and read the inode again for every
+//! operation, so nothing has to be kept in sync between them. Changes are
+//! written through to the device right away and serialised by the lock
+//! around `bitmap::State`, reads don't take it.
+//!
+//! The filesystem is marked as in use while it is mounted writable, and gets
+//! back the state it had once it is unmounted.
+
+mod bitmap;
+mod dir;
+mod disk;
+mod file;
+mod hash;
+#[cfg(test)]
+mod tests;
+
+use crate::fs::block::BlockDeviceRef;
+use crate::fs::{DirEntry, Error, FileType, Fs, Inode, Result, Stat, VNode};
+use crate::util::locked::Locked;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use bitmap::State;
+use disk::{GroupCounts, GroupDesc, RawInode, Superblock};
+
+/// the most links an inode can have, which limits the subdirectories of a
+/// directory.
+const LINK_MAX: u16 = 32000;
+
+pub struct Ext2Fs {
+    fs: Arc<Ext2>,
+}
+
+impl Ext2Fs {
+    /// reads the superblock and group descriptors from `dev`. the
+    /// filesystem is read-only if `dev` can't be written or it uses features
+    /// we can't keep intact.
+    pub fn new(dev: BlockDeviceRef) -> Result<Self> {
+        Ext2::open(dev).map(|fs| Self { fs: Arc::new(fs) })
+    }
+}
+
+impl Fs for Ext2Fs {
+    fn root(&self) -> Result<VNode> {
+        Ok(Ext2::node(&self.fs, disk::ROOT_INO))
+    }
+
+    fn unmount(&self) -> Result<()> {
+        self.fs.unmount()
+    }
+
+    fn page_cached(&self) -> bool {
+        true
+    }
+}
+
+struct Ext2 {
+    dev: BlockDeviceRef,
+    sb: Superblock,
+    groups: Vec<GroupDesc>,
+    block_size: usize,
+    writable: bool,
+    /// `Superblock::state` when mounted, restored when unmounted.
+    mount_state: u16,
+    state: Locked<State>,
+    /// the nodes in use, so inodes are only freed once nothing uses them.
+    nodes: Locked<BTreeMap<u32, Weak<Ext2Node>>>,
+}
+
+impl Ext2 {
+    fn open(dev: BlockDeviceRef) -> Result<Self> {
+        let sector_size = dev.sector_size();
+        // the superblock lies in the first 2KiB whatever the block size.
+        let mut buf = vec![0; (disk::SUPERBLOCK_OFFSET + disk::SUPERBLOCK_SIZE).max(sector_size)];
+        dev.read_blocks(0, &mut buf)?;
+        let sb = Superblock::parse(&buf[disk::SUPERBLOCK_OFFSET..])?;
+        let block_size = sb.block_size();
+        if block_size % sector_size != 0 {
+            return Err(Error::InvalidFile(format!(
+                "ext2: blocks of {block_size} bytes on sectors of {sector_size} bytes"
+            )));
+        }
+        let mut fs = Self {
+            dev,
+            mount_state: sb.state,
+            state: Locked::new(State::new(&sb, Vec::new())),
+            sb,
+            groups: Vec::new(),
+            block_size,
+            writable: false,
+            nodes: Locked::new(BTreeMap::new()),
+        };
+
+        // the descriptors follow the block holding the superblock.
+        let group_count = fs.sb.group_count();
+        let desc_blocks = (group_count * disk::GROUP_DESC_SIZE).div_ceil(block_size);
+        let mut table = vec![0; desc_blocks * block_size];
+        for (i, block) in table.chunks_exact_mut(block_size).enumerate() {
+            fs.read_block(fs.desc_block(i as u32), block)?;
+        }
+        let descs = table.chunks_exact(disk::GROUP_DESC_SIZE).take(group_count);
+        fs.groups = descs.clone().map(GroupDesc::parse).collect();
+        let counts = descs.map(GroupCounts::parse).collect();
+        fs.state = Locked::new(State::new(&fs.sb, counts));
+
+        if fs.sb.feature_ro_compat & !disk::RO_COMPAT_SUPPORTED == 0 {
+            let in_use = fs.mount_state & !disk::STATE_VALID;
+            match fs.update_superblock(|sb| Superblock::store_state(sb, in_use)) {
+                Ok(()) => fs.writable = true,
+                Err(Error::ReadOnly(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(fs)
+    }
+
+    fn unmount(&self) -> Result<()> {
+        if !self.writable {
+            return Ok(());
+        }
+        self.modify(|_| {
+            let state = self.mount_state;
+            self.update_superblock(|sb| Superblock::store_state(sb, state))
+        })?;
+        self.dev.flush()
+    }
+
+    /// the node of inode `ino`, shared with everyone else using it.
+    fn node(fs: &Arc<Self>, ino: u32) -> VNode {
+        let mut nodes = fs.nodes.lock();
+        if let Some(node) = nodes.get(&ino).and_then(Weak::upgrade) {
+            return node;
+        }
+        let node = Arc::new(Ext2Node {
+            fs: fs.clone(),
+            ino,
+        });
+        nodes.insert(ino, Arc::downgrade(&node));
+        node
+    }
+
+    fn read_block(&self, block: u32, buf: &mut [u8]) -> Result<()> {
+        let lba = self.block_lba(block)?;
+        self.dev.read_blocks(lba, buf)
+    }
+
+    fn write_block(&self, block: u32, buf: &[u8]) -> Result<()> {
+        let lba = self.block_lba(block)?;
+        self.dev.write_blocks(lba, buf)
+    }
+
+    fn block_lba(&self, block: u32) -> Result<u64> {
+        if block >= self.sb.blocks_count {
+            return Err(Error::InvalidFile(format!(
+                "ext2: block {block} out of range"
+            )));
+        }
+        Ok(block as u64 * (self.block_size / self.dev.sector_size()) as u64)
+    }
+
+    /// changes the superblock in place, leaving the fields we don't know
+    /// about alone. the backups in other groups aren't updated.
+    fn update_superblock(&self, f: impl FnOnce(&mut [u8])) -> Result<()> {
+        let sector_size = self.dev.sector_size();
+        let first = disk::SUPERBLOCK_OFFSET / sector_size;
+        let end = (disk::SUPERBLOCK_OFFSET + disk::SUPERBLOCK_SIZE).div_ceil(sector_size);
+        let mut buf = vec![0; (end - first) * sector_size];
+        self.dev.read_blocks(first as u64, &mut buf)?;
+        let offset = disk::SUPERBLOCK_OFFSET - first * sector_size;
+        f(&mut buf[offset..offset + disk::SUPERBLOCK_SIZE]);
+        self.dev.write_blocks(first as u64, &buf)
+    }
+
+    /// the block holding inode `ino` and where in it the inode starts.
+    fn inode_location(&self, ino: u32) -> Result<(u32, usize)> {
+        if ino == 0 || ino > self.sb.inodes_count {
+            return Err(Error::InvalidFile(format!(
+                "ext2: inode {ino} out of range"
+            )));
+        }
+        let group = &self.groups[self.inode_group(ino)];
+        let index = ((ino - 1) % self.sb.inodes_per_group) as usize;
+        let offset = index * self.sb.inode_size as usize;
+        Ok((
+            group.inode_table + (offset / self.block_size) as u32,
+            offset % self.block_size,
+        ))
+    }
+
+    fn read_inode(&self, ino: u32) -> Result<RawInode> {
+        let (block, offset) = self.inode_location(ino)?;
+        let mut buf = vec![0; self.block_size];
+        self.read_block(block, &mut buf)?;
+        Ok(RawInode::parse(&buf[offset..]))
+    }
+
+    fn write_inode(&self, ino: u32, inode: &RawInode) -> Result<()> {
+        self.update_inode(ino, |raw| inode.store(raw))
+    }
+
+    /// writes `inode` over whatever inode `ino` held before.
+    fn init_inode(&self, ino: u32, inode: &RawInode) -> Result<()> {
+        self.update_inode(ino, |raw| {
+            raw.fill(0);
+            inode.store(raw);
+        })
+    }
+
+    fn update_inode(&self, ino: u32, f: impl FnOnce(&mut [u8])) -> Result<()> {
+        let (block, offset) = self.inode_location(ino)?;
+        let mut buf = vec![0; self.block_size];
+        self.read_block(block, &mut buf)?;
+        f(&mut buf[offset..offset + self.sb.inode_size as usize]);
+        self.write_block(block, &buf)
+    }
+
+    /// adds `delta` to the links of inode `ino`, returns how many are left.
+    fn add_links(&self, ino: u32, delta: i16) -> Result<u16> {
+        let mut inode = self.read_inode(ino)?;
+        inode.links_count = inode.links_count.saturating_add_signed(delta);
+        self.write_inode(ino, &inode)?;
+        Ok(inode.links_count)
+    }
+
+    /// frees inode `ino` which lost its last link, or leaves that to its
+    /// last node if it is still in use.
+    fn release(&self, state: &mut State, ino: u32) -> Result<()> {
+        // upgrading could drop the last reference right here, and dropping a
+        // node takes the lock we hold.
+        let in_use = self
+            .nodes
+            .lock()
+            .get(&ino)
+            .map_or(false, |node| node.strong_count() > 0);
+        if in_use {
+            state.orphans.insert(ino);
+            Ok(())
+        } else {
+            let mut inode = self.read_inode(ino)?;
+            self.discard(state, ino, &mut inode)
+        }
+    }
+
+    /// frees inode `ino` and everything it holds.
+    fn discard(&self, state: &mut State, ino: u32, inode: &mut RawInode) -> Result<()> {
+        if !inode.is_fast_symlink() {
+            self.free_data_from(state, inode, 0)?;
+        }
+        if inode.file_acl != 0 {
+            self.release_xattrs(state, inode)?;
+        }
+        // a cleared mode tells e2fsck the inode is unused.
+        self.init_inode(ino, &RawInode::default())?;
+        self.free_inode(state, ino, inode.ty() == FileType::Directory)
+    }
+
+    /// drops the reference of `inode` to its block of extended attributes,
+    /// which inodes with the same attributes share.
+    fn release_xattrs(&self, state: &mut State, inode: &mut RawInode) -> Result<()> {
+        const REFCOUNT: usize = 4;
+        let block = core::mem::take(&mut inode.file_acl);
+        let mut buf = vec![0; self.block_size];
+        self.read_block(block, &mut buf)?;
+        let refcount = disk::u32_at(&buf, REFCOUNT);
+        if refcount > 1 {
+            disk::set_u32(&mut buf, REFCOUNT, refcount - 1);
+            self.write_block(block, &buf)
+        } else {
+            self.free_block(state, inode, block)
+        }
+    }
+}
+
+struct Ext2Node {
+    fs: Arc<Ext2>,
+    ino: u32,
+}
+
+impl Ext2Node {
+    fn inode(&self) -> Result<RawInode> {
+        self.fs.read_inode(self.ino)
+    }
+
+    fn dir(&self, name: &str) -> Result<RawInode> {
+        let inode = self.inode()?;
+        if inode.ty() != FileType::Directory {
+            return Err(Error::NotDirectory(name.to_string()));
+        }
+        Ok(inode)
+    }
+
+    /// the inode of the child `name`.
+    fn child(&self, name: &str) -> Result<u32> {
+        self.fs
+            .lookup(&self.dir(name)?, name)?
+            .ok_or_else(|| Error::NoSuchPath(name.to_string()))
+    }
+
+    /// the inode number of `vnode`, if it belongs to this filesystem.
+    fn find(&self, vnode: &VNode) -> Option<u32> {
+        let ino = vnode.stat().ok()?.ino as u32;
+        let node = self.fs.nodes.lock().get(&ino)?.as_ptr();
+        // another filesystem may use the same inode number.
+        let same = core::ptr::eq(Arc::as_ptr(vnode).cast::<()>(), node.cast());
+        same.then_some(ino)
+    }
+
+    /// adds the new inode `name` with `mode`, whose content `init` sets up.
+    fn make(
+        &self,
+        name: &str,
+        mode: u16,
+        init: impl FnOnce(&mut State, u32, &mut RawInode) -> Result<()>,
+    ) -> Result<VNode> {
+        check_name(name)?;
+        let is_dir = mode & disk::S_IFMT == disk::S_IFDIR;
+        let ino = self.fs.modify(|state| {
+            let dir = self.dir(name)?;
+            if dir.links_count == 0 {
+                return Err(Error::NoSuchPath(name.to_string()));
+            }
+            if self.fs.lookup(&dir, name)?.is_some() {
+                return Err(Error::AlreadyExists(name.to_string()));
+            }
+            if is_dir && dir.links_count >= LINK_MAX {
+                return Err(Error::NoSpace(format!("ext2: too many links to '{name}'")));
+            }
+            let goal = self.fs.inode_group(self.ino);
+            let ino = self.fs.alloc_inode(state, goal, is_dir)?;
+            let mut inode = RawInode {
+                mode,
+                links_count: if is_dir { 2 } else { 1 },
+                ..Default::default()
+            };
+            let made = self
+                .fs
+                .init_inode(ino, &inode)
+                .and_then(|_| init(state, ino, &mut inode))
+                .and_then(|_| self.fs.add_entry(state, self.ino, name, ino, mode));
+            if let Err(e) = made {
+                self.fs.discard(state, ino, &mut inode)?;
+                return Err(e);
+            }
+            if is_dir {
+                self.fs.add_links(self.ino, 1)?;
+            }
+            Ok(ino)
+        })?;
+        // created outside the lock, dropping a node takes it.
+        Ok(Ext2::node(&self.fs, ino))
+    }
+}
+
+impl Drop for Ext2Node {
+    fn drop(&mut self) {
+        {
+            let mut nodes = self.fs.nodes.lock();
+            if nodes
+                .get(&self.ino)
+                .map_or(false, |node| node.strong_count() == 0)
+            {
+                nodes.remove(&self.ino);
+            }
+        }
+        if !self.fs.writable {
+            return;
+        }
+        // there is no one to report errors to, the inode stays allocated
+        // then.
+        let _ = self.fs.modify(|state| {
+            if !state.orphans.remove(&self.ino) {
+                return Ok(());
+            }
+            let mut inode = self.inode()?;
+            self.fs.discard(state, self.ino, &mut inode)
+        });
+    }
+}
+
+fn check_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || name == "."
+        || name == ".."
+        || name.contains('/')
+        || name.len() > disk::MAX_NAME_LEN
+    {
+        Err(Error::InvalidPath(name.to_string()))
+    } else {
+        Ok(())
+    }
+}
+
+impl Inode for Ext2Node {
+    fn stat(&self) -> Result<Stat> {
+        let inode = self.inode()?;
+        Ok(Stat {
+            ino: self.ino as u64,
+            ty: inode.ty(),
+            mode: inode.mode & 0o7777,
+            size: inode.size as usize,
+        })
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let inode = self.inode()?;
+        match inode.ty() {
+            FileType::Regular => self.fs.read_data(&inode, offset as u64, buf),
+            FileType::Directory => Err(Error::IsDirectory("read".to_string())),
+            _ => Err(Error::InvalidOperation("read".to_string())),
+        }
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        self.fs.modify(|state| {
+            let mut inode = self.inode()?;
+            match inode.ty() {
+                FileType::Regular => {
+                    self.fs
+                        .write_data(state, self.ino, &mut inode, offset as u64, buf)
+                }
+                FileType::Directory => Err(Error::IsDirectory("write".to_string())),
+                _ => Err(Error::InvalidOperation("write".to_string())),
+            }
+        })
+    }
+
+    fn truncate(&self, size: usize) -> Result<()> {
+        self.fs.modify(|state| {
+            let mut inode = self.inode()?;
+            match inode.ty() {
+                FileType::Regular => self.fs.truncate(state, self.ino, &mut inode, size as u64),
+                FileType::Directory => Err(Error::IsDirectory("truncate".to_string())),
+                _ => Err(Error::InvalidOperation("truncate".to_string())),
+            }
+        })
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        self.fs.readdir(&self.dir("readdir")?)
+    }
+
+    fn lookup(&self, name: &str) -> Result<VNode> {
+        Ok(Ext2::node(&self.fs, self.child(name)?))
+    }
+
+    fn readlink(&self) -> Result<String> {
+        let inode = self.inode()?;
+        if inode.ty() != FileType::Symlink {
+            return Err(Error::InvalidOperation("readlink".to_string()));
+        }
+        let len = inode.size as usize;
+        let target = if inode.is_fast_symlink() {
+            inode.block_bytes()[..len].to_vec()
+        } else {
+            let mut target = vec![0; len];
+            self.fs.read_data(&inode, 0, &mut target)?;
+            target
+        };
+        String::from_utf8(target).map_err(|_| Error::InvalidFile("symlink target".to_string()))
+    }
+
+    fn create(&self, name: &str, mode: u16) -> Result<VNode> {
+        self.make(name, disk::S_IFREG | mode & 0o7777, |_, _, _| Ok(()))
+    }
+
+    fn mkdir(&self, name: &str, mode: u16) -> Result<VNode> {
+        self.make(name, disk::S_IFDIR | mode & 0o7777, |state, ino, inode| {
+            let (block, _) = self.fs.block_map_alloc(state, ino, inode, 0)?;
+            self.fs.init_dir_block(block, ino, self.ino)?;
+            inode.size = self.fs.block_size as u64;
+            self.fs.write_inode(ino, inode)
+        })
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<VNode> {
+        let target = target.as_bytes();
+        if target.len() >= self.fs.block_size {
+            return Err(Error::InvalidPath(name.to_string()));
+        }
+        self.make(name, disk::S_IFLNK | 0o777, |state, ino, inode| {
+            if target.len() < disk::N_BLOCKS * 4 {
+                // short targets are kept in the block pointers.
+                let mut bytes = [0; disk::N_BLOCKS * 4];
+                bytes[..target.len()].copy_from_slice(target);
+                for (block, chunk) in inode.block.iter_mut().zip(bytes.chunks_exact(4)) {
+                    *block = disk::u32_at(chunk, 0);
+                }
+                inode.size = target.len() as u64;
+                self.fs.write_inode(ino, inode)
+            } else {
+                self.fs.write_data(state, ino, inode, 0, target).map(|_| ())
+            }
+        })
+    }
+
+    fn unlink(&self, name: &str) -> Result<()> {
+        self.fs.modify(|state| {
+            let ino = self.child(name)?;
+            if self.fs.read_inode(ino)?.ty() == FileType::Directory {
+                return Err(Error::IsDirectory(name.to_string()));
+            }
+            self.fs.remove_entry(self.ino, name)?;
+            if self.fs.add_links(ino, -1)? == 0 {
+                self.fs.release(state, ino)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn rmdir(&self, name: &str) -> Result<()> {
+        self.fs.modify(|state| {
+            let ino = self.child(name)?;
+            let inode = self.fs.read_inode(ino)?;
+            if inode.ty() != FileType::Directory {
+                return Err(Error::NotDirectory(name.to_string()));
+            }
+            if !self.fs.is_empty(&inode)? {
+                return Err(Error::NotEmpty(name.to_string()));
+            }
+            self.fs.remove_entry(self.ino, name)?;
+            // its `..` no longer links to us.
+            self.fs.add_links(self.ino, -1)?;
+            self.fs.add_links(ino, -(inode.links_count as i16))?;
+            self.fs.release(state, ino)
+        })
+    }
+
+    fn rename(&self, name: &str, new_dir: &VNode, new_name: &str) -> Result<()> {
+        check_name(new_name)?;
+        let new_dir_ino = self
+            .find(new_dir)
+            .ok_or_else(|| Error::CrossDevice(new_name.to_string()))?;
+        self.fs.modify(|state| {
+            let ino = self.child(name)?;
+            let inode = self.fs.read_inode(ino)?;
+            let is_dir = inode.ty() == FileType::Directory;
+            let target_dir = self.fs.read_inode(new_dir_ino)?;
+            if target_dir.ty() != FileType::Directory {
+                return Err(Error::NotDirectory(new_name.to_string()));
+            }
+            if is_dir {
+                // a directory can't be moved below itself.
+                let mut dir = new_dir_ino;
+                while dir != disk::ROOT_INO {
+                    if dir == ino {
+                        return Err(Error::InvalidPath(new_name.to_string()));
+                    }
+                    dir = self.fs.parent(&self.fs.read_inode(dir)?)?;
+                }
+            }
+
+            // the target may be replaced by an inode of the same kind.
+            if let Some(existing) = self.fs.lookup(&target_dir, new_name)? {
+                if existing == ino {
+                    return Ok(());
+                }
+                let existing_inode = self.fs.read_inode(existing)?;
+                match (is_dir, existing_inode.ty() == FileType::Directory) {
+                    (false, true) => return Err(Error::IsDirectory(new_name.to_string())),
+                    (true, false) => return Err(Error::NotDirectory(new_name.to_string())),
+                    (true, true) if !self.fs.is_empty(&existing_inode)? => {
+                        return Err(Error::NotEmpty(new_name.to_string()));
+                    }
+                    _ => {}
+                }
+                self.fs.remove_entry(new_dir_ino, new_name)?;
+                let links = if is_dir {
+                    self.fs.add_links(new_dir_ino, -1)?;
+                    existing_inode.links_count as i16
+                } else {
+                    1
+                };
+                if self.fs.add_links(existing, -links)? == 0 {
+                    self.fs.release(state, existing)?;
+                }
+            }
+
+            self.fs
+                .add_entry(state, new_dir_ino, new_name, ino, inode.mode)?;
+            self.fs.remove_entry(self.ino, name)?;
+            if is_dir && new_dir_ino != self.ino {
+                self.fs.set_parent(ino, new_dir_ino)?;
+                self.fs.add_links(self.ino, -1)?;
+                self.fs.add_links(new_dir_ino, 1)?;
+            }
+            Ok(())
+        })
+    }
+}
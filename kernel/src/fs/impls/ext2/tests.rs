@@ -0,0 +1,547 @@
+//! works on images made by `mkfs.ext2` from e2fsprogs and checks the ones we
+//! wrote with `e2fsck`. The tests are skipped if it isn't installed on the
+//! host.
+
+extern crate std;
+
+use super::dir::HtreeLookup;
+use super::*;
+use crate::fs::block::testing::{self, pattern, run, run_allowing};
+use std::fs as host;
+use std::os::unix::fs::{symlink, FileExt};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// a scratch directory with the root of the image to make.
+struct Scratch(testing::Scratch);
+
+impl Scratch {
+    /// `None` if e2fsprogs isn't installed.
+    fn new() -> Option<Self> {
+        let scratch = testing::Scratch::new("ext2", &["mkfs.ext2", "e2fsck", "debugfs"])?;
+        host::create_dir(scratch.path().join("root")).unwrap();
+        Some(Self(scratch))
+    }
+
+    /// the directory which ends up as the root of the image.
+    fn root(&self) -> PathBuf {
+        self.0.path().join("root")
+    }
+
+    fn image(&self) -> PathBuf {
+        self.0.image()
+    }
+
+    /// makes an image of `block_size` blocks from the root directory.
+    fn mkfs(&self, block_size: usize, blocks: usize) -> Ext2Fs {
+        self.make_image(block_size, blocks);
+        self.open()
+    }
+
+    fn make_image(&self, block_size: usize, blocks: usize) {
+        run(Command::new("mkfs.ext2")
+            .args(["-q", "-F", "-b", &block_size.to_string(), "-d"])
+            .arg(self.root())
+            .arg(self.image())
+            .arg(blocks.to_string()));
+    }
+
+    fn open(&self) -> Ext2Fs {
+        Ext2Fs::new(self.0.device(false)).unwrap()
+    }
+
+    fn open_writable(&self) -> Ext2Fs {
+        Ext2Fs::new(self.0.device(true)).unwrap()
+    }
+
+    /// unmounts `fs` and lets `e2fsck` look for anything it doesn't like.
+    fn fsck(&self, fs: Ext2Fs) {
+        fs.unmount().unwrap();
+        drop(fs);
+        run(Command::new("e2fsck").args(["-f", "-n"]).arg(self.image()));
+    }
+
+    /// the content of `path` in the image, as read by `debugfs`.
+    fn debugfs_cat(&self, path: &str) -> Vec<u8> {
+        let output = Command::new("debugfs")
+            .arg("-R")
+            .arg(format!("cat {path}"))
+            .arg(self.image())
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        output.stdout
+    }
+}
+
+fn write(path: &Path, content: &[u8]) {
+    host::write(path, content).unwrap();
+}
+
+fn walk(fs: &Ext2Fs, path: &str) -> VNode {
+    path.split('/')
+        .filter(|name| !name.is_empty())
+        .fold(fs.root().unwrap(), |dir, name| dir.lookup(name).unwrap())
+}
+
+fn read_all(node: &VNode) -> Vec<u8> {
+    let mut buf = vec![0; node.stat().unwrap().size];
+    assert_eq!(node.read_at(0, &mut buf).unwrap(), buf.len());
+    buf
+}
+
+fn names(dir: &VNode) -> Vec<String> {
+    let mut names: Vec<_> = dir.readdir().unwrap().into_iter().map(|e| e.name).collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn reads_files_and_directories() {
+    for block_size in [1024, 4096] {
+        let Some(scratch) = Scratch::new() else {
+            return;
+        };
+        let root = scratch.root();
+        write(&root.join("hello"), b"hello world\n");
+        host::create_dir_all(root.join("a/b")).unwrap();
+        write(&root.join("a/b/c"), b"nested");
+        host::set_permissions(
+            root.join("hello"),
+            std::os::unix::fs::PermissionsExt::from_mode(0o640),
+        )
+        .unwrap();
+        let fs = scratch.mkfs(block_size, 2048);
+
+        let root = fs.root().unwrap();
+        assert_eq!(root.stat().unwrap().ino, disk::ROOT_INO as u64);
+        assert_eq!(names(&root), ["a", "hello", "lost+found"]);
+        let hello = root.lookup("hello").unwrap();
+        let stat = hello.stat().unwrap();
+        assert_eq!(
+            (stat.ty, stat.mode, stat.size),
+            (FileType::Regular, 0o640, 12)
+        );
+        let mut buf = [0; 32];
+        assert_eq!(hello.read_at(6, &mut buf).unwrap(), 6);
+        assert_eq!(&buf[..6], b"world\n");
+        assert_eq!(hello.read_at(12, &mut buf).unwrap(), 0);
+
+        let dir = walk(&fs, "a/b");
+        assert_eq!(dir.stat().unwrap().ty, FileType::Directory);
+        let entries = dir.readdir().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            (entries[0].name.as_str(), entries[0].ty),
+            ("c", FileType::Regular)
+        );
+        assert_eq!(read_all(&walk(&fs, "a/b/c")), b"nested");
+    }
+}
+
+#[test]
+fn missing_names_and_wrong_types_fail() {
+    let Some(scratch) = Scratch::new() else {
+        return;
+    };
+    write(&scratch.root().join("file"), b"x");
+    let fs = scratch.mkfs(1024, 1024);
+    let root = fs.root().unwrap();
+    assert!(matches!(root.lookup("nope"), Err(Error::NoSuchPath(_))));
+    let file = root.lookup("file").unwrap();
+    assert!(matches!(file.lookup("x"), Err(Error::NotDirectory(_))));
+    assert!(matches!(file.readdir(), Err(Error::NotDirectory(_))));
+    assert!(matches!(
+        root.read_at(0, &mut [0; 4]),
+        Err(Error::IsDirectory(_))
+    ));
+    assert!(matches!(file.write_at(0, b"y"), Err(Error::ReadOnly(_))));
+}
+
+#[test]
+fn reads_through_indirect_blocks() {
+    let Some(scratch) = Scratch::new() else {
+        return;
+    };
+    // with 1KiB blocks, 12 are direct, 256 single and the rest double
+    // indirect.
+    let content = pattern(600 * 1024 + 123);
+    write(&scratch.root().join("big"), &content);
+    let fs = scratch.mkfs(1024, 4096);
+
+    let big = walk(&fs, "big");
+    assert_eq!(read_all(&big), content);
+    // a read across the end of the single indirect blocks.
+    let offset = (12 + 256) * 1024 - 100;
+    let mut buf = [0; 200];
+    assert_eq!(big.read_at(offset, &mut buf).unwrap(), 200);
+    assert_eq!(buf[..], content[offset..offset + 200]);
+}
+
+#[test]
+fn holes_read_as_zeros() {
+    let Some(scratch) = Scratch::new() else {
+        return;
+    };
+    let path = scratch.root().join("sparse");
+    let file = host::File::create(path).unwrap();
+    file.write_all_at(b"start", 0).unwrap();
+    file.write_all_at(b"end", 300 * 1024).unwrap();
+    drop(file);
+    let fs = scratch.mkfs(1024, 2048);
+
+    let sparse = walk(&fs, "sparse");
+    let content = read_all(&sparse);
+    assert_eq!(content.len(), 300 * 1024 + 3);
+    assert_eq!(&content[..5], b"start");
+    assert!(content[5..300 * 1024].iter().all(|&b| b == 0));
+    assert_eq!(&content[300 * 1024..], b"end");
+}
+
+#[test]
+fn reads_fast_and_slow_symlinks() {
+    let Some(scratch) = Scratch::new() else {
+        return;
+    };
+    let long = "x/".repeat(100) + "target";
+    symlink("short/target", scratch.root().join("fast")).unwrap();
+    symlink(&long, scratch.root().join("slow")).unwrap();
+    let fs = scratch.mkfs(1024, 1024);
+
+    let root = fs.root().unwrap();
+    let fast = root.lookup("fast").unwrap();
+    assert_eq!(fast.stat().unwrap().ty, FileType::Symlink);
+    assert_eq!(fast.readlink().unwrap(), "short/target");
+    assert_eq!(root.lookup("slow").unwrap().readlink().unwrap(), long);
+    assert!(matches!(root.readlink(), Err(Error::InvalidOperation(_))));
+    let types: Vec<_> = root.readdir().unwrap().into_iter().map(|e| e.ty).collect();
+    assert!(types.iter().filter(|&&ty| ty == FileType::Symlink).count() == 2);
+}
+
+#[test]
+fn looks_up_names_in_hashed_directories() {
+    let Some(scratch) = Scratch::new() else {
+        return;
+    };
+    let dir = scratch.root().join("many");
+    host::create_dir(&dir).unwrap();
+    // names this long only fit a few to a block, so the index needs a
+    // second level.
+    let name = |i| format!("{}-{i}", "n".repeat(200));
+    let count = 3000;
+    for i in 0..count {
+        write(&dir.join(name(i)), &[]);
+    }
+    scratch.mkfs(1024, 32768);
+    // e2fsck builds the indexes mkfs leaves out, and exits with 1 as it
+    // changed the filesystem.
+    run_allowing(
+        Command::new("e2fsck")
+            .args(["-f", "-y", "-D"])
+            .arg(scratch.image()),
+        1,
+    );
+    let fs = scratch.open();
+
+    let ino = fs
+        .fs
+        .lookup(&fs.fs.read_inode(disk::ROOT_INO).unwrap(), "many");
+    let inode = fs.fs.read_inode(ino.unwrap().unwrap()).unwrap();
+    assert!(inode.flags & disk::INODE_INDEX_FL != 0);
+    let mut block = vec![0; 1024];
+    fs.fs.read_file_block(&inode, 0, &mut block).unwrap();
+    assert_eq!(block[30], 1, "expected an index with two levels");
+    let many = walk(&fs, "many");
+    for i in 0..count {
+        let name = name(i);
+        assert!(
+            matches!(
+                fs.fs.htree_lookup(&inode, name.as_bytes()),
+                Ok(HtreeLookup::Found(_))
+            ),
+            "{name} not found through the index"
+        );
+        many.lookup(&name).unwrap();
+    }
+    assert!(matches!(
+        fs.fs.htree_lookup(&inode, b"missing"),
+        Ok(HtreeLookup::NotFound)
+    ));
+    assert!(matches!(many.lookup("missing"), Err(Error::NoSuchPath(_))));
+    assert_eq!(many.readdir().unwrap().len(), count);
+}
+
+#[test]
+fn writes_files_through_indirect_blocks() {
+    let Some(scratch) = Scratch::new() else {
+        return;
+    };
+    scratch.make_image(1024, 4096);
+    let fs = scratch.open_writable();
+    let root = fs.root().unwrap();
+    let big = root.create("big", 0o644).unwrap();
+    let content = pattern(600 * 1024 + 123);
+    // in odd pieces, so writes start and end inside blocks.
+    for (i, chunk) in content.chunks(3000).enumerate() {
+        assert_eq!(big.write_at(i * 3000, chunk).unwrap(), chunk.len());
+    }
+    assert_eq!(read_all(&big), content);
+    let sparse = root.create("sparse", 0o600).unwrap();
+    sparse.write_at(300 * 1024, b"end").unwrap();
+    assert!(read_all(&sparse)[..300 * 1024].iter().all(|&b| b == 0));
+    drop((big, sparse, root));
+
+    scratch.fsck(fs);
+    assert_eq!(scratch.debugfs_cat("big"), content);
+}
+
+#[test]
+fn truncates_files() {
+    let Some(scratch) = Scratch::new() else {
+        return;
+    };
+    scratch.make_image(1024, 4096);
+    let fs = scratch.open_writable();
+    let root = fs.root().unwrap();
+    let file = root.create("file", 0o644).unwrap();
+    let content = pattern(400 * 1024);
+    file.write_at(0, &content).unwrap();
+    let free = fs.fs.state.lock().free_blocks;
+
+    file.truncate(20 * 1024 + 10).unwrap();
+    assert!(fs.fs.state.lock().free_blocks > free);
+    // what was cut off reads as zeros once the file grows again.
+    file.truncate(30 * 1024).unwrap();
+    let read = read_all(&file);
+    assert_eq!(read[..20 * 1024 + 10], content[..20 * 1024 + 10]);
+    assert!(read[20 * 1024 + 10..].iter().all(|&b| b == 0));
+    file.truncate(0).unwrap();
+    assert_eq!(file.stat().unwrap().size, 0);
+    // the data, the single indirect block and two for the double ones.
+    assert_eq!(fs.fs.state.lock().free_blocks, free + 400 + 3);
+    drop((file, root));
+
+    scratch.fsck(fs);
+}
+
+#[test]
+fn makes_and_removes_directories() {
+    let Some(scratch) = Scratch::new() else {
+        return;
+    };
+    scratch.make_image(1024, 4096);
+    let fs = scratch.open_writable();
+    let root = fs.root().unwrap();
+    let dir = root.mkdir("dir", 0o755).unwrap();
+    let sub = dir.mkdir("sub", 0o700).unwrap();
+    assert_eq!(sub.stat().unwrap().mode, 0o700);
+    assert!(matches!(
+        root.mkdir("dir", 0o755),
+        Err(Error::AlreadyExists(_))
+    ));
+    assert!(matches!(
+        root.create("a/b", 0o644),
+        Err(Error::InvalidPath(_))
+    ));
+    // enough entries to take several blocks.
+    let name = |i| format!("{}-{i}", "f".repeat(100));
+    for i in 0..100 {
+        dir.create(&name(i), 0o644).unwrap();
+    }
+    assert!(dir.stat().unwrap().size > 1024);
+    assert!(matches!(root.rmdir("dir"), Err(Error::NotEmpty(_))));
+    assert!(matches!(dir.unlink("sub"), Err(Error::IsDirectory(_))));
+    for i in (0..100).step_by(2) {
+        dir.unlink(&name(i)).unwrap();
+    }
+    // the gaps are reused.
+    let size = dir.stat().unwrap().size;
+    for i in 0..20 {
+        dir.create(&format!("new-{i}"), 0o644).unwrap();
+    }
+    assert_eq!(dir.stat().unwrap().size, size);
+    assert_eq!(names(&dir).len(), 50 + 20 + 1);
+    dir.rmdir("sub").unwrap();
+    assert!(matches!(dir.lookup("sub"), Err(Error::NoSuchPath(_))));
+    drop((sub, dir, root));
+
+    scratch.fsck(fs);
+}
+
+#[test]
+fn renames_across_directories() {
+    let Some(scratch) = Scratch::new() else {
+        return;
+    };
+    scratch.make_image(1024, 4096);
+    let fs = scratch.open_writable();
+    let root = fs.root().unwrap();
+    let a = root.mkdir("a", 0o755).unwrap();
+    let b = root.mkdir("b", 0o755).unwrap();
+    let moved = a.mkdir("moved", 0o755).unwrap();
+    moved
+        .create("inside", 0o644)
+        .unwrap()
+        .write_at(0, b"x")
+        .unwrap();
+    a.create("file", 0o644)
+        .unwrap()
+        .write_at(0, b"new")
+        .unwrap();
+    b.create("file", 0o644)
+        .unwrap()
+        .write_at(0, b"old")
+        .unwrap();
+
+    a.rename("moved", &b, "here").unwrap();
+    assert!(matches!(a.lookup("moved"), Err(Error::NoSuchPath(_))));
+    assert_eq!(read_all(&walk(&fs, "b/here/inside")), b"x");
+    assert_eq!(
+        fs.fs
+            .parent(&fs.fs.read_inode(moved.stat().unwrap().ino as u32).unwrap())
+            .unwrap(),
+        b.stat().unwrap().ino as u32
+    );
+    // the old file is replaced.
+    a.rename("file", &b, "file").unwrap();
+    assert_eq!(read_all(&walk(&fs, "b/file")), b"new");
+    assert!(matches!(
+        b.rename("here", &moved, "loop"),
+        Err(Error::InvalidPath(_))
+    ));
+    assert!(matches!(
+        b.rename("file", &b, "here"),
+        Err(Error::IsDirectory(_))
+    ));
+    drop((moved, a, b, root));
+
+    scratch.fsck(fs);
+}
+
+#[test]
+fn writes_fast_and_slow_symlinks() {
+    let Some(scratch) = Scratch::new() else {
+        return;
+    };
+    scratch.make_image(1024, 1024);
+    let fs = scratch.open_writable();
+    let root = fs.root().unwrap();
+    let long = "x/".repeat(100) + "target";
+    root.symlink("fast", "short/target").unwrap();
+    root.symlink("slow", &long).unwrap();
+    assert_eq!(
+        root.lookup("fast").unwrap().readlink().unwrap(),
+        "short/target"
+    );
+    assert_eq!(root.lookup("slow").unwrap().readlink().unwrap(), long);
+    root.symlink("gone", &long).unwrap();
+    root.unlink("gone").unwrap();
+    drop(root);
+
+    scratch.fsck(fs);
+}
+
+#[test]
+fn running_out_of_space_fails() {
+    let Some(scratch) = Scratch::new() else {
+        return;
+    };
+    scratch.make_image(1024, 1024);
+    let fs = scratch.open_writable();
+    let root = fs.root().unwrap();
+    let file = root.create("file", 0o644).unwrap();
+    let chunk = pattern(64 * 1024);
+    let mut offset = 0;
+    let err = loop {
+        match file.write_at(offset, &chunk) {
+            Ok(written) => offset += written,
+            Err(e) => break e,
+        }
+    };
+    assert!(matches!(err, Error::NoSpace(_)));
+    assert_eq!(file.stat().unwrap().size, offset);
+    assert_eq!(fs.fs.state.lock().free_blocks, 0);
+    // freeing the blocks again makes room.
+    file.truncate(0).unwrap();
+    root.create("more", 0o644)
+        .unwrap()
+        .write_at(0, &chunk)
+        .unwrap();
+    drop((file, root));
+
+    scratch.fsck(fs);
+}
+
+#[test]
+fn adds_names_to_hashed_directories() {
+    let Some(scratch) = Scratch::new() else {
+        return;
+    };
+    let dir = scratch.root().join("many");
+    host::create_dir(&dir).unwrap();
+    let name = |i| format!("{}-{i}", "n".repeat(200));
+    for i in 0..500 {
+        write(&dir.join(name(i)), &[]);
+    }
+    scratch.make_image(1024, 8192);
+    run_allowing(
+        Command::new("e2fsck")
+            .args(["-f", "-y", "-D"])
+            .arg(scratch.image()),
+        1,
+    );
+    let fs = scratch.open_writable();
+    let many = walk(&fs, "many");
+    many.create("added", 0o644).unwrap();
+    many.unlink(&name(7)).unwrap();
+    assert_eq!(names(&many).len(), 500);
+    many.lookup("added").unwrap();
+    many.lookup(&name(499)).unwrap();
+    drop(many);
+
+    scratch.fsck(fs);
+}
+
+#[test]
+fn unlinked_files_live_until_dropped() {
+    let Some(scratch) = Scratch::new() else {
+        return;
+    };
+    scratch.make_image(1024, 1024);
+    let fs = scratch.open_writable();
+    let root = fs.root().unwrap();
+    let file = root.create("file", 0o644).unwrap();
+    file.write_at(0, &pattern(50 * 1024)).unwrap();
+    let free = fs.fs.state.lock().free_blocks;
+    root.unlink("file").unwrap();
+    assert_eq!(read_all(&file), pattern(50 * 1024));
+    assert_eq!(fs.fs.state.lock().free_blocks, free);
+    drop(file);
+    assert!(fs.fs.state.lock().free_blocks > free);
+    drop(root);
+
+    scratch.fsck(fs);
+}
+
+#[test]
+fn marks_the_filesystem_in_use_while_mounted() {
+    let Some(scratch) = Scratch::new() else {
+        return;
+    };
+    scratch.make_image(1024, 1024);
+    let state = || {
+        let mut buf = vec![0; disk::SUPERBLOCK_SIZE];
+        let file = host::File::open(scratch.image()).unwrap();
+        file.read_exact_at(&mut buf, disk::SUPERBLOCK_OFFSET as u64)
+            .unwrap();
+        Superblock::parse(&buf).unwrap().state
+    };
+    assert_eq!(state(), disk::STATE_VALID);
+    let fs = scratch.open_writable();
+    assert_eq!(state() & disk::STATE_VALID, 0);
+    fs.unmount().unwrap();
+    assert_eq!(state(), disk::STATE_VALID);
+    // read-only mounts leave it alone.
+    let _fs = scratch.open();
+    assert_eq!(state(), disk::STATE_VALID);
+}
@@ -0,0 +1,419 @@
+//! directories, arrays of 32 byte entries where a long name is stored in
+//! entries of its own before the short one.
+
+use super::disk::{self, Dirent, DIRENT_SIZE};
+use super::{Entry, Fat, State};
+use crate::fs::{Error, Result};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// FAT limits directories to this many entries.
+const MAX_DIR_ENTRIES: usize = 65536;
+
+/// characters allowed in short names besides letters and digits.
+const SHORT_NAME_CHARS: &[u8] = b"!#$%&'()-@^_`{}~";
+
+/// A used entry found in a directory.
+pub(super) struct Found {
+    pub name: String,
+    /// where the short entry is.
+    pub pos: u64,
+    /// where the long name entries and the short one are.
+    slots: Vec<u64>,
+    pub dirent: Dirent,
+}
+
+impl Found {
+    fn entry(self) -> Entry {
+        Entry {
+            pos: Some(self.pos),
+            dirent: self.dirent,
+            removed: false,
+        }
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+            || self.dirent.display_name().eq_ignore_ascii_case(name)
+    }
+}
+
+/// The content of a directory and where it is on the disk.
+struct DirData {
+    bytes: Vec<u8>,
+    /// where each cluster, or the fixed root directory, starts.
+    extents: Vec<u64>,
+    extent_len: usize,
+}
+
+impl DirData {
+    fn slots(&self) -> usize {
+        self.bytes.len() / DIRENT_SIZE
+    }
+
+    fn slot(&self, index: usize) -> &[u8] {
+        &self.bytes[index * DIRENT_SIZE..(index + 1) * DIRENT_SIZE]
+    }
+
+    fn pos(&self, index: usize) -> u64 {
+        let offset = index * DIRENT_SIZE;
+        self.extents[offset / self.extent_len] + (offset % self.extent_len) as u64
+    }
+}
+
+/// the pieces of a long name collected so far.
+struct LongName {
+    checksum: u8,
+    pieces: Vec<Vec<u16>>,
+    /// the order of the entry which comes next, zero once complete.
+    next: u8,
+    slots: Vec<u64>,
+}
+
+impl Fat {
+    fn read_dir_data(&self, state: &State, dir: &Dirent) -> Result<DirData> {
+        let (extents, extent_len) = if dir.first_cluster == 0 {
+            // the fixed root directory of FAT12 and FAT16.
+            let start = self.bpb.root_start() * self.bpb.bytes_per_sector as u64;
+            let len = self.bpb.root_sectors() as usize * self.bpb.bytes_per_sector;
+            (vec![start], len)
+        } else {
+            let chain = state.table.chain(dir.first_cluster)?;
+            let extents = chain.iter().map(|&c| self.cluster_pos(c)).collect();
+            (extents, self.cluster_size)
+        };
+        let mut bytes = vec![0; extents.len() * extent_len];
+        for (&start, chunk) in extents.iter().zip(bytes.chunks_exact_mut(extent_len)) {
+            self.read_bytes(start, chunk)?;
+        }
+        Ok(DirData {
+            bytes,
+            extents,
+            extent_len,
+        })
+    }
+
+    /// the used entries of a directory, with `.` and `..` left out.
+    fn scan(&self, data: &DirData) -> Vec<Found> {
+        let mut found = Vec::new();
+        let mut long: Option<LongName> = None;
+        for index in 0..data.slots() {
+            let slot = data.slot(index);
+            match slot[0] {
+                disk::DIRENT_END => break,
+                disk::DIRENT_FREE => {
+                    long = None;
+                    continue;
+                }
+                _ => {}
+            }
+            let attr = slot[11];
+            if attr & 0x3f == disk::ATTR_LONG_NAME {
+                let order = slot[0] & disk::LFN_ORDER;
+                let piece = disk::lfn_chars(slot).collect();
+                if slot[0] & disk::LFN_LAST != 0 {
+                    long = Some(LongName {
+                        checksum: slot[13],
+                        pieces: vec![Vec::new(); order as usize],
+                        next: order,
+                        slots: Vec::new(),
+                    });
+                }
+                // a piece out of order orphans the whole name.
+                long = long
+                    .filter(|long| order != 0 && order == long.next && slot[13] == long.checksum);
+                if let Some(long) = &mut long {
+                    long.pieces[order as usize - 1] = piece;
+                    long.next -= 1;
+                    long.slots.push(data.pos(index));
+                }
+                continue;
+            }
+            let long = long.take();
+            if attr & disk::ATTR_VOLUME_ID != 0 || slot[0] == b'.' {
+                continue;
+            }
+            let dirent = Dirent::parse(slot);
+            let (name, mut slots) = match long {
+                Some(long)
+                    if long.next == 0 && long.checksum == disk::lfn_checksum(&dirent.name) =>
+                {
+                    (String::from_utf16_lossy(&long.pieces.concat()), long.slots)
+                }
+                _ => (dirent.display_name(), Vec::new()),
+            };
+            slots.push(data.pos(index));
+            found.push(Found {
+                name,
+                pos: data.pos(index),
+                slots,
+                dirent,
+            });
+        }
+        found
+    }
+
+    pub(super) fn read_dir(&self, state: &State, dir: &Dirent) -> Result<Vec<Found>> {
+        Ok(self.scan(&self.read_dir_data(state, dir)?))
+    }
+
+    pub(super) fn find_entry(
+        &self,
+        state: &State,
+        dir: &Dirent,
+        name: &str,
+    ) -> Result<Option<Entry>> {
+        Ok(self
+            .read_dir(state, dir)?
+            .into_iter()
+            .find(|found| found.matches(name))
+            .map(Found::entry))
+    }
+
+    /// adds `dirent` as `name` to a directory, with a long name if the name
+    /// doesn't fit in a short one.
+    pub(super) fn add_entry(
+        &self,
+        state: &mut State,
+        dir: &Dirent,
+        name: &str,
+        mut dirent: Dirent,
+    ) -> Result<Entry> {
+        let mut data = self.read_dir_data(state, dir)?;
+        let taken: Vec<[u8; 11]> = (0..data.slots())
+            .map(|index| data.slot(index))
+            .take_while(|slot| slot[0] != disk::DIRENT_END)
+            .filter(|slot| slot[0] != disk::DIRENT_FREE && slot[11] & 0x3f != disk::ATTR_LONG_NAME)
+            .map(|slot| Dirent::parse(slot).name)
+            .collect();
+        let long = match short_name(name).filter(|(short, _)| !taken.contains(short)) {
+            Some((short, case)) => {
+                dirent.name = short;
+                dirent.case = case;
+                Vec::new()
+            }
+            None => {
+                dirent.name = numbered_name(name, &taken)?;
+                dirent.case = 0;
+                disk::lfn_entries(name, disk::lfn_checksum(&dirent.name))
+            }
+        };
+
+        let needed = long.len() + 1;
+        let start = match self.free_slots(&data, needed) {
+            Some(start) => start,
+            None => {
+                self.grow_dir(state, dir, &mut data, needed)?;
+                self.free_slots(&data, needed).unwrap()
+            }
+        };
+        for (i, slot) in long.iter().enumerate() {
+            self.write_bytes(data.pos(start + i), slot)?;
+        }
+        let mut short = [0; DIRENT_SIZE];
+        dirent.store(&mut short);
+        let pos = data.pos(start + long.len());
+        self.write_bytes(pos, &short)?;
+        Ok(Entry {
+            pos: Some(pos),
+            dirent,
+            removed: false,
+        })
+    }
+
+    /// the first of `count` unused entries in a row.
+    fn free_slots(&self, data: &DirData, count: usize) -> Option<usize> {
+        let mut run = 0;
+        for index in 0..data.slots() {
+            match data.slot(index)[0] {
+                // everything after the end is unused too.
+                disk::DIRENT_END if data.slots() - index >= count - run => {
+                    return Some(index - run);
+                }
+                disk::DIRENT_END => return None,
+                disk::DIRENT_FREE => run += 1,
+                _ => run = 0,
+            }
+            if run == count {
+                return Some(index + 1 - run);
+            }
+        }
+        None
+    }
+
+    /// adds zeroed clusters to a directory until `count` entries fit at its
+    /// end.
+    fn grow_dir(
+        &self,
+        state: &mut State,
+        dir: &Dirent,
+        data: &mut DirData,
+        count: usize,
+    ) -> Result<()> {
+        let full = || Error::NoSpace("fat: directory full".to_string());
+        if dir.first_cluster == 0 {
+            return Err(full());
+        }
+        let per_cluster = self.cluster_size / DIRENT_SIZE;
+        let clusters = count.div_ceil(per_cluster);
+        if data.slots() + clusters * per_cluster > MAX_DIR_ENTRIES {
+            return Err(full());
+        }
+        let last = state
+            .table
+            .chain(dir.first_cluster)?
+            .last()
+            .copied()
+            .unwrap_or(0);
+        for cluster in self.alloc_clusters(state, last, clusters, true)? {
+            data.extents.push(self.cluster_pos(cluster));
+            data.bytes.resize(data.bytes.len() + self.cluster_size, 0);
+        }
+        Ok(())
+    }
+
+    /// marks the entries of `entry` in a directory as unused.
+    pub(super) fn remove_entry(&self, state: &State, dir: &Dirent, entry: &Entry) -> Result<()> {
+        let found = self
+            .read_dir(state, dir)?
+            .into_iter()
+            .find(|found| Some(found.pos) == entry.pos)
+            .ok_or_else(|| Error::NoSuchPath("fat: entry moved".to_string()))?;
+        for pos in found.slots {
+            self.write_bytes(pos, &[disk::DIRENT_FREE])?;
+        }
+        Ok(())
+    }
+
+    /// the first cluster of a new directory below `parent`.
+    pub(super) fn init_dir(&self, state: &mut State, parent: u32) -> Result<u32> {
+        let cluster = self.alloc_clusters(state, 0, 1, true)?[0];
+        let mut block = vec![0; self.cluster_size];
+        let mut dot = Dirent {
+            name: *b".          ",
+            attr: disk::ATTR_DIRECTORY,
+            case: 0,
+            first_cluster: cluster,
+            size: 0,
+        };
+        dot.store(&mut block[..DIRENT_SIZE]);
+        dot.name = *b"..         ";
+        dot.first_cluster = self.parent_link(parent);
+        dot.store(&mut block[DIRENT_SIZE..]);
+        self.write_cluster(cluster, &block)?;
+        Ok(cluster)
+    }
+
+    /// what `..` says for a directory in `parent`, zero for the root.
+    fn parent_link(&self, parent: u32) -> u32 {
+        if parent == self.bpb.root_cluster {
+            0
+        } else {
+            parent
+        }
+    }
+
+    /// the first cluster of the directory containing the one starting at
+    /// `cluster`, zero for the root.
+    pub(super) fn parent(&self, state: &State, cluster: u32) -> Result<u32> {
+        state.table.chain(cluster)?;
+        let mut slot = [0; DIRENT_SIZE];
+        self.read_bytes(self.cluster_pos(cluster) + DIRENT_SIZE as u64, &mut slot)?;
+        Ok(Dirent::parse(&slot).first_cluster)
+    }
+
+    /// points `..` of the directory starting at `cluster` to `parent`.
+    pub(super) fn set_parent(&self, cluster: u32, parent: u32) -> Result<()> {
+        let pos = self.cluster_pos(cluster) + DIRENT_SIZE as u64;
+        let mut slot = [0; DIRENT_SIZE];
+        self.read_bytes(pos, &mut slot)?;
+        let mut dotdot = Dirent::parse(&slot);
+        dotdot.first_cluster = self.parent_link(parent);
+        dotdot.store_data(&mut slot);
+        self.write_bytes(pos, &slot)
+    }
+}
+
+pub(super) fn check_name(name: &str) -> Result<()> {
+    let invalid = name.is_empty()
+        || name == "."
+        || name == ".."
+        || name.ends_with(['.', ' '])
+        || name.encode_utf16().count() > disk::MAX_NAME_LEN
+        || name.chars().any(|c| c < ' ' || "\"*/:<>?\\|".contains(c));
+    if invalid {
+        Err(Error::InvalidPath(name.to_string()))
+    } else {
+        Ok(())
+    }
+}
+
+fn short_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || SHORT_NAME_CHARS.contains(&c)
+}
+
+/// `name` as a short name, if it fits one without a long name. parts in
+/// lowercase are marked in the case bits.
+fn short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, ext) = match name.rsplit_once('.') {
+        Some((base, ext)) => (base, ext),
+        None => (name, ""),
+    };
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 {
+        return None;
+    }
+    let mut short = [b' '; 11];
+    let mut case = 0;
+    for (part, start, lower) in [
+        (base, 0, disk::CASE_LOWER_BASE),
+        (ext, 8, disk::CASE_LOWER_EXT),
+    ] {
+        let bytes = part.as_bytes();
+        if !bytes.iter().all(|&c| short_char(c)) {
+            return None;
+        }
+        match (
+            bytes.iter().any(u8::is_ascii_lowercase),
+            bytes.iter().any(u8::is_ascii_uppercase),
+        ) {
+            (true, true) => return None,
+            (true, false) => case |= lower,
+            _ => {}
+        }
+        short[start..start + bytes.len()].copy_from_slice(&part.to_ascii_uppercase().into_bytes());
+    }
+    Some((short, case))
+}
+
+/// a short name like `LONGNA~1.TXT` for the long `name`, which isn't one
+/// of the `taken` ones.
+fn numbered_name(name: &str, taken: &[[u8; 11]]) -> Result<[u8; 11]> {
+    let clean = |part: &str| -> Vec<u8> {
+        part.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| match u8::try_from(c.to_ascii_uppercase()) {
+                Ok(c) if short_char(c) => c,
+                _ => b'_',
+            })
+            .collect()
+    };
+    let trimmed = name.trim_start_matches('.');
+    let (base, ext) = match trimmed.rsplit_once('.') {
+        Some((base, ext)) => (clean(base), clean(ext)),
+        None => (clean(trimmed), Vec::new()),
+    };
+    let mut short = [b' '; 11];
+    let ext = &ext[..ext.len().min(3)];
+    short[8..8 + ext.len()].copy_from_slice(ext);
+    for n in 1..1_000_000 {
+        let tail = format!("~{n}");
+        let keep = base.len().min(8 - tail.len());
+        short[..8].fill(b' ');
+        short[..keep].copy_from_slice(&base[..keep]);
+        short[keep..keep + tail.len()].copy_from_slice(tail.as_bytes());
+        if !taken.contains(&short) {
+            return Ok(short);
+        }
+    }
+    Err(Error::NoSpace("fat: no short name left".to_string()))
+}
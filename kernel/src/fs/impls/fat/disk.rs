@@ -0,0 +1,303 @@
+//! on-disk structures, all little endian.
+
+use crate::fs::{Error, Result};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+pub const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+
+/// FAT12 volumes have fewer clusters than this, FAT16 ones at least as many.
+pub const MIN_FAT16_CLUSTERS: u32 = 4085;
+
+/// the first cluster of the data area, the FAT entries before it are
+/// reserved.
+pub const FIRST_CLUSTER: u32 = 2;
+
+pub const DIRENT_SIZE: usize = 32;
+
+pub const ATTR_READ_ONLY: u8 = 0x01;
+pub const ATTR_HIDDEN: u8 = 0x02;
+pub const ATTR_SYSTEM: u8 = 0x04;
+pub const ATTR_VOLUME_ID: u8 = 0x08;
+pub const ATTR_DIRECTORY: u8 = 0x10;
+pub const ATTR_ARCHIVE: u8 = 0x20;
+/// marks the entries holding pieces of a long name.
+pub const ATTR_LONG_NAME: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+
+/// the first name byte of an unused entry, and of all entries after it.
+pub const DIRENT_END: u8 = 0x00;
+/// the first name byte of a deleted entry.
+pub const DIRENT_FREE: u8 = 0xe5;
+/// stands for a first name byte of `DIRENT_FREE` in a used entry.
+pub const DIRENT_KANJI_E5: u8 = 0x05;
+
+/// `Dirent::case` bits set by Windows NT for short names which are all
+/// lowercase, instead of adding a long name.
+pub const CASE_LOWER_BASE: u8 = 0x08;
+pub const CASE_LOWER_EXT: u8 = 0x10;
+
+/// the last piece of a long name, which comes first in the directory.
+pub const LFN_LAST: u8 = 0x40;
+pub const LFN_ORDER: u8 = 0x1f;
+/// name characters per long name entry.
+pub const LFN_CHARS: usize = 13;
+/// where the characters of a long name entry are.
+pub const LFN_CHAR_OFFSETS: [usize; LFN_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+pub const MAX_NAME_LEN: usize = 255;
+
+/// the date written for everything, 1980-01-01 as we have no clock.
+pub const DOS_EPOCH_DATE: u16 = 1 << 5 | 1;
+
+/// `state` bit of a volume which wasn't unmounted cleanly.
+pub const STATE_DIRTY: u8 = 0x01;
+
+pub const FSINFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+pub const FSINFO_STRUCT_SIGNATURE: u32 = 0x6141_7272;
+/// an unknown free cluster count or hint in the FSInfo sector.
+pub const FSINFO_UNKNOWN: u32 = 0xffff_ffff;
+
+pub fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap())
+}
+
+pub fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+pub fn set_u16(buf: &mut [u8], offset: usize, v: u16) {
+    buf[offset..offset + 2].copy_from_slice(&v.to_le_bytes());
+}
+
+pub fn set_u32(buf: &mut [u8], offset: usize, v: u32) {
+    buf[offset..offset + 4].copy_from_slice(&v.to_le_bytes());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+/// The BIOS parameter block at the start of the boot sector, and what
+/// follows from it.
+pub struct Bpb {
+    pub fat_type: FatType,
+    pub bytes_per_sector: usize,
+    pub sectors_per_cluster: usize,
+    pub reserved_sectors: u64,
+    pub num_fats: u64,
+    pub root_entries: usize,
+    pub total_sectors: u64,
+    pub fat_sectors: u64,
+    /// the first cluster of the root directory on FAT32, the others keep it
+    /// in a region of its own before the data area.
+    pub root_cluster: u32,
+    pub fs_info: u64,
+    /// offset of the `state` byte, which moved for FAT32.
+    pub state_offset: usize,
+    pub state: u8,
+}
+
+impl Bpb {
+    pub fn parse(buf: &[u8]) -> Result<Self> {
+        if buf[510..512] != BOOT_SIGNATURE {
+            return Err(Error::InvalidFile("fat: no boot signature".to_string()));
+        }
+        let bytes_per_sector = u16_at(buf, 11) as usize;
+        let sectors_per_cluster = buf[13] as usize;
+        let fat_sectors_16 = u16_at(buf, 22) as u64;
+        let total_sectors_16 = u16_at(buf, 19) as u64;
+        let is_fat32 = fat_sectors_16 == 0;
+        let mut bpb = Self {
+            // decided below, once the clusters are counted.
+            fat_type: FatType::Fat32,
+            bytes_per_sector,
+            sectors_per_cluster,
+            reserved_sectors: u16_at(buf, 14) as u64,
+            num_fats: buf[16] as u64,
+            root_entries: u16_at(buf, 17) as usize,
+            total_sectors: if total_sectors_16 != 0 {
+                total_sectors_16
+            } else {
+                u32_at(buf, 32) as u64
+            },
+            fat_sectors: if is_fat32 {
+                u32_at(buf, 36) as u64
+            } else {
+                fat_sectors_16
+            },
+            root_cluster: if is_fat32 { u32_at(buf, 44) } else { 0 },
+            fs_info: if is_fat32 { u16_at(buf, 48) as u64 } else { 0 },
+            state_offset: if is_fat32 { 65 } else { 37 },
+            state: 0,
+        };
+        bpb.state = buf[bpb.state_offset];
+
+        if !bytes_per_sector.is_power_of_two()
+            || !(512..=4096).contains(&bytes_per_sector)
+            || !sectors_per_cluster.is_power_of_two()
+            || bpb.reserved_sectors == 0
+            || bpb.num_fats == 0
+            || bpb.fat_sectors == 0
+            || bpb.data_start() >= bpb.total_sectors
+        {
+            return Err(Error::InvalidFile("fat: invalid BPB".to_string()));
+        }
+        // like Linux, FAT32 is told apart by the FAT size and the others by
+        // the number of clusters.
+        bpb.fat_type = if is_fat32 {
+            FatType::Fat32
+        } else if bpb.cluster_count() < MIN_FAT16_CLUSTERS {
+            FatType::Fat12
+        } else {
+            FatType::Fat16
+        };
+        if is_fat32 && (bpb.root_entries != 0 || bpb.root_cluster < FIRST_CLUSTER) {
+            return Err(Error::InvalidFile("fat: invalid FAT32 BPB".to_string()));
+        }
+        Ok(bpb)
+    }
+
+    pub fn cluster_size(&self) -> usize {
+        self.bytes_per_sector * self.sectors_per_cluster
+    }
+
+    /// sectors of the fixed root directory, none on FAT32.
+    pub fn root_sectors(&self) -> u64 {
+        (self.root_entries * DIRENT_SIZE).div_ceil(self.bytes_per_sector) as u64
+    }
+
+    pub fn root_start(&self) -> u64 {
+        self.reserved_sectors + self.num_fats * self.fat_sectors
+    }
+
+    pub fn data_start(&self) -> u64 {
+        self.root_start() + self.root_sectors()
+    }
+
+    pub fn cluster_count(&self) -> u32 {
+        ((self.total_sectors - self.data_start()) / self.sectors_per_cluster as u64) as u32
+    }
+}
+
+/// The short entry of a file or directory, which any long name entries
+/// come before.
+#[derive(Debug, Clone)]
+pub struct Dirent {
+    /// the 8.3 name, padded with spaces.
+    pub name: [u8; 11],
+    pub attr: u8,
+    pub case: u8,
+    pub first_cluster: u32,
+    pub size: u32,
+}
+
+impl Dirent {
+    pub fn parse(buf: &[u8]) -> Self {
+        let mut name: [u8; 11] = buf[..11].try_into().unwrap();
+        if name[0] == DIRENT_KANJI_E5 {
+            name[0] = DIRENT_FREE;
+        }
+        Self {
+            name,
+            attr: buf[11],
+            case: buf[12],
+            first_cluster: (u16_at(buf, 20) as u32) << 16 | u16_at(buf, 26) as u32,
+            size: u32_at(buf, 28),
+        }
+    }
+
+    /// writes a whole new entry.
+    pub fn store(&self, buf: &mut [u8]) {
+        buf[..DIRENT_SIZE].fill(0);
+        buf[..11].copy_from_slice(&self.name);
+        if buf[0] == DIRENT_FREE {
+            buf[0] = DIRENT_KANJI_E5;
+        }
+        buf[11] = self.attr;
+        buf[12] = self.case;
+        // created, accessed and modified at the epoch.
+        set_u16(buf, 16, DOS_EPOCH_DATE);
+        set_u16(buf, 18, DOS_EPOCH_DATE);
+        set_u16(buf, 24, DOS_EPOCH_DATE);
+        self.store_data(buf);
+    }
+
+    /// updates where the data is, leaving the rest of the entry alone.
+    pub fn store_data(&self, buf: &mut [u8]) {
+        set_u16(buf, 20, (self.first_cluster >> 16) as u16);
+        set_u16(buf, 26, self.first_cluster as u16);
+        set_u32(buf, 28, self.size);
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.attr & ATTR_DIRECTORY != 0
+    }
+
+    /// the short name as it is shown, in lowercase where NT says so.
+    pub fn display_name(&self) -> String {
+        let part = |bytes: &[u8], lower: bool| -> String {
+            let len = bytes.iter().rposition(|&b| b != b' ').map_or(0, |i| i + 1);
+            bytes[..len]
+                .iter()
+                .map(|&b| {
+                    let c = b as char;
+                    if lower {
+                        c.to_ascii_lowercase()
+                    } else {
+                        c
+                    }
+                })
+                .collect()
+        };
+        let mut name = part(&self.name[..8], self.case & CASE_LOWER_BASE != 0);
+        let ext = part(&self.name[8..], self.case & CASE_LOWER_EXT != 0);
+        if !ext.is_empty() {
+            name.push('.');
+            name.push_str(&ext);
+        }
+        name
+    }
+}
+
+/// the checksum of a short name, which its long name entries carry.
+pub fn lfn_checksum(name: &[u8; 11]) -> u8 {
+    name.iter()
+        .fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b))
+}
+
+/// the entries holding the long name `name` for the short name with
+/// `checksum`, in the order they are stored.
+pub fn lfn_entries(name: &str, checksum: u8) -> Vec<[u8; DIRENT_SIZE]> {
+    let mut chars: Vec<u16> = name.encode_utf16().collect();
+    let count = chars.len().div_ceil(LFN_CHARS);
+    // a name which doesn't fill the last entry ends with a null, the rest
+    // is padding.
+    if chars.len() % LFN_CHARS != 0 {
+        chars.push(0);
+    }
+    chars.resize(count * LFN_CHARS, 0xffff);
+    (0..count)
+        .rev()
+        .map(|i| {
+            let mut entry = [0; DIRENT_SIZE];
+            entry[0] = (i + 1) as u8 | if i + 1 == count { LFN_LAST } else { 0 };
+            entry[11] = ATTR_LONG_NAME;
+            entry[13] = checksum;
+            let piece = &chars[i * LFN_CHARS..(i + 1) * LFN_CHARS];
+            for (&offset, &c) in LFN_CHAR_OFFSETS.iter().zip(piece) {
+                set_u16(&mut entry, offset, c);
+            }
+            entry
+        })
+        .collect()
+}
+
+/// the characters of a long name entry, up to the null ending the name.
+pub fn lfn_chars(entry: &[u8]) -> impl Iterator<Item = u16> + '_ {
+    LFN_CHAR_OFFSETS
+        .iter()
+        .map(|&offset| u16_at(entry, offset))
+        .take_while(|&c| c != 0)
+}
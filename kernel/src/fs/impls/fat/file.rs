@@ -0,0 +1,129 @@
+//! the data of files, in the chain of clusters starting at their entry.
+
+use super::disk::{Dirent, DIRENT_SIZE};
+use super::{Entry, Fat, State};
+use crate::fs::{Error, Result};
+use alloc::string::ToString;
+use core::cmp::Ordering;
+
+impl Fat {
+    pub(super) fn read_data(&self, dirent: &Dirent, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let size = dirent.size as u64;
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = (buf.len() as u64).min(size - offset) as usize;
+        let cluster_size = self.cluster_size as u64;
+        let chain = self.state.lock().table.chain(dirent.first_cluster)?;
+        let mut cluster = vec![0; self.cluster_size];
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done as u64;
+            let in_cluster = (pos % cluster_size) as usize;
+            let chunk = (self.cluster_size - in_cluster).min(len - done);
+            let index = (pos / cluster_size) as usize;
+            let &lba = chain.get(index).ok_or_else(|| {
+                Error::InvalidFile("fat: chain shorter than the file".to_string())
+            })?;
+            self.read_cluster(lba, &mut cluster)?;
+            buf[done..done + chunk].copy_from_slice(&cluster[in_cluster..in_cluster + chunk]);
+            done += chunk;
+        }
+        Ok(len)
+    }
+
+    /// writes `buf` at `offset`, growing the file as needed. a write which
+    /// doesn't fit is cut short.
+    pub(super) fn write_data(
+        &self,
+        state: &mut State,
+        entry: &mut Entry,
+        offset: u64,
+        buf: &[u8],
+    ) -> Result<usize> {
+        let too_large = || Error::NoSpace("fat: file too large".to_string());
+        let end = offset.checked_add(buf.len() as u64).ok_or_else(too_large)?;
+        let end = u32::try_from(end).map_err(|_| too_large())?;
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let cluster_size = self.cluster_size as u64;
+        let mut len = buf.len();
+        if end > entry.dirent.size {
+            // as much as there are clusters for.
+            let have = state.table.chain(entry.dirent.first_cluster)?.len() as u64;
+            let room = (have + state.table.free as u64) * cluster_size;
+            if room <= offset {
+                return Err(Error::NoSpace("fat".to_string()));
+            }
+            len = len.min((room - offset) as usize);
+            self.resize(state, entry, (offset + len as u64) as u32)?;
+        }
+
+        let chain = state.table.chain(entry.dirent.first_cluster)?;
+        let mut cluster = vec![0; self.cluster_size];
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done as u64;
+            let in_cluster = (pos % cluster_size) as usize;
+            let chunk = (self.cluster_size - in_cluster).min(len - done);
+            let lba = chain[(pos / cluster_size) as usize];
+            if chunk == self.cluster_size {
+                self.write_cluster(lba, &buf[done..done + chunk])?;
+            } else {
+                self.read_cluster(lba, &mut cluster)?;
+                cluster[in_cluster..in_cluster + chunk].copy_from_slice(&buf[done..done + chunk]);
+                self.write_cluster(lba, &cluster)?;
+            }
+            done += chunk;
+        }
+        Ok(len)
+    }
+
+    /// sets the size of a file and stores it in its entry. clusters are
+    /// freed when it shrinks, and what it grows by reads as zeros.
+    pub(super) fn resize(&self, state: &mut State, entry: &mut Entry, size: u32) -> Result<()> {
+        let cluster_size = self.cluster_size as u64;
+        let old = entry.dirent.size as u64;
+        let chain = state.table.chain(entry.dirent.first_cluster)?;
+        let needed = (size as u64).div_ceil(cluster_size) as usize;
+        match needed.cmp(&chain.len()) {
+            Ordering::Less => {
+                self.free_clusters(state, &chain, needed);
+                if needed == 0 {
+                    entry.dirent.first_cluster = 0;
+                }
+            }
+            Ordering::Greater => {
+                let last = chain.last().copied().unwrap_or(0);
+                let added = self.alloc_clusters(state, last, needed - chain.len(), true)?;
+                if last == 0 {
+                    entry.dirent.first_cluster = added[0];
+                }
+            }
+            Ordering::Equal => {}
+        }
+        // the rest of the old last cluster may hold anything.
+        let tail = (old % cluster_size) as usize;
+        if size as u64 > old && tail != 0 {
+            let lba = chain[(old / cluster_size) as usize];
+            let mut cluster = vec![0; self.cluster_size];
+            self.read_cluster(lba, &mut cluster)?;
+            cluster[tail..].fill(0);
+            self.write_cluster(lba, &cluster)?;
+        }
+        entry.dirent.size = size;
+        self.store_dirent(entry)
+    }
+
+    /// writes where the data of `entry` is back to its short entry.
+    fn store_dirent(&self, entry: &Entry) -> Result<()> {
+        let Some(pos) = entry.pos else {
+            return Ok(());
+        };
+        let mut slot = [0; DIRENT_SIZE];
+        self.read_bytes(pos, &mut slot)?;
+        entry.dirent.store_data(&mut slot);
+        self.write_bytes(pos, &slot)
+    }
+}
@@ -0,0 +1,590 @@
+//! FAT12, FAT16 and FAT32 with long file names, as used for EFI system
+//! partitions.
+//!
+//! FAT has no inodes, everything about a file is kept in its directory
+//! entry. The nodes in use remember where their entry is and what it says,
+//! so they follow renames and can still be read after they are removed.
+//! Inode numbers are the position of the entry on the disk.
+//!
+//! Like Linux, names are matched ignoring ASCII case, and the volume is
+//! marked dirty while it is mounted writable.
+
+mod dir;
+mod disk;
+mod file;
+mod table;
+#[cfg(test)]
+mod tests;
+
+use crate::fs::block::BlockDeviceRef;
+use crate::fs::{DirEntry, Error, FileType, Fs, Inode, Result, Stat, VNode};
+use crate::util::locked::Locked;
+use alloc::collections::BTreeMap;
+use alloc::string::ToString;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use disk::{Bpb, Dirent, FatType};
+use table::Table;
+
+/// the inode number of the root directory, which has no entry.
+const ROOT_INO: u64 = 1;
+
+pub struct FatFs {
+    fs: Arc<Fat>,
+}
+
+impl FatFs {
+    /// reads the boot sector and FAT from `dev`, which is mounted read-only
+    /// if it can't be written.
+    pub fn new(dev: BlockDeviceRef) -> Result<Self> {
+        Fat::open(dev).map(|fs| Self { fs: Arc::new(fs) })
+    }
+
+    pub fn fat_type(&self) -> FatType {
+        self.fs.bpb.fat_type
+    }
+}
+
+impl Fs for FatFs {
+    fn root(&self) -> Result<VNode> {
+        Ok(self.fs.root())
+    }
+
+    fn unmount(&self) -> Result<()> {
+        self.fs.unmount()
+    }
+
+    fn page_cached(&self) -> bool {
+        true
+    }
+}
+
+/// What a node knows about its directory entry.
+#[derive(Clone)]
+struct Entry {
+    /// where the short entry is on the disk, none for the root.
+    pos: Option<u64>,
+    dirent: Dirent,
+    /// the entry is gone, its clusters are freed with the node.
+    removed: bool,
+}
+
+/// Everything which changes when the filesystem is written, behind one lock
+/// which every change holds from start to end.
+struct State {
+    table: Table,
+    /// the entries of the nodes in use, by node id.
+    nodes: BTreeMap<u64, (Weak<FatNode>, Entry)>,
+    /// the id of the node using the entry at each position.
+    ids: BTreeMap<u64, u64>,
+    next_id: u64,
+}
+
+struct Fat {
+    dev: BlockDeviceRef,
+    bpb: Bpb,
+    cluster_size: usize,
+    writable: bool,
+    state: Locked<State>,
+}
+
+impl Fat {
+    fn open(dev: BlockDeviceRef) -> Result<Self> {
+        let mut buf = vec![0; dev.sector_size().max(512)];
+        dev.read_blocks(0, &mut buf)?;
+        let bpb = Bpb::parse(&buf)?;
+        if bpb.bytes_per_sector % dev.sector_size() != 0
+            || bpb.total_sectors * (bpb.bytes_per_sector / dev.sector_size()) as u64
+                > dev.sector_count()
+        {
+            return Err(Error::InvalidFile(format!(
+                "fat: {} sectors of {} bytes on the device",
+                bpb.total_sectors, bpb.bytes_per_sector
+            )));
+        }
+        let mut fat = vec![0; bpb.fat_sectors as usize * bpb.bytes_per_sector];
+        let fat_lba = bpb.reserved_sectors * (bpb.bytes_per_sector / dev.sector_size()) as u64;
+        dev.read_blocks(fat_lba, &mut fat)?;
+        let table = Table::new(bpb.fat_type, fat, bpb.cluster_count())?;
+        let mut fs = Self {
+            dev,
+            cluster_size: bpb.cluster_size(),
+            bpb,
+            writable: false,
+            state: Locked::new(State {
+                table,
+                nodes: BTreeMap::new(),
+                ids: BTreeMap::new(),
+                next_id: 0,
+            }),
+        };
+
+        let dirty = fs.bpb.state | disk::STATE_DIRTY;
+        match fs.update_boot_sector(|sector| sector[fs.bpb.state_offset] = dirty) {
+            Ok(()) => fs.writable = true,
+            Err(Error::ReadOnly(_)) => {}
+            Err(e) => return Err(e),
+        }
+        Ok(fs)
+    }
+
+    fn unmount(&self) -> Result<()> {
+        if !self.writable {
+            return Ok(());
+        }
+        self.modify(|state| {
+            self.store_fs_info(state)?;
+            let clean = self.bpb.state;
+            self.update_boot_sector(|sector| sector[self.bpb.state_offset] = clean)
+        })?;
+        self.dev.flush()
+    }
+
+    /// runs `f` with the lock for changes held, and writes the FAT sectors
+    /// it changed afterwards.
+    fn modify<T>(&self, f: impl FnOnce(&mut State) -> Result<T>) -> Result<T> {
+        if !self.writable {
+            return Err(Error::ReadOnly("fat".to_string()));
+        }
+        let mut state = self.state.lock();
+        let result = f(&mut state);
+        let committed = self.commit(&mut state);
+        let value = result?;
+        committed.map(|_| value)
+    }
+
+    fn update_boot_sector(&self, f: impl FnOnce(&mut [u8])) -> Result<()> {
+        let mut sector = vec![0; self.bpb.bytes_per_sector];
+        self.read_sectors(0, &mut sector)?;
+        f(&mut sector);
+        self.write_sectors(0, &sector)
+    }
+
+    // all positions and sectors are in the units of the filesystem, which
+    // may be larger than those of the device.
+
+    fn read_sectors(&self, sector: u64, buf: &mut [u8]) -> Result<()> {
+        self.read_bytes(sector * self.bpb.bytes_per_sector as u64, buf)
+    }
+
+    fn write_sectors(&self, sector: u64, buf: &[u8]) -> Result<()> {
+        self.write_bytes(sector * self.bpb.bytes_per_sector as u64, buf)
+    }
+
+    /// reads `buf.len()` bytes at `pos`, which don't have to be whole
+    /// sectors.
+    fn read_bytes(&self, pos: u64, buf: &mut [u8]) -> Result<()> {
+        let sector_size = self.dev.sector_size() as u64;
+        if pos % sector_size == 0 && buf.len() as u64 % sector_size == 0 {
+            return self.dev.read_blocks(pos / sector_size, buf);
+        }
+        let first = pos / sector_size;
+        let end = (pos + buf.len() as u64).div_ceil(sector_size);
+        let mut sectors = vec![0; ((end - first) * sector_size) as usize];
+        self.dev.read_blocks(first, &mut sectors)?;
+        let offset = (pos - first * sector_size) as usize;
+        buf.copy_from_slice(&sectors[offset..offset + buf.len()]);
+        Ok(())
+    }
+
+    /// writes `buf` at `pos`, reading the rest of partial sectors first.
+    fn write_bytes(&self, pos: u64, buf: &[u8]) -> Result<()> {
+        let sector_size = self.dev.sector_size() as u64;
+        if pos % sector_size == 0 && buf.len() as u64 % sector_size == 0 {
+            return self.dev.write_blocks(pos / sector_size, buf);
+        }
+        let first = pos / sector_size;
+        let end = (pos + buf.len() as u64).div_ceil(sector_size);
+        let mut sectors = vec![0; ((end - first) * sector_size) as usize];
+        self.dev.read_blocks(first, &mut sectors)?;
+        let offset = (pos - first * sector_size) as usize;
+        sectors[offset..offset + buf.len()].copy_from_slice(buf);
+        self.dev.write_blocks(first, &sectors)
+    }
+
+    /// where `cluster` starts on the disk.
+    fn cluster_pos(&self, cluster: u32) -> u64 {
+        let sector = self.bpb.data_start()
+            + (cluster - disk::FIRST_CLUSTER) as u64 * self.bpb.sectors_per_cluster as u64;
+        sector * self.bpb.bytes_per_sector as u64
+    }
+
+    fn read_cluster(&self, cluster: u32, buf: &mut [u8]) -> Result<()> {
+        self.read_bytes(self.cluster_pos(cluster), buf)
+    }
+
+    fn write_cluster(&self, cluster: u32, buf: &[u8]) -> Result<()> {
+        self.write_bytes(self.cluster_pos(cluster), buf)
+    }
+
+    fn root_entry(&self) -> Entry {
+        Entry {
+            pos: None,
+            dirent: Dirent {
+                name: [b' '; 11],
+                attr: disk::ATTR_DIRECTORY,
+                case: 0,
+                first_cluster: self.bpb.root_cluster,
+                size: 0,
+            },
+            removed: false,
+        }
+    }
+
+    fn root(self: &Arc<Self>) -> VNode {
+        let mut state = self.state.lock();
+        let root = state
+            .nodes
+            .iter()
+            .find(|(_, (_, entry))| entry.pos.is_none() && !entry.removed)
+            .and_then(|(_, (node, _))| node.upgrade());
+        match root {
+            Some(root) => root,
+            None => self.add_node(&mut state, self.root_entry()),
+        }
+    }
+
+    /// the node of the entry at `entry.pos`, shared with everyone else
+    /// using it.
+    fn node(self: &Arc<Self>, state: &mut State, entry: Entry) -> VNode {
+        let existing = entry
+            .pos
+            .and_then(|pos| state.ids.get(&pos))
+            .and_then(|id| state.nodes[id].0.upgrade());
+        match existing {
+            Some(node) => node,
+            None => self.add_node(state, entry),
+        }
+    }
+
+    fn add_node(self: &Arc<Self>, state: &mut State, entry: Entry) -> VNode {
+        let id = state.next_id;
+        state.next_id += 1;
+        let node = Arc::new(FatNode {
+            fs: self.clone(),
+            id,
+        });
+        if let Some(pos) = entry.pos {
+            state.ids.insert(pos, id);
+        }
+        state.nodes.insert(id, (Arc::downgrade(&node), entry));
+        node
+    }
+
+    /// frees the clusters of an entry which is gone, or leaves that to the
+    /// node using it.
+    fn release(&self, state: &mut State, pos: u64, dirent: &Dirent) -> Result<()> {
+        match state.ids.remove(&pos) {
+            Some(id) => {
+                let (_, entry) = state.nodes.get_mut(&id).unwrap();
+                entry.pos = None;
+                entry.removed = true;
+                Ok(())
+            }
+            None => {
+                let chain = state.table.chain(dirent.first_cluster)?;
+                self.free_clusters(state, &chain, 0);
+                Ok(())
+            }
+        }
+    }
+}
+
+struct FatNode {
+    fs: Arc<Fat>,
+    id: u64,
+}
+
+impl FatNode {
+    /// what the node knows about its entry. `State::nodes` has an entry for
+    /// every node until it is dropped.
+    fn entry<'a>(&self, state: &'a mut State) -> &'a mut Entry {
+        &mut state.nodes.get_mut(&self.id).unwrap().1
+    }
+
+    fn current(&self) -> Entry {
+        self.entry(&mut self.fs.state.lock()).clone()
+    }
+
+    fn dir(&self, state: &mut State, name: &str) -> Result<Dirent> {
+        let entry = self.entry(state);
+        if !entry.dirent.is_dir() {
+            return Err(Error::NotDirectory(name.to_string()));
+        }
+        Ok(entry.dirent.clone())
+    }
+
+    /// the first cluster of `vnode` if it is a directory of this filesystem.
+    fn find_dir(&self, state: &mut State, vnode: &VNode) -> Option<Entry> {
+        let ptr = Arc::as_ptr(vnode) as *const ();
+        state
+            .nodes
+            .values()
+            .find(|(node, _)| node.as_ptr() as *const () == ptr)
+            .map(|(_, entry)| entry.clone())
+    }
+
+    /// adds the new entry `name` with `attr`, whose first cluster `init`
+    /// sets up.
+    fn make(
+        &self,
+        name: &str,
+        attr: u8,
+        init: impl FnOnce(&mut State) -> Result<u32>,
+    ) -> Result<VNode> {
+        dir::check_name(name)?;
+        let entry = self.fs.modify(|state| {
+            let dir = self.dir(state, name)?;
+            if self.entry(state).removed {
+                return Err(Error::NoSuchPath(name.to_string()));
+            }
+            if self.fs.find_entry(state, &dir, name)?.is_some() {
+                return Err(Error::AlreadyExists(name.to_string()));
+            }
+            let first_cluster = init(state)?;
+            let dirent = Dirent {
+                name: [b' '; 11],
+                attr,
+                case: 0,
+                first_cluster,
+                size: 0,
+            };
+            let added = self.fs.add_entry(state, &dir, name, dirent);
+            if added.is_err() {
+                let chain = state.table.chain(first_cluster)?;
+                self.fs.free_clusters(state, &chain, 0);
+            }
+            added
+        })?;
+        // made outside `modify`, dropping a node takes the lock.
+        Ok(self.fs.node(&mut self.fs.state.lock(), entry))
+    }
+
+    /// the entry `name` in this directory, which has to be of the kind
+    /// `is_dir` says.
+    fn child(&self, state: &mut State, name: &str, is_dir: bool) -> Result<Entry> {
+        let dir = self.dir(state, name)?;
+        let entry = self
+            .fs
+            .find_entry(state, &dir, name)?
+            .ok_or_else(|| Error::NoSuchPath(name.to_string()))?;
+        match (is_dir, entry.dirent.is_dir()) {
+            (true, false) => Err(Error::NotDirectory(name.to_string())),
+            (false, true) => Err(Error::IsDirectory(name.to_string())),
+            _ => Ok(entry),
+        }
+    }
+}
+
+impl Drop for FatNode {
+    fn drop(&mut self) {
+        let mut state = self.fs.state.lock();
+        let Some((_, entry)) = state.nodes.remove(&self.id) else {
+            return;
+        };
+        if let Some(pos) = entry.pos {
+            if state.ids.get(&pos) == Some(&self.id) {
+                state.ids.remove(&pos);
+            }
+        }
+        if entry.removed {
+            // there is no one to report errors to, the clusters stay
+            // allocated then.
+            if let Ok(chain) = state.table.chain(entry.dirent.first_cluster) {
+                self.fs.free_clusters(&mut state, &chain, 0);
+                let _ = self.fs.commit(&mut state);
+            }
+        }
+    }
+}
+
+impl Inode for FatNode {
+    fn stat(&self) -> Result<Stat> {
+        let entry = self.current();
+        let ino = entry
+            .pos
+            .map_or(ROOT_INO, |pos| pos / disk::DIRENT_SIZE as u64);
+        let mode = match (
+            entry.dirent.is_dir(),
+            entry.dirent.attr & disk::ATTR_READ_ONLY,
+        ) {
+            (true, _) => 0o755,
+            (false, 0) => 0o644,
+            (false, _) => 0o444,
+        };
+        Ok(Stat {
+            ino,
+            ty: if entry.dirent.is_dir() {
+                FileType::Directory
+            } else {
+                FileType::Regular
+            },
+            mode,
+            size: entry.dirent.size as usize,
+        })
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let entry = self.current();
+        if entry.dirent.is_dir() {
+            return Err(Error::IsDirectory("read".to_string()));
+        }
+        self.fs.read_data(&entry.dirent, offset as u64, buf)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        self.fs.modify(|state| {
+            let mut entry = self.entry(state).clone();
+            if entry.dirent.is_dir() {
+                return Err(Error::IsDirectory("write".to_string()));
+            }
+            let written = self.fs.write_data(state, &mut entry, offset as u64, buf);
+            *self.entry(state) = entry;
+            written
+        })
+    }
+
+    fn truncate(&self, size: usize) -> Result<()> {
+        self.fs.modify(|state| {
+            let mut entry = self.entry(state).clone();
+            if entry.dirent.is_dir() {
+                return Err(Error::IsDirectory("truncate".to_string()));
+            }
+            let size = u32::try_from(size)
+                .map_err(|_| Error::NoSpace("fat: file too large".to_string()))?;
+            let resized = self.fs.resize(state, &mut entry, size);
+            *self.entry(state) = entry;
+            resized
+        })
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        let mut state = self.fs.state.lock();
+        let dir = self.dir(&mut state, "readdir")?;
+        let entries = self.fs.read_dir(&state, &dir)?;
+        Ok(entries
+            .into_iter()
+            .map(|found| DirEntry {
+                ino: found.pos / disk::DIRENT_SIZE as u64,
+                ty: if found.dirent.is_dir() {
+                    FileType::Directory
+                } else {
+                    FileType::Regular
+                },
+                name: found.name,
+            })
+            .collect())
+    }
+
+    fn lookup(&self, name: &str) -> Result<VNode> {
+        let mut state = self.fs.state.lock();
+        let dir = self.dir(&mut state, name)?;
+        let entry = self
+            .fs
+            .find_entry(&state, &dir, name)?
+            .ok_or_else(|| Error::NoSuchPath(name.to_string()))?;
+        Ok(self.fs.node(&mut state, entry))
+    }
+
+    fn create(&self, name: &str, mode: u16) -> Result<VNode> {
+        let attr = if mode & 0o222 == 0 {
+            disk::ATTR_ARCHIVE | disk::ATTR_READ_ONLY
+        } else {
+            disk::ATTR_ARCHIVE
+        };
+        self.make(name, attr, |_| Ok(0))
+    }
+
+    fn mkdir(&self, name: &str, _mode: u16) -> Result<VNode> {
+        self.make(name, disk::ATTR_DIRECTORY, |state| {
+            let parent = self.entry(state).dirent.first_cluster;
+            self.fs.init_dir(state, parent)
+        })
+    }
+
+    fn unlink(&self, name: &str) -> Result<()> {
+        self.fs.modify(|state| {
+            let entry = self.child(state, name, false)?;
+            let dir = self.dir(state, name)?;
+            self.fs.remove_entry(state, &dir, &entry)?;
+            self.fs.release(state, entry.pos.unwrap(), &entry.dirent)
+        })
+    }
+
+    fn rmdir(&self, name: &str) -> Result<()> {
+        self.fs.modify(|state| {
+            let entry = self.child(state, name, true)?;
+            if !self.fs.read_dir(state, &entry.dirent)?.is_empty() {
+                return Err(Error::NotEmpty(name.to_string()));
+            }
+            let dir = self.dir(state, name)?;
+            self.fs.remove_entry(state, &dir, &entry)?;
+            self.fs.release(state, entry.pos.unwrap(), &entry.dirent)
+        })
+    }
+
+    fn rename(&self, name: &str, new_dir: &VNode, new_name: &str) -> Result<()> {
+        dir::check_name(new_name)?;
+        self.fs.modify(|state| {
+            let target_dir = self
+                .find_dir(state, new_dir)
+                .ok_or_else(|| Error::CrossDevice(new_name.to_string()))?;
+            if !target_dir.dirent.is_dir() {
+                return Err(Error::NotDirectory(new_name.to_string()));
+            }
+            if target_dir.removed {
+                return Err(Error::NoSuchPath(new_name.to_string()));
+            }
+            let dir = self.dir(state, name)?;
+            let entry = self
+                .fs
+                .find_entry(state, &dir, name)?
+                .ok_or_else(|| Error::NoSuchPath(name.to_string()))?;
+            let is_dir = entry.dirent.is_dir();
+            let moves = dir.first_cluster != target_dir.dirent.first_cluster;
+            if is_dir && moves {
+                // a directory can't be moved below itself.
+                let mut cluster = target_dir.dirent.first_cluster;
+                while cluster != 0 && cluster != self.fs.bpb.root_cluster {
+                    if cluster == entry.dirent.first_cluster {
+                        return Err(Error::InvalidPath(new_name.to_string()));
+                    }
+                    cluster = self.fs.parent(state, cluster)?;
+                }
+            }
+
+            // the target may be replaced by an entry of the same kind, or be
+            // the entry itself with another case.
+            let existing = self.fs.find_entry(state, &target_dir.dirent, new_name)?;
+            if let Some(existing) = existing.filter(|existing| existing.pos != entry.pos) {
+                match (is_dir, existing.dirent.is_dir()) {
+                    (false, true) => return Err(Error::IsDirectory(new_name.to_string())),
+                    (true, false) => return Err(Error::NotDirectory(new_name.to_string())),
+                    (true, true) if !self.fs.read_dir(state, &existing.dirent)?.is_empty() => {
+                        return Err(Error::NotEmpty(new_name.to_string()));
+                    }
+                    _ => {}
+                }
+                self.fs.remove_entry(state, &target_dir.dirent, &existing)?;
+                self.fs
+                    .release(state, existing.pos.unwrap(), &existing.dirent)?;
+            }
+
+            let old_pos = entry.pos.unwrap();
+            let moved =
+                self.fs
+                    .add_entry(state, &target_dir.dirent, new_name, entry.dirent.clone())?;
+            self.fs.remove_entry(state, &dir, &entry)?;
+            if let Some(id) = state.ids.remove(&old_pos) {
+                state.ids.insert(moved.pos.unwrap(), id);
+                state.nodes.get_mut(&id).unwrap().1.pos = moved.pos;
+            }
+            if is_dir && moves {
+                let (cluster, parent) =
+                    (entry.dirent.first_cluster, target_dir.dirent.first_cluster);
+                self.fs.set_parent(cluster, parent)?;
+            }
+            Ok(())
+        })
+    }
+}
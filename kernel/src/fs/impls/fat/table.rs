@@ -0,0 +1,247 @@
+//! the file allocation table, which links the clusters of every file into a
+//! chain and tells which ones are free.
+
+use super::disk::{self, FatType};
+use super::{Fat, State};
+use crate::fs::{Error, Result};
+use alloc::collections::BTreeSet;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+/// A copy of the first FAT.
+///
+/// It is at most a few hundred KiB on the volumes this is meant for, and
+/// keeping it around saves going to the disk for every link in a chain.
+pub(super) struct Table {
+    fat_type: FatType,
+    bytes: Vec<u8>,
+    /// one past the last cluster.
+    end: u32,
+    pub free: u32,
+    /// where the search for a free cluster goes on.
+    next_free: u32,
+    /// sectors of the FAT which have to be written back.
+    dirty: BTreeSet<usize>,
+}
+
+/// what a FAT entry says about its cluster.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Link {
+    Free,
+    Next(u32),
+    End,
+    /// bad or reserved, neither used nor free.
+    Bad,
+}
+
+impl Table {
+    pub fn new(fat_type: FatType, bytes: Vec<u8>, clusters: u32) -> Result<Self> {
+        let end = clusters + disk::FIRST_CLUSTER;
+        let mut table = Self {
+            fat_type,
+            bytes,
+            end,
+            free: 0,
+            next_free: disk::FIRST_CLUSTER,
+            dirty: BTreeSet::new(),
+        };
+        if table.entry_range(end - 1).end > table.bytes.len() {
+            return Err(Error::InvalidFile("fat: FAT too small".to_string()));
+        }
+        table.free = (disk::FIRST_CLUSTER..end)
+            .filter(|&cluster| table.link(cluster) == Link::Free)
+            .count() as u32;
+        Ok(table)
+    }
+
+    /// the bytes holding the entry of `cluster`.
+    fn entry_range(&self, cluster: u32) -> core::ops::Range<usize> {
+        let cluster = cluster as usize;
+        match self.fat_type {
+            FatType::Fat12 => cluster + cluster / 2..cluster + cluster / 2 + 2,
+            FatType::Fat16 => cluster * 2..cluster * 2 + 2,
+            FatType::Fat32 => cluster * 4..cluster * 4 + 4,
+        }
+    }
+
+    fn get(&self, cluster: u32) -> u32 {
+        let range = self.entry_range(cluster);
+        match self.fat_type {
+            // odd entries start in the middle of a byte.
+            FatType::Fat12 if cluster % 2 == 1 => {
+                disk::u16_at(&self.bytes, range.start) as u32 >> 4
+            }
+            FatType::Fat12 => disk::u16_at(&self.bytes, range.start) as u32 & 0xfff,
+            FatType::Fat16 => disk::u16_at(&self.bytes, range.start) as u32,
+            // the top four bits are reserved.
+            FatType::Fat32 => disk::u32_at(&self.bytes, range.start) & 0x0fff_ffff,
+        }
+    }
+
+    fn set(&mut self, cluster: u32, value: u32, sector_size: usize) {
+        let range = self.entry_range(cluster);
+        match self.fat_type {
+            FatType::Fat12 => {
+                let old = disk::u16_at(&self.bytes, range.start);
+                let new = if cluster % 2 == 1 {
+                    old & 0xf | (value as u16) << 4
+                } else {
+                    old & 0xf000 | value as u16 & 0xfff
+                };
+                disk::set_u16(&mut self.bytes, range.start, new);
+            }
+            FatType::Fat16 => disk::set_u16(&mut self.bytes, range.start, value as u16),
+            FatType::Fat32 => {
+                let old = disk::u32_at(&self.bytes, range.start);
+                disk::set_u32(&mut self.bytes, range.start, old & 0xf000_0000 | value);
+            }
+        }
+        self.dirty.insert(range.start / sector_size);
+        self.dirty.insert((range.end - 1) / sector_size);
+    }
+
+    /// the value of the end of chain mark, and of the first bad one.
+    fn marks(&self) -> (u32, u32) {
+        match self.fat_type {
+            FatType::Fat12 => (0xfff, 0xff7),
+            FatType::Fat16 => (0xffff, 0xfff7),
+            FatType::Fat32 => (0x0fff_ffff, 0x0fff_fff7),
+        }
+    }
+
+    fn link(&self, cluster: u32) -> Link {
+        let (_, bad) = self.marks();
+        match self.get(cluster) {
+            0 => Link::Free,
+            value if value > bad => Link::End,
+            value if value == bad || value < disk::FIRST_CLUSTER || value >= self.end => Link::Bad,
+            next => Link::Next(next),
+        }
+    }
+
+    fn check(&self, cluster: u32) -> Result<()> {
+        if (disk::FIRST_CLUSTER..self.end).contains(&cluster) {
+            Ok(())
+        } else {
+            Err(Error::InvalidFile(format!(
+                "fat: cluster {cluster} out of range"
+            )))
+        }
+    }
+
+    /// the clusters of the chain starting at `first`, none for zero.
+    pub fn chain(&self, first: u32) -> Result<Vec<u32>> {
+        let mut chain = Vec::new();
+        let mut cluster = first;
+        while cluster != 0 {
+            self.check(cluster)?;
+            // a longer chain has to loop somewhere.
+            if chain.len() as u32 >= self.end {
+                return Err(Error::InvalidFile("fat: chain loops".to_string()));
+            }
+            chain.push(cluster);
+            cluster = match self.link(cluster) {
+                Link::Next(next) => next,
+                Link::End => 0,
+                Link::Free | Link::Bad => {
+                    return Err(Error::InvalidFile(format!(
+                        "fat: broken chain at cluster {cluster}"
+                    )))
+                }
+            };
+        }
+        Ok(chain)
+    }
+}
+
+impl Fat {
+    /// appends `count` free clusters to the chain ending with `last`, or
+    /// starts a new one for zero. returns the new clusters, which are zeroed
+    /// if `zero` is set.
+    pub(super) fn alloc_clusters(
+        &self,
+        state: &mut State,
+        last: u32,
+        count: usize,
+        zero: bool,
+    ) -> Result<Vec<u32>> {
+        let table = &mut state.table;
+        if (table.free as usize) < count {
+            return Err(Error::NoSpace("fat".to_string()));
+        }
+        let sector_size = self.bpb.bytes_per_sector;
+        let (end_mark, _) = table.marks();
+        let mut clusters = Vec::with_capacity(count);
+        let mut prev = last;
+        let mut cluster = table.next_free;
+        while clusters.len() < count {
+            if cluster >= table.end {
+                cluster = disk::FIRST_CLUSTER;
+            }
+            if table.link(cluster) == Link::Free {
+                table.set(cluster, end_mark, sector_size);
+                if prev != 0 {
+                    table.set(prev, cluster, sector_size);
+                }
+                table.free -= 1;
+                clusters.push(cluster);
+                prev = cluster;
+            }
+            cluster += 1;
+        }
+        table.next_free = cluster;
+        if zero {
+            let empty = vec![0; self.cluster_size];
+            for &cluster in &clusters {
+                self.write_cluster(cluster, &empty)?;
+            }
+        }
+        Ok(clusters)
+    }
+
+    /// frees the clusters of `chain` from the `keep`th on, and ends the
+    /// chain before them.
+    pub(super) fn free_clusters(&self, state: &mut State, chain: &[u32], keep: usize) {
+        let sector_size = self.bpb.bytes_per_sector;
+        let table = &mut state.table;
+        if keep > 0 && keep < chain.len() {
+            let (end_mark, _) = table.marks();
+            table.set(chain[keep - 1], end_mark, sector_size);
+        }
+        for &cluster in chain.iter().skip(keep) {
+            table.set(cluster, 0, sector_size);
+            table.free += 1;
+        }
+    }
+
+    /// writes the FAT sectors changed since the last call to every FAT.
+    pub(super) fn commit(&self, state: &mut State) -> Result<()> {
+        let sector_size = self.bpb.bytes_per_sector;
+        for sector in core::mem::take(&mut state.table.dirty) {
+            let bytes = &state.table.bytes[sector * sector_size..(sector + 1) * sector_size];
+            for fat in 0..self.bpb.num_fats {
+                let start = self.bpb.reserved_sectors + fat * self.bpb.fat_sectors;
+                self.write_sectors(start + sector as u64, bytes)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// stores the free cluster count and search hint for FAT32, which keeps
+    /// them in the FSInfo sector.
+    pub(super) fn store_fs_info(&self, state: &State) -> Result<()> {
+        if self.bpb.fat_type != FatType::Fat32 || self.bpb.fs_info == 0 {
+            return Ok(());
+        }
+        let mut sector = vec![0; self.bpb.bytes_per_sector];
+        self.read_sectors(self.bpb.fs_info, &mut sector)?;
+        if disk::u32_at(&sector, 0) != disk::FSINFO_LEAD_SIGNATURE
+            || disk::u32_at(&sector, 484) != disk::FSINFO_STRUCT_SIGNATURE
+        {
+            return Ok(());
+        }
+        disk::set_u32(&mut sector, 488, state.table.free);
+        disk::set_u32(&mut sector, 492, state.table.next_free);
+        self.write_sectors(self.bpb.fs_info, &sector)
+    }
+}
@@ -0,0 +1,417 @@
+//! works on images made by `mkfs.fat` from dosfstools and checks the ones we
+//! wrote with `fsck.fat`. The tests are skipped if it isn't installed on the
+//! host.
+
+extern crate std;
+
+use super::*;
+use crate::fs::block::testing::{self, pattern, run, SECTOR_SIZE};
+use std::fs as host;
+use std::os::unix::fs::FileExt;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// a scratch directory for an image.
+struct Scratch(testing::Scratch);
+
+impl Scratch {
+    /// `None` if dosfstools isn't installed.
+    fn new() -> Option<Self> {
+        testing::Scratch::new("fat", &["mkfs.fat", "fsck.fat"]).map(Self)
+    }
+
+    fn image(&self) -> PathBuf {
+        self.0.image()
+    }
+
+    /// makes an image of `kib` KiB with one sector per cluster, so files
+    /// take many of them.
+    fn mkfs(&self, bits: u8, kib: usize, args: &[&str]) -> FatFs {
+        run(Command::new("mkfs.fat")
+            .args(["-C", "-F", &bits.to_string(), "-s", "1"])
+            .args(args)
+            .arg(self.image())
+            .arg(kib.to_string()));
+        let fs = self.open(true);
+        let expected = match bits {
+            12 => FatType::Fat12,
+            16 => FatType::Fat16,
+            _ => FatType::Fat32,
+        };
+        assert_eq!(fs.fat_type(), expected);
+        fs
+    }
+
+    fn open(&self, writable: bool) -> FatFs {
+        FatFs::new(self.0.device(writable)).unwrap()
+    }
+
+    /// unmounts `fs` and lets `fsck.fat` look for anything it doesn't like.
+    fn fsck(&self, fs: FatFs) {
+        fs.unmount().unwrap();
+        drop(fs);
+        run(Command::new("fsck.fat").arg("-n").arg(self.image()));
+    }
+
+    /// unmounts `fs`, checks the image and mounts it again.
+    fn remount(&self, fs: FatFs) -> FatFs {
+        self.fsck(fs);
+        self.open(true)
+    }
+}
+
+/// images of each FAT type, all with plenty of clusters to spare.
+fn each_type(mut f: impl FnMut(&Scratch, FatFs)) {
+    for (bits, kib) in [(12, 1024), (16, 8192), (32, 40000)] {
+        let Some(scratch) = Scratch::new() else {
+            return;
+        };
+        let fs = scratch.mkfs(bits, kib, &[]);
+        f(&scratch, fs);
+    }
+}
+
+fn walk(fs: &FatFs, path: &str) -> VNode {
+    path.split('/')
+        .filter(|name| !name.is_empty())
+        .fold(fs.root().unwrap(), |dir, name| dir.lookup(name).unwrap())
+}
+
+fn read_all(node: &VNode) -> Vec<u8> {
+    let mut buf = vec![0; node.stat().unwrap().size];
+    assert_eq!(node.read_at(0, &mut buf).unwrap(), buf.len());
+    buf
+}
+
+fn names(dir: &VNode) -> Vec<String> {
+    let mut names: Vec<_> = dir.readdir().unwrap().into_iter().map(|e| e.name).collect();
+    names.sort();
+    names
+}
+
+fn free_clusters(fs: &FatFs) -> u32 {
+    fs.fs.state.lock().table.free
+}
+
+#[test]
+fn keeps_long_and_short_names() {
+    each_type(|scratch, fs| {
+        let root = fs.root().unwrap();
+        assert!(names(&root).is_empty());
+        let long = "A long file name with spaces.text";
+        root.create(long, 0o644)
+            .unwrap()
+            .write_at(0, b"long")
+            .unwrap();
+        root.create("README.TXT", 0o644)
+            .unwrap()
+            .write_at(0, b"short")
+            .unwrap();
+        root.create("lower.txt", 0o444).unwrap();
+        root.create("Ünïcode ✓", 0o644).unwrap();
+        root.mkdir("EFI", 0o755)
+            .unwrap()
+            .mkdir("BOOT", 0o755)
+            .unwrap();
+        drop(root);
+
+        let fs = scratch.remount(fs);
+        let root = fs.root().unwrap();
+        assert_eq!(
+            names(&root),
+            [
+                "A long file name with spaces.text",
+                "EFI",
+                "README.TXT",
+                "lower.txt",
+                "Ünïcode ✓"
+            ]
+        );
+        // names are matched ignoring case, and by their short name.
+        assert_eq!(read_all(&root.lookup("readme.txt").unwrap()), b"short");
+        assert_eq!(
+            read_all(&root.lookup(&long.to_uppercase()).unwrap()),
+            b"long"
+        );
+        assert_eq!(read_all(&root.lookup("ALONGF~1.TEX").unwrap()), b"long");
+        let lower = root.lookup("lower.txt").unwrap().stat().unwrap();
+        assert_eq!((lower.ty, lower.mode), (FileType::Regular, 0o444));
+        let boot = walk(&fs, "efi/boot").stat().unwrap();
+        assert_eq!(boot.ty, FileType::Directory);
+        assert!(matches!(
+            root.create("Readme.txt", 0o644),
+            Err(Error::AlreadyExists(_))
+        ));
+        for name in ["a/b", "a:b", "trailing.", "", ".."] {
+            assert!(matches!(
+                root.create(name, 0o644),
+                Err(Error::InvalidPath(_))
+            ));
+        }
+        drop(root);
+        scratch.fsck(fs);
+    });
+}
+
+#[test]
+fn numbers_short_names_apart() {
+    let Some(scratch) = Scratch::new() else {
+        return;
+    };
+    let fs = scratch.mkfs(16, 8192, &[]);
+    let dir = fs.root().unwrap().mkdir("dir", 0o755).unwrap();
+    for i in 0..20 {
+        dir.create(&format!("Same beginning {i}.txt"), 0o644)
+            .unwrap();
+    }
+    let state = fs.fs.state.lock();
+    let entry = fs.fs.find_entry(&state, &fs.fs.root_entry().dirent, "dir");
+    let shorts: Vec<_> = fs
+        .fs
+        .read_dir(&state, &entry.unwrap().unwrap().dirent)
+        .unwrap()
+        .into_iter()
+        .map(|found| found.dirent.display_name())
+        .collect();
+    drop(state);
+    assert!(shorts.contains(&"SAMEBE~1.TXT".to_string()));
+    assert!(shorts.contains(&"SAMEB~10.TXT".to_string()));
+    drop(dir);
+    scratch.fsck(fs);
+}
+
+#[test]
+fn writes_files_across_clusters() {
+    each_type(|scratch, fs| {
+        let root = fs.root().unwrap();
+        let big = root.create("big", 0o644).unwrap();
+        let content = pattern(200 * 1024 + 123);
+        // in odd pieces, so writes start and end inside clusters.
+        for (i, chunk) in content.chunks(3000).enumerate() {
+            assert_eq!(big.write_at(i * 3000, chunk).unwrap(), chunk.len());
+        }
+        assert_eq!(read_all(&big), content);
+        big.write_at(1000, b"changed").unwrap();
+        let sparse = root.create("sparse", 0o644).unwrap();
+        sparse.write_at(10 * 1024, b"end").unwrap();
+        drop((big, sparse, root));
+
+        let fs = scratch.remount(fs);
+        let mut expected = content;
+        expected[1000..1007].copy_from_slice(b"changed");
+        assert_eq!(read_all(&walk(&fs, "big")), expected);
+        let sparse = read_all(&walk(&fs, "sparse"));
+        assert!(sparse[..10 * 1024].iter().all(|&b| b == 0));
+        assert_eq!(&sparse[10 * 1024..], b"end");
+        scratch.fsck(fs);
+    });
+}
+
+#[test]
+fn truncates_files() {
+    each_type(|scratch, fs| {
+        let root = fs.root().unwrap();
+        let free = free_clusters(&fs);
+        let file = root.create("file", 0o644).unwrap();
+        let content = pattern(100 * 1024);
+        file.write_at(0, &content).unwrap();
+        assert_eq!(free_clusters(&fs), free - 200);
+
+        file.truncate(20 * 1024 + 10).unwrap();
+        assert_eq!(free_clusters(&fs), free - 41);
+        // what was cut off reads as zeros once the file grows again.
+        file.truncate(30 * 1024).unwrap();
+        let read = read_all(&file);
+        assert_eq!(read[..20 * 1024 + 10], content[..20 * 1024 + 10]);
+        assert!(read[20 * 1024 + 10..].iter().all(|&b| b == 0));
+        file.truncate(0).unwrap();
+        assert_eq!(file.stat().unwrap().size, 0);
+        assert_eq!(free_clusters(&fs), free);
+        drop((file, root));
+        scratch.fsck(fs);
+    });
+}
+
+#[test]
+fn makes_and_removes_directories() {
+    each_type(|scratch, fs| {
+        let root = fs.root().unwrap();
+        let dir = root.mkdir("dir", 0o755).unwrap();
+        let sub = dir.mkdir("sub", 0o755).unwrap();
+        sub.create("inside", 0o644).unwrap();
+        // enough long names to take several clusters.
+        let name = |i| format!("{}-{i}", "f".repeat(40));
+        for i in 0..100 {
+            dir.create(&name(i), 0o644).unwrap();
+        }
+        assert!(matches!(root.rmdir("dir"), Err(Error::NotEmpty(_))));
+        assert!(matches!(dir.unlink("sub"), Err(Error::IsDirectory(_))));
+        assert!(matches!(dir.rmdir(&name(0)), Err(Error::NotDirectory(_))));
+        for i in (0..100).step_by(2) {
+            dir.unlink(&name(i)).unwrap();
+        }
+        // the entries freed are reused.
+        let free = free_clusters(&fs);
+        for i in 0..20 {
+            dir.create(&format!("new-{i}"), 0o644).unwrap();
+        }
+        assert_eq!(free_clusters(&fs), free);
+        assert_eq!(names(&dir).len(), 50 + 20 + 1);
+        assert!(matches!(dir.rmdir("sub"), Err(Error::NotEmpty(_))));
+        sub.unlink("inside").unwrap();
+        dir.rmdir("sub").unwrap();
+        assert!(matches!(dir.lookup("sub"), Err(Error::NoSuchPath(_))));
+        // the removed directory can't get new entries.
+        assert!(matches!(sub.create("x", 0o644), Err(Error::NoSuchPath(_))));
+        drop((sub, dir, root));
+        scratch.fsck(fs);
+    });
+}
+
+#[test]
+fn fixed_root_directory_fills_up() {
+    let Some(scratch) = Scratch::new() else {
+        return;
+    };
+    let fs = scratch.mkfs(16, 8192, &["-r", "16"]);
+    let root = fs.root().unwrap();
+    for i in 0..16 {
+        root.create(&format!("F{i}"), 0o644).unwrap();
+    }
+    assert!(matches!(root.create("more", 0o644), Err(Error::NoSpace(_))));
+    root.unlink("F3").unwrap();
+    root.create("F3", 0o644).unwrap();
+    drop(root);
+    scratch.fsck(fs);
+}
+
+#[test]
+fn renames_across_directories() {
+    each_type(|scratch, fs| {
+        let root = fs.root().unwrap();
+        let a = root.mkdir("a", 0o755).unwrap();
+        let b = root.mkdir("b", 0o755).unwrap();
+        let moved = a.mkdir("moved", 0o755).unwrap();
+        moved
+            .create("inside", 0o644)
+            .unwrap()
+            .write_at(0, b"x")
+            .unwrap();
+        a.create("file", 0o644)
+            .unwrap()
+            .write_at(0, b"new")
+            .unwrap();
+        b.create("file", 0o644)
+            .unwrap()
+            .write_at(0, b"old")
+            .unwrap();
+
+        a.rename("moved", &b, "A much longer name").unwrap();
+        assert!(matches!(a.lookup("moved"), Err(Error::NoSuchPath(_))));
+        assert_eq!(read_all(&walk(&fs, "b/A much longer name/inside")), b"x");
+        // the node follows its entry.
+        moved.create("later", 0o644).unwrap();
+        // the old file is replaced.
+        a.rename("file", &b, "file").unwrap();
+        assert_eq!(read_all(&walk(&fs, "b/file")), b"new");
+        b.rename("file", &b, "FILE").unwrap();
+        assert_eq!(names(&b), ["A much longer name", "FILE"]);
+        assert!(matches!(
+            b.rename("a much longer name", &moved, "loop"),
+            Err(Error::InvalidPath(_))
+        ));
+        assert!(matches!(
+            b.rename("FILE", &b, "a much longer name"),
+            Err(Error::IsDirectory(_))
+        ));
+        // directories move back to the root, whose `..` is special.
+        b.rename("A much longer name", &root, "top").unwrap();
+        drop((moved, a, b, root));
+
+        let fs = scratch.remount(fs);
+        assert_eq!(names(&walk(&fs, "top")), ["inside", "later"]);
+        scratch.fsck(fs);
+    });
+}
+
+#[test]
+fn running_out_of_space_fails() {
+    let Some(scratch) = Scratch::new() else {
+        return;
+    };
+    let fs = scratch.mkfs(12, 256, &[]);
+    let root = fs.root().unwrap();
+    let file = root.create("file", 0o644).unwrap();
+    let chunk = pattern(64 * 1024);
+    let mut offset = 0;
+    let err = loop {
+        match file.write_at(offset, &chunk) {
+            Ok(written) => offset += written,
+            Err(e) => break e,
+        }
+    };
+    assert!(matches!(err, Error::NoSpace(_)));
+    assert_eq!(file.stat().unwrap().size, offset);
+    assert_eq!(free_clusters(&fs), 0);
+    assert!(matches!(root.mkdir("dir", 0o755), Err(Error::NoSpace(_))));
+    // freeing the clusters again makes room.
+    file.truncate(0).unwrap();
+    root.create("more", 0o644)
+        .unwrap()
+        .write_at(0, &chunk)
+        .unwrap();
+    drop((file, root));
+    scratch.fsck(fs);
+}
+
+#[test]
+fn removed_files_live_until_dropped() {
+    let Some(scratch) = Scratch::new() else {
+        return;
+    };
+    let fs = scratch.mkfs(32, 40000, &[]);
+    let root = fs.root().unwrap();
+    let file = root.create("file", 0o644).unwrap();
+    file.write_at(0, &pattern(50 * 1024)).unwrap();
+    let free = free_clusters(&fs);
+    root.unlink("file").unwrap();
+    // a new entry in the same place is another file.
+    root.create("other", 0o644).unwrap();
+    assert_eq!(read_all(&file), pattern(50 * 1024));
+    file.write_at(0, b"still there").unwrap();
+    assert_eq!(free_clusters(&fs), free);
+    drop(file);
+    assert_eq!(free_clusters(&fs), free + 100);
+    assert_eq!(read_all(&root.lookup("other").unwrap()), b"");
+    drop(root);
+    scratch.fsck(fs);
+}
+
+#[test]
+fn marks_the_volume_dirty_while_mounted() {
+    for bits in [16, 32] {
+        let Some(scratch) = Scratch::new() else {
+            return;
+        };
+        let fs = scratch.mkfs(bits, if bits == 32 { 40000 } else { 8192 }, &[]);
+        let dirty = || {
+            let mut sector = [0; SECTOR_SIZE];
+            host::File::open(scratch.image())
+                .unwrap()
+                .read_exact_at(&mut sector, 0)
+                .unwrap();
+            sector[fs.fs.bpb.state_offset] & disk::STATE_DIRTY != 0
+        };
+        assert!(dirty());
+        fs.unmount().unwrap();
+        assert!(!dirty());
+        drop(fs);
+
+        // read-only mounts leave it alone.
+        let fs = scratch.open(false);
+        let root = fs.root().unwrap();
+        assert!(matches!(root.create("x", 0o644), Err(Error::ReadOnly(_))));
+        drop(root);
+        scratch.fsck(fs);
+    }
+}
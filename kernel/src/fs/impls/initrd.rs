@@ -1,105 +1,111 @@
-use crate::fs::{Error, Fs, Metadata, Result, VNode};
-use alloc::slice;
+use super::tree::{Tree, TreeEntry};
+use super::{cpio, ustar};
+use crate::fs::{Error, FileType, Fs, Result, VNode};
 use alloc::string::ToString;
-use alloc::vec::Vec;
-use allocators::freelist::Node;
-use core::mem::size_of;
+use alloc::sync::Arc;
+use core::fmt::Display;
+use core::slice;
+use initrd_fmt::{EntryType, Initrd};
 
-const HEADER_NAME_LEN: usize = 32;
+/// Formats the initrd can be in, told apart by their magic bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// our own format from `initrd_fmt`.
+    Acorn,
+    /// `newc` cpio, as built by `cpio -H newc`.
+    Cpio,
+    /// POSIX ustar, as built by `tar --format=ustar` or GNU tar.
+    Ustar,
+}
 
-#[repr(C, packed)]
-struct Header {
-    entries: u64,
-    /// length in bytes for the whole initrd.
-    length: u64,
+impl Format {
+    pub fn detect(image: &[u8]) -> Option<Self> {
+        if image.starts_with(&initrd_fmt::MAGIC) {
+            Some(Self::Acorn)
+        } else if cpio::is_cpio(image) {
+            Some(Self::Cpio)
+        } else if ustar::is_ustar(image) {
+            Some(Self::Ustar)
+        } else {
+            None
+        }
+    }
 }
 
-#[repr(C, packed)]
-struct EntryHeader {
-    start: u64,
-    /// The entry's size in bytes, excluding the header.
-    length: u64,
-    name: [u8; HEADER_NAME_LEN],
+impl Display for Format {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            Self::Acorn => "acorn",
+            Self::Cpio => "cpio",
+            Self::Ustar => "ustar",
+        })
+    }
 }
 
-#[repr(C, packed)]
+/// The initrd, in any of the supported formats.
 pub struct InitrdFs {
-    inner: *const u8,
+    format: Format,
+    tree: Arc<Tree>,
 }
 
 impl InitrdFs {
-    pub unsafe fn from_raw(initd_ptr: *const u8, length: usize) -> Self {
-        assert!(length >= size_of::<Header>());
-        let ret = Self { inner: initd_ptr };
-        assert!(ret.get_header().length <= length as u64);
-        ret
+    /// detects the format of the image and reads its directory tree.
+    ///
+    /// # Safety
+    /// `initrd_ptr` has to point to `length` bytes which are never written to
+    /// or freed.
+    pub unsafe fn from_raw(initrd_ptr: *const u8, length: usize) -> Result<Self> {
+        let image = slice::from_raw_parts(initrd_ptr, length);
+        let format = Format::detect(image)
+            .ok_or_else(|| Error::InvalidFile("initrd: unknown format".to_string()))?;
+        let tree = match format {
+            Format::Acorn => load(image)?,
+            Format::Cpio => cpio::load(image)?,
+            Format::Ustar => ustar::load(image)?,
+        };
+        Ok(Self {
+            format,
+            tree: Arc::new(tree),
+        })
     }
 
-    unsafe fn get_header(&self) -> &Header {
-        &*(self.inner as *const Header)
-    }
-
-    unsafe fn get_entry_header(&self, entry: usize) -> &EntryHeader {
-        &*(self.inner.add(size_of::<Header>()) as *const EntryHeader).add(entry)
+    pub fn format(&self) -> Format {
+        self.format
     }
+}
 
-    unsafe fn fs_open(&self, name: &str) -> Result<Node> {
-        let header = self.get_header();
-        if name.len() > HEADER_NAME_LEN {
-            return Err(Error::InvalidFile(
-                "file name was too large for initrd fs".to_string(),
-            ));
-        }
-        let mut name_array = [0; 32];
-        for (i, b) in name.bytes().enumerate() {
-            name_array[i] = b;
-        }
-        for i in 0..header.entries as usize {
-            let entry = self.get_entry_header(i);
-            if entry.name == name_array {
-                return todo!();
-                //return Ok(i as u64);
-            }
-        }
-        return Err(Error::NoSuchPath(name.to_string()));
+impl Fs for InitrdFs {
+    fn root(&self) -> Result<VNode> {
+        Ok(self.tree.root())
     }
 
-    unsafe fn fs_read(&self, index: u64) -> Result<&[u8]> {
-        let header = self.get_entry_header(index as usize);
-        Ok(slice::from_raw_parts(
-            (self.inner as u64 + header.start) as *const _,
-            header.length as usize,
-        ))
+    /// cached so executables can be mapped instead of copied.
+    fn page_cached(&self) -> bool {
+        true
     }
 
-    unsafe fn fs_ls(&self) -> Result<Vec<Metadata>> {
-        let count = self.get_header().entries;
-        let mut vec = vec![];
-        for i in 0..count as usize {
-            let header = self.get_entry_header(i);
-            let name = header
-                .name
-                .iter()
-                .cloned()
-                .take_while(|&c| c != 0)
-                .map(|c| c as char)
-                .collect();
-            vec.push(Metadata {
-                name,
-                size: header.length as usize,
-            });
-        }
-        Ok(vec)
+    fn read_only(&self) -> bool {
+        true
     }
 }
 
-impl Fs for InitrdFs {
-    fn open(&mut self, path: &str) -> Result<VNode> {
-        todo!();
-        //Ok(unsafe { self.fs_open(path)? })
-    }
-
-    fn ls(&mut self, _: &str) -> Result<Vec<Metadata>> {
-        unsafe { self.fs_ls() }
+/// parses an image in our own format and checks its checksums.
+fn load(image: &'static [u8]) -> Result<Tree> {
+    let initrd = Initrd::parse(image)
+        .and_then(|initrd| initrd.verify().map(|_| initrd))
+        .map_err(|e| Error::InvalidFile(format!("initrd: {e}")))?;
+    let mut tree = Tree::new();
+    for entry in initrd.entries() {
+        tree.insert(TreeEntry {
+            path: entry.path(),
+            ty: match entry.ty() {
+                EntryType::Regular => FileType::Regular,
+                EntryType::Directory => FileType::Directory,
+                EntryType::Symlink => FileType::Symlink,
+            },
+            mode: entry.mode(),
+            data: entry.data(),
+        })?;
     }
+    Ok(tree)
 }
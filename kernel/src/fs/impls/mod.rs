@@ -1 +1,9 @@
+pub mod cpio;
+pub mod devfs;
+pub mod ext2;
+pub mod fat;
 pub mod initrd;
+pub mod procfs;
+pub mod tmpfs;
+pub mod tree;
+pub mod ustar;
@@ -0,0 +1,319 @@
+//! synthetic view of the kernel's state, usually mounted at `/proc`.
+//!
+//! Nothing is stored, every file is generated again on each read. The root
+//! holds the global files and a directory per process named after its id.
+
+use crate::boot::BootInfo;
+use crate::fs::{Access, DirEntry, Error, FileType, Fs, Inode, Result, Stat, VNode};
+use crate::mm::vmm::{Flags, RegionType, USERSPACE_END};
+use crate::mm::{heap, page_cache, pmm};
+use crate::process::thread::{sched, ThreadStatus};
+use crate::process::{self, ProcessPtr};
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ffi::CStr;
+use core::fmt::Write;
+
+const ROOT_INO: u64 = 1;
+
+/// name of a file in the root and the function generating it.
+type GlobalFile = (&'static str, fn() -> Result<String>);
+/// name of a file in a process directory and the function generating it.
+type PerProcessFile = (&'static str, fn(ProcessPtr) -> Result<String>);
+
+/// files in the root, with the function generating each of them.
+const GLOBAL_FILES: &[GlobalFile] = &[
+    ("meminfo", meminfo),
+    ("modules", modules),
+    ("sched", sched_info),
+    ("uptime", uptime),
+];
+
+/// files in each process directory.
+const PROCESS_FILES: &[PerProcessFile] = &[
+    ("fds", fds),
+    ("maps", maps),
+    ("status", status),
+    ("threads", threads),
+];
+
+pub struct ProcFs;
+
+impl Fs for ProcFs {
+    fn root(&self) -> Result<VNode> {
+        Ok(Arc::new(ProcNode::Root))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum ProcNode {
+    Root,
+    /// index into `GLOBAL_FILES`.
+    Global(usize),
+    /// directory of the process with this id.
+    Process(u16),
+    /// index into `PROCESS_FILES` for the process with this id.
+    ProcessFile(u16, usize),
+}
+
+impl ProcNode {
+    /// unique as long as there are fewer than 255 global files and files
+    /// per process.
+    fn ino(self) -> u64 {
+        match self {
+            Self::Root => ROOT_INO,
+            Self::Global(index) => ROOT_INO + 1 + index as u64,
+            Self::Process(pid) => (pid as u64 + 1) << 8,
+            Self::ProcessFile(pid, index) => ((pid as u64 + 1) << 8) + 1 + index as u64,
+        }
+    }
+
+    fn ty(self) -> FileType {
+        match self {
+            Self::Root | Self::Process(_) => FileType::Directory,
+            Self::Global(_) | Self::ProcessFile(..) => FileType::Regular,
+        }
+    }
+
+    fn entry(self, name: String) -> DirEntry {
+        DirEntry {
+            name,
+            ino: self.ino(),
+            ty: self.ty(),
+        }
+    }
+
+    fn generate(self) -> Result<String> {
+        match self {
+            Self::Global(index) => (GLOBAL_FILES[index].1)(),
+            Self::ProcessFile(pid, index) => (PROCESS_FILES[index].1)(find_process(pid)?),
+            _ => Err(Error::IsDirectory("read".to_string())),
+        }
+    }
+}
+
+fn find_process(pid: u16) -> Result<ProcessPtr> {
+    process::all()
+        .into_iter()
+        .find(|proc| unsafe { proc.get() }.id.raw() == pid)
+        .ok_or_else(|| Error::NoSuchPath(pid.to_string()))
+}
+
+impl Inode for ProcNode {
+    fn stat(&self) -> Result<Stat> {
+        let size = match self {
+            Self::Root => GLOBAL_FILES.len() + process::all().len(),
+            Self::Process(_) => PROCESS_FILES.len(),
+            // generated files have no size until they are read.
+            _ => 0,
+        };
+        let mode = match self.ty() {
+            FileType::Directory => 0o555,
+            _ => 0o444,
+        };
+        Ok(Stat {
+            ino: self.ino(),
+            ty: self.ty(),
+            mode,
+            size,
+        })
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let text = self.generate()?;
+        let text = text.as_bytes();
+        if offset >= text.len() {
+            return Ok(0);
+        }
+        let len = buf.len().min(text.len() - offset);
+        buf[..len].copy_from_slice(&text[offset..offset + len]);
+        Ok(len)
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        match *self {
+            Self::Root => {
+                let globals = GLOBAL_FILES
+                    .iter()
+                    .enumerate()
+                    .map(|(index, (name, _))| Self::Global(index).entry(name.to_string()));
+                let procs = process::all().into_iter().map(|proc| {
+                    let pid = unsafe { proc.get() }.id.raw();
+                    Self::Process(pid).entry(pid.to_string())
+                });
+                Ok(globals.chain(procs).collect())
+            }
+            Self::Process(pid) => {
+                find_process(pid)?;
+                Ok(PROCESS_FILES
+                    .iter()
+                    .enumerate()
+                    .map(|(index, (name, _))| Self::ProcessFile(pid, index).entry(name.to_string()))
+                    .collect())
+            }
+            _ => Err(Error::NotDirectory("readdir".to_string())),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<VNode> {
+        let node = match *self {
+            Self::Root => match GLOBAL_FILES.iter().position(|(file, _)| *file == name) {
+                Some(index) => Self::Global(index),
+                None => {
+                    let pid = name
+                        .parse()
+                        .map_err(|_| Error::NoSuchPath(name.to_string()))?;
+                    find_process(pid)?;
+                    Self::Process(pid)
+                }
+            },
+            Self::Process(pid) => {
+                let index = PROCESS_FILES
+                    .iter()
+                    .position(|(file, _)| *file == name)
+                    .ok_or_else(|| Error::NoSuchPath(name.to_string()))?;
+                Self::ProcessFile(pid, index)
+            }
+            _ => return Err(Error::NotDirectory(name.to_string())),
+        };
+        Ok(Arc::new(node))
+    }
+}
+
+// the generated files. they are small, so formatting can't really fail.
+
+fn meminfo() -> Result<String> {
+    let pmm = pmm::stats();
+    let heap = heap::stats();
+    let cache = page_cache::stats();
+    let mut out = String::new();
+    let _ = writeln!(out, "page_size: {}", pmm::PAGE_SIZE);
+    let _ = writeln!(out, "pages_total: {}", pmm.total_pages);
+    let _ = writeln!(out, "pages_used: {}", pmm.used_pages);
+    let _ = writeln!(out, "pages_free: {}", pmm.free_pages());
+    let _ = writeln!(out, "pages_cached: {}", cache.pages);
+    let _ = writeln!(out, "pages_dirty: {}", cache.dirty_pages);
+    let _ = writeln!(out, "heap_bytes: {}", heap.allocated_bytes);
+    let _ = writeln!(out, "heap_allocations: {}", heap.allocations);
+    Ok(out)
+}
+
+fn modules() -> Result<String> {
+    let boot_info = unsafe { BootInfo::get() };
+    let mut out = String::new();
+    for i in 0..boot_info.modules.module_count as usize {
+        let module = unsafe { &*boot_info.modules.modules.as_ptr().add(i) };
+        let Some(path) = module.path.as_ptr() else {
+            continue;
+        };
+        let path = unsafe { CStr::from_ptr(path) };
+        // where the module was loaded would give away the kernel's layout.
+        let _ = writeln!(
+            out,
+            "{} {}",
+            String::from_utf8_lossy(path.to_bytes()),
+            module.length
+        );
+    }
+    Ok(out)
+}
+
+fn sched_info() -> Result<String> {
+    Ok(format!("run_queue: {}\n", sched::run_queue_len()))
+}
+
+/// in timer ticks, see `sched::ticks`.
+fn uptime() -> Result<String> {
+    Ok(format!("{}\n", sched::ticks()))
+}
+
+fn status(proc: ProcessPtr) -> Result<String> {
+    let proc = proc.get_locked();
+    // the most active of its threads.
+    let state = proc
+        .threads
+        .iter()
+        .map(|thread| unsafe { thread.get() }.get_status())
+        .max_by_key(|status| match status {
+            ThreadStatus::Running => 2,
+            ThreadStatus::Waiting => 1,
+            ThreadStatus::Sleeping => 0,
+        })
+        .map_or("exited".to_string(), |status| format!("{status:?}"));
+    let mut out = String::new();
+    let _ = writeln!(out, "pid: {}", proc.id);
+    let _ = writeln!(out, "state: {state}");
+    let _ = writeln!(out, "threads: {}", proc.threads.len());
+    let _ = writeln!(out, "fds: {}", proc.files.iter().count());
+    Ok(out)
+}
+
+fn threads(proc: ProcessPtr) -> Result<String> {
+    let proc = proc.get_locked();
+    let mut out = String::new();
+    for thread in &proc.threads {
+        // the thread lock is also taken by the scheduler, a racy read is
+        // good enough here.
+        let thread = unsafe { thread.get() };
+        let _ = writeln!(
+            out,
+            "{} {:?} {:?}",
+            thread.get_id(),
+            thread.get_status(),
+            thread.get_schedule_status()
+        );
+    }
+    Ok(out)
+}
+
+/// only the userspace half, the kernel's regions are the same everywhere and
+/// would give away its layout.
+fn maps(proc: ProcessPtr) -> Result<String> {
+    let proc = proc.get_locked();
+    let mut out = String::new();
+    let regions = proc.vmm.regions();
+    for region in regions.filter(|region| region.start.adr() < USERSPACE_END) {
+        let flag = |flag, c| if region.flags.has(flag) { c } else { '-' };
+        let ty = match region.ty {
+            RegionType::Reserved => "reserved",
+            RegionType::Normal => "",
+            RegionType::Guard => "guard",
+        };
+        let _ = writeln!(
+            out,
+            "{:016x}-{:016x} r{}{}{} {ty}",
+            region.start.adr(),
+            region.start.adr() + region.len as u64,
+            flag(Flags::RW, 'w'),
+            flag(Flags::EXECUTABLE, 'x'),
+            flag(Flags::USER, 'u'),
+        );
+    }
+    Ok(out)
+}
+
+fn fds(proc: ProcessPtr) -> Result<String> {
+    // the files are cloned out first, as their nodes may take other locks.
+    let files: Vec<_> = proc
+        .get_locked()
+        .files
+        .iter()
+        .map(|(fd, file)| (fd, file.clone()))
+        .collect();
+    let mut out = String::new();
+    for (fd, file) in files {
+        let stat = file.stat()?;
+        let access = file.access();
+        let _ = writeln!(
+            out,
+            "{fd} {:?} {} {}{} {}",
+            stat.ty,
+            stat.ino,
+            if access.has(Access::READ) { 'r' } else { '-' },
+            if access.has(Access::WRITE) { 'w' } else { '-' },
+            file.offset()
+        );
+    }
+    Ok(out)
+}
@@ -0,0 +1,682 @@
+//! writable filesystem which lives entirely in memory.
+//!
+//! File data is kept in whole pages indexed by their offset, pages which
+//! were never written to are holes that read as zeros. Every filesystem has
+//! a limit on the pages its files may use together, writes past it fail with
+//! `Error::NoSpace`.
+
+use crate::fs::{DirEntry, Error, FileType, Fs, Inode, Result, Stat, VNode};
+use crate::util::locked::Locked;
+use alloc::collections::btree_map::{self, BTreeMap};
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+#[cfg(not(test))]
+use crate::mm::pmm::{self, PagePtr, PAGE_SIZE};
+#[cfg(test)]
+const PAGE_SIZE: usize = 4096;
+
+const ROOT_INO: u64 = 1;
+
+/// A page of file data, freed when dropped.
+#[cfg(not(test))]
+struct DataPage(PagePtr);
+
+// the page is only reachable through the node that owns it.
+#[cfg(not(test))]
+unsafe impl Send for DataPage {}
+#[cfg(not(test))]
+unsafe impl Sync for DataPage {}
+
+#[cfg(not(test))]
+impl DataPage {
+    fn new() -> Option<Self> {
+        pmm::try_alloc_pages_zeroed(1).map(Self)
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.0.virt().ptr(), PAGE_SIZE) }
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.0.virt().ptr(), PAGE_SIZE) }
+    }
+}
+
+#[cfg(not(test))]
+impl Drop for DataPage {
+    fn drop(&mut self) {
+        pmm::free_pages(self.0.clone());
+    }
+}
+
+/// host builds keep pages on the heap, as there is no PMM to take them from.
+#[cfg(test)]
+struct DataPage(alloc::boxed::Box<[u8; PAGE_SIZE]>);
+
+#[cfg(test)]
+impl DataPage {
+    fn new() -> Option<Self> {
+        Some(Self(alloc::boxed::Box::new([0; PAGE_SIZE])))
+    }
+
+    fn bytes(&self) -> &[u8] {
+        &self.0[..]
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.0[..]
+    }
+}
+
+enum Content {
+    File {
+        size: usize,
+        pages: BTreeMap<usize, DataPage>,
+    },
+    Dir {
+        children: BTreeMap<String, Arc<TmpNode>>,
+        /// `None` for the root.
+        parent: Option<Weak<TmpNode>>,
+    },
+    Symlink(String),
+}
+
+impl Content {
+    fn ty(&self) -> FileType {
+        match self {
+            Content::File { .. } => FileType::Regular,
+            Content::Dir { .. } => FileType::Directory,
+            Content::Symlink(_) => FileType::Symlink,
+        }
+    }
+}
+
+struct State {
+    mode: u16,
+    content: Content,
+}
+
+/// State shared by every node of one filesystem.
+struct Shared {
+    next_ino: AtomicU64,
+    /// every live node, so `rename` can find its target directory.
+    nodes: Locked<BTreeMap<u64, Weak<TmpNode>>>,
+    /// serialises renames, which lock two directories at once.
+    rename_lock: Locked<()>,
+    /// data pages the files may use together.
+    max_pages: usize,
+    used_pages: AtomicUsize,
+}
+
+impl Shared {
+    /// a new data page, if the filesystem is below its limit.
+    fn alloc_page(&self) -> Result<DataPage> {
+        let no_space = || Error::NoSpace("tmpfs".to_string());
+        self.used_pages
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                (used < self.max_pages).then_some(used + 1)
+            })
+            .map_err(|_| no_space())?;
+        DataPage::new().ok_or_else(|| {
+            self.release_pages(1);
+            no_space()
+        })
+    }
+
+    /// gives back `count` data pages which were dropped.
+    fn release_pages(&self, count: usize) {
+        self.used_pages.fetch_sub(count, Ordering::Relaxed);
+    }
+}
+
+pub struct TmpFs {
+    root: Arc<TmpNode>,
+}
+
+impl TmpFs {
+    /// an empty filesystem, whose files may use up to `max_pages` pages.
+    pub fn new(max_pages: usize) -> Self {
+        let shared = Arc::new(Shared {
+            next_ino: AtomicU64::new(ROOT_INO),
+            nodes: Locked::new(BTreeMap::new()),
+            rename_lock: Locked::new(()),
+            max_pages,
+            used_pages: AtomicUsize::new(0),
+        });
+        let root = TmpNode::new(
+            &shared,
+            0o755,
+            Content::Dir {
+                children: BTreeMap::new(),
+                parent: None,
+            },
+        );
+        Self { root }
+    }
+}
+
+impl Fs for TmpFs {
+    fn root(&self) -> Result<VNode> {
+        Ok(self.root.clone())
+    }
+}
+
+struct TmpNode {
+    ino: u64,
+    /// the node itself, for handing out new references to it.
+    this: Weak<TmpNode>,
+    shared: Arc<Shared>,
+    state: Locked<State>,
+}
+
+impl TmpNode {
+    fn new(shared: &Arc<Shared>, mode: u16, content: Content) -> Arc<Self> {
+        let ino = shared.next_ino.fetch_add(1, Ordering::Relaxed);
+        let node = Arc::new_cyclic(|this| Self {
+            ino,
+            this: this.clone(),
+            shared: shared.clone(),
+            state: Locked::new(State {
+                mode: mode & 0o7777,
+                content,
+            }),
+        });
+        shared.nodes.lock().insert(ino, Arc::downgrade(&node));
+        node
+    }
+
+    /// adds a new child to this directory.
+    fn add_child(&self, name: &str, mode: u16, content: Content) -> Result<VNode> {
+        check_name(name)?;
+        let mut state = self.state.lock();
+        let Content::Dir { children, .. } = &mut state.content else {
+            return Err(Error::NotDirectory(name.to_string()));
+        };
+        if children.contains_key(name) {
+            return Err(Error::AlreadyExists(name.to_string()));
+        }
+        let content = match content {
+            Content::Dir { children, .. } => Content::Dir {
+                children,
+                parent: Some(self.this.clone()),
+            },
+            content => content,
+        };
+        let node = TmpNode::new(&self.shared, mode, content);
+        children.insert(name.to_string(), node.clone());
+        Ok(node as VNode)
+    }
+
+    /// removes the child `name` if `check` accepts it.
+    fn remove_child(&self, name: &str, check: impl FnOnce(&TmpNode) -> Result<()>) -> Result<()> {
+        let mut state = self.state.lock();
+        let Content::Dir { children, .. } = &mut state.content else {
+            return Err(Error::NotDirectory(name.to_string()));
+        };
+        let child = children
+            .get(name)
+            .ok_or_else(|| Error::NoSuchPath(name.to_string()))?;
+        check(child)?;
+        children.remove(name);
+        Ok(())
+    }
+
+    fn ty(&self) -> FileType {
+        self.state.lock().content.ty()
+    }
+
+    fn is_empty_dir(&self) -> bool {
+        matches!(&self.state.lock().content, Content::Dir { children, .. } if children.is_empty())
+    }
+
+    fn parent(&self) -> Option<Arc<TmpNode>> {
+        match &self.state.lock().content {
+            Content::Dir {
+                parent: Some(parent),
+                ..
+            } => parent.upgrade(),
+            _ => None,
+        }
+    }
+
+    /// whether this node is `ancestor` or lies below it.
+    fn is_below(&self, ancestor: &Arc<TmpNode>) -> bool {
+        let mut node = self.this.upgrade();
+        while let Some(dir) = node {
+            if Arc::ptr_eq(&dir, ancestor) {
+                return true;
+            }
+            node = dir.parent();
+        }
+        false
+    }
+
+    /// the node of this filesystem behind `vnode`.
+    fn find(&self, vnode: &VNode) -> Option<Arc<TmpNode>> {
+        let ino = vnode.stat().ok()?.ino;
+        let node = self.shared.nodes.lock().get(&ino).and_then(Weak::upgrade)?;
+        // another filesystem may use the same inode number.
+        let same = core::ptr::eq(Arc::as_ptr(vnode).cast::<()>(), Arc::as_ptr(&node).cast());
+        same.then_some(node)
+    }
+}
+
+impl Drop for TmpNode {
+    fn drop(&mut self) {
+        self.shared.nodes.lock().remove(&self.ino);
+        if let Content::File { pages, .. } = &self.state.lock().content {
+            self.shared.release_pages(pages.len());
+        }
+    }
+}
+
+fn check_name(name: &str) -> Result<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        Err(Error::InvalidPath(name.to_string()))
+    } else {
+        Ok(())
+    }
+}
+
+impl Inode for TmpNode {
+    fn stat(&self) -> Result<Stat> {
+        let state = self.state.lock();
+        let size = match &state.content {
+            Content::File { size, .. } => *size,
+            Content::Dir { children, .. } => children.len(),
+            Content::Symlink(target) => target.len(),
+        };
+        Ok(Stat {
+            ino: self.ino,
+            ty: state.content.ty(),
+            mode: state.mode,
+            size,
+        })
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let state = self.state.lock();
+        let (size, pages) = match &state.content {
+            Content::File { size, pages } => (*size, pages),
+            Content::Dir { .. } => return Err(Error::IsDirectory("read".to_string())),
+            Content::Symlink(_) => return Err(Error::InvalidOperation("read".to_string())),
+        };
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min(size - offset);
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done;
+            let in_page = pos % PAGE_SIZE;
+            let chunk = (PAGE_SIZE - in_page).min(len - done);
+            let dst = &mut buf[done..done + chunk];
+            match pages.get(&(pos / PAGE_SIZE)) {
+                Some(page) => dst.copy_from_slice(&page.bytes()[in_page..in_page + chunk]),
+                None => dst.fill(0),
+            }
+            done += chunk;
+        }
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        let mut state = self.state.lock();
+        let (size, pages) = match &mut state.content {
+            Content::File { size, pages } => (size, pages),
+            Content::Dir { .. } => return Err(Error::IsDirectory("write".to_string())),
+            Content::Symlink(_) => return Err(Error::InvalidOperation("write".to_string())),
+        };
+        offset
+            .checked_add(buf.len())
+            .ok_or_else(|| Error::InvalidOperation("write past the end".to_string()))?;
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done;
+            let in_page = pos % PAGE_SIZE;
+            let chunk = (PAGE_SIZE - in_page).min(buf.len() - done);
+            let page = match pages.entry(pos / PAGE_SIZE) {
+                btree_map::Entry::Occupied(entry) => entry.into_mut(),
+                btree_map::Entry::Vacant(entry) => match self.shared.alloc_page() {
+                    Ok(page) => entry.insert(page),
+                    Err(e) if done == 0 => return Err(e),
+                    // the part which fit was written.
+                    Err(_) => break,
+                },
+            };
+            page.bytes_mut()[in_page..in_page + chunk].copy_from_slice(&buf[done..done + chunk]);
+            done += chunk;
+        }
+        *size = (*size).max(offset + done);
+        Ok(done)
+    }
+
+    fn truncate(&self, new_size: usize) -> Result<()> {
+        let mut state = self.state.lock();
+        let Content::File { size, pages } = &mut state.content else {
+            return Err(Error::IsDirectory("truncate".to_string()));
+        };
+        if new_size < *size {
+            // drop whole pages past the end and zero the tail of the last
+            // one, so growing the file again reads zeros.
+            let cut = pages.split_off(&new_size.div_ceil(PAGE_SIZE));
+            self.shared.release_pages(cut.len());
+            if new_size % PAGE_SIZE != 0 {
+                if let Some(page) = pages.get_mut(&(new_size / PAGE_SIZE)) {
+                    page.bytes_mut()[new_size % PAGE_SIZE..].fill(0);
+                }
+            }
+        }
+        *size = new_size;
+        Ok(())
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        let state = self.state.lock();
+        let Content::Dir { children, .. } = &state.content else {
+            return Err(Error::NotDirectory("readdir".to_string()));
+        };
+        Ok(children
+            .iter()
+            .map(|(name, node)| DirEntry {
+                name: name.clone(),
+                ino: node.ino,
+                ty: node.ty(),
+            })
+            .collect())
+    }
+
+    fn lookup(&self, name: &str) -> Result<VNode> {
+        let state = self.state.lock();
+        let Content::Dir { children, .. } = &state.content else {
+            return Err(Error::NotDirectory(name.to_string()));
+        };
+        children
+            .get(name)
+            .map(|node| node.clone() as VNode)
+            .ok_or_else(|| Error::NoSuchPath(name.to_string()))
+    }
+
+    fn readlink(&self) -> Result<String> {
+        match &self.state.lock().content {
+            Content::Symlink(target) => Ok(target.clone()),
+            _ => Err(Error::InvalidOperation("readlink".to_string())),
+        }
+    }
+
+    fn create(&self, name: &str, mode: u16) -> Result<VNode> {
+        let content = Content::File {
+            size: 0,
+            pages: BTreeMap::new(),
+        };
+        self.add_child(name, mode, content)
+    }
+
+    fn mkdir(&self, name: &str, mode: u16) -> Result<VNode> {
+        let content = Content::Dir {
+            children: BTreeMap::new(),
+            parent: None,
+        };
+        self.add_child(name, mode, content)
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<VNode> {
+        self.add_child(name, 0o777, Content::Symlink(target.to_string()))
+    }
+
+    fn unlink(&self, name: &str) -> Result<()> {
+        self.remove_child(name, |child| match child.ty() {
+            FileType::Directory => Err(Error::IsDirectory(name.to_string())),
+            _ => Ok(()),
+        })
+    }
+
+    fn rmdir(&self, name: &str) -> Result<()> {
+        self.remove_child(name, |child| {
+            if child.ty() != FileType::Directory {
+                Err(Error::NotDirectory(name.to_string()))
+            } else if !child.is_empty_dir() {
+                Err(Error::NotEmpty(name.to_string()))
+            } else {
+                Ok(())
+            }
+        })
+    }
+
+    fn rename(&self, name: &str, new_dir: &VNode, new_name: &str) -> Result<()> {
+        check_name(new_name)?;
+        let _guard = self.shared.rename_lock.lock();
+        let new_dir = self
+            .find(new_dir)
+            .ok_or_else(|| Error::CrossDevice(new_name.to_string()))?;
+        let node = match &self.state.lock().content {
+            Content::Dir { children, .. } => children
+                .get(name)
+                .cloned()
+                .ok_or_else(|| Error::NoSuchPath(name.to_string()))?,
+            _ => return Err(Error::NotDirectory(name.to_string())),
+        };
+        let is_dir = node.ty() == FileType::Directory;
+        if is_dir && new_dir.is_below(&node) {
+            return Err(Error::InvalidPath(new_name.to_string()));
+        }
+
+        // the target may be replaced by a node of the same kind.
+        {
+            let state = new_dir.state.lock();
+            let Content::Dir { children, .. } = &state.content else {
+                return Err(Error::NotDirectory(new_name.to_string()));
+            };
+            if let Some(existing) = children.get(new_name) {
+                if Arc::ptr_eq(existing, &node) {
+                    return Ok(());
+                }
+                match (is_dir, existing.ty() == FileType::Directory) {
+                    (true, false) => return Err(Error::NotDirectory(new_name.to_string())),
+                    (false, true) => return Err(Error::IsDirectory(new_name.to_string())),
+                    (true, true) if !existing.is_empty_dir() => {
+                        return Err(Error::NotEmpty(new_name.to_string()))
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        if let Content::Dir { children, .. } = &mut self.state.lock().content {
+            children.remove(name);
+        }
+        if let Content::Dir { children, .. } = &mut new_dir.state.lock().content {
+            children.insert(new_name.to_string(), node.clone());
+        }
+        if let Content::Dir { parent, .. } = &mut node.state.lock().content {
+            *parent = Some(new_dir.this.clone());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX_PAGES: usize = 64;
+
+    fn root() -> VNode {
+        TmpFs::new(MAX_PAGES).root().unwrap()
+    }
+
+    fn names(dir: &VNode) -> Vec<String> {
+        dir.readdir().unwrap().into_iter().map(|e| e.name).collect()
+    }
+
+    #[test]
+    fn write_and_read_back() {
+        let root = root();
+        let file = root.create("a", 0o644).unwrap();
+        assert_eq!(file.write_at(0, b"hello world").unwrap(), 11);
+        let mut buf = [0; 32];
+        assert_eq!(file.read_at(6, &mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], b"world");
+        assert_eq!(file.read_at(11, &mut buf).unwrap(), 0);
+        let stat = root.lookup("a").unwrap().stat().unwrap();
+        assert_eq!(
+            (stat.ty, stat.mode, stat.size),
+            (FileType::Regular, 0o644, 11)
+        );
+    }
+
+    #[test]
+    fn writes_across_pages_leave_holes() {
+        let file = root().create("sparse", 0o644).unwrap();
+        let offset = 3 * PAGE_SIZE - 2;
+        file.write_at(offset, b"abcd").unwrap();
+        assert_eq!(file.stat().unwrap().size, offset + 4);
+        let mut buf = vec![0xff; offset + 4];
+        assert_eq!(file.read_at(0, &mut buf).unwrap(), offset + 4);
+        assert!(buf[..offset].iter().all(|&b| b == 0));
+        assert_eq!(&buf[offset..], b"abcd");
+    }
+
+    #[test]
+    fn truncate_zeroes_the_cut_off_part() {
+        let file = root().create("t", 0o644).unwrap();
+        file.write_at(0, &[1; PAGE_SIZE + 10]).unwrap();
+        file.truncate(5).unwrap();
+        assert_eq!(file.stat().unwrap().size, 5);
+        file.truncate(PAGE_SIZE + 10).unwrap();
+        let mut buf = vec![0xff; PAGE_SIZE + 10];
+        file.read_at(0, &mut buf).unwrap();
+        assert_eq!(&buf[..5], &[1; 5]);
+        assert!(buf[5..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn writes_stop_at_the_page_limit() {
+        let root = TmpFs::new(2).root().unwrap();
+        let a = root.create("a", 0o644).unwrap();
+        assert_eq!(
+            a.write_at(PAGE_SIZE / 2, &[1; 2 * PAGE_SIZE]).unwrap(),
+            PAGE_SIZE + PAGE_SIZE / 2
+        );
+        assert_eq!(a.stat().unwrap().size, 2 * PAGE_SIZE);
+        let b = root.create("b", 0o644).unwrap();
+        assert!(matches!(b.write_at(0, b"x"), Err(Error::NoSpace(_))));
+        assert_eq!(b.stat().unwrap().size, 0);
+
+        // pages given back by truncating or unlinking can be used again.
+        a.truncate(PAGE_SIZE).unwrap();
+        assert_eq!(b.write_at(0, b"x").unwrap(), 1);
+        assert!(matches!(
+            b.write_at(PAGE_SIZE, b"x"),
+            Err(Error::NoSpace(_))
+        ));
+        drop(a);
+        root.unlink("a").unwrap();
+        assert_eq!(b.write_at(PAGE_SIZE, b"x").unwrap(), 1);
+    }
+
+    #[test]
+    fn directories() {
+        let root = root();
+        let dir = root.mkdir("d", 0o755).unwrap();
+        dir.create("f", 0o644).unwrap();
+        assert!(matches!(
+            root.mkdir("d", 0o755),
+            Err(Error::AlreadyExists(_))
+        ));
+        assert!(matches!(root.rmdir("d"), Err(Error::NotEmpty(_))));
+        assert!(matches!(root.unlink("d"), Err(Error::IsDirectory(_))));
+        assert!(matches!(dir.rmdir("f"), Err(Error::NotDirectory(_))));
+        dir.unlink("f").unwrap();
+        root.rmdir("d").unwrap();
+        assert!(names(&root).is_empty());
+        assert!(matches!(root.lookup("d"), Err(Error::NoSuchPath(_))));
+    }
+
+    #[test]
+    fn invalid_names() {
+        let root = root();
+        for name in ["", ".", "..", "a/b"] {
+            assert!(matches!(
+                root.create(name, 0o644),
+                Err(Error::InvalidPath(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn symlinks() {
+        let root = root();
+        let link = root.symlink("l", "/some/where").unwrap();
+        assert_eq!(link.stat().unwrap().ty, FileType::Symlink);
+        assert_eq!(link.readlink().unwrap(), "/some/where");
+    }
+
+    #[test]
+    fn rename_moves_and_replaces() {
+        let root = root();
+        let a = root.mkdir("a", 0o755).unwrap();
+        let b = root.mkdir("b", 0o755).unwrap();
+        a.create("f", 0o644).unwrap().write_at(0, b"1").unwrap();
+        b.create("g", 0o644).unwrap();
+        a.rename("f", &b, "g").unwrap();
+        assert!(names(&a).is_empty());
+        assert_eq!(names(&b), ["g"]);
+        assert_eq!(b.lookup("g").unwrap().stat().unwrap().size, 1);
+
+        root.rename("a", &b, "a2").unwrap();
+        assert_eq!(names(&root), ["b"]);
+        assert_eq!(names(&b), ["a2", "g"]);
+    }
+
+    #[test]
+    fn rename_checks_types() {
+        let root = root();
+        let dir = root.mkdir("d", 0o755).unwrap();
+        root.mkdir("e", 0o755).unwrap().create("x", 0o644).unwrap();
+        root.create("f", 0o644).unwrap();
+        assert!(matches!(
+            root.rename("f", &root, "d"),
+            Err(Error::IsDirectory(_))
+        ));
+        assert!(matches!(
+            root.rename("d", &root, "f"),
+            Err(Error::NotDirectory(_))
+        ));
+        assert!(matches!(
+            root.rename("d", &root, "e"),
+            Err(Error::NotEmpty(_))
+        ));
+        assert!(matches!(
+            root.rename("d", &dir, "sub"),
+            Err(Error::InvalidPath(_))
+        ));
+        root.rename("e", &root, "d").unwrap();
+        assert_eq!(names(&root.lookup("d").unwrap()), ["x"]);
+    }
+
+    #[test]
+    fn rename_to_another_filesystem_fails() {
+        let root = root();
+        let other = self::root();
+        root.create("f", 0o644).unwrap();
+        assert!(matches!(
+            root.rename("f", &other, "f"),
+            Err(Error::CrossDevice(_))
+        ));
+    }
+
+    #[test]
+    fn removed_nodes_are_freed() {
+        let fs = TmpFs::new(MAX_PAGES);
+        let root = fs.root().unwrap();
+        root.mkdir("d", 0o755).unwrap().create("f", 0o644).unwrap();
+        assert_eq!(fs.root.shared.nodes.lock().len(), 3);
+        root.lookup("d").unwrap().unlink("f").unwrap();
+        root.rmdir("d").unwrap();
+        assert_eq!(fs.root.shared.nodes.lock().len(), 1);
+    }
+}
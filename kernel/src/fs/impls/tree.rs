@@ -0,0 +1,212 @@
+//! read-only directory tree over an image in memory, shared by the archive
+//! formats the initrd can be in.
+
+use crate::fs::{DirEntry, Error, FileType, Inode, Result, Stat, VNode};
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// inode number of the root directory, the other nodes are numbered in the
+/// order they are created.
+const ROOT_INO: usize = 0;
+
+/// mode of directories which are only implied by the paths below them.
+const IMPLIED_DIR_MODE: u16 = 0o555;
+
+/// A node to add to a tree.
+pub struct TreeEntry<'a> {
+    /// relative or absolute path, `.` components and trailing slashes are
+    /// ignored.
+    pub path: &'a str,
+    pub ty: FileType,
+    pub mode: u16,
+    /// contents of a file or the target of a symlink.
+    pub data: &'static [u8],
+}
+
+struct Node {
+    ty: FileType,
+    mode: u16,
+    data: &'static [u8],
+    children: BTreeMap<String, usize>,
+}
+
+impl Node {
+    fn dir(mode: u16) -> Self {
+        Self {
+            ty: FileType::Directory,
+            mode,
+            data: &[],
+            children: BTreeMap::new(),
+        }
+    }
+}
+
+pub struct Tree {
+    nodes: Vec<Node>,
+}
+
+impl Tree {
+    pub fn new() -> Self {
+        Self {
+            nodes: vec![Node::dir(IMPLIED_DIR_MODE)],
+        }
+    }
+
+    /// adds `entry`, creating the directories above it which don't exist yet.
+    /// adding a directory which was implied before sets its mode.
+    pub fn insert(&mut self, entry: TreeEntry) -> Result<()> {
+        let mut names = entry
+            .path
+            .split('/')
+            .filter(|name| !name.is_empty() && *name != ".");
+        if names.clone().any(|name| name == "..") {
+            return Err(Error::InvalidPath(entry.path.to_string()));
+        }
+        let Some(name) = names.next_back() else {
+            // the root itself.
+            if entry.ty != FileType::Directory {
+                return Err(Error::NotDirectory(entry.path.to_string()));
+            }
+            self.nodes[ROOT_INO].mode = entry.mode;
+            return Ok(());
+        };
+        let parent = self.make_dirs(names, entry.path)?;
+        match self.nodes[parent].children.get(name) {
+            Some(&index) if entry.ty == FileType::Directory => {
+                if self.nodes[index].ty != FileType::Directory {
+                    return Err(Error::InvalidFile(entry.path.to_string()));
+                }
+                self.nodes[index].mode = entry.mode;
+            }
+            Some(_) => return Err(Error::InvalidFile(entry.path.to_string())),
+            None => {
+                let index = self.nodes.len();
+                self.nodes.push(Node {
+                    ty: entry.ty,
+                    mode: entry.mode,
+                    data: entry.data,
+                    children: BTreeMap::new(),
+                });
+                self.nodes[parent].children.insert(name.to_string(), index);
+            }
+        }
+        Ok(())
+    }
+
+    /// the root directory of the finished tree.
+    pub fn root(self: &Arc<Self>) -> VNode {
+        Arc::new(TreeNode {
+            tree: self.clone(),
+            index: ROOT_INO,
+        })
+    }
+
+    /// creates the missing directories `names` and returns the last one.
+    fn make_dirs<'a>(&mut self, names: impl Iterator<Item = &'a str>, path: &str) -> Result<usize> {
+        let mut dir = ROOT_INO;
+        for name in names {
+            dir = match self.nodes[dir].children.get(name) {
+                Some(&index) if self.nodes[index].ty == FileType::Directory => index,
+                Some(_) => return Err(Error::NotDirectory(path.to_string())),
+                None => {
+                    let index = self.nodes.len();
+                    self.nodes.push(Node::dir(IMPLIED_DIR_MODE));
+                    self.nodes[dir].children.insert(name.to_string(), index);
+                    index
+                }
+            };
+        }
+        Ok(dir)
+    }
+}
+
+impl Default for Tree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct TreeNode {
+    tree: Arc<Tree>,
+    index: usize,
+}
+
+impl TreeNode {
+    fn node(&self) -> &Node {
+        &self.tree.nodes[self.index]
+    }
+}
+
+impl Inode for TreeNode {
+    fn stat(&self) -> Result<Stat> {
+        let node = self.node();
+        Ok(Stat {
+            ino: self.index as u64,
+            ty: node.ty,
+            mode: node.mode,
+            size: node.data.len(),
+        })
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let node = self.node();
+        match node.ty {
+            FileType::Regular => {}
+            FileType::Directory => return Err(Error::IsDirectory("read".to_string())),
+            _ => return Err(Error::InvalidOperation("read".to_string())),
+        }
+        if offset >= node.data.len() {
+            return Ok(0);
+        }
+        let len = buf.len().min(node.data.len() - offset);
+        buf[..len].copy_from_slice(&node.data[offset..offset + len]);
+        Ok(len)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(Error::ReadOnly("write".to_string()))
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        let node = self.node();
+        if node.ty != FileType::Directory {
+            return Err(Error::NotDirectory("readdir".to_string()));
+        }
+        Ok(node
+            .children
+            .iter()
+            .map(|(name, &index)| DirEntry {
+                name: name.clone(),
+                ino: index as u64,
+                ty: self.tree.nodes[index].ty,
+            })
+            .collect())
+    }
+
+    fn lookup(&self, name: &str) -> Result<VNode> {
+        let node = self.node();
+        if node.ty != FileType::Directory {
+            return Err(Error::NotDirectory(name.to_string()));
+        }
+        let index = *node
+            .children
+            .get(name)
+            .ok_or_else(|| Error::NoSuchPath(name.to_string()))?;
+        Ok(Arc::new(TreeNode {
+            tree: self.tree.clone(),
+            index,
+        }))
+    }
+
+    fn readlink(&self) -> Result<String> {
+        let node = self.node();
+        if node.ty != FileType::Symlink {
+            return Err(Error::InvalidOperation("readlink".to_string()));
+        }
+        core::str::from_utf8(node.data)
+            .map(ToString::to_string)
+            .map_err(|_| Error::InvalidFile("symlink target".to_string()))
+    }
+}
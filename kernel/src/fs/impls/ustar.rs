@@ -0,0 +1,328 @@
+//! POSIX ustar archives, including the GNU long name and pax path
+//! extensions `tar` uses for paths over 100 bytes.
+//!
+//! Every member is a 512 byte header block followed by its data, padded to
+//! a whole block. The archive ends with a block of zeros.
+
+use super::tree::{Tree, TreeEntry};
+use crate::fs::{Error, FileType, Result};
+use alloc::string::{String, ToString};
+use core::str;
+
+const BLOCK_SIZE: usize = 512;
+
+const NAME: (usize, usize) = (0, 100);
+const MODE: (usize, usize) = (100, 8);
+const SIZE: (usize, usize) = (124, 12);
+const CHECKSUM: (usize, usize) = (148, 8);
+const TYPEFLAG: usize = 156;
+const LINKNAME: (usize, usize) = (157, 100);
+const MAGIC: (usize, usize) = (257, 6);
+const PREFIX: (usize, usize) = (345, 155);
+
+/// `ustar\0` from POSIX, or `ustar ` from older GNU tar.
+const MAGIC_POSIX: &[u8] = b"ustar\0";
+const MAGIC_GNU: &[u8] = b"ustar ";
+
+const TYPE_REGULAR: u8 = b'0';
+const TYPE_REGULAR_OLD: u8 = 0;
+const TYPE_SYMLINK: u8 = b'2';
+const TYPE_DIRECTORY: u8 = b'5';
+const TYPE_CONTIGUOUS: u8 = b'7';
+/// pax extended header for the next member.
+const TYPE_PAX: u8 = b'x';
+/// GNU long name for the next member, in the data.
+const TYPE_GNU_LONGNAME: u8 = b'L';
+/// GNU long link target for the next member, in the data.
+const TYPE_GNU_LONGLINK: u8 = b'K';
+
+pub fn is_ustar(image: &[u8]) -> bool {
+    image
+        .get(MAGIC.0..MAGIC.0 + MAGIC.1)
+        .map_or(false, |magic| magic == MAGIC_POSIX || magic == MAGIC_GNU)
+}
+
+fn error(offset: usize, msg: &str) -> Error {
+    Error::InvalidFile(format!("ustar at {offset:#x}: {msg}"))
+}
+
+fn field(header: &[u8], (start, len): (usize, usize)) -> &[u8] {
+    &header[start..start + len]
+}
+
+/// a NUL terminated string field, which may also fill the whole field.
+fn str_field(header: &'static [u8], range: (usize, usize)) -> Option<&'static str> {
+    let bytes = &header[range.0..range.0 + range.1];
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    str::from_utf8(&bytes[..len]).ok()
+}
+
+/// an octal number padded with spaces or NULs, or a big endian binary number
+/// after a set high bit as GNU tar writes sizes over 8GiB.
+fn num_field(header: &[u8], range: (usize, usize)) -> Option<u64> {
+    let bytes = field(header, range);
+    if bytes[0] & 0x80 != 0 {
+        return Some(
+            bytes[1..]
+                .iter()
+                .fold((bytes[0] & 0x7F) as u64, |num, &b| (num << 8) | b as u64),
+        );
+    }
+    let digits = str::from_utf8(bytes).ok()?;
+    let digits = digits.trim_matches(|c| c == ' ' || c == '\0');
+    if digits.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(digits, 8).ok()
+}
+
+/// the sum of the header bytes with the checksum field taken as spaces.
+fn checksum_matches(header: &[u8]) -> bool {
+    let sum: u64 = header
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| {
+            if (CHECKSUM.0..CHECKSUM.0 + CHECKSUM.1).contains(&i) {
+                b' ' as u64
+            } else {
+                b as u64
+            }
+        })
+        .sum();
+    num_field(header, CHECKSUM) == Some(sum)
+}
+
+/// the value of `key` in pax extended header records, `<len> <key>=<value>\n`.
+fn pax_value(mut records: &'static [u8], key: &str) -> Option<&'static str> {
+    while !records.is_empty() {
+        let space = records.iter().position(|&b| b == b' ')?;
+        let len: usize = str::from_utf8(&records[..space]).ok()?.parse().ok()?;
+        let record = records.get(space + 1..len)?;
+        let record = str::from_utf8(record).ok()?.strip_suffix('\n')?;
+        if let Some((k, value)) = record.split_once('=') {
+            if k == key {
+                return Some(value);
+            }
+        }
+        records = &records[len..];
+    }
+    None
+}
+
+/// a path from a GNU long name member, which is NUL terminated.
+fn long_name(data: &'static [u8]) -> Option<&'static str> {
+    let len = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    str::from_utf8(&data[..len]).ok()
+}
+
+/// reads every member up to the end of archive block. hard links, devices
+/// and fifos are skipped.
+pub fn load(image: &'static [u8]) -> Result<Tree> {
+    let mut tree = Tree::new();
+    let mut offset = 0;
+    // names and link targets for the next member from extension headers.
+    let mut next_path: Option<&'static str> = None;
+    let mut next_link: Option<&'static str> = None;
+    loop {
+        let Some(header) = image.get(offset..offset + BLOCK_SIZE) else {
+            // archives without an end block are cut off, not broken.
+            return Ok(tree);
+        };
+        if header.iter().all(|&b| b == 0) {
+            return Ok(tree);
+        }
+        if !is_ustar(header) {
+            return Err(error(offset, "invalid magic"));
+        }
+        if !checksum_matches(header) {
+            return Err(error(offset, "checksum mismatch"));
+        }
+        // base-256 sizes can be close to 2^64, so the member's end can overflow.
+        let data_start = offset + BLOCK_SIZE;
+        let (data_end, next) = num_field(header, SIZE)
+            .and_then(|size| usize::try_from(size).ok())
+            .and_then(|size| {
+                let end = data_start.checked_add(size)?;
+                let next = data_start.checked_add(size.checked_next_multiple_of(BLOCK_SIZE)?)?;
+                Some((end, next))
+            })
+            .ok_or_else(|| error(offset, "invalid size"))?;
+        let data = image
+            .get(data_start..data_end)
+            .ok_or_else(|| error(offset, "truncated data"))?;
+
+        let typeflag = header[TYPEFLAG];
+        match typeflag {
+            TYPE_PAX => {
+                next_path = pax_value(data, "path").or(next_path);
+                next_link = pax_value(data, "linkpath").or(next_link);
+                offset = next;
+                continue;
+            }
+            TYPE_GNU_LONGNAME => {
+                next_path = Some(long_name(data).ok_or_else(|| error(offset, "invalid name"))?);
+                offset = next;
+                continue;
+            }
+            TYPE_GNU_LONGLINK => {
+                next_link = Some(long_name(data).ok_or_else(|| error(offset, "invalid name"))?);
+                offset = next;
+                continue;
+            }
+            _ => {}
+        }
+
+        let mut path_buf = String::new();
+        let path = match next_path.take() {
+            Some(path) => path,
+            None => {
+                let name = str_field(header, NAME).ok_or_else(|| error(offset, "invalid name"))?;
+                let prefix =
+                    str_field(header, PREFIX).ok_or_else(|| error(offset, "invalid name"))?;
+                if prefix.is_empty() {
+                    name
+                } else {
+                    path_buf.push_str(prefix);
+                    path_buf.push('/');
+                    path_buf.push_str(name);
+                    &path_buf
+                }
+            }
+        };
+        let link = match next_link.take() {
+            Some(link) => link,
+            None => str_field(header, LINKNAME).ok_or_else(|| error(offset, "invalid link"))?,
+        };
+        let mode = num_field(header, MODE).ok_or_else(|| error(offset, "invalid mode"))?;
+
+        let entry = match typeflag {
+            TYPE_REGULAR | TYPE_REGULAR_OLD | TYPE_CONTIGUOUS => Some((FileType::Regular, data)),
+            TYPE_DIRECTORY => Some((FileType::Directory, &[][..])),
+            TYPE_SYMLINK => Some((FileType::Symlink, link.as_bytes())),
+            _ => None,
+        };
+        if let Some((ty, data)) = entry {
+            tree.insert(TreeEntry {
+                path,
+                ty,
+                mode: (mode & 0o7777) as u16,
+                data,
+            })
+            .map_err(|e| error(offset, &e.to_string()))?;
+        }
+        offset = next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::sync::Arc;
+    use alloc::vec::Vec;
+
+    fn set(header: &mut [u8], (start, len): (usize, usize), value: &[u8]) {
+        header[start..start + len].fill(0);
+        header[start..start + value.len()].copy_from_slice(value);
+    }
+
+    /// stores the checksum of everything else in `header`.
+    fn seal(header: &mut [u8]) {
+        set(header, CHECKSUM, b"        ");
+        let sum: u32 = header.iter().map(|&b| b as u32).sum();
+        set(header, CHECKSUM, format!("{sum:06o}\0 ").as_bytes());
+    }
+
+    /// a member with the fields we read, padded to whole blocks.
+    fn member(name: &str, typeflag: u8, link: &str, data: &[u8]) -> Vec<u8> {
+        let mut member = vec![0; BLOCK_SIZE];
+        set(&mut member, NAME, name.as_bytes());
+        set(&mut member, MODE, b"0000644");
+        set(&mut member, SIZE, format!("{:011o}", data.len()).as_bytes());
+        member[TYPEFLAG] = typeflag;
+        set(&mut member, LINKNAME, link.as_bytes());
+        set(&mut member, MAGIC, MAGIC_POSIX);
+        seal(&mut member);
+        member.extend(data);
+        member.resize(member.len().next_multiple_of(BLOCK_SIZE), 0);
+        member
+    }
+
+    fn archive(members: &[Vec<u8>]) -> Vec<u8> {
+        let mut image = members.concat();
+        image.resize(image.len() + 2 * BLOCK_SIZE, 0);
+        image
+    }
+
+    fn is_invalid(result: Result<Tree>, msg: &str) -> bool {
+        matches!(result, Err(Error::InvalidFile(e)) if e.ends_with(msg))
+    }
+
+    #[test]
+    fn reads_members_and_long_names() {
+        let long = "d/".to_string() + &"n".repeat(150);
+        let image = archive(&[
+            member("d/", TYPE_DIRECTORY, "", &[]),
+            member("d/file", TYPE_REGULAR, "", b"hello"),
+            member("link", TYPE_SYMLINK, "d/file", &[]),
+            member("././@LongLink", TYPE_GNU_LONGNAME, "", long.as_bytes()),
+            member("cut", TYPE_REGULAR, "", b"long"),
+        ]);
+        let root = Arc::new(load(image.leak()).unwrap()).root();
+        let dir = root.lookup("d").unwrap();
+        let mut buf = [0; 8];
+        let file = dir.lookup("file").unwrap();
+        assert_eq!(file.read_at(0, &mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], b"hello");
+        assert_eq!(root.lookup("link").unwrap().readlink().unwrap(), "d/file");
+        let long = dir.lookup(&long[2..]).unwrap();
+        assert_eq!(long.stat().unwrap().size, 4);
+        assert!(root.lookup("cut").is_err());
+    }
+
+    #[test]
+    fn cut_off_archives_end_early() {
+        let image = archive(&[
+            member("a", TYPE_REGULAR, "", b"a"),
+            member("b", TYPE_REGULAR, "", b"b"),
+        ]);
+        // the second header is only half there.
+        let image = &image.leak()[..2 * BLOCK_SIZE + BLOCK_SIZE / 2];
+        let root = Arc::new(load(image).unwrap()).root();
+        assert!(root.lookup("a").is_ok());
+        assert!(root.lookup("b").is_err());
+    }
+
+    #[test]
+    fn bad_magic_and_checksums_fail() {
+        let mut header = member("a", TYPE_REGULAR, "", &[]);
+        set(&mut header, MAGIC, b"ustaR\0");
+        seal(&mut header);
+        assert!(is_invalid(load(archive(&[header]).leak()), "invalid magic"));
+
+        let mut header = member("a", TYPE_REGULAR, "", &[]);
+        header[NAME.0] = b'b';
+        assert!(is_invalid(
+            load(archive(&[header]).leak()),
+            "checksum mismatch"
+        ));
+    }
+
+    #[test]
+    fn sizes_past_the_end_fail() {
+        let mut header = member("a", TYPE_REGULAR, "", &[]);
+        set(&mut header, SIZE, b"77777777777");
+        seal(&mut header);
+        assert!(is_invalid(
+            load(archive(&[header]).leak()),
+            "truncated data"
+        ));
+
+        // a base-256 size which overflows the end of the member.
+        let mut header = member("a", TYPE_REGULAR, "", &[]);
+        let mut size = [0xFF; 12];
+        size[0] = 0x80;
+        set(&mut header, SIZE, &size);
+        seal(&mut header);
+        assert!(is_invalid(load(archive(&[header]).leak()), "invalid size"));
+    }
+}
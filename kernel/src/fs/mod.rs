@@ -1,11 +1,22 @@
+pub mod block;
+pub mod file;
 pub mod impls;
+pub mod path;
+pub mod pipe;
+pub mod vnode;
 
-use crate::util::locked::{Locked, ManualLock};
+use crate::mm::page_cache;
+use crate::util::locked::Locked;
+use ::syscall::Errno;
 use alloc::boxed::Box;
 use alloc::collections::BTreeMap;
 use alloc::string::{String, ToString};
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 
+pub use file::{Access, FileRef, OpenFile, SeekFrom};
+pub use vnode::{DirEntry, FileType, Inode, Poll, Stat, VNode};
+
 #[derive(Debug)]
 pub enum Error {
     InvalidOperation(String),
@@ -13,6 +24,20 @@ pub enum Error {
     NoSuchPath(String),
     InvalidMountPoint(String),
     InvalidPath(String),
+    NotDirectory(String),
+    IsDirectory(String),
+    Busy(String),
+    TooManySymlinks(String),
+    InvalidAccess(String),
+    AlreadyExists(String),
+    NotEmpty(String),
+    ReadOnly(String),
+    CrossDevice(String),
+    NoSpace(String),
+    Io(String),
+    /// the operation has to wait for something else to happen first.
+    WouldBlock(String),
+    BrokenPipe(String),
 }
 
 impl core::fmt::Display for Error {
@@ -23,136 +48,382 @@ impl core::fmt::Display for Error {
             Self::NoSuchPath(str) => format!("no such path: '{str}'"),
             Self::InvalidMountPoint(str) => format!("invalid mount point: {str}"),
             Self::InvalidPath(str) => format!("invalid path: '{str}'"),
+            Self::NotDirectory(str) => format!("not a directory: '{str}'"),
+            Self::IsDirectory(str) => format!("is a directory: '{str}'"),
+            Self::Busy(str) => format!("busy: '{str}'"),
+            Self::TooManySymlinks(str) => format!("too many levels of symlinks: '{str}'"),
+            Self::InvalidAccess(str) => format!("invalid access: {str}"),
+            Self::AlreadyExists(str) => format!("already exists: '{str}'"),
+            Self::NotEmpty(str) => format!("directory not empty: '{str}'"),
+            Self::ReadOnly(str) => format!("read-only filesystem: '{str}'"),
+            Self::CrossDevice(str) => format!("cross-device link: '{str}'"),
+            Self::NoSpace(str) => format!("no space left: '{str}'"),
+            Self::Io(str) => format!("io error: {str}"),
+            Self::WouldBlock(str) => format!("would block: '{str}'"),
+            Self::BrokenPipe(str) => format!("broken pipe: '{str}'"),
         })
     }
 }
 
 impl core::error::Error for Error {}
 
+impl From<Error> for Errno {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::InvalidOperation(_) => Errno::EINVAL,
+            Error::InvalidFile(_) => Errno::EINVAL,
+            Error::NoSuchPath(_) => Errno::ENOENT,
+            Error::InvalidMountPoint(_) => Errno::EINVAL,
+            Error::InvalidPath(_) => Errno::EINVAL,
+            Error::NotDirectory(_) => Errno::ENOTDIR,
+            Error::IsDirectory(_) => Errno::EISDIR,
+            Error::Busy(_) => Errno::EBUSY,
+            Error::TooManySymlinks(_) => Errno::ELOOP,
+            Error::InvalidAccess(_) => Errno::EBADF,
+            Error::AlreadyExists(_) => Errno::EEXIST,
+            Error::NotEmpty(_) => Errno::ENOTEMPTY,
+            Error::ReadOnly(_) => Errno::EROFS,
+            Error::CrossDevice(_) => Errno::EXDEV,
+            Error::NoSpace(_) => Errno::ENOSPC,
+            Error::Io(_) => Errno::EIO,
+            Error::WouldBlock(_) => Errno::EAGAIN,
+            Error::BrokenPipe(_) => Errno::EPIPE,
+        }
+    }
+}
+
 pub type Result<T> = core::result::Result<T, Error>;
 
-pub struct VNode {
-    lock: ManualLock,
-    write: fn(),
-    read: fn(),
-    close: fn(),
-    ioctl: fn(),
-    id: u64,
-}
+/// A mountable filesystem, everything else goes through its nodes.
+pub trait Fs: Send + Sync {
+    fn root(&self) -> Result<VNode>;
+
+    /// called before the filesystem is unmounted, once nothing uses it
+    /// anymore, to write back what it still holds.
+    fn unmount(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// whether regular files are read and written through the page cache,
+    /// which filesystems kept in memory anyway only need for their files to
+    /// be mapped.
+    fn page_cached(&self) -> bool {
+        false
+    }
+
+    /// whether nothing can be written, which the page cache checks before
+    /// taking writes it could never write back.
+    fn read_only(&self) -> bool {
+        false
+    }
+
+    /// fills `page` with the data of `node` from `offset`, which is page
+    /// aligned, for the page cache. what lies past the end of the file reads
+    /// as zeros.
+    fn readpage(&self, node: &VNode, offset: usize, page: &mut [u8]) -> Result<()> {
+        let mut done = 0;
+        while done < page.len() {
+            let read = node.read_at(offset + done, &mut page[done..])?;
+            if read == 0 {
+                break;
+            }
+            done += read;
+        }
+        page[done..].fill(0);
+        Ok(())
+    }
 
-pub struct Metadata {
-    pub name: String,
-    pub size: usize,
+    /// writes a dirty page of the page cache back to `node`, except for what
+    /// lies past the end of the file.
+    fn writepage(&self, node: &VNode, offset: usize, page: &[u8]) -> Result<()> {
+        let len = node.stat()?.size.saturating_sub(offset).min(page.len());
+        let mut done = 0;
+        while done < len {
+            let written = node.write_at(offset + done, &page[done..len])?;
+            if written == 0 {
+                return Err(Error::Io("page written back short".to_string()));
+            }
+            done += written;
+        }
+        Ok(())
+    }
 }
 
-pub trait Fs {
-    fn open(&mut self, path: &str) -> Result<VNode>;
+/// symlinks followed while resolving a single path, like Linux' `MAXSYMLINKS`.
+pub const MAX_SYMLINK_DEPTH: usize = 40;
 
-    fn ls(&mut self, path: &str) -> Result<Vec<Metadata>>;
+/// A filesystem mounted on a directory.
+pub struct Mount {
+    path: String,
+    fs: Arc<dyn Fs>,
 }
 
-struct MountPoint {
-    name: String,
-    fs: Box<dyn Fs>,
+impl Mount {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
 }
 
+/// held by every open file, so a mount can tell whether it is in use.
+pub type MountRef = Arc<Mount>;
+
 struct Vfs {
-    root_mount: Option<Box<dyn Fs>>,
-    mount_points: BTreeMap<String, MountPoint>,
+    /// mounts keyed by their normalised path.
+    mounts: BTreeMap<String, MountRef>,
 }
 
 impl Vfs {
     // standard functions.
 
-    fn mount(&mut self, name: &str, fs: Box<dyn Fs>) -> Result<()> {
-        if Self::is_mount_name_root(name) {
-            self.root_mount = Some(fs)
-        } else {
-            let string = name.to_string();
-            self.mount_points
-                .insert(string.clone(), MountPoint { name: string, fs });
+    fn mount(&mut self, path: &str, fs: Box<dyn Fs>) -> Result<()> {
+        let path = path::normalize(path)?;
+        if self.mounts.contains_key(&path) {
+            return Err(Error::InvalidMountPoint(format!(
+                "'{path}' is already mounted"
+            )));
         }
+        // a mount point has to be a directory, unless no filesystem is
+        // mounted above it yet.
+        if self.find_mount(&path).is_ok() {
+            let (node, _) = self.lookup(&path)?;
+            if node.stat()?.ty != FileType::Directory {
+                return Err(Error::NotDirectory(path));
+            }
+        }
+        let mount = Arc::new(Mount {
+            path: path.clone(),
+            fs: fs.into(),
+        });
+        self.mounts.insert(path, mount);
         Ok(())
     }
 
-    fn umount(&mut self, name: &str) -> Result<()> {
-        if self.mount_points.contains_key(name) {
-            Ok(())
-        } else {
-            Err(Error::InvalidMountPoint(name.to_string()))
+    fn umount(&mut self, path: &str) -> Result<()> {
+        let path = path::normalize(path)?;
+        let mount = self
+            .mounts
+            .get(&path)
+            .ok_or_else(|| Error::InvalidMountPoint(path.clone()))?;
+        let has_submounts = self
+            .mounts
+            .keys()
+            .any(|other| *other != path && path::starts_with(other, &path));
+        if has_submounts || Arc::strong_count(mount) > 1 {
+            return Err(Error::Busy(path));
         }
+        page_cache::release_fs(&mount.fs)?;
+        mount.fs.unmount()?;
+        self.mounts.remove(&path);
+        Ok(())
     }
 
-    fn open(&mut self, path: &str) -> Result<VNode> {
-        let (mount, path) = self.resolve_path(path)?;
-        todo!()
+    /// resolves `path` to a node and the mount it belongs to, following
+    /// symlinks on the way.
+    fn lookup(&self, path: &str) -> Result<(VNode, MountRef)> {
+        let mut path = path::normalize(path)?;
+        let mut symlinks = 0;
+        'resolve: loop {
+            // the deepest mount containing the path hides everything above it.
+            let mount = self.find_mount(&path)?;
+            let mut node = mount.fs.root()?;
+            let mut walked = mount.path.clone();
+            let rest = path::strip_prefix(&path, &mount.path).unwrap_or("");
+            let names: Vec<&str> = path::components(rest).collect();
+            for (i, name) in names.iter().enumerate() {
+                let child = node.lookup(name)?;
+                walked = path::join(&walked, name);
+                if child.stat()?.ty == FileType::Symlink {
+                    symlinks += 1;
+                    if symlinks > MAX_SYMLINK_DEPTH {
+                        return Err(Error::TooManySymlinks(path));
+                    }
+                    let target = child.readlink()?;
+                    let mut resolved = path::join(path::parent(&walked), &target);
+                    for name in &names[i + 1..] {
+                        resolved = path::join(&resolved, name);
+                    }
+                    path = path::normalize(&resolved)?;
+                    continue 'resolve;
+                }
+                node = child;
+            }
+            return Ok((node, mount.clone()));
+        }
     }
 
-    fn ls(&mut self, path: &str) -> Result<Vec<Metadata>> {
-        todo!()
+    /// resolves the directory containing `path` and the name of `path` in
+    /// it. the name itself isn't looked up, so it may not exist yet.
+    fn lookup_parent(&self, path: &str) -> Result<(VNode, MountRef, String)> {
+        let path = path::normalize(path)?;
+        let name = path::components(&path)
+            .last()
+            .ok_or_else(|| Error::InvalidPath(path.clone()))?
+            .to_string();
+        if self.mounts.contains_key(&path) {
+            return Err(Error::Busy(path));
+        }
+        let (dir, mount) = self.lookup(path::parent(&path))?;
+        Ok((dir, mount, name))
     }
 
     // helper functions
 
-    fn resolve_path<'a>(&'a self, path: &'a str) -> Result<(&str, &str)> {
-        if path.chars().next() == Some(':') {
-            let find = path.find('/').ok_or(Error::InvalidPath(path.to_string()))?;
-            unsafe {
-                Ok((
-                    &*core::ptr::from_raw_parts::<str>(path.as_ptr().add(1) as *const _, find - 1),
-                    &*core::ptr::from_raw_parts::<str>(
-                        path.as_ptr().add(find) as *const _,
-                        path.len() - find,
-                    ),
-                ))
-            }
-        } else {
-            Ok(("", path))
-        }
-    }
-
-    fn is_mount_name_root(name: &str) -> bool {
-        name == ""
+    /// the mount with the longest path containing the normalised `path`.
+    fn find_mount(&self, path: &str) -> Result<&MountRef> {
+        self.mounts
+            .iter()
+            .filter(|(mount_path, _)| path::starts_with(path, mount_path))
+            .max_by_key(|(mount_path, _)| mount_path.len())
+            .map(|(_, mount)| mount)
+            .ok_or_else(|| Error::NoSuchPath(path.to_string()))
     }
 }
 
 static VFS: Locked<Vfs> = Locked::new(Vfs {
-    root_mount: None,
-    mount_points: BTreeMap::new(),
+    mounts: BTreeMap::new(),
 });
 
-pub fn mount(name: &str, fs: Box<dyn Fs>) -> Result<()> {
-    VFS.lock().mount(name, fs)
+/// mounts `fs` on the directory `path`.
+pub fn mount(path: &str, fs: Box<dyn Fs>) -> Result<()> {
+    VFS.lock().mount(path, fs)
+}
+
+/// fails if files are still open in the mount or other filesystems are
+/// mounted below it.
+pub fn umount(path: &str) -> Result<()> {
+    VFS.lock().umount(path)
+}
+
+pub fn open(path: &str, access: Access) -> Result<FileRef> {
+    let (vnode, mount) = VFS.lock().lookup(path)?;
+    Ok(OpenFile::new_mounted(vnode, access, mount))
+}
+
+pub fn stat(path: &str) -> Result<Stat> {
+    let (vnode, _) = VFS.lock().lookup(path)?;
+    vnode.stat()
 }
 
-pub fn umount(name: &str) -> Result<()> {
-    VFS.lock().umount(name)
+pub fn ls(path: &str) -> Result<Vec<DirEntry>> {
+    let (vnode, _) = VFS.lock().lookup(path)?;
+    vnode.readdir()
 }
 
-pub fn open(path: &str) -> Result<VNode> {
-    VFS.lock().open(path)
+/// creates the empty file `path` and opens it.
+pub fn create(path: &str, mode: u16, access: Access) -> Result<FileRef> {
+    let (dir, mount, name) = VFS.lock().lookup_parent(path)?;
+    let vnode = dir.create(&name, mode)?;
+    Ok(OpenFile::new_mounted(vnode, access, mount))
 }
 
-pub fn ls(path: &str) -> Result<Vec<Metadata>> {
-    VFS.lock().ls(path)
+pub fn mkdir(path: &str, mode: u16) -> Result<()> {
+    let (dir, _, name) = VFS.lock().lookup_parent(path)?;
+    dir.mkdir(&name, mode).map(|_| ())
 }
 
-pub fn close(inode: VNode) -> Result<()> {
-    //let _lock = inode.lock.lock_empty();
+/// creates `path` as a symlink to `target`, which isn't checked.
+pub fn symlink(path: &str, target: &str) -> Result<()> {
+    let (dir, _, name) = VFS.lock().lookup_parent(path)?;
+    dir.symlink(&name, target).map(|_| ())
+}
+
+/// the cached pages of the file are written back and dropped, as nothing
+/// can open it anymore through this name.
+pub fn unlink(path: &str) -> Result<()> {
+    let (dir, _, name) = VFS.lock().lookup_parent(path)?;
+    let node = dir.lookup(&name)?;
+    dir.unlink(&name)?;
+    page_cache::release(&node)
+}
+
+pub fn rmdir(path: &str) -> Result<()> {
+    let (dir, _, name) = VFS.lock().lookup_parent(path)?;
+    dir.rmdir(&name)
+}
+
+/// moves `from` to `to`, which have to be on the same mount.
+pub fn rename(from: &str, to: &str) -> Result<()> {
+    let (from_dir, from_mount, from_name, to_dir, to_mount, to_name) = {
+        let vfs = VFS.lock();
+        let (from_dir, from_mount, from_name) = vfs.lookup_parent(from)?;
+        let (to_dir, to_mount, to_name) = vfs.lookup_parent(to)?;
+        (from_dir, from_mount, from_name, to_dir, to_mount, to_name)
+    };
+    if !Arc::ptr_eq(&from_mount, &to_mount) {
+        return Err(Error::CrossDevice(to.to_string()));
+    }
+    let replaced = to_dir.lookup(&to_name).ok();
+    from_dir.rename(&from_name, &to_dir, &to_name)?;
+    match replaced {
+        Some(node) => page_cache::release(&node),
+        None => Ok(()),
+    }
+}
+
+pub fn truncate(path: &str, size: usize) -> Result<()> {
+    let (vnode, _) = VFS.lock().lookup(path)?;
+    vnode.truncate(size)?;
+    page_cache::truncate(&vnode, size);
     Ok(())
 }
 
-pub fn write(inode: VNode, buf: &[u8]) -> Result<usize> {
-    //let _lock = inode.lock.lock_empty();
-    Ok(todo!())
+/// recreates everything below the directory `src` in the directory `dst`,
+/// which may belong to another filesystem.
+pub fn copy_tree(src: &VNode, dst: &VNode) -> Result<()> {
+    let mut buf = vec![0; 4096];
+    for entry in src.readdir()? {
+        let node = src.lookup(&entry.name)?;
+        let stat = node.stat()?;
+        match stat.ty {
+            FileType::Directory => {
+                let dir = match dst.mkdir(&entry.name, stat.mode) {
+                    Err(Error::AlreadyExists(_)) => dst.lookup(&entry.name)?,
+                    dir => dir?,
+                };
+                copy_tree(&node, &dir)?;
+            }
+            FileType::Regular => {
+                let file = dst.create(&entry.name, stat.mode)?;
+                let mut offset = 0;
+                loop {
+                    let read = node.read_at(offset, &mut buf)?;
+                    if read == 0 {
+                        break;
+                    }
+                    file.write_at(offset, &buf[..read])?;
+                    offset += read;
+                }
+            }
+            FileType::Symlink => {
+                dst.symlink(&entry.name, &node.readlink()?)?;
+            }
+            // device nodes and pipes only make sense where they are.
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// drops this handle, the file is closed once every handle to it is gone.
+pub fn close(file: FileRef) -> Result<()> {
+    drop(file);
+    Ok(())
+}
+
+pub fn write(file: &FileRef, buf: &[u8]) -> Result<usize> {
+    file.write(buf)
+}
+
+pub fn read(file: &FileRef, buf: &mut [u8]) -> Result<usize> {
+    file.read(buf)
 }
 
-pub fn read(inode: VNode, buf: &mut [u8]) -> Result<usize> {
-    //let _lock = inode.lock.lock_empty();
-    Ok(todo!())
+/// forwards `request` to the node behind `file`, which for devices is the
+/// driver's `CharDevice::ioctl`.
+pub fn ioctl(file: &FileRef, request: u64, buf: &mut [u8]) -> Result<u64> {
+    file.ioctl(request, buf)
 }
 
-pub fn ioctl(inode: VNode, request: u64, buf: &mut [u8]) -> Result<()> {
-    //let _lock = inode.lock.lock_empty();
-    Ok(todo!())
+pub fn poll(file: &FileRef) -> Poll {
+    file.poll()
 }
@@ -0,0 +1,132 @@
+//! POSIX style paths, which the VFS only handles in their normalised form: an
+//! absolute path without `.` or `..` components, duplicate slashes or a
+//! trailing slash.
+
+use super::{Error, Result};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+pub const SEPARATOR: char = '/';
+
+/// the names in `path`, skipping empty ones from duplicate slashes.
+pub fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split(SEPARATOR).filter(|name| !name.is_empty())
+}
+
+/// normalises the absolute path `path`. `..` at the root stays at the root.
+pub fn normalize(path: &str) -> Result<String> {
+    if !path.starts_with(SEPARATOR) {
+        return Err(Error::InvalidPath(path.to_string()));
+    }
+    let mut names: Vec<&str> = Vec::new();
+    for name in components(path) {
+        match name {
+            "." => {}
+            ".." => {
+                names.pop();
+            }
+            name => names.push(name),
+        }
+    }
+    let mut normalized = String::with_capacity(path.len());
+    for name in &names {
+        normalized.push(SEPARATOR);
+        normalized.push_str(name);
+    }
+    if normalized.is_empty() {
+        normalized.push(SEPARATOR);
+    }
+    Ok(normalized)
+}
+
+/// appends `rel` to `base`, or returns `rel` if it is absolute. the result
+/// still has to be normalised.
+pub fn join(base: &str, rel: &str) -> String {
+    if rel.starts_with(SEPARATOR) {
+        return rel.to_string();
+    }
+    let mut joined = String::with_capacity(base.len() + rel.len() + 1);
+    joined.push_str(base);
+    if !joined.ends_with(SEPARATOR) {
+        joined.push(SEPARATOR);
+    }
+    joined.push_str(rel);
+    joined
+}
+
+/// parent directory of the normalised path `path`, the root is its own parent.
+pub fn parent(path: &str) -> &str {
+    match path.rfind(SEPARATOR) {
+        Some(0) | None => "/",
+        Some(i) => &path[..i],
+    }
+}
+
+/// whether the normalised path `path` is `prefix` or lies below it.
+pub fn starts_with(path: &str, prefix: &str) -> bool {
+    prefix == "/"
+        || path == prefix
+        || (path.starts_with(prefix) && path[prefix.len()..].starts_with(SEPARATOR))
+}
+
+/// the part of the normalised path `path` below `prefix`.
+pub fn strip_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    if !starts_with(path, prefix) {
+        return None;
+    }
+    if prefix == "/" {
+        Some(path)
+    } else {
+        Some(&path[prefix.len()..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_dots_and_slashes() {
+        assert_eq!(normalize("/").unwrap(), "/");
+        assert_eq!(normalize("//a//b/").unwrap(), "/a/b");
+        assert_eq!(normalize("/a/./b/../c").unwrap(), "/a/c");
+        assert_eq!(normalize("/a/b/../..").unwrap(), "/");
+    }
+
+    #[test]
+    fn dotdot_stays_at_the_root() {
+        assert_eq!(normalize("/..").unwrap(), "/");
+        assert_eq!(normalize("/../../a").unwrap(), "/a");
+    }
+
+    #[test]
+    fn relative_paths_are_invalid() {
+        assert!(matches!(normalize(""), Err(Error::InvalidPath(_))));
+        assert!(matches!(normalize("a/b"), Err(Error::InvalidPath(_))));
+    }
+
+    #[test]
+    fn joins_relative_paths() {
+        assert_eq!(join("/a", "b"), "/a/b");
+        assert_eq!(join("/a/", "b"), "/a/b");
+        assert_eq!(join("/", "b/c"), "/b/c");
+        assert_eq!(join("/a", "/b"), "/b");
+        assert_eq!(normalize(&join("/a/b", "../c")).unwrap(), "/a/c");
+    }
+
+    #[test]
+    fn parent_of_root_is_root() {
+        assert_eq!(parent("/"), "/");
+        assert_eq!(parent("/a"), "/");
+        assert_eq!(parent("/a/b"), "/a");
+    }
+
+    #[test]
+    fn strips_whole_components_only() {
+        assert_eq!(strip_prefix("/mnt/disk", "/mnt"), Some("/disk"));
+        assert_eq!(strip_prefix("/mnt", "/mnt"), Some(""));
+        assert_eq!(strip_prefix("/mnt/disk", "/"), Some("/mnt/disk"));
+        assert_eq!(strip_prefix("/mntx", "/mnt"), None);
+        assert_eq!(strip_prefix("/a", "/mnt"), None);
+    }
+}
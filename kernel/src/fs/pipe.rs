@@ -0,0 +1,209 @@
+//! anonymous pipes, a buffer written at one end and read from the other.
+//!
+//! A read from an empty pipe or a write to a full one fails with
+//! `Error::WouldBlock`. The syscalls put their thread to sleep on the end's
+//! wait queue then, which the other end wakes once it made progress or was
+//! closed.
+
+use super::vnode::{FileType, Inode, Poll, Stat};
+use super::{Access, Error, FileRef, OpenFile, Result};
+use crate::process::thread::WaitQueue;
+use crate::util::locked::Locked;
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use buf::ring::RingBuf;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// bytes a pipe holds before writes block.
+pub const PIPE_SIZE: usize = 4096;
+
+/// pipes don't belong to a filesystem, their inode numbers only tell them
+/// apart.
+static NEXT_INO: AtomicU64 = AtomicU64::new(1);
+
+struct Pipe {
+    ino: u64,
+    buf: Locked<RingBuf<u8, PIPE_SIZE>>,
+    /// cleared once the last reference to the end is gone.
+    reader_open: AtomicBool,
+    writer_open: AtomicBool,
+    /// threads waiting for something to read.
+    readers: WaitQueue,
+    /// threads waiting for room to write.
+    writers: WaitQueue,
+}
+
+impl Pipe {
+    fn stat(&self) -> Stat {
+        Stat {
+            ino: self.ino,
+            ty: FileType::Pipe,
+            mode: 0o600,
+            size: self.buf.lock().len(),
+        }
+    }
+}
+
+struct ReadEnd(Arc<Pipe>);
+
+struct WriteEnd(Arc<Pipe>);
+
+/// a new pipe, opened as its read and its write end. fails if there is no
+/// memory left for its buffer.
+pub fn pipe() -> Result<(FileRef, FileRef)> {
+    let pipe = Arc::try_new(Pipe {
+        ino: NEXT_INO.fetch_add(1, Ordering::Relaxed),
+        buf: Locked::new(RingBuf::new()),
+        reader_open: AtomicBool::new(true),
+        writer_open: AtomicBool::new(true),
+        readers: WaitQueue::new(),
+        writers: WaitQueue::new(),
+    })
+    .map_err(|_| Error::NoSpace("pipe".to_string()))?;
+    Ok((
+        OpenFile::new(Arc::new(ReadEnd(pipe.clone())), Access::READ),
+        OpenFile::new(Arc::new(WriteEnd(pipe)), Access::WRITE),
+    ))
+}
+
+impl Inode for ReadEnd {
+    fn stat(&self) -> Result<Stat> {
+        Ok(self.0.stat())
+    }
+
+    /// reads what is buffered, which is nothing at the end of the file once
+    /// the write end is closed.
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        // checked first, everything written before the close is read then.
+        let writer_open = self.0.writer_open.load(Ordering::Acquire);
+        let read = self.0.buf.lock().pop_into(buf);
+        if read == 0 && !buf.is_empty() && writer_open {
+            return Err(Error::WouldBlock("pipe empty".to_string()));
+        }
+        if read > 0 {
+            self.0.writers.wake_all();
+        }
+        Ok(read)
+    }
+
+    fn poll(&self) -> Poll {
+        if !self.0.buf.lock().is_empty() || !self.0.writer_open.load(Ordering::Acquire) {
+            Poll::READ
+        } else {
+            Poll::NONE
+        }
+    }
+
+    fn wait_queue(&self, ready: Poll) -> Option<&WaitQueue> {
+        ready.has(Poll::READ).then_some(&self.0.readers)
+    }
+}
+
+impl Drop for ReadEnd {
+    fn drop(&mut self) {
+        self.0.reader_open.store(false, Ordering::Release);
+        self.0.writers.wake_all();
+    }
+}
+
+impl Inode for WriteEnd {
+    fn stat(&self) -> Result<Stat> {
+        Ok(self.0.stat())
+    }
+
+    /// writes as much as fits, nothing is taken once the read end is closed.
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        if !self.0.reader_open.load(Ordering::Acquire) {
+            return Err(Error::BrokenPipe("read end closed".to_string()));
+        }
+        let written = self.0.buf.lock().push_slice(buf);
+        if written == 0 && !buf.is_empty() {
+            return Err(Error::WouldBlock("pipe full".to_string()));
+        }
+        if written > 0 {
+            self.0.readers.wake_all();
+        }
+        Ok(written)
+    }
+
+    /// writes to a pipe without reader are ready to fail right away.
+    fn poll(&self) -> Poll {
+        if !self.0.buf.lock().is_full() || !self.0.reader_open.load(Ordering::Acquire) {
+            Poll::WRITE
+        } else {
+            Poll::NONE
+        }
+    }
+
+    fn wait_queue(&self, ready: Poll) -> Option<&WaitQueue> {
+        ready.has(Poll::WRITE).then_some(&self.0.writers)
+    }
+}
+
+impl Drop for WriteEnd {
+    fn drop(&mut self) {
+        self.0.writer_open.store(false, Ordering::Release);
+        self.0.readers.wake_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::syscall::Errno;
+
+    #[test]
+    fn reads_what_was_written() {
+        let (reader, writer) = pipe().unwrap();
+        assert_eq!(writer.write(b"hello").unwrap(), 5);
+        assert_eq!(reader.stat().unwrap().size, 5);
+        let mut buf = [0; 3];
+        assert_eq!(reader.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf, b"hel");
+        assert_eq!(reader.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"lo");
+        assert!(matches!(reader.read(&mut buf), Err(Error::WouldBlock(_))));
+        assert!(!reader.poll().has(Poll::READ));
+    }
+
+    #[test]
+    fn full_pipe_blocks_writes() {
+        let (reader, writer) = pipe().unwrap();
+        assert_eq!(writer.write(&[1; PIPE_SIZE + 10]).unwrap(), PIPE_SIZE);
+        assert!(matches!(writer.write(&[2]), Err(Error::WouldBlock(_))));
+        assert!(!writer.poll().has(Poll::WRITE));
+        let mut buf = [0; 10];
+        reader.read(&mut buf).unwrap();
+        assert!(writer.poll().has(Poll::WRITE));
+        assert_eq!(writer.write(&[2; 20]).unwrap(), 10);
+    }
+
+    #[test]
+    fn ends_wait_for_their_own_direction() {
+        let (reader, writer) = pipe().unwrap();
+        assert!(reader.wait_queue(Poll::READ).is_some());
+        assert!(reader.wait_queue(Poll::WRITE).is_none());
+        assert!(writer.wait_queue(Poll::WRITE).is_some());
+        assert!(writer.wait_queue(Poll::READ).is_none());
+    }
+
+    #[test]
+    fn closed_write_end_reads_as_end_of_file() {
+        let (reader, writer) = pipe().unwrap();
+        writer.write(b"last").unwrap();
+        drop(writer);
+        let mut buf = [0; 8];
+        assert_eq!(reader.read(&mut buf).unwrap(), 4);
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+        assert!(reader.poll().has(Poll::READ));
+    }
+
+    #[test]
+    fn closed_read_end_breaks_the_pipe() {
+        let (reader, writer) = pipe().unwrap();
+        drop(reader);
+        let err = writer.write(b"lost").unwrap_err();
+        assert!(matches!(err, Error::BrokenPipe(_)));
+        assert_eq!(Errno::from(err), Errno::EPIPE);
+    }
+}
@@ -0,0 +1,135 @@
+use super::{Error, Result};
+use crate::mm::shared::SharedRef;
+use crate::process::thread::WaitQueue;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    Regular,
+    Directory,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+    Pipe,
+}
+
+// what a node is ready for without blocking, as reported by `Inode::poll`.
+bit_flags!(
+    pub struct Poll(u8);
+    READ = 0;
+    WRITE = 1;
+);
+
+#[derive(Debug, Clone, Copy)]
+pub struct Stat {
+    /// inode number, unique within the filesystem.
+    pub ino: u64,
+    pub ty: FileType,
+    /// permission bits, like the lower 12 bits of a unix mode.
+    pub mode: u16,
+    pub size: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    pub ino: u64,
+    pub ty: FileType,
+}
+
+/// A file, directory or device inside a filesystem.
+///
+/// Operations a node doesn't support fail with an error by default, so
+/// implementations only override what makes sense for them. Nodes are shared
+/// between everyone who opened them and have to handle their own locking.
+pub trait Inode: Send + Sync {
+    fn stat(&self) -> Result<Stat>;
+
+    /// reads from `offset` into `buf`, returns the number of bytes read which
+    /// is zero at the end of the file.
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize> {
+        Err(Error::InvalidOperation("read".to_string()))
+    }
+
+    /// writes `buf` at `offset`, returns the number of bytes written.
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(Error::InvalidOperation("write".to_string()))
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        Err(Error::NotDirectory("readdir".to_string()))
+    }
+
+    /// looks up the direct child `name` of a directory.
+    fn lookup(&self, name: &str) -> Result<VNode> {
+        Err(Error::NotDirectory(name.to_string()))
+    }
+
+    /// target of a symlink.
+    fn readlink(&self) -> Result<String> {
+        Err(Error::InvalidOperation("readlink".to_string()))
+    }
+
+    /// cuts off or extends a file to `size` bytes, extended parts read as
+    /// zeros.
+    fn truncate(&self, _size: usize) -> Result<()> {
+        Err(Error::InvalidOperation("truncate".to_string()))
+    }
+
+    /// creates the empty file `name` in a directory.
+    fn create(&self, name: &str, _mode: u16) -> Result<VNode> {
+        Err(Error::InvalidOperation(format!("create '{name}'")))
+    }
+
+    fn mkdir(&self, name: &str, _mode: u16) -> Result<VNode> {
+        Err(Error::InvalidOperation(format!("mkdir '{name}'")))
+    }
+
+    fn symlink(&self, name: &str, _target: &str) -> Result<VNode> {
+        Err(Error::InvalidOperation(format!("symlink '{name}'")))
+    }
+
+    /// removes the child `name` of a directory, which can't be a directory.
+    fn unlink(&self, name: &str) -> Result<()> {
+        Err(Error::InvalidOperation(format!("unlink '{name}'")))
+    }
+
+    /// removes the empty directory `name`.
+    fn rmdir(&self, name: &str) -> Result<()> {
+        Err(Error::InvalidOperation(format!("rmdir '{name}'")))
+    }
+
+    /// moves the child `name` to `new_name` in `new_dir`, which belongs to the
+    /// same filesystem. an existing `new_name` is replaced if it is a file or
+    /// an empty directory and `name` is of the same kind.
+    fn rename(&self, name: &str, _new_dir: &VNode, _new_name: &str) -> Result<()> {
+        Err(Error::InvalidOperation(format!("rename '{name}'")))
+    }
+
+    /// device specific request, `buf` is read and written by the device.
+    fn ioctl(&self, request: u64, _buf: &mut [u8]) -> Result<u64> {
+        Err(Error::InvalidOperation(format!("ioctl {request:#x}")))
+    }
+
+    /// whether a read or write would make progress right now. files in
+    /// memory are always ready.
+    fn poll(&self) -> Poll {
+        Poll::READ | Poll::WRITE
+    }
+
+    /// the threads sleeping until `poll` reports the node ready for `ready`,
+    /// for nodes whose reads or writes block.
+    fn wait_queue(&self, _ready: Poll) -> Option<&WaitQueue> {
+        None
+    }
+
+    /// the shared memory object behind the node, which is mapped instead of
+    /// the node's data.
+    fn shared_memory(&self) -> Option<SharedRef> {
+        None
+    }
+}
+
+pub type VNode = Arc<dyn Inode>;
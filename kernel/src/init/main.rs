@@ -1,19 +1,153 @@
 use crate::arch::interrupt;
 use crate::boot::BootInfo;
-use alloc::string::String;
+use crate::drivers::ata;
+use crate::fs::block::{self, BlockDeviceRef, Partition};
+use crate::fs::impls::ext2::Ext2Fs;
+use crate::fs::impls::fat::FatFs;
+use crate::fs::impls::{devfs::DevFs, initrd::InitrdFs, procfs::ProcFs, tmpfs::TmpFs};
+use crate::fs::{self, Access, Error, Fs, Result};
+use crate::mm::pmm;
+use crate::process;
+use crate::process::handle::{Entry, Object, Rights};
+use crate::process::loader::elf;
+use crate::process::thread::DEFAULT_STACK_PAGES;
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::ffi::CStr;
+use core::ops::Range;
+
+/// where the scratch tmpfs is mounted below the root.
+const TMP_MOUNT: &str = "/tmp";
+/// where drivers publish their devices.
+const DEV_MOUNT: &str = "/dev";
+/// where the kernel's state can be inspected.
+const PROC_MOUNT: &str = "/proc";
+/// where the boot partition of the disk is mounted.
+const BOOT_MOUNT: &str = "/boot";
+/// where the EFI system partition is mounted, if the disk has one.
+const EFI_MOUNT: &str = "/efi";
+/// the keyboard driver, from the initrd.
+const PS2_DRIVER: &str = "/ps2";
+/// the ISA line of the PS/2 keyboard.
+const PS2_KEYBOARD_IRQ: u8 = 1;
+/// the data port of the PS/2 controller.
+const PS2_DATA_PORT: Range<u16> = 0x60..0x61;
+/// the status and command port of the PS/2 controller.
+const PS2_STATUS_PORT: Range<u16> = 0x64..0x65;
+
+/// pages each tmpfs may fill, half of the memory.
+fn tmpfs_pages() -> usize {
+    pmm::stats().total_pages / 2
+}
+
+/// mounts a tmpfs as the root and copies the initrd into it, so everything
+/// from the initrd can be changed at runtime.
+unsafe fn mount_root(initrd: Option<(*const u8, usize)>) {
+    let root = TmpFs::new(tmpfs_pages());
+    if let Some((base, len)) = initrd {
+        match InitrdFs::from_raw(base, len) {
+            Ok(initrd) => {
+                let copied = initrd
+                    .root()
+                    .and_then(|src| fs::copy_tree(&src, &root.root()?));
+                match copied {
+                    Ok(()) => info!("copied {} initrd into '/'", initrd.format()),
+                    Err(e) => error!("failed to copy initrd: {e}"),
+                }
+            }
+            Err(e) => error!("failed to load initrd: {e}"),
+        }
+    }
+    if let Err(e) = fs::mount("/", Box::new(root)) {
+        error!("failed to mount root: {e}");
+        return;
+    }
+    mount_below_root(TMP_MOUNT, 0o1777, Box::new(TmpFs::new(tmpfs_pages())));
+    mount_below_root(DEV_MOUNT, 0o755, Box::new(DevFs));
+    mount_below_root(PROC_MOUNT, 0o555, Box::new(ProcFs));
+    // probed once, the disk's lock has to be shared by its partitions.
+    let disk = ata::primary();
+    match partition(disk.as_ref(), block::MBR_TYPE_LINUX).and_then(Ext2Fs::new) {
+        Ok(boot) => mount_below_root(BOOT_MOUNT, 0o755, Box::new(boot)),
+        Err(e) => error!("no boot partition: {e}"),
+    }
+    match partition(disk.as_ref(), block::MBR_TYPE_EFI).and_then(FatFs::new) {
+        Ok(esp) => mount_below_root(EFI_MOUNT, 0o755, Box::new(esp)),
+        Err(e) => info!("no EFI system partition: {e}"),
+    }
+    match fs::ls("/") {
+        Ok(entries) => {
+            for entry in entries {
+                info!("    {}", entry.name);
+            }
+        }
+        Err(e) => error!("failed to list root: {e}"),
+    }
+}
+
+/// the first partition of type `ty` on `disk`.
+fn partition(disk: Option<&BlockDeviceRef>, ty: u8) -> Result<BlockDeviceRef> {
+    let disk = disk.ok_or_else(|| Error::NoSuchPath("ata disk".to_string()))?;
+    let entry = block::mbr_partitions(&**disk)?
+        .into_iter()
+        .find(|entry| entry.ty == ty)
+        .ok_or_else(|| Error::NoSuchPath(format!("partition of type {ty:#x}")))?;
+    let partition = Partition::new(disk.clone(), entry.start, entry.count)?;
+    Ok(Arc::new(partition))
+}
+
+/// mounts `fs` at `path`, creating the directory if the initrd had none.
+fn mount_below_root(path: &str, mode: u16, fs: Box<dyn Fs>) {
+    let dir = match fs::stat(path) {
+        Ok(_) => Ok(()),
+        Err(_) => fs::mkdir(path, mode),
+    };
+    if let Err(e) = dir.and_then(|_| fs::mount(path, fs)) {
+        error!("failed to mount '{path}': {e}");
+    }
+}
+
+/// spawns the driver at `path`, granting it handles to `objects` from
+/// `HANDLE_GRANTED` on.
+fn spawn_driver(path: &str, objects: &[Object]) -> process::Result<()> {
+    let file = fs::open(path, Access::READ)?;
+    let rights = Rights::MANAGE | Rights::DUPLICATE | Rights::TRANSFER;
+    let grants: Vec<Entry> = objects
+        .iter()
+        .map(|object| Entry::new(object.clone(), rights))
+        .collect();
+    let (_, id) = elf::spawn_file(&file, DEFAULT_STACK_PAGES, &grants)?;
+    info!("spawned driver '{path}' as process {id}");
+    Ok(())
+}
 
 pub unsafe extern "C" fn main() -> ! {
     info!("entered kernel main...");
     let boot_info = BootInfo::get();
     info!("loaded modules count: {}", boot_info.modules.module_count);
     info!("loaded modules:");
+    let mut initrd = None;
     for i in 0..boot_info.modules.module_count as usize {
-        let path = unsafe {
-            let ptr = boot_info.modules.modules.as_ptr().add(i);
-            CStr::from_ptr((*ptr).path.as_ptr().unwrap())
-        };
+        let module = unsafe { &*boot_info.modules.modules.as_ptr().add(i) };
+        let path = unsafe { CStr::from_ptr(module.path.as_ptr().unwrap()) };
         info!("    {}", String::from_utf8_lossy(path.to_bytes()));
+        if path.to_bytes().ends_with(b"/initrd") {
+            initrd = Some((
+                module.base.as_ptr().unwrap() as *const u8,
+                module.length as usize,
+            ));
+        }
+    }
+    mount_root(initrd);
+    let ps2 = [
+        Object::Irq(PS2_KEYBOARD_IRQ),
+        Object::IoPorts(PS2_DATA_PORT),
+        Object::IoPorts(PS2_STATUS_PORT),
+    ];
+    if let Err(e) = spawn_driver(PS2_DRIVER, &ps2) {
+        error!("failed to spawn '{PS2_DRIVER}': {e}");
     }
     loop {
         debug!("main loop!");
@@ -6,45 +6,25 @@ use crate::arch::interrupt::StackFrame;
 use crate::boot::BootInfo;
 use crate::drivers;
 use crate::mm::heap;
-use crate::mm::pmm;
 use crate::mm::vmm;
-use crate::mm::vmm::Flags;
-use crate::mm::vmm::VMM;
 use crate::process::thread::{self, sched, ThreadId, ThreadPtr, ThreadScheduleStatus};
 use crate::process::ProcessPtr;
-use crate::util::adr::VirtAdr;
 use alloc::boxed::Box;
 
 static mut INIT_PROC: ProcessPtr = unsafe { ProcessPtr::nullptr() };
-static mut EMPTY_THREAD: ThreadPtr = unsafe { ThreadPtr::nullptr() };
 
-unsafe fn create_init_thread_stack(vmm: &mut VMM) -> VirtAdr {
-    vmm.map(
-        None,
-        256,
-        Flags::RW,
-        vmm::MapTy::Phys {
-            adr: pmm::alloc_pages(256).phys(),
-        },
-    )
-    .unwrap()
-    .add(256 * vmm::PAGE_SIZE)
-}
+/// the in-kernel drivers barely touch their stacks.
+const DRIVER_STACK_PAGES: usize = 16;
 
 unsafe fn create_init_proc_thread(
     id: ThreadId,
     proc: ProcessPtr,
     entry: unsafe extern "C" fn() -> !,
+    stack_pages: usize,
 ) -> ThreadPtr {
     trace!("creating init thread {id}");
-    let entry = entry as u64;
-    let stack = create_init_thread_stack(&mut proc.get_mut().vmm);
-    let stackframe = Box::new(StackFrame::new_kernel(
-        entry,
-        stack.adr(),
-        proc.get_mut().vmm.get_page_map(),
-    ));
-    let thread = thread::new(id, proc, stackframe).expect("failed to create init proc thread");
+    let thread = thread::new_kernel(id, proc, entry as usize as u64, stack_pages)
+        .expect("failed to create init proc thread");
     sched::schedule(thread);
     thread
 }
@@ -61,23 +41,40 @@ pub unsafe fn run(boot_info: BootInfo) -> ! {
 
     let vmm = vmm::init_kernel_vmm(&boot_info);
     vmm.install();
+    // processes open the console device for their stdio.
+    drivers::init().expect("failed to register devices");
     let proc = process::new_proc(vmm).unwrap().0;
     INIT_PROC = proc;
 
     trace!("initializing kernel heap");
     heap::init();
 
-    let empty_thread =
-        thread::new(ThreadId::resv_id(0), proc, Box::new(StackFrame::zeroed())).unwrap();
+    let empty_thread = thread::new(
+        ThreadId::resv_id(0),
+        proc,
+        Box::new(StackFrame::zeroed()),
+        None,
+    )
+    .unwrap();
     empty_thread
         .get_mut()
         .set_schedule_status(ThreadScheduleStatus::Sleep);
     thread::make_thread_current(empty_thread.get_mut());
-    EMPTY_THREAD = empty_thread;
+    // keeps halting below once every other thread sleeps.
+    sched::set_idle(empty_thread);
 
-    create_init_proc_thread(ThreadId::resv_id(1), proc, main::main);
-    create_init_proc_thread(ThreadId::resv_id(2), proc, drivers::kbd::main);
-    create_init_proc_thread(ThreadId::resv_id(3), proc, drivers::crsr::main);
+    create_init_proc_thread(
+        ThreadId::resv_id(1),
+        proc,
+        main::main,
+        thread::DEFAULT_STACK_PAGES,
+    );
+    create_init_proc_thread(
+        ThreadId::resv_id(2),
+        proc,
+        drivers::crsr::main,
+        DRIVER_STACK_PAGES,
+    );
 
     trace!("starting kernel main thread.");
     interrupt::enable();
@@ -0,0 +1,491 @@
+//! synchronous message passing between processes over endpoints.
+//!
+//! A client calls an endpoint with a message and waits until the server
+//! receiving on it replies. Messages carry a few words and optionally a
+//! payload, which is either copied or granted: the pages holding it are
+//! mapped into the receiver as well. A message can also move a handle from
+//! the sender's handle table to the receiver's, which goes back to the
+//! sender if the message isn't delivered.
+//!
+//! Besides messages, an endpoint collects notification bits, which the
+//! kernel sets for interrupts a driver bound to it. They are received as a
+//! message of their own, with the token `TOKEN_NOTIFY`.
+//!
+//! Like pipes, a call or receive which has to wait puts its thread to sleep,
+//! on the call or the endpoint, and its syscall runs again once woken. The
+//! call in flight is kept by its thread in the meantime.
+
+use crate::mm::vmm::PinnedPage;
+use crate::process::handle::Entry;
+use crate::process::thread::WaitQueue;
+use crate::process::ProcessPtr;
+use crate::util::locked::Locked;
+use ::syscall::handle::Handle;
+use ::syscall::ipc::{MSG_WORDS, TOKEN_NOTIFY};
+use ::syscall::{Errno, Result};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use core::mem;
+
+/// the most pages a single message can grant.
+pub const MAX_GRANT_PAGES: usize = 1024;
+
+pub enum Payload {
+    None,
+    Copy(Vec<u8>),
+    /// pages of the sender, the payload starts `offset` bytes into the first
+    /// one. they are pinned until the receiver's mapping of them is gone.
+    Grant {
+        pages: Vec<PinnedPage>,
+        offset: usize,
+        len: usize,
+        writable: bool,
+    },
+}
+
+impl Payload {
+    /// the bytes a receiver needs room for.
+    fn copy_len(&self) -> usize {
+        match self {
+            Self::Copy(data) => data.len(),
+            _ => 0,
+        }
+    }
+}
+
+pub struct Message {
+    pub words: [u64; MSG_WORDS],
+    pub payload: Payload,
+    /// a handle moved from the sender to the receiver.
+    pub handle: Option<MovedHandle>,
+}
+
+/// A handle taken from a process' table to move it with a message. It goes
+/// back to where it was taken from when dropped, unless it was `delivered`.
+pub struct MovedHandle {
+    entry: Option<Entry>,
+    from: ProcessPtr,
+    handle: Handle,
+}
+
+impl MovedHandle {
+    /// `entry` was taken from `handle` of `from`'s handle table.
+    pub fn new(entry: Entry, from: ProcessPtr, handle: Handle) -> Self {
+        Self {
+            entry: Some(entry),
+            from,
+            handle,
+        }
+    }
+
+    pub fn entry(&self) -> &Entry {
+        self.entry.as_ref().expect("handle already moved")
+    }
+
+    /// the receiver got the handle, frees it in the sender's table.
+    pub fn delivered(mut self) {
+        self.entry = None;
+        self.from.get_locked().handles.release(self.handle);
+    }
+}
+
+impl Drop for MovedHandle {
+    fn drop(&mut self) {
+        if let Some(entry) = self.entry.take() {
+            self.from.get_locked().handles.restore(self.handle, entry);
+        }
+    }
+}
+
+enum CallState {
+    /// waiting in the endpoint's queue.
+    Queued(Message),
+    /// taken by the receiver, which hasn't replied yet.
+    Received,
+    Replied(Message),
+    Failed(Errno),
+    /// the outcome was handed to the caller.
+    Done,
+}
+
+/// A message in flight, from being sent until the caller has its reply.
+pub struct Call {
+    state: Locked<CallState>,
+    /// room for a copied reply payload, `None` for sends which don't wait
+    /// for a reply.
+    reply_len: Option<usize>,
+    /// the caller, until the call is finished.
+    waiters: WaitQueue,
+}
+
+pub type CallRef = Arc<Call>;
+
+impl Call {
+    /// the outcome of the call once there is one: the reply, which sends
+    /// don't get.
+    pub fn finish(&self) -> Option<Result<Option<Message>>> {
+        let mut state = self.state.lock();
+        match &*state {
+            CallState::Queued(_) => return None,
+            CallState::Received if self.reply_len.is_some() => return None,
+            _ => {}
+        }
+        Some(match mem::replace(&mut *state, CallState::Done) {
+            CallState::Received => Ok(None),
+            CallState::Replied(reply) => Ok(Some(reply)),
+            CallState::Failed(errno) => Err(errno),
+            CallState::Queued(_) | CallState::Done => unreachable!("call finished twice"),
+        })
+    }
+
+    /// where the caller waits until `finish` has the outcome.
+    pub fn wait_queue(&self) -> &WaitQueue {
+        &self.waiters
+    }
+
+    fn set_state(&self, state: CallState) {
+        *self.state.lock() = state;
+        self.waiters.wake_all();
+    }
+
+    fn fail(&self, errno: Errno) {
+        self.set_state(CallState::Failed(errno));
+    }
+}
+
+impl Debug for Call {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let state = match &*self.state.lock() {
+            CallState::Queued(_) => "queued",
+            CallState::Received => "received",
+            CallState::Replied(_) => "replied",
+            CallState::Failed(_) => "failed",
+            CallState::Done => "done",
+        };
+        write!(f, "Call {{ state: {state} }}")
+    }
+}
+
+struct State {
+    queue: VecDeque<CallRef>,
+    /// calls waiting for a reply, by the token the receiver replies with.
+    received: BTreeMap<u64, CallRef>,
+    next_token: u64,
+    /// bits notified since the last receive.
+    notifications: u64,
+    closed: bool,
+}
+
+/// A place messages are sent to and received from.
+pub struct Endpoint {
+    state: Locked<State>,
+    /// threads waiting for a message or notification to receive.
+    receivers: WaitQueue,
+}
+
+pub type EndpointRef = Arc<Endpoint>;
+
+/// the endpoints servers registered, by name.
+static NAMED: Locked<BTreeMap<String, Weak<Endpoint>>> = Locked::new(BTreeMap::new());
+
+impl Endpoint {
+    pub fn new() -> EndpointRef {
+        Arc::new(Self {
+            state: Locked::new(State {
+                queue: VecDeque::new(),
+                received: BTreeMap::new(),
+                next_token: 1,
+                notifications: 0,
+                closed: false,
+            }),
+            receivers: WaitQueue::new(),
+        })
+    }
+
+    /// a new endpoint which can be opened as `name` until it is closed.
+    pub fn create(name: &str) -> Result<EndpointRef> {
+        let mut named = NAMED.lock();
+        named.retain(|_, endpoint| endpoint.upgrade().map_or(false, |e| !e.is_closed()));
+        if named.contains_key(name) {
+            return Err(Errno::EEXIST);
+        }
+        let endpoint = Self::new();
+        named.insert(name.to_string(), Arc::downgrade(&endpoint));
+        Ok(endpoint)
+    }
+
+    pub fn open(name: &str) -> Result<EndpointRef> {
+        NAMED
+            .lock()
+            .get(name)
+            .and_then(Weak::upgrade)
+            .filter(|endpoint| !endpoint.is_closed())
+            .ok_or(Errno::ENOENT)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().closed
+    }
+
+    /// where receivers wait until there is something to receive.
+    pub fn wait_queue(&self) -> &WaitQueue {
+        &self.receivers
+    }
+
+    /// queues `message`. the call is finished once it was received, or once
+    /// it was replied to if `reply_len` gives the room for a reply payload.
+    pub fn send(&self, message: Message, reply_len: Option<usize>) -> Result<CallRef> {
+        let mut state = self.state.lock();
+        if state.closed {
+            return Err(Errno::EPIPE);
+        }
+        let call = Arc::new(Call {
+            state: Locked::new(CallState::Queued(message)),
+            reply_len,
+            waiters: WaitQueue::new(),
+        });
+        state.queue.push_back(call.clone());
+        self.receivers.wake_all();
+        Ok(call)
+    }
+
+    /// sets notification `bits`, which are received ahead of any message.
+    pub fn notify(&self, bits: u64) -> Result<()> {
+        let mut state = self.state.lock();
+        if state.closed {
+            return Err(Errno::EPIPE);
+        }
+        state.notifications |= bits;
+        self.receivers.wake_all();
+        Ok(())
+    }
+
+    /// takes the oldest message along with the token to reply to it with,
+    /// which is 0 for sends. messages with a copied payload larger than
+    /// `buf_len` fail with `EMSGSIZE` and are skipped. pending notifications
+    /// come first, as a message holding their bits in its first word.
+    pub fn receive(&self, buf_len: usize) -> Option<(u64, Message)> {
+        let mut state = self.state.lock();
+        if state.notifications != 0 {
+            let mut words = [0; MSG_WORDS];
+            words[0] = mem::take(&mut state.notifications);
+            let message = Message {
+                words,
+                payload: Payload::None,
+                handle: None,
+            };
+            return Some((TOKEN_NOTIFY, message));
+        }
+        while let Some(call) = state.queue.pop_front() {
+            let mut call_state = call.state.lock();
+            let CallState::Queued(message) = mem::replace(&mut *call_state, CallState::Received)
+            else {
+                unreachable!("queued call in another state");
+            };
+            if message.payload.copy_len() > buf_len {
+                drop(call_state);
+                call.fail(Errno::EMSGSIZE);
+                continue;
+            }
+            drop(call_state);
+            if call.reply_len.is_none() {
+                // a send is done once received.
+                call.waiters.wake_all();
+                return Some((0, message));
+            }
+            let token = state.next_token;
+            state.next_token += 1;
+            state.received.insert(token, call);
+            return Some((token, message));
+        }
+        None
+    }
+
+    /// answers the call received with `token`, which fails with `reply`'s
+    /// error. a copied payload larger than the caller can take fails with
+    /// `EMSGSIZE`, the call can be replied to again then.
+    pub fn reply(&self, token: u64, reply: Result<Message>) -> Result<()> {
+        let mut state = self.state.lock();
+        let call = state.received.get(&token).ok_or(Errno::EINVAL)?;
+        match reply {
+            Ok(reply) => {
+                if reply.payload.copy_len() > call.reply_len.unwrap_or(0) {
+                    return Err(Errno::EMSGSIZE);
+                }
+                call.set_state(CallState::Replied(reply));
+            }
+            Err(errno) => call.fail(errno),
+        }
+        state.received.remove(&token);
+        Ok(())
+    }
+
+    /// fails every message which is waiting, and every one sent from now on.
+    pub fn close(&self) {
+        let mut state = self.state.lock();
+        state.closed = true;
+        for call in state.queue.drain(..) {
+            call.fail(Errno::EPIPE);
+        }
+        for (_, call) in mem::take(&mut state.received) {
+            call.fail(Errno::EPIPE);
+        }
+        self.receivers.wake_all();
+    }
+}
+
+/// The receiving side of an endpoint, which closes it when it goes away.
+pub struct Server(EndpointRef);
+
+pub type ServerRef = Arc<Server>;
+
+impl Server {
+    pub fn new(endpoint: EndpointRef) -> ServerRef {
+        Arc::new(Self(endpoint))
+    }
+
+    pub fn endpoint(&self) -> &EndpointRef {
+        &self.0
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(word: u64, payload: Payload) -> Message {
+        let mut words = [0; MSG_WORDS];
+        words[0] = word;
+        Message {
+            words,
+            payload,
+            handle: None,
+        }
+    }
+
+    fn copied(message: &Message) -> &[u8] {
+        match &message.payload {
+            Payload::Copy(data) => data,
+            _ => &[],
+        }
+    }
+
+    #[test]
+    fn sends_finish_once_received() {
+        let endpoint = Endpoint::new();
+        let call = endpoint.send(message(7, Payload::None), None).unwrap();
+        assert!(call.finish().is_none());
+        let (token, received) = endpoint.receive(0).unwrap();
+        assert_eq!((token, received.words[0]), (0, 7));
+        assert!(matches!(call.finish(), Some(Ok(None))));
+        assert!(endpoint.receive(0).is_none());
+    }
+
+    #[test]
+    fn calls_finish_with_the_reply() {
+        let endpoint = Endpoint::new();
+        let call = endpoint.send(message(1, Payload::None), Some(4)).unwrap();
+        let (token, _) = endpoint.receive(0).unwrap();
+        assert_ne!(token, 0);
+        // received, but not replied to yet.
+        assert!(call.finish().is_none());
+
+        let reply = message(2, Payload::Copy(vec![1, 2, 3, 4, 5]));
+        assert_eq!(
+            endpoint.reply(token, Ok(reply)).err(),
+            Some(Errno::EMSGSIZE)
+        );
+        let reply = message(2, Payload::Copy(vec![1, 2, 3, 4]));
+        endpoint.reply(token, Ok(reply)).unwrap();
+        match call.finish() {
+            Some(Ok(Some(reply))) => {
+                assert_eq!((reply.words[0], copied(&reply)), (2, &[1, 2, 3, 4][..]))
+            }
+            _ => panic!("call not replied to"),
+        }
+        // the token is used up.
+        assert_eq!(
+            endpoint.reply(token, Err(Errno::EIO)).err(),
+            Some(Errno::EINVAL)
+        );
+    }
+
+    #[test]
+    fn calls_fail_with_the_error_replied() {
+        let endpoint = Endpoint::new();
+        let call = endpoint.send(message(1, Payload::None), Some(0)).unwrap();
+        let (token, _) = endpoint.receive(0).unwrap();
+        endpoint.reply(token, Err(Errno::ENOENT)).unwrap();
+        assert!(matches!(call.finish(), Some(Err(Errno::ENOENT))));
+        assert_eq!(
+            endpoint.reply(token + 1, Err(Errno::EIO)).err(),
+            Some(Errno::EINVAL)
+        );
+    }
+
+    #[test]
+    fn payloads_larger_than_the_buffer_are_skipped() {
+        let endpoint = Endpoint::new();
+        let large = endpoint
+            .send(message(1, Payload::Copy(vec![0; 8])), None)
+            .unwrap();
+        let small = endpoint
+            .send(message(2, Payload::Copy(vec![0; 4])), None)
+            .unwrap();
+        let (_, received) = endpoint.receive(4).unwrap();
+        assert_eq!(received.words[0], 2);
+        assert!(matches!(large.finish(), Some(Err(Errno::EMSGSIZE))));
+        assert!(matches!(small.finish(), Some(Ok(None))));
+    }
+
+    #[test]
+    fn notifications_come_first_and_merge() {
+        let endpoint = Endpoint::new();
+        endpoint.send(message(9, Payload::None), None).unwrap();
+        endpoint.notify(0b01).unwrap();
+        endpoint.notify(0b100).unwrap();
+        let (token, received) = endpoint.receive(0).unwrap();
+        assert_eq!((token, received.words[0]), (TOKEN_NOTIFY, 0b101));
+        let (token, received) = endpoint.receive(0).unwrap();
+        assert_eq!((token, received.words[0]), (0, 9));
+    }
+
+    #[test]
+    fn closing_fails_every_call() {
+        let endpoint = Endpoint::new();
+        let received = endpoint.send(message(1, Payload::None), Some(0)).unwrap();
+        let queued = endpoint.send(message(2, Payload::None), Some(0)).unwrap();
+        let (token, _) = endpoint.receive(0).unwrap();
+        drop(Server::new(endpoint.clone()));
+        assert!(matches!(received.finish(), Some(Err(Errno::EPIPE))));
+        assert!(matches!(queued.finish(), Some(Err(Errno::EPIPE))));
+        assert_eq!(
+            endpoint.reply(token, Err(Errno::EIO)).err(),
+            Some(Errno::EINVAL)
+        );
+        assert!(endpoint.send(message(3, Payload::None), None).is_err());
+        assert_eq!(endpoint.notify(1).err(), Some(Errno::EPIPE));
+    }
+
+    #[test]
+    fn names_are_free_again_once_closed() {
+        let server = Server::new(Endpoint::create("ipc-test").unwrap());
+        assert_eq!(Endpoint::create("ipc-test").err(), Some(Errno::EEXIST));
+        assert!(Arc::ptr_eq(
+            &Endpoint::open("ipc-test").unwrap(),
+            server.endpoint()
+        ));
+        drop(server);
+        assert_eq!(Endpoint::open("ipc-test").err(), Some(Errno::ENOENT));
+        assert!(Endpoint::create("ipc-test").is_ok());
+    }
+}
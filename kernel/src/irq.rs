@@ -0,0 +1,69 @@
+//! device interrupt lines, which userspace drivers bind to endpoints.
+//!
+//! A raised line is masked and notifies the endpoint bound to it. The driver
+//! receives the notification, services its device and acknowledges the
+//! line, which unmasks it. Lines nobody is bound to stay masked.
+
+use crate::arch;
+use crate::ipc::EndpointRef;
+use crate::util::locked::Locked;
+use ::syscall::{Errno, Result};
+
+pub const LINES: u8 = arch::irq::LINES;
+
+struct Binding {
+    endpoint: EndpointRef,
+    /// the notification bits the endpoint gets.
+    bits: u64,
+}
+
+const UNBOUND: Option<Binding> = None;
+
+static BINDINGS: Locked<[Option<Binding>; LINES as usize]> = Locked::new([UNBOUND; LINES as usize]);
+
+/// makes `line` notify `endpoint` with `bits`, and unmasks it. a line stays
+/// bound until its endpoint is closed.
+pub fn bind(line: u8, endpoint: EndpointRef, bits: u64) -> Result<()> {
+    if bits == 0 {
+        return Err(Errno::EINVAL);
+    }
+    let mut bindings = BINDINGS.lock();
+    let binding = bindings.get_mut(line as usize).ok_or(Errno::EINVAL)?;
+    if binding
+        .as_ref()
+        .map_or(false, |bound| !bound.endpoint.is_closed())
+    {
+        return Err(Errno::EBUSY);
+    }
+    *binding = Some(Binding { endpoint, bits });
+    arch::irq::set_masked(line, false);
+    Ok(())
+}
+
+/// unmasks `line` once the notification for it was handled.
+pub fn ack(line: u8) -> Result<()> {
+    let bindings = BINDINGS.lock();
+    match bindings.get(line as usize) {
+        Some(Some(_)) => {
+            arch::irq::set_masked(line, false);
+            Ok(())
+        }
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+/// called from the interrupt handler of `line`. the line stays masked until
+/// it is acknowledged, or forever once its endpoint is closed.
+pub fn raise(line: u8) {
+    arch::irq::set_masked(line, true);
+    let mut bindings = BINDINGS.lock();
+    let Some(binding) = bindings.get_mut(line as usize) else {
+        return;
+    };
+    let notified = binding
+        .as_ref()
+        .map(|bound| bound.endpoint.notify(bound.bits));
+    if let Some(Err(_)) = notified {
+        *binding = None;
+    }
+}
@@ -1,5 +1,7 @@
-#![no_std]
-#![no_main]
+// the tests run on the host, from this directory:
+// `cargo test -Z build-std --target x86_64-unknown-linux-gnu`
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 #![allow(dead_code)]
 #![feature(trait_alias)]
 #![feature(const_maybe_uninit_zeroed)]
@@ -15,6 +17,7 @@
 #![feature(strict_provenance)]
 #![feature(alloc_layout_extra)]
 #![feature(btreemap_alloc)]
+#![feature(asm_const)]
 
 #[macro_use]
 extern crate static_assertions;
@@ -48,6 +51,8 @@ mod boot;
 mod drivers;
 mod fs;
 mod init;
+mod ipc;
+mod irq;
 mod kernel_elf;
 mod logging;
 mod mm;
@@ -1,16 +1,42 @@
 use crate::boot::BootInfo;
 use elf::Elf64;
 
+const PT_LOAD: u32 = 1;
+
 static mut KERNEL_ELF_BASE: (u64, usize) = (0, 0);
+static mut KERNEL_SLIDE: u64 = 0;
 
 pub unsafe fn init(boot_info: &BootInfo) {
     let kernel_file = boot_info.file.kernel_file.get().unwrap();
     KERNEL_ELF_BASE = (
         kernel_file.base.as_ptr().unwrap() as u64,
         kernel_file.length as usize,
-    )
+    );
+    let link_base = elf()
+        .program_headers()
+        .iter()
+        .filter(|header| header.p_type == PT_LOAD)
+        .map(|header| header.p_vaddr)
+        .min()
+        .unwrap_or(0);
+    KERNEL_SLIDE = boot_info
+        .kernel_address
+        .virtual_base
+        .wrapping_sub(link_base);
+    debug!("kernel slide: 0x{:016x}", KERNEL_SLIDE);
 }
 
 pub fn elf() -> &'static Elf64 {
     unsafe { &*Elf64::from_raw_parts(KERNEL_ELF_BASE.0 as *const _, KERNEL_ELF_BASE.1) }
 }
+
+/// how far the kernel was moved from its link address by KASLR.
+pub fn slide() -> u64 {
+    unsafe { KERNEL_SLIDE }
+}
+
+/// translates a runtime address in the kernel image to its link address, as
+/// found in the symbols of the kernel ELF.
+pub fn unslide(adr: u64) -> u64 {
+    adr.wrapping_sub(slide())
+}
@@ -14,7 +14,12 @@ pub unsafe fn alloc(layout: Layout) -> *mut u8 {
         Err(Error::InsufficientSpace) => {
             let aligned_size = align_ceil!(layout.size(), PAGE_SIZE);
             let pages = aligned_size / PAGE_SIZE;
-            let vadr = pmm::alloc_pages(pages).virt();
+            // running out is reported to the caller, `try_new` and friends
+            // can handle it.
+            let Some(alloc) = pmm::try_alloc_pages(pages) else {
+                return null_mut();
+            };
+            let vadr = alloc.virt();
             unsafe {
                 FREE_LIST
                     .lock()
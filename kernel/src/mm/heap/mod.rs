@@ -7,12 +7,31 @@ use alloc::alloc::Global;
 use allocators::bitmap::BitMapPtrAllocator;
 use core::alloc::{Allocator, Layout};
 use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 pub use error::{Error, Result};
 
 type NodeAllocator = BitMapPtrAllocator<3>;
 type AllocatorTy = allocators::freelist::FreeListAllocator<NodeAllocator>;
 
+/// bytes in live allocations, as requested by their layouts.
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+/// number of live allocations.
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+    pub allocated_bytes: usize,
+    pub allocations: usize,
+}
+
+pub fn stats() -> Stats {
+    Stats {
+        allocated_bytes: ALLOCATED_BYTES.load(Ordering::Relaxed),
+        allocations: ALLOCATIONS.load(Ordering::Relaxed),
+    }
+}
+
 struct GlobalAlloc {
     alloc_fn: unsafe fn(Layout) -> *mut u8,
     free_fn: unsafe fn(*mut u8, Layout),
@@ -20,15 +39,22 @@ struct GlobalAlloc {
 
 unsafe impl core::alloc::GlobalAlloc for GlobalAlloc {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        (self.alloc_fn)(layout)
+        let ptr = (self.alloc_fn)(layout);
+        if !ptr.is_null() {
+            ALLOCATED_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+        ptr
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        (self.free_fn)(ptr, layout)
+        (self.free_fn)(ptr, layout);
+        ALLOCATED_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+        ALLOCATIONS.fetch_sub(1, Ordering::Relaxed);
     }
 }
 
-#[global_allocator]
+#[cfg_attr(not(test), global_allocator)]
 static mut GLOBAL_ALLOC: GlobalAlloc = GlobalAlloc {
     alloc_fn: primordial::alloc,
     free_fn: primordial::free,
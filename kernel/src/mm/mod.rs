@@ -1,3 +1,6 @@
 pub mod heap;
+pub mod page_cache;
 pub mod pmm;
+pub mod shared;
+pub mod uaccess;
 pub mod vmm;
@@ -0,0 +1,600 @@
+//! the data of files on disk, cached in whole pages from the PMM.
+//!
+//! Pages are keyed by the node they belong to and their index in the file.
+//! The cache holds on to the nodes it has pages of, so their address stays
+//! unique for as long as any of them is cached. Filesystems fill and write
+//! back pages through `Fs::readpage` and `Fs::writepage`.
+//!
+//! Writes only mark pages dirty, they go to the disk once too many pages are
+//! dirty, when their page is evicted or when their file or filesystem is
+//! released. The least recently used pages are evicted when the PMM runs low
+//! or the cache grows past its limit, pages mapped into a process stay.
+
+use super::pmm::{self, PagePtr, PAGE_SIZE};
+use crate::fs::{Error, Fs, Result, VNode};
+use crate::util::adr::PhysAdr;
+use crate::util::locked::Locked;
+use alloc::collections::btree_map::{self, BTreeMap};
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// pages the cache evicts its own pages to keep free in the PMM.
+const MIN_FREE_PAGES: usize = 1024;
+/// the cache never holds more pages than this, 64MiB.
+const MAX_PAGES: usize = 16384;
+/// dirty pages are all written back once there are more than this.
+const MAX_DIRTY_PAGES: usize = 1024;
+
+/// the identity of a node, its address.
+type NodeId = usize;
+
+fn node_id(node: &VNode) -> NodeId {
+    Arc::as_ptr(node) as *const () as NodeId
+}
+
+/// A file with pages in the cache.
+struct File {
+    node: VNode,
+    fs: Arc<dyn Fs>,
+    pages: usize,
+}
+
+struct Page {
+    page: PagePtr,
+    dirty: bool,
+    /// how often the page is mapped into a process.
+    pins: usize,
+    /// how many of `pins` are writable. the page stays dirty while there are
+    /// any, as the process can change it at any time.
+    writable_pins: usize,
+    /// the key of the page in `Cache::lru`.
+    used: u64,
+}
+
+impl Page {
+    fn bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.page.virt().ptr(), PAGE_SIZE) }
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.page.virt().ptr(), PAGE_SIZE) }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+    pub pages: usize,
+    pub dirty_pages: usize,
+}
+
+/// A file of a filesystem using the cache, whose pages can be pinned in it
+/// to map them into a process.
+#[derive(Clone)]
+pub struct CachedInode {
+    fs: Arc<dyn Fs>,
+    node: VNode,
+}
+
+impl CachedInode {
+    pub fn new(fs: Arc<dyn Fs>, node: VNode) -> Self {
+        Self { fs, node }
+    }
+
+    /// the physical address of page `index`, which stays cached until
+    /// `unpin` is called for it as often. pages pinned writable count as
+    /// dirty until they are unpinned, as changing them can't be noticed.
+    pub fn pin(&self, index: usize, writable: bool) -> Result<PhysAdr> {
+        if writable && self.fs.read_only() {
+            return Err(Error::ReadOnly("map".to_string()));
+        }
+        let size = self.node.stat()?.size;
+        with_cache(|cache| cache.pin(&self.fs, &self.node, index, size, writable))
+    }
+
+    /// whether the file can't be mapped writable.
+    pub fn read_only(&self) -> bool {
+        self.fs.read_only()
+    }
+
+    /// drops a pin of page `index` which `pin` took with the same `writable`.
+    pub fn unpin(&self, index: usize, writable: bool) {
+        CACHE.lock().unpin((node_id(&self.node), index), writable);
+    }
+}
+
+struct Cache {
+    /// the cache evicts pages before it grows past this.
+    max_pages: usize,
+    files: BTreeMap<NodeId, File>,
+    /// pages by node and index in the file.
+    pages: BTreeMap<(NodeId, usize), Page>,
+    /// the keys of all pages, least recently used first.
+    lru: BTreeMap<u64, (NodeId, usize)>,
+    clock: u64,
+    dirty: usize,
+    /// files which lost their last page, dropped once the lock is gone.
+    released: Vec<File>,
+}
+
+static CACHE: Locked<Cache> = Locked::new(Cache::new(MAX_PAGES));
+
+/// runs `f` with the cache locked. the files it released are dropped after
+/// the lock, as dropping the last reference to a node may go back to its
+/// filesystem.
+fn with_cache<T>(f: impl FnOnce(&mut Cache) -> Result<T>) -> Result<T> {
+    let (result, released) = {
+        let mut cache = CACHE.lock();
+        let result = f(&mut cache);
+        (result, core::mem::take(&mut cache.released))
+    };
+    drop(released);
+    result
+}
+
+fn same_fs(a: &Arc<dyn Fs>, b: &Arc<dyn Fs>) -> bool {
+    core::ptr::eq(Arc::as_ptr(a).cast::<()>(), Arc::as_ptr(b).cast())
+}
+
+/// a zeroed page for the cache.
+#[cfg(not(test))]
+fn alloc_page() -> Option<PagePtr> {
+    pmm::try_alloc_pages_zeroed(1)
+}
+
+#[cfg(not(test))]
+fn free_page(page: PagePtr) {
+    pmm::free_pages(page)
+}
+
+/// whether the cache should give pages back to the PMM.
+#[cfg(not(test))]
+fn pmm_low() -> bool {
+    pmm::stats().free_pages() < MIN_FREE_PAGES
+}
+
+/// host builds take pages from the heap, as there is no PMM to take them
+/// from, and never run low on them.
+#[cfg(test)]
+fn alloc_page() -> Option<PagePtr> {
+    let ptr = unsafe { alloc::alloc::alloc_zeroed(core::alloc::Layout::new::<pmm::Page>()) };
+    (!ptr.is_null()).then(|| unsafe { PagePtr::from_parts(ptr.cast(), 1) })
+}
+
+#[cfg(test)]
+fn free_page(page: PagePtr) {
+    let layout = core::alloc::Layout::new::<pmm::Page>();
+    unsafe { alloc::alloc::dealloc(page.virt().ptr(), layout) };
+}
+
+#[cfg(test)]
+fn pmm_low() -> bool {
+    false
+}
+
+impl Cache {
+    const fn new(max_pages: usize) -> Self {
+        Self {
+            max_pages,
+            files: BTreeMap::new(),
+            pages: BTreeMap::new(),
+            lru: BTreeMap::new(),
+            clock: 0,
+            dirty: 0,
+            released: Vec::new(),
+        }
+    }
+
+    /// the page `index` of `node`, read from the disk if it isn't cached.
+    /// pages starting at or past `size` are just zeroed.
+    fn page(
+        &mut self,
+        fs: &Arc<dyn Fs>,
+        node: &VNode,
+        index: usize,
+        size: usize,
+    ) -> Result<&mut Page> {
+        let key = (node_id(node), index);
+        if !self.pages.contains_key(&key) {
+            self.make_room()?;
+        }
+        if let btree_map::Entry::Vacant(entry) = self.pages.entry(key) {
+            let mut page = Page {
+                page: alloc_page().ok_or_else(|| Error::NoSpace("page cache".to_string()))?,
+                dirty: false,
+                pins: 0,
+                writable_pins: 0,
+                used: 0,
+            };
+            if index * PAGE_SIZE < size {
+                if let Err(e) = fs.readpage(node, index * PAGE_SIZE, page.bytes_mut()) {
+                    free_page(page.page);
+                    return Err(e);
+                }
+            }
+            entry.insert(page);
+            self.files
+                .entry(key.0)
+                .or_insert_with(|| File {
+                    node: node.clone(),
+                    fs: fs.clone(),
+                    pages: 0,
+                })
+                .pages += 1;
+        }
+        self.touch(key);
+        Ok(self.pages.get_mut(&key).unwrap())
+    }
+
+    fn pin(
+        &mut self,
+        fs: &Arc<dyn Fs>,
+        node: &VNode,
+        index: usize,
+        size: usize,
+        writable: bool,
+    ) -> Result<PhysAdr> {
+        let page = self.page(fs, node, index, size)?;
+        page.pins += 1;
+        let phys = page.page.phys();
+        if writable {
+            page.writable_pins += 1;
+            self.set_dirty((node_id(node), index));
+        }
+        Ok(phys)
+    }
+
+    fn unpin(&mut self, key: (NodeId, usize), writable: bool) {
+        if let Some(page) = self.pages.get_mut(&key) {
+            page.pins -= 1;
+            if writable {
+                page.writable_pins -= 1;
+            }
+        }
+    }
+
+    /// makes `key` the most recently used page.
+    fn touch(&mut self, key: (NodeId, usize)) {
+        let page = self.pages.get_mut(&key).unwrap();
+        self.lru.remove(&page.used);
+        self.clock += 1;
+        page.used = self.clock;
+        self.lru.insert(self.clock, key);
+    }
+
+    fn set_dirty(&mut self, key: (NodeId, usize)) {
+        let page = self.pages.get_mut(&key).unwrap();
+        if !page.dirty {
+            page.dirty = true;
+            self.dirty += 1;
+        }
+    }
+
+    /// writes the page `key` back if it is dirty. pages mapped writable stay
+    /// dirty.
+    fn write_back(&mut self, key: (NodeId, usize)) -> Result<()> {
+        let page = self.pages.get_mut(&key).unwrap();
+        if !page.dirty {
+            return Ok(());
+        }
+        let file = &self.files[&key.0];
+        file.fs
+            .writepage(&file.node, key.1 * PAGE_SIZE, page.bytes())?;
+        if page.writable_pins == 0 {
+            page.dirty = false;
+            self.dirty -= 1;
+        }
+        Ok(())
+    }
+
+    /// drops the page `key`, whatever it holds.
+    fn remove(&mut self, key: (NodeId, usize)) {
+        let page = self.pages.remove(&key).unwrap();
+        if page.dirty {
+            self.dirty -= 1;
+        }
+        self.lru.remove(&page.used);
+        free_page(page.page);
+        let file = self.files.get_mut(&key.0).unwrap();
+        file.pages -= 1;
+        if file.pages == 0 {
+            let file = self.files.remove(&key.0).unwrap();
+            self.released.push(file);
+        }
+    }
+
+    /// evicts the least recently used pages while the PMM or the cache are
+    /// about to run full, and writes back the dirty pages if there are too
+    /// many.
+    fn make_room(&mut self) -> Result<()> {
+        while self.pages.len() >= self.max_pages || pmm_low() {
+            let victim = self
+                .lru
+                .values()
+                .copied()
+                .find(|key| self.pages[key].pins == 0);
+            let Some(key) = victim else {
+                break;
+            };
+            self.write_back(key)?;
+            self.remove(key);
+        }
+        if self.dirty > MAX_DIRTY_PAGES {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        let dirty: Vec<_> = self
+            .pages
+            .iter()
+            .filter(|(_, page)| page.dirty)
+            .map(|(&key, _)| key)
+            .collect();
+        for key in dirty {
+            self.write_back(key)?;
+        }
+        Ok(())
+    }
+
+    fn keys(&self, id: NodeId, first: usize) -> Vec<(NodeId, usize)> {
+        self.pages
+            .range((id, first)..=(id, usize::MAX))
+            .map(|(&key, _)| key)
+            .collect()
+    }
+
+    /// drops what lies past `size` from the pages of the node `id`, except
+    /// for those which are mapped.
+    fn truncate(&mut self, id: NodeId, size: usize) {
+        for key in self.keys(id, size.div_ceil(PAGE_SIZE)) {
+            if self.pages[&key].pins == 0 {
+                self.remove(key);
+            }
+        }
+        // the rest of the last page has to read as zeros if the file grows
+        // again.
+        if let Some(page) = self.pages.get_mut(&(id, size / PAGE_SIZE)) {
+            page.bytes_mut()[size % PAGE_SIZE..].fill(0);
+        }
+    }
+
+    /// writes back every page of the node `id` and drops those which aren't
+    /// mapped. returns whether all of them are gone.
+    fn release(&mut self, id: NodeId) -> Result<bool> {
+        let mut all = true;
+        for key in self.keys(id, 0) {
+            self.write_back(key)?;
+            if self.pages[&key].pins == 0 {
+                self.remove(key);
+            } else {
+                all = false;
+            }
+        }
+        Ok(all)
+    }
+}
+
+/// reads from `offset` of `node`, which belongs to `fs`, into `buf` through
+/// the cache.
+pub fn read(fs: &Arc<dyn Fs>, node: &VNode, offset: usize, buf: &mut [u8]) -> Result<usize> {
+    let size = node.stat()?.size;
+    if offset >= size {
+        return Ok(0);
+    }
+    let len = buf.len().min(size - offset);
+    with_cache(|cache| {
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done;
+            let in_page = pos % PAGE_SIZE;
+            let chunk = (PAGE_SIZE - in_page).min(len - done);
+            let page = cache.page(fs, node, pos / PAGE_SIZE, size)?;
+            buf[done..done + chunk].copy_from_slice(&page.bytes()[in_page..in_page + chunk]);
+            done += chunk;
+        }
+        Ok(len)
+    })
+}
+
+/// writes `buf` at `offset` of `node` into the cache, growing the file
+/// first if it ends past it.
+pub fn write(fs: &Arc<dyn Fs>, node: &VNode, offset: usize, buf: &[u8]) -> Result<usize> {
+    if fs.read_only() {
+        return Err(Error::ReadOnly("write".to_string()));
+    }
+    let end = offset
+        .checked_add(buf.len())
+        .ok_or_else(|| Error::NoSpace("file too large".to_string()))?;
+    let size = node.stat()?.size;
+    if end > size {
+        node.truncate(end)?;
+    }
+    with_cache(|cache| {
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done;
+            let in_page = pos % PAGE_SIZE;
+            let chunk = (PAGE_SIZE - in_page).min(buf.len() - done);
+            let index = pos / PAGE_SIZE;
+            // a page which is overwritten as a whole needn't be read.
+            let known = if chunk == PAGE_SIZE { 0 } else { size };
+            let page = cache.page(fs, node, index, known)?;
+            page.bytes_mut()[in_page..in_page + chunk].copy_from_slice(&buf[done..done + chunk]);
+            cache.set_dirty((node_id(node), index));
+            done += chunk;
+        }
+        Ok(buf.len())
+    })
+}
+
+/// drops what lies past `size` from the cached pages of `node`, once the
+/// node itself was cut to `size`.
+pub fn truncate(node: &VNode, size: usize) {
+    let _ = with_cache(|cache| {
+        cache.truncate(node_id(node), size);
+        Ok(())
+    });
+}
+
+/// writes back every dirty page.
+pub fn sync() -> Result<()> {
+    with_cache(|cache| cache.sync())
+}
+
+/// writes back and drops the pages of `node`, once it was removed from its
+/// directory. pages which are mapped stay.
+pub fn release(node: &VNode) -> Result<()> {
+    with_cache(|cache| cache.release(node_id(node)).map(|_| ()))
+}
+
+/// writes back and drops the pages of every file of `fs`, before it is
+/// unmounted. fails if any of them are still mapped.
+pub fn release_fs(fs: &Arc<dyn Fs>) -> Result<()> {
+    with_cache(|cache| {
+        let ids: Vec<_> = cache
+            .files
+            .iter()
+            .filter(|(_, file)| same_fs(&file.fs, fs))
+            .map(|(&id, _)| id)
+            .collect();
+        let mut all = true;
+        for id in ids {
+            all &= cache.release(id)?;
+        }
+        if !all {
+            return Err(Error::Busy("files are mapped".to_string()));
+        }
+        Ok(())
+    })
+}
+
+pub fn stats() -> Stats {
+    let cache = CACHE.lock();
+    Stats {
+        pages: cache.pages.len(),
+        dirty_pages: cache.dirty,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::impls::tmpfs::TmpFs;
+
+    /// a file of `pages` pages on a fresh tmpfs, each filled with its index.
+    fn file(pages: usize) -> (Arc<dyn Fs>, VNode) {
+        let fs: Arc<dyn Fs> = Arc::new(TmpFs::new(64));
+        let node = fs.root().unwrap().create("file", 0o644).unwrap();
+        for index in 0..pages {
+            node.write_at(index * PAGE_SIZE, &[index as u8; PAGE_SIZE])
+                .unwrap();
+        }
+        (fs, node)
+    }
+
+    fn cached(cache: &Cache, node: &VNode) -> Vec<usize> {
+        cache
+            .keys(node_id(node), 0)
+            .into_iter()
+            .map(|(_, index)| index)
+            .collect()
+    }
+
+    /// the first byte of page `index` as stored in the file.
+    fn stored(node: &VNode, index: usize) -> u8 {
+        let mut buf = [0];
+        node.read_at(index * PAGE_SIZE, &mut buf).unwrap();
+        buf[0]
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_pages() {
+        let (fs, node) = file(8);
+        let size = 8 * PAGE_SIZE;
+        let mut cache = Cache::new(4);
+        for index in 0..4 {
+            assert_eq!(
+                cache.page(&fs, &node, index, size).unwrap().bytes()[0],
+                index as u8
+            );
+        }
+        cache.page(&fs, &node, 0, size).unwrap();
+        cache.page(&fs, &node, 4, size).unwrap();
+        assert_eq!(cached(&cache, &node), [0, 2, 3, 4]);
+
+        // pinned pages stay, however long they weren't used.
+        cache.pin(&fs, &node, 2, size, false).unwrap();
+        cache.page(&fs, &node, 0, size).unwrap();
+        cache.page(&fs, &node, 5, size).unwrap();
+        cache.page(&fs, &node, 6, size).unwrap();
+        assert_eq!(cached(&cache, &node), [0, 2, 5, 6]);
+    }
+
+    #[test]
+    fn dirty_pages_are_counted_once_and_written_back() {
+        let (fs, node) = file(4);
+        let size = 4 * PAGE_SIZE;
+        let mut cache = Cache::new(2);
+        for _ in 0..2 {
+            cache.page(&fs, &node, 0, size).unwrap().bytes_mut()[0] = 10;
+            cache.set_dirty((node_id(&node), 0));
+        }
+        assert_eq!(cache.dirty, 1);
+        assert_eq!(stored(&node, 0), 0);
+
+        cache.sync().unwrap();
+        assert_eq!((cache.dirty, stored(&node, 0)), (0, 10));
+
+        // evicting a dirty page writes it back first.
+        cache.page(&fs, &node, 1, size).unwrap().bytes_mut()[0] = 11;
+        cache.set_dirty((node_id(&node), 1));
+        cache.page(&fs, &node, 2, size).unwrap();
+        cache.page(&fs, &node, 3, size).unwrap();
+        assert_eq!(cached(&cache, &node), [2, 3]);
+        assert_eq!((cache.dirty, stored(&node, 1)), (0, 11));
+    }
+
+    #[test]
+    fn writably_pinned_pages_stay_dirty() {
+        let (fs, node) = file(1);
+        let mut cache = Cache::new(4);
+        let key = (node_id(&node), 0);
+        cache.pin(&fs, &node, 0, PAGE_SIZE, true).unwrap();
+        cache.pin(&fs, &node, 0, PAGE_SIZE, false).unwrap();
+        assert_eq!(cache.dirty, 1);
+
+        // stores through the mapping after a sync still reach the file.
+        for value in [20, 21] {
+            cache.pages.get_mut(&key).unwrap().bytes_mut()[0] = value;
+            cache.sync().unwrap();
+            assert_eq!((cache.dirty, stored(&node, 0)), (1, value));
+        }
+        assert!(!cache.release(key.0).unwrap());
+        assert_eq!(cache.dirty, 1);
+
+        cache.unpin(key, true);
+        cache.sync().unwrap();
+        assert_eq!(cache.dirty, 0);
+        cache.unpin(key, false);
+        assert!(cache.release(key.0).unwrap());
+    }
+
+    #[test]
+    fn truncate_keeps_pinned_pages() {
+        let (fs, node) = file(4);
+        let size = 4 * PAGE_SIZE;
+        let mut cache = Cache::new(8);
+        for index in 0..4 {
+            cache.page(&fs, &node, index, size).unwrap();
+        }
+        cache.pin(&fs, &node, 2, size, false).unwrap();
+        cache.truncate(node_id(&node), PAGE_SIZE + 10);
+        assert_eq!(cached(&cache, &node), [0, 1, 2]);
+        // the cut off part of the last page reads as zeros.
+        let page = cache.page(&fs, &node, 1, size).unwrap().bytes();
+        assert!(page[..10].iter().all(|&b| b == 1));
+        assert!(page[10..].iter().all(|&b| b == 0));
+    }
+}
@@ -5,6 +5,7 @@ use crate::util::locked::Locked;
 use allocators::intrustive::free_list::IntrusiveFreeList;
 use core::alloc::Layout;
 use core::slice;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 pub const PAGE_EXP: usize = 12;
 pub const PAGE_SIZE: usize = 1 << PAGE_EXP;
@@ -32,6 +33,30 @@ pub unsafe fn phys_to_hhdm(adr: PhysAdr) -> VirtAdr {
 
 static ALLOCATOR: Locked<IntrusiveFreeList<PAGE_SIZE>> = Locked::new(IntrusiveFreeList::new());
 
+/// pages handed to the allocator at boot.
+static TOTAL_PAGES: AtomicUsize = AtomicUsize::new(0);
+/// pages currently allocated.
+static USED_PAGES: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+    pub total_pages: usize,
+    pub used_pages: usize,
+}
+
+impl Stats {
+    pub fn free_pages(&self) -> usize {
+        self.total_pages.saturating_sub(self.used_pages)
+    }
+}
+
+pub fn stats() -> Stats {
+    Stats {
+        total_pages: TOTAL_PAGES.load(Ordering::Relaxed),
+        used_pages: USED_PAGES.load(Ordering::Relaxed),
+    }
+}
+
 #[repr(C, align(4096))]
 pub struct Page([u8; PAGE_SIZE]);
 
@@ -76,26 +101,44 @@ impl PagePtr {
     }
 }
 
+/// `count` contiguous pages, or `None` if there are not enough free ones.
+/// for memory userspace asks for, which must not bring the kernel down.
 #[must_use = "unused allocation causes memory leak"]
 #[inline]
-pub fn alloc_pages(count: usize) -> PagePtr {
+pub fn try_alloc_pages(count: usize) -> Option<PagePtr> {
     let mut lock = ALLOCATOR.lock();
-    let alloc = match lock.alloc_layout(Layout::new::<Page>().repeat(count).unwrap().0) {
-        Ok(ok) => ok,
-        Err(e) => {
-            panic!("failed to allocate {count} pages in the PMM with error '{e}'")
-        }
-    };
-    PagePtr(alloc as *mut Page, count)
+    let alloc = lock
+        .alloc_layout(Layout::new::<Page>().repeat(count).unwrap().0)
+        .ok()?;
+    USED_PAGES.fetch_add(count, Ordering::Relaxed);
+    Some(PagePtr(alloc as *mut Page, count))
 }
 
 #[must_use = "unused allocation causes memory leak"]
 #[inline]
-pub fn alloc_pages_zeroed(count: usize) -> PagePtr {
-    let ptr = alloc_pages(count);
+pub fn try_alloc_pages_zeroed(count: usize) -> Option<PagePtr> {
+    let ptr = try_alloc_pages(count)?;
     let virt_ptr = ptr.virt().ptr();
     unsafe { virt_ptr.write_bytes(0, count << PAGE_EXP) };
-    PagePtr(virt_ptr as *mut Page, count)
+    Some(PagePtr(virt_ptr as *mut Page, count))
+}
+
+#[must_use = "unused allocation causes memory leak"]
+#[inline]
+pub fn alloc_pages(count: usize) -> PagePtr {
+    match try_alloc_pages(count) {
+        Some(pages) => pages,
+        None => panic!("failed to allocate {count} pages in the PMM"),
+    }
+}
+
+#[must_use = "unused allocation causes memory leak"]
+#[inline]
+pub fn alloc_pages_zeroed(count: usize) -> PagePtr {
+    match try_alloc_pages_zeroed(count) {
+        Some(pages) => pages,
+        None => panic!("failed to allocate {count} pages in the PMM"),
+    }
 }
 
 #[inline]
@@ -105,6 +148,7 @@ pub fn free_pages(pages: PagePtr) {
     let count = pages.1;
     let layout = Layout::new::<Page>().repeat(count).unwrap().0;
     lock.free_layout(ptr, layout);
+    USED_PAGES.fetch_sub(count, Ordering::Relaxed);
 }
 
 pub unsafe fn init(boot_info: &mut BootInfo) {
@@ -128,6 +172,7 @@ pub unsafe fn init(boot_info: &mut BootInfo) {
         }
         found = true;
         lock.push_region_unchecked(phys_to_hhdm(PhysAdr::new(base)).ptr(), len as usize);
+        TOTAL_PAGES.fetch_add(len as usize / PAGE_SIZE, Ordering::Relaxed);
         info!("PMM region: ");
         info!("    base: 0x{base:016x}");
         info!("    len:  0x{len:x}");
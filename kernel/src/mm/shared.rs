@@ -0,0 +1,159 @@
+//! memory objects which several processes can map at once, so they can
+//! exchange data without copying it.
+//!
+//! Objects are found by name and live as long as anything refers to them,
+//! an open descriptor or a mapping. Their pages are allocated zeroed the
+//! first time they are touched.
+
+use super::pmm::{self, PagePtr, PAGE_SIZE};
+use crate::fs::{Error, FileType, Inode, Result, Stat};
+use crate::util::adr::PhysAdr;
+use crate::util::locked::Locked;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+
+/// the largest shared memory object, 1GiB.
+pub const MAX_LEN: usize = 1 << 30;
+
+pub struct SharedMemory {
+    len: usize,
+    pages: Locked<Vec<Option<PagePtr>>>,
+}
+
+pub type SharedRef = Arc<SharedMemory>;
+
+/// the objects with a name, which goes away with the object.
+static NAMED: Locked<BTreeMap<String, Weak<SharedMemory>>> = Locked::new(BTreeMap::new());
+
+impl SharedMemory {
+    /// a new object of `len` bytes, rounded up to whole pages.
+    pub fn new(len: usize) -> Result<SharedRef> {
+        if len == 0 || len > MAX_LEN {
+            return Err(Error::InvalidOperation(format!(
+                "shared memory of {len} bytes"
+            )));
+        }
+        let pages = len.div_ceil(PAGE_SIZE);
+        Ok(Arc::new(Self {
+            len: pages * PAGE_SIZE,
+            pages: Locked::new((0..pages).map(|_| None).collect()),
+        }))
+    }
+
+    /// the object called `name`, which is created with `len` bytes if there
+    /// is none.
+    pub fn open(name: &str, len: usize) -> Result<SharedRef> {
+        let mut named = NAMED.lock();
+        if let Some(object) = named.get(name).and_then(Weak::upgrade) {
+            return Ok(object);
+        }
+        let object = Self::new(len)?;
+        named.retain(|_, object| object.strong_count() > 0);
+        named.insert(name.to_string(), Arc::downgrade(&object));
+        Ok(object)
+    }
+
+    pub fn size(&self) -> usize {
+        self.len
+    }
+
+    /// the physical address of page `index`, allocated if it wasn't
+    /// touched before.
+    pub fn page(&self, index: usize) -> Result<PhysAdr> {
+        let mut pages = self.pages.lock();
+        let slot = pages
+            .get_mut(index)
+            .ok_or_else(|| Error::InvalidOperation(format!("shared page {index}")))?;
+        Ok(touch(slot)?.phys())
+    }
+
+    /// runs `f` on the bytes of page `index`, which is allocated if it
+    /// wasn't touched before.
+    fn with_page<T>(&self, index: usize, f: impl FnOnce(&mut [u8]) -> T) -> Result<T> {
+        let mut pages = self.pages.lock();
+        let page = touch(&mut pages[index])?;
+        Ok(f(unsafe {
+            core::slice::from_raw_parts_mut(page.virt().ptr(), PAGE_SIZE)
+        }))
+    }
+}
+
+/// the page in `slot`, allocated zeroed if there is none yet.
+fn touch(slot: &mut Option<PagePtr>) -> Result<&PagePtr> {
+    let page = match slot.take() {
+        Some(page) => page,
+        None => pmm::try_alloc_pages_zeroed(1)
+            .ok_or_else(|| Error::NoSpace("shared memory".to_string()))?,
+    };
+    Ok(slot.insert(page))
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        for page in self.pages.lock().drain(..).flatten() {
+            pmm::free_pages(page);
+        }
+    }
+}
+
+/// An object opened as a file, which can be read and written like one too.
+pub struct SharedNode {
+    object: SharedRef,
+}
+
+impl SharedNode {
+    pub fn new(object: SharedRef) -> Self {
+        Self { object }
+    }
+}
+
+impl Inode for SharedNode {
+    fn stat(&self) -> Result<Stat> {
+        Ok(Stat {
+            ino: Arc::as_ptr(&self.object) as u64,
+            ty: FileType::Regular,
+            mode: 0o600,
+            size: self.object.len,
+        })
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let len = buf.len().min(self.object.len.saturating_sub(offset));
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done;
+            let in_page = pos % PAGE_SIZE;
+            let chunk = (PAGE_SIZE - in_page).min(len - done);
+            self.object.with_page(pos / PAGE_SIZE, |page| {
+                buf[done..done + chunk].copy_from_slice(&page[in_page..in_page + chunk])
+            })?;
+            done += chunk;
+        }
+        Ok(len)
+    }
+
+    /// objects have a fixed size, writes past the end are cut short.
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        let len = buf.len().min(self.object.len.saturating_sub(offset));
+        if len == 0 && !buf.is_empty() {
+            return Err(Error::NoSpace("shared memory".to_string()));
+        }
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done;
+            let in_page = pos % PAGE_SIZE;
+            let chunk = (PAGE_SIZE - in_page).min(len - done);
+            self.object.with_page(pos / PAGE_SIZE, |page| {
+                page[in_page..in_page + chunk].copy_from_slice(&buf[done..done + chunk])
+            })?;
+            done += chunk;
+        }
+        Ok(len)
+    }
+
+    fn shared_memory(&self) -> Option<SharedRef> {
+        Some(self.object.clone())
+    }
+}
@@ -0,0 +1,155 @@
+//! Checked access to userspace memory from syscalls.
+//!
+//! Every address handed to the kernel by a process is checked against the
+//! current process' VMM before it is touched, and the copy itself recovers
+//! from page faults through the exception fixup table, so a bad pointer ends
+//! in `EFAULT` rather than a kernel panic.
+
+use crate::arch::uaccess;
+use crate::process::thread;
+use crate::util::adr::VirtAdr;
+use ::syscall::{Errno, Result};
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::mem::{size_of, MaybeUninit};
+
+/// longest slice `UserSlice::read_to_vec` copies. syscalls cap their own
+/// lengths below it.
+pub const MAX_VEC_LEN: usize = 64 * 1024;
+
+fn check_range(adr: VirtAdr, len: usize, write: bool) -> Result<()> {
+    let proc = unsafe { thread::cur_thread().get() }.get_proc();
+    if proc.get_locked().vmm.is_user_range(adr, len, write) {
+        Ok(())
+    } else {
+        Err(Errno::EFAULT)
+    }
+}
+
+/// copies `dst.len()` bytes from the userspace address `src` into `dst`.
+pub fn copy_from_user(dst: &mut [u8], src: VirtAdr) -> Result<()> {
+    check_range(src, dst.len(), false)?;
+    match unsafe { uaccess::copy(dst.as_mut_ptr(), src.ptr(), dst.len()) } {
+        0 => Ok(()),
+        _ => Err(Errno::EFAULT),
+    }
+}
+
+/// copies `src` to the userspace address `dst`.
+pub fn copy_to_user(dst: VirtAdr, src: &[u8]) -> Result<()> {
+    check_range(dst, src.len(), true)?;
+    match unsafe { uaccess::copy(dst.ptr(), src.as_ptr(), src.len()) } {
+        0 => Ok(()),
+        _ => Err(Errno::EFAULT),
+    }
+}
+
+/// A userspace pointer to a single `T`.
+pub struct UserPtr<T> {
+    adr: VirtAdr,
+    _marker: PhantomData<*mut T>,
+}
+
+impl<T> Clone for UserPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for UserPtr<T> {}
+
+impl<T: Copy> UserPtr<T> {
+    pub fn new(adr: u64) -> Self {
+        Self {
+            adr: VirtAdr::new(adr),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn is_null(self) -> bool {
+        self.adr.is_null()
+    }
+
+    pub fn read(self) -> Result<T> {
+        let mut val = MaybeUninit::<T>::uninit();
+        let bytes =
+            unsafe { core::slice::from_raw_parts_mut(val.as_mut_ptr() as *mut u8, size_of::<T>()) };
+        copy_from_user(bytes, self.adr)?;
+        Ok(unsafe { val.assume_init() })
+    }
+
+    /// checks that the `T` can be written, before a syscall does anything
+    /// it would have to undo if the write failed.
+    pub fn check_writable(self) -> Result<()> {
+        check_range(self.adr, size_of::<T>(), true)
+    }
+
+    pub fn write(self, val: &T) -> Result<()> {
+        let bytes =
+            unsafe { core::slice::from_raw_parts(val as *const T as *const u8, size_of::<T>()) };
+        copy_to_user(self.adr, bytes)
+    }
+}
+
+/// A userspace byte buffer.
+#[derive(Clone, Copy)]
+pub struct UserSlice {
+    adr: VirtAdr,
+    len: usize,
+}
+
+impl UserSlice {
+    pub fn new(adr: u64, len: usize) -> Self {
+        Self {
+            adr: VirtAdr::new(adr),
+            len,
+        }
+    }
+
+    pub fn adr(&self) -> VirtAdr {
+        self.adr
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// fills `buf` with the first `buf.len()` bytes of the slice.
+    pub fn read(&self, buf: &mut [u8]) -> Result<()> {
+        if buf.len() > self.len {
+            return Err(Errno::EINVAL);
+        }
+        copy_from_user(buf, self.adr)
+    }
+
+    /// copies the whole slice into the kernel. the range is checked before
+    /// anything is allocated, and slices longer than `MAX_VEC_LEN` fail with
+    /// `EINVAL`, so userspace can't make the kernel allocate at will.
+    pub fn read_to_vec(&self) -> Result<Vec<u8>> {
+        if self.len > MAX_VEC_LEN {
+            return Err(Errno::EINVAL);
+        }
+        check_range(self.adr, self.len, false)?;
+        let mut vec = vec![0; self.len];
+        self.read(&mut vec)?;
+        Ok(vec)
+    }
+
+    /// checks that the whole slice can be written, before a syscall consumes
+    /// anything it couldn't give back if the copy to userspace failed.
+    pub fn check_writable(&self) -> Result<()> {
+        check_range(self.adr, self.len, true)
+    }
+
+    /// writes `buf` to the start of the slice.
+    pub fn write(&self, buf: &[u8]) -> Result<()> {
+        if buf.len() > self.len {
+            return Err(Errno::EINVAL);
+        }
+        copy_to_user(self.adr, buf)
+    }
+}
@@ -4,6 +4,8 @@ use core::fmt::{self, Display};
 #[derive(Debug)]
 pub enum Error {
     AllocatorError(freelist::Error),
+    /// no mapping starts at the address.
+    NotMapped(u64),
 }
 
 impl From<freelist::Error> for Error {
@@ -16,6 +18,7 @@ impl Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Error::AllocatorError(e) => f.write_fmt(format_args!("allocation error: {e}")),
+            Error::NotMapped(adr) => f.write_fmt(format_args!("nothing mapped at {adr:016x}")),
         }
     }
 }
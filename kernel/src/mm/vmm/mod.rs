@@ -1,36 +1,70 @@
 pub mod error;
 
+use super::page_cache::CachedInode;
 use super::pmm;
+use super::shared::SharedRef;
 use crate::arch::vadr;
 use crate::arch::vm::{self, PageMapPtr, VMFlags};
 use crate::boot::BootInfo;
 use crate::symbols;
 use crate::util::adr::{PhysAdr, VirtAdr};
+use crate::util::random;
 use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
 use allocators::freelist::FreeList;
 use core::fmt::Debug;
-use error::Result;
+use error::{Error, Result};
 
 pub const PAGE_SIZE: usize = vm::PAGE_SIZE;
 pub const MEDIUM_PAGE_SIZE: usize = vm::MEDIUM_PAGE_SIZE;
 pub const LARGE_PAGE_SIZE: usize = vm::LARGE_PAGE_SIZE;
 
+/// first address past the lower half, which is all userspace may access.
+pub const USERSPACE_END: vadr = 1 << 47;
+
 type AllocatorTy = FreeList;
 
-enum RegionType {
+/// random addresses tried before falling back to the first free range.
+const RANDOM_PLACEMENT_ATTEMPTS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionType {
     Reserved,
     Normal,
+    /// unmapped pages placed below a mapping to catch overruns.
+    Guard,
 }
 
 struct Region {
     page_cnt: usize,
+    page_size: usize,
+    flags: Flags,
     ty: RegionType,
+    /// what the pages are filled from.
+    source: MapTy,
+}
+
+impl Region {
+    fn len(&self) -> usize {
+        self.page_cnt * self.page_size
+    }
+}
+
+/// A range of a `VMM`, as returned by `VMM::regions`.
+#[derive(Clone, Copy)]
+pub struct RegionInfo {
+    pub start: VirtAdr,
+    pub len: usize,
+    pub flags: Flags,
+    pub ty: RegionType,
 }
 
 pub struct VMM {
     root_map: PageMapPtr,
     regions: BTreeMap<vadr, Region>,
     free_regions: AllocatorTy,
+    /// start and length of the range new allocations are randomly placed in.
+    random_range: Option<(vadr, usize)>,
 }
 
 bit_flags!(
@@ -44,7 +78,63 @@ bit_flags!(
 
 pub enum MapTy {
     None,
-    Phys { adr: PhysAdr },
+    Phys {
+        adr: PhysAdr,
+    },
+    /// the pages of a file from the page aligned `offset` on, which are
+    /// taken from the page cache the first time they are touched.
+    File {
+        inode: CachedInode,
+        offset: usize,
+    },
+    /// the pages of a shared memory object, from its start.
+    Shared {
+        object: SharedRef,
+    },
+    /// pages of another process, mapped right away and held until the
+    /// mapping is gone.
+    Pinned {
+        pages: Vec<PinnedPage>,
+    },
+}
+
+/// A userspace page held in place so it can be mapped into another process,
+/// as IPC grants do.
+pub struct PinnedPage {
+    phys: PhysAdr,
+    owner: PageOwner,
+}
+
+enum PageOwner {
+    /// anonymous memory and pages of another process, which go away only
+    /// with the address space they belong to. nothing frees those yet.
+    Process,
+    /// a page cache page, pinned once more for as long as this lives.
+    File {
+        inode: CachedInode,
+        index: usize,
+        writable: bool,
+    },
+    Shared(SharedRef),
+}
+
+impl PinnedPage {
+    pub fn phys(&self) -> PhysAdr {
+        self.phys
+    }
+}
+
+impl Drop for PinnedPage {
+    fn drop(&mut self) {
+        if let PageOwner::File {
+            inode,
+            index,
+            writable,
+        } = &self.owner
+        {
+            inode.unpin(*index, *writable);
+        }
+    }
 }
 
 impl Debug for VMM {
@@ -73,43 +163,210 @@ impl VMM {
         } else {
             self.alloc_new(page_size, pages)?
         };
-        let mut vm_flags = VMFlags::NONE;
-        macro_rules! sf {
-            ($f:expr => $f2:expr) => {
-                if flags.has($f) {
-                    vm_flags |= $f2
-                }
-            };
-            (not $f:expr => $f2:expr) => {
-                if !flags.has($f) {
-                    vm_flags |= $f2
-                }
-            };
+        self.map_reserved(virt, pages, flags, ty);
+        Ok(virt)
+    }
+
+    /// maps `pages` pages directly above `guard_pages` pages which are reserved
+    /// but never mapped, so running off the low end of the mapping faults.
+    /// returns the address of the first mapped page.
+    pub fn map_guarded(
+        &mut self,
+        pages: usize,
+        guard_pages: usize,
+        flags: Flags,
+        ty: MapTy,
+    ) -> Result<VirtAdr> {
+        let page_size = Self::page_size_from_flags(flags);
+        let guard = self.alloc_new(page_size, guard_pages + pages)?;
+        self.regions.insert(
+            guard.adr(),
+            Region {
+                page_cnt: guard_pages,
+                page_size,
+                flags: Flags::NONE,
+                ty: RegionType::Guard,
+                source: MapTy::None,
+            },
+        );
+        let virt = guard.add(guard_pages * page_size);
+        self.map_reserved(virt, pages, flags, ty);
+        Ok(virt)
+    }
+
+    /// maps `pages`, which needn't be contiguous, one after another at a new
+    /// address. they stay pinned for as long as they are mapped.
+    pub fn map_pages(&mut self, pages: Vec<PinnedPage>, flags: Flags) -> Result<VirtAdr> {
+        debug_assert_eq!(Self::page_size_from_flags(flags), PAGE_SIZE);
+        let virt = self.alloc_new(PAGE_SIZE, pages.len())?;
+        let vm_flags = Self::vm_flags(flags) | VMFlags::PRESENT;
+        for (i, page) in pages.iter().enumerate() {
+            let adr = virt.add(i * PAGE_SIZE);
+            unsafe { vm::map(self.root_map, adr, 1, page.phys, vm_flags) };
         }
-        sf!(not Flags::EXECUTABLE => VMFlags::XD);
-        sf!(Flags::LARGE_PAGE_SIZE => VMFlags::SIZE_LARGE);
-        sf!(Flags::MEDIUM_PAGE_SIZE => VMFlags::SIZE_MEDIUM);
-        sf!(Flags::RW => VMFlags::RW);
-        sf!(Flags::USER => VMFlags::USER);
+        self.regions.insert(
+            virt.adr(),
+            Region {
+                page_cnt: pages.len(),
+                page_size: PAGE_SIZE,
+                flags,
+                ty: RegionType::Normal,
+                source: MapTy::Pinned { pages },
+            },
+        );
+        Ok(virt)
+    }
 
+    fn map_reserved(&mut self, virt: VirtAdr, pages: usize, flags: Flags, ty: MapTy) {
+        let page_size = Self::page_size_from_flags(flags);
+        let mut vm_flags = Self::vm_flags(flags);
         let phys_adr = match ty {
-            MapTy::None => PhysAdr::null(),
             MapTy::Phys { adr } => {
                 vm_flags |= VMFlags::PRESENT;
                 debug_assert!(adr.is_aligned(page_size));
                 adr.align_floor(page_size)
             }
+            MapTy::File { .. } | MapTy::Shared { .. } => {
+                debug_assert_eq!(page_size, PAGE_SIZE);
+                PhysAdr::null()
+            }
+            MapTy::None | MapTy::Pinned { .. } => PhysAdr::null(),
         };
+        self.regions.insert(
+            virt.adr(),
+            Region {
+                page_cnt: pages,
+                page_size,
+                flags,
+                ty: RegionType::Normal,
+                source: ty,
+            },
+        );
         debug!("virt: {:016x}", virt.adr());
         debug!("{:?}", self.free_regions);
         unsafe { vm::map(self.root_map, virt, pages, phys_adr, vm_flags) };
-        Ok(virt)
+    }
+
+    /// maps the page containing `virt` if it belongs to a file or shared
+    /// mapping and wasn't touched before. returns whether it did, any other
+    /// fault is a real one.
+    pub fn populate(&mut self, virt: VirtAdr) -> bool {
+        if self.contains_page(virt) {
+            return false;
+        }
+        let (start, region) = match self.regions.range(..=virt.adr()).next_back() {
+            Some((&start, region)) if virt.adr() < start + region.len() as vadr => (start, region),
+            _ => return false,
+        };
+        let index = ((virt.adr() - start) / PAGE_SIZE as vadr) as usize;
+        let writable = region.flags.has(Flags::RW);
+        let phys = match &region.source {
+            MapTy::File { inode, offset } => inode.pin(offset / PAGE_SIZE + index, writable),
+            MapTy::Shared { object } => object.page(index),
+            MapTy::None | MapTy::Phys { .. } | MapTy::Pinned { .. } => return false,
+        };
+        let phys = match phys {
+            Ok(phys) => phys,
+            Err(e) => {
+                warn!("failed to populate page at {:016x}: {e}", virt.adr());
+                return false;
+            }
+        };
+        let page = VirtAdr::new(start + (index * PAGE_SIZE) as vadr);
+        let vm_flags = Self::vm_flags(region.flags) | VMFlags::PRESENT;
+        unsafe { vm::map(self.root_map, page, 1, phys, vm_flags) };
+        true
+    }
+
+    /// changes the flags of the mapping starting at `virt`, its pages keep
+    /// their contents. stale TLB entries aren't flushed, so this is only for
+    /// page maps which aren't installed yet.
+    pub fn protect(&mut self, virt: VirtAdr, flags: Flags) -> Result<()> {
+        let region = self
+            .regions
+            .get_mut(&virt.adr())
+            .ok_or(Error::NotMapped(virt.adr()))?;
+        debug_assert_eq!(Self::page_size_from_flags(flags), region.page_size);
+        region.flags = flags;
+        let (pages, page_size) = (region.page_cnt, region.page_size);
+        let vm_flags = Self::vm_flags(flags) | VMFlags::PRESENT;
+        for i in 0..pages {
+            let page = virt.add(i * page_size);
+            if let Some(phys) = self.virt_to_phys(page) {
+                unsafe { vm::map(self.root_map, page, 1, phys, vm_flags) };
+            }
+        }
+        Ok(())
     }
 
     pub unsafe fn unmap(&mut self, _ptr: *mut u8, _pages: usize) -> Result<()> {
         unimplemented!()
     }
 
+    /// returns true if every byte in `virt..virt + len` lies in the lower half and
+    /// is covered by mappings accessible from userspace, and writable if `write` is set.
+    pub fn is_user_range(&self, virt: VirtAdr, len: usize, write: bool) -> bool {
+        let end = match virt.adr().checked_add(len as vadr) {
+            Some(end) if end <= USERSPACE_END => end,
+            _ => return false,
+        };
+        let mut cur = virt.adr();
+        while cur < end {
+            let (start, region) = match self.regions.range(..=cur).next_back() {
+                Some((&start, region)) => (start, region),
+                None => return false,
+            };
+            let region_end = start + region.len() as vadr;
+            if cur >= region_end
+                || region.ty != RegionType::Normal
+                || !region.flags.has(Flags::USER)
+                || (write && !region.flags.has(Flags::RW))
+            {
+                return false;
+            }
+            cur = region_end;
+        }
+        true
+    }
+
+    /// the reserved and mapped ranges in ascending order.
+    pub fn regions(&self) -> impl Iterator<Item = RegionInfo> + '_ {
+        self.regions.iter().map(|(&start, region)| RegionInfo {
+            start: VirtAdr::new(start),
+            len: region.len(),
+            flags: region.flags,
+            ty: region.ty,
+        })
+    }
+
+    /// the userspace page at `virt`, which is populated first if it wasn't
+    /// touched yet and can't be freed or evicted while it is pinned. `None`
+    /// unless the page is accessible from userspace, and writable if `write`
+    /// is set.
+    pub fn pin_user_page(&mut self, virt: VirtAdr, write: bool) -> Option<PinnedPage> {
+        if !self.is_user_range(virt, PAGE_SIZE, write) {
+            return None;
+        }
+        self.populate(virt);
+        let phys = self.virt_to_phys(virt)?;
+        let (&start, region) = self.regions.range(..=virt.adr()).next_back()?;
+        let owner = match &region.source {
+            MapTy::File { inode, offset } => {
+                let index = offset / PAGE_SIZE + ((virt.adr() - start) as usize / PAGE_SIZE);
+                // the page is populated, so this only counts the pin.
+                inode.pin(index, write).ok()?;
+                PageOwner::File {
+                    inode: inode.clone(),
+                    index,
+                    writable: write,
+                }
+            }
+            MapTy::Shared { object } => PageOwner::Shared(object.clone()),
+            MapTy::None | MapTy::Phys { .. } | MapTy::Pinned { .. } => PageOwner::Process,
+        };
+        Some(PinnedPage { phys, owner })
+    }
+
     pub fn contains_page(&self, virt: VirtAdr) -> bool {
         self.virt_to_phys(virt).is_some()
     }
@@ -129,7 +386,7 @@ impl VMM {
     pub fn new_userland() -> Self {
         let mut allocator = AllocatorTy::new();
         let allocator_start = 1u64 << 20;
-        let allocator_len = (1 << 47) - PAGE_SIZE - allocator_start as usize;
+        let allocator_len = USERSPACE_END as usize - PAGE_SIZE - allocator_start as usize;
         allocator
             .push_region(allocator_start, allocator_len)
             .expect("failed to push region for allocator");
@@ -137,9 +394,32 @@ impl VMM {
             root_map: unsafe { vm::new_userland_page_map() },
             regions: BTreeMap::new(),
             free_regions: allocator,
+            random_range: None,
         }
     }
 
+    fn vm_flags(flags: Flags) -> VMFlags {
+        let mut vm_flags = VMFlags::NONE;
+        macro_rules! sf {
+            ($f:expr => $f2:expr) => {
+                if flags.has($f) {
+                    vm_flags |= $f2
+                }
+            };
+            (not $f:expr => $f2:expr) => {
+                if !flags.has($f) {
+                    vm_flags |= $f2
+                }
+            };
+        }
+        sf!(not Flags::EXECUTABLE => VMFlags::XD);
+        sf!(Flags::LARGE_PAGE_SIZE => VMFlags::SIZE_LARGE);
+        sf!(Flags::MEDIUM_PAGE_SIZE => VMFlags::SIZE_MEDIUM);
+        sf!(Flags::RW => VMFlags::RW);
+        sf!(Flags::USER => VMFlags::USER);
+        vm_flags
+    }
+
     fn page_size_from_flags(flags: Flags) -> usize {
         if flags.has(Flags::LARGE_PAGE_SIZE) {
             LARGE_PAGE_SIZE
@@ -159,12 +439,32 @@ impl VMM {
     }
 
     fn alloc_new(&mut self, page_size: usize, pages: usize) -> Result<VirtAdr> {
+        if let Some(adr) = self.alloc_random(page_size, pages) {
+            return Ok(adr);
+        }
         Ok(self
             .free_regions
             .alloc_aligned_bytes(page_size, pages * page_size)
             .map(|adr| VirtAdr::new(adr as u64))?)
     }
 
+    /// tries to reserve the range at a random address in `random_range`.
+    fn alloc_random(&mut self, page_size: usize, pages: usize) -> Option<VirtAdr> {
+        let (start, len) = self.random_range?;
+        let bytes = pages * page_size;
+        if bytes > len {
+            return None;
+        }
+        let slots = ((len - bytes) / page_size) as u64 + 1;
+        for _ in 0..RANDOM_PLACEMENT_ATTEMPTS {
+            let adr = start + random::next_below(slots) * page_size as u64;
+            if let Ok(true) = self.free_regions.reserve_bytes(adr, bytes) {
+                return Some(VirtAdr::new(adr));
+            }
+        }
+        None
+    }
+
     fn alloc_free(&mut self, vadr: VirtAdr, page_size: usize, pages: usize) -> Result<()> {
         Ok(unsafe {
             self.free_regions
@@ -192,6 +492,7 @@ pub unsafe fn init_kernel_vmm(boot_info: &BootInfo) -> VMM {
         root_map: vm::kernel_page_map(),
         regions: BTreeMap::new(),
         free_regions: allocator,
+        random_range: Some((allocator_start, allocator_len)),
     };
     // Memory map physical memory as HHDM.
     let hhdm_pages = pmm::hhdm_len() / LARGE_PAGE_SIZE;
@@ -1,6 +1,7 @@
 use crate::arch::interrupt;
 use crate::arch::stack_unwind::StackFrame;
 use crate::kernel_elf;
+#[cfg(not(test))]
 use core::panic::PanicInfo;
 
 #[inline(always)]
@@ -10,11 +11,19 @@ unsafe fn unwind_stack() {
     info!("stacktrace: \n");
     while !stack_frame.is_null() {
         let ip = (*stack_frame).ip();
-        info!("{} [0x{:016x}] ({}:{})", "symbol", ip, "file.rs", 0);
+        info!(
+            "{} [0x{:016x}, link 0x{:016x}] ({}:{})",
+            "symbol",
+            ip,
+            kernel_elf::unslide(ip),
+            "file.rs",
+            0
+        );
         stack_frame = (*stack_frame).next();
     }
 }
 
+#[cfg(not(test))]
 #[panic_handler]
 unsafe fn panic(info: &PanicInfo) -> ! {
     interrupt::disable();
@@ -1,18 +1,24 @@
 use core::fmt::Display;
 
-use crate::mm::heap;
+use crate::fs;
+use crate::mm::{heap, vmm};
+use elf::elf64;
 
 #[derive(Debug)]
 pub enum Error {
-    HeapAllocationError(heap::Error),
+    HeapAllocation(heap::Error),
+    VirtualMemory(vmm::error::Error),
+    Fs(fs::Error),
+    Elf(elf64::Error),
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
-            Error::HeapAllocationError(err) => {
-                f.write_fmt(format_args!("heap allocation error: {err}"))
-            }
+            Error::HeapAllocation(err) => f.write_fmt(format_args!("heap allocation error: {err}")),
+            Error::VirtualMemory(err) => f.write_fmt(format_args!("virtual memory error: {err}")),
+            Error::Fs(err) => f.write_fmt(format_args!("filesystem error: {err}")),
+            Error::Elf(err) => f.write_fmt(format_args!("elf error: {err}")),
         }
     }
 }
@@ -21,7 +27,25 @@ impl core::error::Error for Error {}
 
 impl From<heap::Error> for Error {
     fn from(value: heap::Error) -> Self {
-        Self::HeapAllocationError(value)
+        Self::HeapAllocation(value)
+    }
+}
+
+impl From<vmm::error::Error> for Error {
+    fn from(value: vmm::error::Error) -> Self {
+        Self::VirtualMemory(value)
+    }
+}
+
+impl From<fs::Error> for Error {
+    fn from(value: fs::Error) -> Self {
+        Self::Fs(value)
+    }
+}
+
+impl From<elf64::Error> for Error {
+    fn from(value: elf64::Error) -> Self {
+        Self::Elf(value)
     }
 }
 
@@ -0,0 +1,108 @@
+//! per-process file descriptor table.
+
+use crate::fs::FileRef;
+use ::syscall::fs::{Fd, STDERR, STDIN, STDOUT};
+use ::syscall::{Errno, Result};
+use alloc::vec::Vec;
+use core::fmt::Debug;
+
+/// descriptors a single process can have open at once.
+pub const MAX_FDS: usize = 256;
+
+/// Maps descriptors to open files. Several descriptors, also from other
+/// processes, can refer to the same open file and share its offset.
+#[derive(Default)]
+pub struct FdTable {
+    files: Vec<Option<FileRef>>,
+}
+
+impl FdTable {
+    pub fn new() -> Self {
+        Self { files: Vec::new() }
+    }
+
+    /// a table with `console` open as stdin, stdout and stderr.
+    pub fn with_stdio(console: FileRef) -> Self {
+        let mut table = Self::new();
+        for fd in [STDIN, STDOUT, STDERR] {
+            table
+                .install(fd, console.clone())
+                .expect("stdio descriptors are below MAX_FDS");
+        }
+        table
+    }
+
+    /// the open descriptors in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = (Fd, &FileRef)> {
+        self.files
+            .iter()
+            .enumerate()
+            .filter_map(|(fd, file)| Some((fd, file.as_ref()?)))
+    }
+
+    pub fn get(&self, fd: Fd) -> Result<FileRef> {
+        self.files
+            .get(fd)
+            .and_then(Option::clone)
+            .ok_or(Errno::EBADF)
+    }
+
+    /// stores `file` in the lowest free descriptor.
+    pub fn alloc(&mut self, file: FileRef) -> Result<Fd> {
+        let fd = match self.files.iter().position(Option::is_none) {
+            Some(fd) => fd,
+            None if self.files.len() < MAX_FDS => {
+                self.files.push(None);
+                self.files.len() - 1
+            }
+            None => return Err(Errno::EMFILE),
+        };
+        self.files[fd] = Some(file);
+        Ok(fd)
+    }
+
+    /// stores `file` in `fd` and returns what was open there before.
+    pub fn install(&mut self, fd: Fd, file: FileRef) -> Result<Option<FileRef>> {
+        if fd >= MAX_FDS {
+            return Err(Errno::EBADF);
+        }
+        if fd >= self.files.len() {
+            self.files.resize(fd + 1, None);
+        }
+        Ok(self.files[fd].replace(file))
+    }
+
+    /// removes `fd` from the table, the file itself is closed once the last
+    /// reference to it is gone.
+    pub fn close(&mut self, fd: Fd) -> Result<FileRef> {
+        let file = self
+            .files
+            .get_mut(fd)
+            .and_then(Option::take)
+            .ok_or(Errno::EBADF)?;
+        while let Some(None) = self.files.last() {
+            self.files.pop();
+        }
+        Ok(file)
+    }
+
+    pub fn dup(&mut self, fd: Fd) -> Result<Fd> {
+        let file = self.get(fd)?;
+        self.alloc(file)
+    }
+
+    pub fn dup2(&mut self, old: Fd, new: Fd) -> Result<Fd> {
+        let file = self.get(old)?;
+        if old != new {
+            self.install(new, file)?;
+        }
+        Ok(new)
+    }
+}
+
+impl Debug for FdTable {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let open = self.files.iter().filter(|file| file.is_some()).count();
+        write!(f, "FdTable {{ open: {open} }}")
+    }
+}
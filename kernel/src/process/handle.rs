@@ -0,0 +1,366 @@
+//! per-process table of handles to kernel objects.
+//!
+//! A handle is a process' capability on an object: syscalls only act on
+//! objects the caller holds a handle to, with the rights the syscall needs.
+//! Handles can be duplicated with fewer rights and moved to other processes
+//! over IPC, but never gain rights.
+//!
+//! Looking up endpoints and shared memory by name is the exception, it needs
+//! no handle. Names are reached like paths, which every process opens files
+//! by without a handle too, so gating them alone wouldn't confine anything.
+//! Objects meant for a few processes only are created without a name and
+//! passed as handles.
+
+use super::thread::ThreadPtr;
+use super::ProcessPtr;
+use crate::ipc::{EndpointRef, ServerRef};
+use crate::mm::shared::SharedRef;
+use ::syscall::handle::{self as sc, Handle, HandleInfo};
+use ::syscall::{Errno, Result};
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use core::mem;
+use core::ops::Range;
+
+/// handles a single process can hold at once.
+pub const MAX_HANDLES: usize = 1024;
+
+// the bits match the `RIGHT_*` constants of the syscall crate.
+bit_flags!(
+    pub struct Rights(u32);
+    DUPLICATE = 0;
+    TRANSFER = 1;
+    READ = 2;
+    WRITE = 3;
+    MAP = 4;
+    SEND = 5;
+    RECEIVE = 6;
+    MANAGE = 7;
+);
+
+const_assert!(Rights::DUPLICATE.0 == sc::RIGHT_DUPLICATE);
+const_assert!(Rights::MANAGE.0 == sc::RIGHT_MANAGE);
+
+impl Rights {
+    pub const ALL: Rights = Rights(sc::RIGHTS_ALL);
+
+    pub fn from_raw(raw: u32) -> Result<Self> {
+        if raw & !sc::RIGHTS_ALL != 0 {
+            return Err(Errno::EINVAL);
+        }
+        Ok(Self(raw))
+    }
+
+    pub fn raw(self) -> u32 {
+        self.0
+    }
+}
+
+/// A kernel object a handle refers to.
+#[derive(Clone)]
+pub enum Object {
+    Process(ProcessPtr),
+    Thread(ThreadPtr),
+    /// memory which can be mapped into processes.
+    Memory(SharedRef),
+    /// an endpoint to send to.
+    Endpoint(EndpointRef),
+    /// an endpoint to receive on and send to.
+    Server(ServerRef),
+    Irq(u8),
+    IoPorts(Range<u16>),
+}
+
+impl Object {
+    /// the `OBJECT_*` type of the syscall crate.
+    pub fn ty(&self) -> u32 {
+        match self {
+            Self::Process(_) => sc::OBJECT_PROCESS,
+            Self::Thread(_) => sc::OBJECT_THREAD,
+            Self::Memory(_) => sc::OBJECT_MEMORY,
+            Self::Endpoint(_) | Self::Server(_) => sc::OBJECT_ENDPOINT,
+            Self::Irq(_) => sc::OBJECT_IRQ,
+            Self::IoPorts(_) => sc::OBJECT_IO_PORTS,
+        }
+    }
+}
+
+/// An object along with the rights its handle has.
+#[derive(Clone)]
+pub struct Entry {
+    pub object: Object,
+    pub rights: Rights,
+}
+
+impl Entry {
+    pub fn new(object: Object, rights: Rights) -> Self {
+        Self { object, rights }
+    }
+
+    pub fn info(&self) -> HandleInfo {
+        HandleInfo {
+            ty: self.object.ty(),
+            rights: self.rights.raw(),
+        }
+    }
+}
+
+#[derive(Default)]
+enum Slot {
+    #[default]
+    Free,
+    Open(Entry),
+    /// taken with a message which isn't delivered yet, the entry comes back
+    /// if it isn't.
+    Moving,
+}
+
+/// Maps handles to the objects they refer to, like `FdTable` does for files.
+#[derive(Default)]
+pub struct HandleTable {
+    entries: Vec<Slot>,
+}
+
+impl HandleTable {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    /// the object `handle` refers to, which fails with `EACCES` if the handle
+    /// lacks any of `rights`.
+    pub fn get(&self, handle: Handle, rights: Rights) -> Result<&Object> {
+        let entry = self.entry(handle)?;
+        if !entry.rights.has(rights) {
+            return Err(Errno::EACCES);
+        }
+        Ok(&entry.object)
+    }
+
+    pub fn entry(&self, handle: Handle) -> Result<&Entry> {
+        match self.entries.get(handle) {
+            Some(Slot::Open(entry)) => Ok(entry),
+            _ => Err(Errno::EBADF),
+        }
+    }
+
+    pub fn process(&self, handle: Handle, rights: Rights) -> Result<ProcessPtr> {
+        match self.get(handle, rights)? {
+            Object::Process(process) => Ok(*process),
+            _ => Err(Errno::EBADF),
+        }
+    }
+
+    pub fn memory(&self, handle: Handle, rights: Rights) -> Result<SharedRef> {
+        match self.get(handle, rights)? {
+            Object::Memory(object) => Ok(object.clone()),
+            _ => Err(Errno::EBADF),
+        }
+    }
+
+    /// the endpoint `handle` sends to.
+    pub fn endpoint(&self, handle: Handle) -> Result<EndpointRef> {
+        match self.get(handle, Rights::SEND)? {
+            Object::Endpoint(endpoint) => Ok(endpoint.clone()),
+            Object::Server(server) => Ok(server.endpoint().clone()),
+            _ => Err(Errno::EBADF),
+        }
+    }
+
+    /// the endpoint `handle` receives on.
+    pub fn server(&self, handle: Handle) -> Result<ServerRef> {
+        match self.get(handle, Rights::RECEIVE)? {
+            Object::Server(server) => Ok(server.clone()),
+            _ => Err(Errno::EBADF),
+        }
+    }
+
+    /// the interrupt line `handle` refers to.
+    pub fn irq(&self, handle: Handle, rights: Rights) -> Result<u8> {
+        match self.get(handle, rights)? {
+            Object::Irq(line) => Ok(*line),
+            _ => Err(Errno::EBADF),
+        }
+    }
+
+    /// the ports `handle` refers to.
+    pub fn io_ports(&self, handle: Handle, rights: Rights) -> Result<Range<u16>> {
+        match self.get(handle, rights)? {
+            Object::IoPorts(ports) => Ok(ports.clone()),
+            _ => Err(Errno::EBADF),
+        }
+    }
+
+    /// stores `entry` in the lowest free handle.
+    pub fn alloc(&mut self, entry: Entry) -> Result<Handle> {
+        let free = self
+            .entries
+            .iter()
+            .position(|slot| matches!(slot, Slot::Free));
+        let handle = match free {
+            Some(handle) => handle,
+            None if self.entries.len() < MAX_HANDLES => {
+                self.entries.push(Slot::Free);
+                self.entries.len() - 1
+            }
+            None => return Err(Errno::EMFILE),
+        };
+        self.entries[handle] = Slot::Open(entry);
+        Ok(handle)
+    }
+
+    /// a new handle to the object of `handle`, with `rights` which it has to
+    /// have as well as `Rights::DUPLICATE`.
+    pub fn duplicate(&mut self, handle: Handle, rights: Rights) -> Result<Handle> {
+        let object = self.get(handle, Rights::DUPLICATE)?.clone();
+        if !self.entry(handle)?.rights.has(rights) {
+            return Err(Errno::EACCES);
+        }
+        self.alloc(Entry::new(object, rights))
+    }
+
+    /// takes `handle` to move it to another process, which it needs
+    /// `Rights::TRANSFER` for. the handle isn't reused until the move is done
+    /// with `release`, or undone with `restore`.
+    pub fn take(&mut self, handle: Handle) -> Result<Entry> {
+        self.get(handle, Rights::TRANSFER)?;
+        match mem::replace(&mut self.entries[handle], Slot::Moving) {
+            Slot::Open(entry) => Ok(entry),
+            _ => unreachable!("handle checked to be open"),
+        }
+    }
+
+    /// puts `entry` back to `handle`, which `take` took it from.
+    pub fn restore(&mut self, handle: Handle, entry: Entry) {
+        debug_assert!(matches!(self.entries.get(handle), Some(Slot::Moving)));
+        self.entries[handle] = Slot::Open(entry);
+    }
+
+    /// frees `handle` once the entry `take` took from it was moved.
+    pub fn release(&mut self, handle: Handle) {
+        debug_assert!(matches!(self.entries.get(handle), Some(Slot::Moving)));
+        self.free(handle);
+    }
+
+    /// removes `handle` from the table, the object goes away with the last
+    /// handle to it.
+    pub fn close(&mut self, handle: Handle) -> Result<Entry> {
+        self.entry(handle)?;
+        match self.free(handle) {
+            Slot::Open(entry) => Ok(entry),
+            _ => unreachable!("handle checked to be open"),
+        }
+    }
+
+    fn free(&mut self, handle: Handle) -> Slot {
+        let slot = mem::take(&mut self.entries[handle]);
+        while let Some(Slot::Free) = self.entries.last() {
+            self.entries.pop();
+        }
+        slot
+    }
+}
+
+impl Debug for HandleTable {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let open = self
+            .entries
+            .iter()
+            .filter(|slot| matches!(slot, Slot::Open(_)))
+            .count();
+        write!(f, "HandleTable {{ open: {open} }}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn irq(table: &mut HandleTable, rights: Rights) -> Handle {
+        table.alloc(Entry::new(Object::Irq(1), rights)).unwrap()
+    }
+
+    fn rights(table: &HandleTable, handle: Handle) -> Result<u32> {
+        table.entry(handle).map(|entry| entry.rights.raw())
+    }
+
+    #[test]
+    fn handles_need_the_rights_they_are_used_with() {
+        let mut table = HandleTable::new();
+        let handle = irq(&mut table, Rights::READ | Rights::WRITE);
+        assert_eq!(table.irq(handle, Rights::READ), Ok(1));
+        assert_eq!(table.irq(handle, Rights::READ | Rights::WRITE), Ok(1));
+        assert_eq!(table.irq(handle, Rights::MANAGE), Err(Errno::EACCES));
+        assert_eq!(
+            table.irq(handle, Rights::READ | Rights::MAP),
+            Err(Errno::EACCES)
+        );
+        // the wrong type of object or no object at all.
+        assert!(matches!(
+            table.process(handle, Rights::READ),
+            Err(Errno::EBADF)
+        ));
+        assert_eq!(table.irq(handle + 1, Rights::READ), Err(Errno::EBADF));
+    }
+
+    #[test]
+    fn duplicates_never_gain_rights() {
+        let mut table = HandleTable::new();
+        let handle = irq(&mut table, Rights::DUPLICATE | Rights::READ);
+        let copy = table.duplicate(handle, Rights::READ).unwrap();
+        assert_eq!(rights(&table, copy), Ok(Rights::READ.raw()));
+        assert_eq!(
+            table.duplicate(handle, Rights::READ | Rights::WRITE),
+            Err(Errno::EACCES)
+        );
+        // the copy lacks the right to be duplicated itself.
+        assert_eq!(table.duplicate(copy, Rights::READ), Err(Errno::EACCES));
+        assert_eq!(table.duplicate(copy, Rights(0)), Err(Errno::EACCES));
+    }
+
+    #[test]
+    fn moving_a_handle_needs_transfer() {
+        let mut table = HandleTable::new();
+        let kept = irq(&mut table, Rights::READ);
+        assert!(matches!(table.take(kept), Err(Errno::EACCES)));
+        assert_eq!(rights(&table, kept), Ok(Rights::READ.raw()));
+
+        let moved = irq(&mut table, Rights::TRANSFER | Rights::READ);
+        let entry = table.take(moved).unwrap();
+        assert_eq!(entry.rights.raw(), (Rights::TRANSFER | Rights::READ).raw());
+        assert_eq!(rights(&table, moved), Err(Errno::EBADF));
+        assert!(matches!(table.take(moved), Err(Errno::EBADF)));
+        assert_eq!(table.close(moved).err(), Some(Errno::EBADF));
+    }
+
+    #[test]
+    fn moving_handles_are_not_reused() {
+        let mut table = HandleTable::new();
+        let moved = irq(&mut table, Rights::TRANSFER);
+        let last = irq(&mut table, Rights::READ);
+        let _entry = table.take(moved).unwrap();
+        // neither the moving handle nor the one after it when the last is
+        // closed.
+        table.close(last).unwrap();
+        assert_eq!(irq(&mut table, Rights::READ), last);
+        assert_eq!(irq(&mut table, Rights::READ), last + 1);
+
+        table.release(moved);
+        assert_eq!(irq(&mut table, Rights::READ), moved);
+    }
+
+    #[test]
+    fn undelivered_handles_come_back() {
+        let mut table = HandleTable::new();
+        let handle = irq(&mut table, Rights::TRANSFER | Rights::READ);
+        let entry = table.take(handle).unwrap();
+        table.restore(handle, entry);
+        assert_eq!(table.irq(handle, Rights::READ), Ok(1));
+        // and can be moved again.
+        let entry = table.take(handle).unwrap();
+        table.release(handle);
+        assert_eq!(rights(&table, handle), Err(Errno::EBADF));
+        drop(entry);
+    }
+}
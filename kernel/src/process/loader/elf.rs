@@ -1,58 +1,266 @@
-use crate::arch::interrupt::StackFrame;
-use crate::mm::pmm;
-use crate::mm::vmm::{Flags, MapTy, VMM};
-use crate::process::thread::{self, ThreadId};
+use crate::fs::FileRef;
+use crate::mm::page_cache::CachedInode;
+use crate::mm::pmm::{self, PAGE_SIZE};
+use crate::mm::vmm::{Flags, MapTy, USERSPACE_END, VMM};
+use crate::process::handle::{Entry, Object, Rights};
+use crate::process::thread::{self, sched, ThreadId};
 use crate::process::{self, ProcessId, ProcessPtr, Result};
 use crate::util::adr::VirtAdr;
-use alloc::boxed::Box;
+use ::syscall::handle::{HANDLE_GRANTED, HANDLE_MAIN_THREAD};
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::{ptr, slice};
+use elf::elf64::{Elf64Ehdr, Elf64Owned, Elf64Phdr, Error as ElfError};
 use elf::Elf64;
 
-pub fn spawn(elf: &Elf64) -> Result<(ProcessPtr, ProcessId)> {
-    let mut vmm = unsafe { map(elf, VMM::new_userland()) };
-    let stack = unsafe { thread::create_userspace_thread_stack(&mut vmm, 256) };
-    let stackframe = Box::new(StackFrame::new_userspace(
-        elf.program_entry(),
-        stack.adr(),
-        vmm.get_page_map(),
-    ));
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1 << 0;
+const PF_W: u32 = 1 << 1;
+
+/// how far into an executable its program headers may lie, only this much is
+/// read before the segments.
+const MAX_HEADERS_LEN: usize = 64 * 1024;
+
+/// spawns a new process running `elf`, with a main thread stack of `stack_pages` pages.
+/// it starts out with handles to `grants`, from `HANDLE_GRANTED` on.
+pub fn spawn(elf: &Elf64, stack_pages: usize, grants: &[Entry]) -> Result<(ProcessPtr, ProcessId)> {
+    let segments: Vec<&Elf64Phdr> = loadable(elf).collect();
+    let mut vmm = VMM::new_userland();
+    let mut copied = BTreeMap::new();
+    for segment in &segments {
+        unsafe {
+            copy_segment(
+                &mut vmm,
+                &Source::Memory(elf.as_bytes()),
+                segment,
+                &mut copied,
+            )?
+        };
+    }
+    seal(&mut vmm, copied)?;
+    start(vmm, elf.program_entry(), stack_pages, grants)
+}
+
+/// spawns a new process running the executable `file`. only its headers are
+/// read up front. its read-only segments are mapped from the page cache, so
+/// every process running it shares them, only writable ones are copied. it
+/// starts out with handles to `grants`, like with `spawn`.
+pub fn spawn_file(
+    file: &FileRef,
+    stack_pages: usize,
+    grants: &[Entry],
+) -> Result<(ProcessPtr, ProcessId)> {
+    let elf = read_headers(file)?;
+    // files outside the page cache can only be copied.
+    let inode = file.cached_inode().ok();
+    let segments: Vec<&Elf64Phdr> = loadable(&elf).collect();
+    let mut vmm = VMM::new_userland();
+    let mut copied = BTreeMap::new();
+    for segment in &segments {
+        match &inode {
+            Some(inode) if mappable(segment, &segments) => map_file(&mut vmm, inode, segment)?,
+            _ => unsafe { copy_segment(&mut vmm, &Source::File(file), segment, &mut copied)? },
+        }
+    }
+    seal(&mut vmm, copied)?;
+    start(vmm, elf.program_entry(), stack_pages, grants)
+}
+
+fn loadable(elf: &Elf64) -> impl Iterator<Item = &Elf64Phdr> {
+    elf.program_headers()
+        .iter()
+        .filter(|segment| segment.p_type == PT_LOAD)
+}
+
+/// reads the ELF header and the program headers of `file`, but none of its
+/// segments.
+fn read_headers(file: &FileRef) -> Result<Elf64Owned> {
+    let mut header = vec![0; size_of::<Elf64Ehdr>()].into_boxed_slice();
+    read_exact(file, 0, &mut header)?;
+    let elf = Elf64::parse_elf(header)?;
+    if elf.program_header_size() != size_of::<Elf64Phdr>() {
+        return Err(ElfError::MalformedHeader("program header size".into()).into());
+    }
+    let len = (elf.program_header_count() * size_of::<Elf64Phdr>())
+        .checked_add(elf.program_header_offset() as usize)
+        .filter(|&len| len <= MAX_HEADERS_LEN)
+        .ok_or_else(|| ElfError::MalformedHeader("program headers out of reach".into()))?;
+    let mut headers = vec![0; len.max(size_of::<Elf64Ehdr>())].into_boxed_slice();
+    read_exact(file, 0, &mut headers)?;
+    Ok(Elf64::parse_elf(headers)?)
+}
+
+/// fills `buf` from `offset` on, failing if the file ends before.
+fn read_exact(file: &FileRef, offset: usize, buf: &mut [u8]) -> Result<()> {
+    let mut done = 0;
+    while done < buf.len() {
+        let read = file.read_at(offset + done, &mut buf[done..])?;
+        if read == 0 {
+            return Err(ElfError::MalformedHeader("unexpected end of file".into()).into());
+        }
+        done += read;
+    }
+    Ok(())
+}
+
+/// where copied segments are read from.
+enum Source<'a> {
+    Memory(&'a [u8]),
+    File(&'a FileRef),
+}
+
+impl Source<'_> {
+    fn read(&self, offset: usize, buf: &mut [u8]) -> Result<()> {
+        match self {
+            Source::Memory(data) => {
+                let contents = data
+                    .get(offset..)
+                    .and_then(|data| data.get(..buf.len()))
+                    .ok_or_else(|| ElfError::MalformedHeader("segment past end of file".into()))?;
+                buf.copy_from_slice(contents);
+                Ok(())
+            }
+            Source::File(file) => read_exact(file, offset, buf),
+        }
+    }
+}
+
+/// the flags `segment` asks for.
+fn segment_flags(segment: &Elf64Phdr) -> Flags {
+    let mut flags = Flags::USER;
+    if segment.p_flags & PF_W != 0 {
+        flags |= Flags::RW;
+    }
+    if segment.p_flags & PF_X != 0 {
+        flags |= Flags::EXECUTABLE;
+    }
+    flags
+}
+
+/// the main thread is scheduled once the handles are in place, so the
+/// process can't allocate any of its own before.
+fn start(
+    vmm: VMM,
+    entry: u64,
+    stack_pages: usize,
+    grants: &[Entry],
+) -> Result<(ProcessPtr, ProcessId)> {
     let (proc, id) = process::new_proc(vmm).expect("failed to create new process");
     unsafe {
-        proc.get_mut()
-            .add_thread(thread::new(ThreadId::gen(), proc, stackframe)?)?
+        let thread = thread::new_userspace(ThreadId::gen(), proc, entry, stack_pages)?;
+        let proc = proc.get_mut();
+        proc.add_thread(thread)?;
+        let handle = proc
+            .handles
+            .alloc(Entry::new(Object::Thread(thread), Rights::ALL))
+            .expect("handle table of a new process is full");
+        debug_assert_eq!(handle, HANDLE_MAIN_THREAD);
+        for (i, grant) in grants.iter().enumerate() {
+            let handle = proc
+                .handles
+                .alloc(grant.clone())
+                .expect("handle table of a new process is full");
+            debug_assert_eq!(handle, HANDLE_GRANTED + i);
+        }
+        sched::schedule(thread);
     };
     Ok((proc, id))
 }
 
-unsafe fn map(elf: &Elf64, mut vmm: VMM) -> VMM {
-    // load program headers.
-    for program_header in elf.program_headers() {
-        let mut bytes = program_header.p_memsz;
-        let mut vadr = program_header.p_vaddr;
-        let mut off = program_header.p_offset;
-        let pages = pages!(bytes) as usize;
-        for _ in 0..pages {
-            let hhdm = if let Some(phys) = vmm.virt_to_phys(VirtAdr::new(vadr)) {
-                pmm::phys_to_hhdm(phys)
-            } else {
-                let alloc = pmm::alloc_pages(1);
-                vmm.map(
-                    Some(VirtAdr::new(vadr)),
-                    1,
-                    Flags::RW | Flags::USER | Flags::EXECUTABLE,
-                    MapTy::Phys { adr: alloc.phys() },
-                )
-                .unwrap();
-                alloc.virt()
-            };
-            let unalignment = (vadr & (pmm::PAGE_SIZE as u64 - 1)) as usize;
-            let hhdm = hhdm.ptr();
-            let step = (pmm::PAGE_SIZE - unalignment) as u64;
-            hhdm.add(unalignment)
-                .copy_from(elf.as_ptr().add(off as usize), step as usize);
-            vadr += step;
-            off += step;
-            bytes = bytes.saturating_sub(step);
+/// whether `segment` can be mapped straight from the file: it's read-only,
+/// lies in the file as it does in memory and shares no page with any other
+/// segment, which may need to be copied.
+fn mappable(segment: &Elf64Phdr, segments: &[&Elf64Phdr]) -> bool {
+    let page = PAGE_SIZE as u64;
+    let pages = |segment: &Elf64Phdr| {
+        let start = segment.p_vaddr / page;
+        (start, pages!(segment.p_vaddr + segment.p_memsz) as u64)
+    };
+    let (start, end) = pages(segment);
+    segment.p_flags & PF_W == 0
+        && segment.p_memsz > 0
+        && segment.p_filesz == segment.p_memsz
+        && segment.p_offset % page == segment.p_vaddr % page
+        && segments
+            .iter()
+            .filter(|other| !ptr::eq(**other, segment))
+            .map(|other| pages(other))
+            .all(|(other_start, other_end)| other_end <= start || other_start >= end)
+}
+
+/// maps `segment` from the file, its pages are read in as they are touched.
+fn map_file(vmm: &mut VMM, inode: &CachedInode, segment: &Elf64Phdr) -> Result<()> {
+    let unalignment = segment.p_vaddr % PAGE_SIZE as u64;
+    vmm.map(
+        Some(VirtAdr::new(segment.p_vaddr - unalignment)),
+        pages!(unalignment + segment.p_memsz),
+        segment_flags(segment),
+        MapTy::File {
+            inode: inode.clone(),
+            offset: (segment.p_offset - unalignment) as usize,
+        },
+    )?;
+    Ok(())
+}
+
+/// copies `segment` from `source` into freshly allocated pages, zeroing what
+/// lies past its file contents. the pages stay writable until `seal`, which
+/// gives them the flags of the segments in `copied`.
+unsafe fn copy_segment(
+    vmm: &mut VMM,
+    source: &Source,
+    segment: &Elf64Phdr,
+    copied: &mut BTreeMap<u64, Flags>,
+) -> Result<()> {
+    let malformed = |what: &str| ElfError::MalformedHeader(format!("segment {what}"));
+    segment
+        .p_offset
+        .checked_add(segment.p_filesz)
+        .ok_or_else(|| malformed("past end of file"))?;
+    let end = segment
+        .p_vaddr
+        .checked_add(segment.p_memsz)
+        .filter(|&end| end <= USERSPACE_END)
+        .ok_or_else(|| malformed("outside of userspace"))?;
+    let flags = segment_flags(segment);
+    let mut vadr = segment.p_vaddr;
+    while vadr < end {
+        let page = VirtAdr::new(vadr).align_floor(PAGE_SIZE);
+        let hhdm = if let Some(phys) = vmm.virt_to_phys(page) {
+            pmm::phys_to_hhdm(phys)
+        } else {
+            let alloc = pmm::alloc_pages(1);
+            vmm.map(
+                Some(page),
+                1,
+                Flags::RW | Flags::USER,
+                MapTy::Phys { adr: alloc.phys() },
+            )?;
+            alloc.virt()
+        };
+        *copied.entry(page.adr()).or_insert(Flags::USER) |= flags;
+        let unalignment = (vadr % PAGE_SIZE as u64) as usize;
+        let chunk = (PAGE_SIZE - unalignment).min((end - vadr) as usize);
+        let bytes = slice::from_raw_parts_mut(hhdm.ptr().add(unalignment), chunk);
+        let pos = (vadr - segment.p_vaddr) as usize;
+        let from_file = (segment.p_filesz as usize).saturating_sub(pos).min(chunk);
+        if from_file > 0 {
+            source.read(segment.p_offset as usize + pos, &mut bytes[..from_file])?;
+        }
+        bytes[from_file..].fill(0);
+        vadr += chunk as u64;
+    }
+    Ok(())
+}
+
+/// gives every copied page the flags of the segments lying in it, which
+/// drops write access from read-only ones.
+fn seal(vmm: &mut VMM, copied: BTreeMap<u64, Flags>) -> Result<()> {
+    for (page, flags) in copied {
+        if !flags.has(Flags::RW) || flags.has(Flags::EXECUTABLE) {
+            vmm.protect(VirtAdr::new(page), flags)?;
         }
     }
-    vmm
+    Ok(())
 }
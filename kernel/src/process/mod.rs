@@ -39,12 +39,19 @@ macro_rules! def_locked_ptr {
     };
 }
 
+pub mod fd;
+pub mod handle;
 pub mod loader;
 pub mod thread;
 
 mod error;
 
+use self::fd::FdTable;
+use self::handle::{Entry, HandleTable, Object, Rights};
 use self::thread::ThreadPtr;
+use crate::arch;
+use crate::arch::io_bitmap::IoBitmap;
+use crate::drivers::console;
 use crate::mm::heap;
 use crate::mm::vmm::VMM;
 use crate::util::adr::VirtAdr;
@@ -61,6 +68,12 @@ type ProcessIdPrimitive = u16;
 #[derive(Clone, Copy, Debug)]
 pub struct ProcessId(ProcessIdPrimitive);
 
+impl ProcessId {
+    pub fn raw(self) -> u16 {
+        self.0
+    }
+}
+
 impl Display for ProcessId {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         Display::fmt(&self.0, f)
@@ -80,6 +93,10 @@ pub struct Process {
     pub id: ProcessId,
     thread_id_counter: u64,
     pub threads: Vec<ThreadPtr>,
+    pub files: FdTable,
+    pub handles: HandleTable,
+    /// the ports granted with `ioport_grant`, `None` until the first grant.
+    pub io_bitmap: Option<IoBitmap>,
 }
 
 impl Process {
@@ -136,6 +153,14 @@ pub fn get(id: ProcessId) -> Option<ProcessPtr> {
     }
 }
 
+/// every process which exists right now.
+pub fn all() -> Vec<ProcessPtr> {
+    let count = PROCESS_COUNTER.load(Ordering::Relaxed);
+    (0..count)
+        .filter_map(|index| get(ProcessId(index)))
+        .collect()
+}
+
 pub fn new_proc(vmm: VMM) -> Result<(ProcessPtr, ProcessId)> {
     let index = PROCESS_COUNTER.fetch_add(1, Ordering::Relaxed);
     let id = ProcessId(index);
@@ -147,9 +172,30 @@ pub fn new_proc(vmm: VMM) -> Result<(ProcessPtr, ProcessId)> {
             id,
             thread_id_counter: 0,
             threads: vec![],
+            files: FdTable::with_stdio(console::open()),
+            handles: HandleTable::new(),
+            io_bitmap: None,
         })
         .as_ptr();
         PROCESSES[index as usize]
     };
-    Ok((unsafe { ProcessPtr::from_ptr(process) }, id))
+    let process = unsafe { ProcessPtr::from_ptr(process) };
+    // every process holds itself as `HANDLE_SELF`.
+    let handle = unsafe { process.get_mut() }
+        .handles
+        .alloc(Entry::new(Object::Process(process), Rights::ALL))
+        .expect("handle table of a new process is full");
+    debug_assert_eq!(handle, ::syscall::handle::HANDLE_SELF);
+    Ok((process, id))
+}
+
+/// maps the page at `adr` if the current process has a file or shared
+/// mapping there which wasn't touched yet. returns false for every other
+/// fault.
+pub unsafe fn populate_fault(adr: VirtAdr) -> bool {
+    if !arch::thread::has_thread() {
+        return false;
+    }
+    let proc = thread::cur_thread().get().get_proc();
+    proc.get_locked().vmm.populate(adr)
 }
@@ -1,10 +1,12 @@
 pub mod sched;
+pub mod stack;
+mod wait;
 
 use crate::arch;
 use crate::arch::interrupt::StackFrame;
 use crate::arch::thread::ArchThread;
-use crate::mm::vmm::{Flags, MapTy, PAGE_SIZE, VMM};
-use crate::mm::{heap, pmm};
+use crate::ipc::CallRef;
+use crate::mm::heap;
 use crate::util::adr::VirtAdr;
 use crate::util::locked::{LockGuard, LockPrimitive};
 use alloc::boxed::Box;
@@ -13,21 +15,10 @@ use core::cell::UnsafeCell;
 use core::fmt::{self, Debug, Display};
 use core::ptr::{null_mut, NonNull};
 
-use super::ProcessPtr;
-
-pub unsafe fn create_userspace_thread_stack(vmm: &mut VMM, pages: usize) -> VirtAdr {
-    let total_bytes = pages * PAGE_SIZE;
-    let alloc = pmm::alloc_pages(pages);
-    let virt_adr = VirtAdr::new((((1 << 47) - PAGE_SIZE * 2) - PAGE_SIZE * 512) as u64);
-    vmm.map(
-        Some(virt_adr),
-        pages,
-        Flags::RW | Flags::USER,
-        MapTy::Phys { adr: alloc.phys() },
-    )
-    .unwrap()
-    .add(total_bytes)
-}
+use super::{ProcessPtr, Result};
+
+pub use stack::{Stack, DEFAULT_STACK_PAGES};
+pub use wait::WaitQueue;
 
 #[derive(Debug, Clone, Copy)]
 pub struct ThreadId(usize);
@@ -75,9 +66,11 @@ pub struct Thread {
     id: ThreadId,
     proc: ProcessPtr,
     stackframe: Box<StackFrame>,
-    stack: *mut u8,
+    stack: Option<Stack>,
     status: ThreadStatus,
     schedule_status: ThreadScheduleStatus,
+    /// the IPC call the thread sleeps on until it's finished.
+    pub ipc_call: Option<CallRef>,
 }
 
 impl Thread {
@@ -85,6 +78,7 @@ impl Thread {
         id: ThreadId,
         proc: ProcessPtr,
         stackframe: Box<StackFrame>,
+        stack: Option<Stack>,
     ) -> heap::Result<ThreadPtr> {
         let ptr = heap::alloc_layout(Layout::new::<Thread>()).as_ptr() as *mut Thread;
         ptr.write(Thread {
@@ -92,10 +86,11 @@ impl Thread {
             lock: LockPrimitive::new(),
             arch: ArchThread::new(),
             proc,
-            stack: null_mut(),
+            stack,
             stackframe,
             status: ThreadStatus::Waiting,
             schedule_status: ThreadScheduleStatus::Running,
+            ipc_call: None,
         });
         ArchThread::init(&mut *ptr);
         Ok(ThreadPtr::from_ptr(ptr))
@@ -109,7 +104,9 @@ impl Thread {
     #[inline]
     pub fn make_thread_current(thread: &mut Thread) {
         unsafe {
-            thread.proc.get().vmm.install();
+            let proc = thread.proc.get();
+            proc.vmm.install();
+            arch::io_bitmap::load(proc.io_bitmap.as_ref());
             arch::thread::set_thread(thread)
         }
     }
@@ -144,6 +141,11 @@ impl Thread {
         &self.stackframe
     }
 
+    #[inline]
+    pub fn get_stack(&self) -> Option<&Stack> {
+        self.stack.as_ref()
+    }
+
     #[inline]
     pub fn set_proc(&mut self, proc: ProcessPtr) {
         self.proc = proc;
@@ -183,8 +185,43 @@ pub unsafe fn new(
     id: ThreadId,
     proc: ProcessPtr,
     stackframe: Box<StackFrame>,
+    stack: Option<Stack>,
 ) -> heap::Result<ThreadPtr> {
-    Thread::new(id, proc, stackframe)
+    Thread::new(id, proc, stackframe, stack)
+}
+
+/// creates a kernel thread in `proc` starting at `entry` on a new stack of `stack_pages` pages.
+pub unsafe fn new_kernel(
+    id: ThreadId,
+    proc: ProcessPtr,
+    entry: u64,
+    stack_pages: usize,
+) -> Result<ThreadPtr> {
+    let vmm = &mut proc.get_mut().vmm;
+    let stack = Stack::alloc_kernel(vmm, stack_pages)?;
+    let stackframe = Box::new(StackFrame::new_kernel(
+        entry,
+        stack.top().adr(),
+        vmm.get_page_map(),
+    ));
+    Ok(Thread::new(id, proc, stackframe, Some(stack))?)
+}
+
+/// creates a userspace thread in `proc` starting at `entry` on a new stack of `stack_pages` pages.
+pub unsafe fn new_userspace(
+    id: ThreadId,
+    proc: ProcessPtr,
+    entry: u64,
+    stack_pages: usize,
+) -> Result<ThreadPtr> {
+    let vmm = &mut proc.get_mut().vmm;
+    let stack = Stack::alloc_userspace(vmm, stack_pages)?;
+    let stackframe = Box::new(StackFrame::new_userspace(
+        entry,
+        stack.top().adr(),
+        vmm.get_page_map(),
+    ));
+    Ok(Thread::new(id, proc, stackframe, Some(stack))?)
 }
 
 pub fn free(_thread: NonNull<Thread>) {}
@@ -198,3 +235,23 @@ pub unsafe fn cur_thread() -> ThreadPtr {
 pub unsafe fn make_thread_current(thread: &mut Thread) {
     Thread::make_thread_current(thread)
 }
+
+/// returns the id of the thread whose stack guard page contains `adr`.
+/// only the current thread and the other threads of its process are searched.
+pub unsafe fn find_guard_page_owner(adr: VirtAdr) -> Option<ThreadId> {
+    if !arch::thread::has_thread() {
+        return None;
+    }
+    let is_owner = |thread: &Thread| thread.stack.map_or(false, |s| s.is_guard_page(adr));
+    let cur = Thread::cur_thread().get();
+    if is_owner(cur) {
+        return Some(cur.id);
+    }
+    cur.proc
+        .get()
+        .threads
+        .iter()
+        .map(|thread| thread.get())
+        .find(|&thread| is_owner(thread))
+        .map(|thread| thread.id)
+}
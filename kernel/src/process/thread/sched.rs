@@ -2,12 +2,17 @@ use crate::arch::interrupt::StackFrame;
 use crate::process::thread::{self, Thread, ThreadPtr, ThreadStatus};
 use crate::util::locked::Locked;
 use buf::ring::RingBuf;
+use core::sync::atomic::{AtomicU64, Ordering};
 use thread::ThreadScheduleStatus;
 
 const MAX_SCHEDULED_THREADS: usize = 256;
 
 struct Scheduler {
     run_queue: RingBuf<ThreadPtr, MAX_SCHEDULED_THREADS>,
+    /// threads in `run_queue`.
+    queued: usize,
+    /// runs whenever every other thread sleeps, it's never queued.
+    idle: Option<ThreadPtr>,
 }
 
 unsafe impl Send for Scheduler {}
@@ -15,26 +20,77 @@ unsafe impl Send for Scheduler {}
 impl Scheduler {
     /// return true if thread was sucessfully scheduled.
     fn push(&mut self, thread: ThreadPtr) -> bool {
-        self.run_queue.push(thread).is_ok()
+        let pushed = self.run_queue.push(thread).is_ok();
+        if pushed {
+            self.queued += 1;
+        }
+        pushed
     }
 
     fn advance(&mut self) -> Option<ThreadPtr> {
-        self.run_queue.pop().ok()
+        let thread = self.run_queue.pop()?;
+        self.queued -= 1;
+        Some(thread)
     }
 }
 
-// TODO: Replace with a circular buffer.
 static SCHEDULER: Locked<Scheduler> = Locked::new(Scheduler {
     run_queue: RingBuf::new(),
+    queued: 0,
+    idle: None,
 });
 
+/// timer interrupts since the scheduler started. the timer isn't calibrated,
+/// so this only orders events and doesn't measure time.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// threads waiting in the run queue.
+pub fn run_queue_len() -> usize {
+    SCHEDULER.lock().queued
+}
+
 /// return `true` if the thread was successfully scheduled.
 pub fn schedule(thread: ThreadPtr) -> bool {
     trace!("scheduling thread: {}", unsafe { thread.get().get_id() });
     SCHEDULER.lock().push(thread)
 }
 
+/// sets the thread which runs when no other one can. it has to sleep, its
+/// stack frame is saved the first time it's interrupted.
+pub fn set_idle(thread: ThreadPtr) {
+    SCHEDULER.lock().idle = Some(thread);
+}
+
+/// schedules `thread` again if it sleeps, threads which are awake are left
+/// alone.
+pub fn wake(thread: ThreadPtr) {
+    {
+        let mut lock = thread.get_locked();
+        if let ThreadScheduleStatus::Running = lock.get_schedule_status() {
+            return;
+        }
+        lock.set_schedule_status(ThreadScheduleStatus::Running);
+    }
+    if !schedule(thread) {
+        error!("scheduler: run queue full, thread {} is lost", unsafe {
+            thread.get().get_id()
+        });
+    }
+}
+
+/// called on every timer interrupt, switches to the next thread.
 pub unsafe fn step(stackframe: *mut StackFrame) {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+    switch(stackframe);
+}
+
+/// saves `stackframe` for the current thread, which is queued again unless
+/// it sleeps, and replaces it with the one of the next thread.
+pub unsafe fn switch(stackframe: *mut StackFrame) {
     let mut scheduler = SCHEDULER.lock();
     let cur_thread_ptr = thread::cur_thread();
     {
@@ -49,7 +105,7 @@ pub unsafe fn step(stackframe: *mut StackFrame) {
         };
         lock.set_status(sched_status);
     }
-    match scheduler.advance() {
+    match scheduler.advance().or(scheduler.idle) {
         Some(thread_ptr) => {
             let mut lock = thread_ptr.get_locked();
             stackframe.write(lock.get_stackframe().clone());
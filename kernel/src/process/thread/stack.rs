@@ -0,0 +1,65 @@
+use crate::mm::pmm;
+use crate::mm::vmm::{self, Flags, MapTy, PAGE_SIZE, VMM};
+use crate::util::adr::VirtAdr;
+
+/// Stack size in pages used when a thread doesn't ask for anything else.
+pub const DEFAULT_STACK_PAGES: usize = 256;
+
+/// Unmapped pages left directly below every stack.
+pub const GUARD_PAGES: usize = 1;
+
+/// A thread stack allocated from a process' VMM.
+///
+/// The stack grows down from `top` towards `base`, and the `GUARD_PAGES` pages
+/// below `base` are reserved in the VMM but never mapped, so an overflow faults
+/// instead of silently running into whatever was allocated next to it.
+#[derive(Debug, Clone, Copy)]
+pub struct Stack {
+    base: VirtAdr,
+    pages: usize,
+}
+
+impl Stack {
+    pub fn alloc_kernel(vmm: &mut VMM, pages: usize) -> vmm::error::Result<Self> {
+        Self::alloc(vmm, pages, Flags::RW)
+    }
+
+    pub fn alloc_userspace(vmm: &mut VMM, pages: usize) -> vmm::error::Result<Self> {
+        Self::alloc(vmm, pages, Flags::RW | Flags::USER)
+    }
+
+    fn alloc(vmm: &mut VMM, pages: usize, flags: Flags) -> vmm::error::Result<Self> {
+        debug_assert!(pages > 0, "a stack needs at least one page");
+        let base = vmm.map_guarded(
+            pages,
+            GUARD_PAGES,
+            flags,
+            MapTy::Phys {
+                adr: pmm::alloc_pages(pages).phys(),
+            },
+        )?;
+        Ok(Self { base, pages })
+    }
+
+    /// initial stack pointer of the stack.
+    #[inline]
+    pub fn top(&self) -> VirtAdr {
+        self.base.add(self.pages * PAGE_SIZE)
+    }
+
+    /// lowest mapped address of the stack.
+    #[inline]
+    pub fn base(&self) -> VirtAdr {
+        self.base
+    }
+
+    #[inline]
+    pub fn pages(&self) -> usize {
+        self.pages
+    }
+
+    pub fn is_guard_page(&self, adr: VirtAdr) -> bool {
+        let guard = self.base.sub(GUARD_PAGES * PAGE_SIZE);
+        adr.adr() >= guard.adr() && adr.adr() < self.base.adr()
+    }
+}
@@ -0,0 +1,45 @@
+use super::{sched, ThreadPtr, ThreadScheduleStatus};
+use crate::util::locked::Locked;
+use alloc::collections::VecDeque;
+
+/// Threads sleeping until something they wait for happens, like a pipe
+/// being written to.
+///
+/// A thread waits from a syscall, which runs again from the start once the
+/// thread is woken. It checks again whether it can go on then, so wakeups
+/// don't have to be exact, only none may be missed.
+pub struct WaitQueue {
+    threads: Locked<VecDeque<ThreadPtr>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            threads: Locked::new(VecDeque::new()),
+        }
+    }
+
+    /// puts the current thread to sleep until the queue is woken. it keeps
+    /// running until it leaves the kernel.
+    pub unsafe fn wait(&self) {
+        let thread = super::cur_thread();
+        thread
+            .get_locked()
+            .set_schedule_status(ThreadScheduleStatus::Sleep);
+        self.threads.lock().push_back(thread);
+    }
+
+    /// wakes every thread waiting on the queue.
+    pub fn wake_all(&self) {
+        let threads = core::mem::take(&mut *self.threads.lock());
+        for thread in threads {
+            sched::wake(thread);
+        }
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -19,3 +19,12 @@ def_symbol!(section_rw_end: __section_rw_end);
 
 def_symbol!(section_text_start: __section_text_start);
 def_symbol!(section_text_end: __section_text_end);
+
+def_symbol!(trampoline_text_start: __trampoline_text_start);
+def_symbol!(trampoline_text_end: __trampoline_text_end);
+
+def_symbol!(trampoline_data_start: __trampoline_data_start);
+def_symbol!(trampoline_data_end: __trampoline_data_end);
+
+def_symbol!(ex_table_start: __ex_table_start);
+def_symbol!(ex_table_end: __ex_table_end);
@@ -0,0 +1,212 @@
+//! file syscalls, working on the current process' descriptor table.
+
+use super::{sleep, Args};
+use crate::fs::{self, Access, FileRef, FileType, Poll, SeekFrom};
+use crate::mm::uaccess::{UserPtr, UserSlice};
+use crate::process::fd::FdTable;
+use crate::process::thread;
+use ::syscall as sc;
+use core::mem::size_of;
+use core::str;
+use sc::fs::{Dirent, Fd, Stat};
+use sc::{Errno, Result};
+
+/// longest path accepted by `open`, including every component.
+const MAX_PATH_LEN: usize = 4096;
+
+/// reads and writes are cut to this many bytes, so a single call can't make
+/// the kernel allocate arbitrarily large buffers.
+const MAX_IO_LEN: usize = 64 * 1024;
+
+/// runs `f` on the current process' descriptor table. `f` must not touch
+/// userspace memory, which needs the process lock itself.
+pub(super) unsafe fn with_files<T>(f: impl FnOnce(&mut FdTable) -> Result<T>) -> Result<T> {
+    let proc = thread::cur_thread().get().get_proc();
+    let mut proc = proc.get_locked();
+    f(&mut proc.files)
+}
+
+pub(super) unsafe fn file(fd: u64) -> Result<FileRef> {
+    with_files(|files| files.get(fd as Fd))
+}
+
+fn file_type(ty: FileType) -> u8 {
+    match ty {
+        FileType::Regular => sc::fs::FT_REGULAR,
+        FileType::Directory => sc::fs::FT_DIRECTORY,
+        FileType::Symlink => sc::fs::FT_SYMLINK,
+        FileType::CharDevice => sc::fs::FT_CHAR_DEVICE,
+        FileType::BlockDevice => sc::fs::FT_BLOCK_DEVICE,
+        FileType::Pipe => sc::fs::FT_PIPE,
+    }
+}
+
+pub unsafe fn open(args: &Args) -> Result<u64> {
+    let len = args[1] as usize;
+    if len > MAX_PATH_LEN {
+        return Err(Errno::ENAMETOOLONG);
+    }
+    let path = UserSlice::new(args[0], len).read_to_vec()?;
+    let path = str::from_utf8(&path).map_err(|_| Errno::EINVAL)?;
+    let flags = args[2] as u32;
+    if flags & !sc::fs::O_ACCMODE != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let access = match flags & sc::fs::O_ACCMODE {
+        sc::fs::O_RDONLY => Access::READ,
+        sc::fs::O_WRONLY => Access::WRITE,
+        sc::fs::O_RDWR => Access::READ | Access::WRITE,
+        _ => return Err(Errno::EINVAL),
+    };
+    let file = fs::open(path, access)?;
+    with_files(|files| files.alloc(file)).map(|fd| fd as u64)
+}
+
+pub unsafe fn close(args: &Args) -> Result<u64> {
+    let file = with_files(|files| files.close(args[0] as Fd))?;
+    fs::close(file)?;
+    Ok(0)
+}
+
+pub unsafe fn read(args: &Args) -> Result<u64> {
+    let file = file(args[0])?;
+    let len = (args[2] as usize).min(MAX_IO_LEN);
+    let user_buf = UserSlice::new(args[1], len);
+    user_buf.check_writable()?;
+    let mut buf = vec![0; len];
+    let read = match fs::read(&file, &mut buf) {
+        Err(e @ fs::Error::WouldBlock(_)) => match file.wait_queue(Poll::READ) {
+            Some(queue) => return Ok(sleep(queue)),
+            None => return Err(e.into()),
+        },
+        read => read?,
+    };
+    user_buf.write(&buf[..read])?;
+    Ok(read as u64)
+}
+
+pub unsafe fn write(args: &Args) -> Result<u64> {
+    let file = file(args[0])?;
+    let len = (args[2] as usize).min(MAX_IO_LEN);
+    let buf = UserSlice::new(args[1], len).read_to_vec()?;
+    let written = match fs::write(&file, &buf) {
+        Err(e @ fs::Error::WouldBlock(_)) => match file.wait_queue(Poll::WRITE) {
+            Some(queue) => return Ok(sleep(queue)),
+            None => return Err(e.into()),
+        },
+        written => written?,
+    };
+    Ok(written as u64)
+}
+
+/// creates a pipe and stores the descriptors of its read and write end at
+/// `args[0]`.
+pub unsafe fn pipe(args: &Args) -> Result<u64> {
+    let (reader, writer) = fs::pipe::pipe()?;
+    let fds = with_files(|files| {
+        let reader = files.alloc(reader)?;
+        match files.alloc(writer) {
+            Ok(writer) => Ok([reader, writer]),
+            Err(e) => {
+                files.close(reader)?;
+                Err(e)
+            }
+        }
+    })?;
+    if let Err(e) = UserPtr::new(args[0]).write(&fds) {
+        with_files(|files| {
+            for fd in fds {
+                files.close(fd)?;
+            }
+            Ok(())
+        })?;
+        return Err(e);
+    }
+    Ok(0)
+}
+
+pub unsafe fn lseek(args: &Args) -> Result<u64> {
+    let file = file(args[0])?;
+    let offset = args[1] as i64;
+    let pos = match args[2] as u32 {
+        sc::fs::SEEK_SET => {
+            let offset = usize::try_from(offset).map_err(|_| Errno::EINVAL)?;
+            SeekFrom::Start(offset)
+        }
+        sc::fs::SEEK_CUR => SeekFrom::Current(offset as isize),
+        sc::fs::SEEK_END => SeekFrom::End(offset as isize),
+        _ => return Err(Errno::EINVAL),
+    };
+    Ok(file.seek(pos)? as u64)
+}
+
+pub unsafe fn fstat(args: &Args) -> Result<u64> {
+    let stat = file(args[0])?.stat()?;
+    UserPtr::new(args[1]).write(&Stat {
+        ino: stat.ino,
+        size: stat.size as u64,
+        mode: stat.mode,
+        ty: file_type(stat.ty),
+        _pad: [0; 5],
+    })?;
+    Ok(0)
+}
+
+pub unsafe fn dup(args: &Args) -> Result<u64> {
+    with_files(|files| files.dup(args[0] as Fd)).map(|fd| fd as u64)
+}
+
+pub unsafe fn dup2(args: &Args) -> Result<u64> {
+    with_files(|files| files.dup2(args[0] as Fd, args[1] as Fd)).map(|fd| fd as u64)
+}
+
+/// the offset of a directory is the index of the next entry to return.
+pub unsafe fn getdents(args: &Args) -> Result<u64> {
+    let file = file(args[0])?;
+    let entries = file.readdir()?;
+    let start = file.offset();
+    let mut buf = vec![0u8; (args[2] as usize).min(MAX_IO_LEN)];
+    let mut used = 0;
+    let mut count = 0;
+    for entry in entries.iter().skip(start) {
+        let name = entry.name.as_bytes();
+        let name_len = u8::try_from(name.len()).map_err(|_| Errno::ENAMETOOLONG)?;
+        let rec_len = Dirent::rec_len(name.len());
+        if used + rec_len > buf.len() {
+            break;
+        }
+        let dirent = Dirent {
+            ino: entry.ino,
+            rec_len: rec_len as u16,
+            name_len,
+            ty: file_type(entry.ty),
+            _pad: 0,
+        };
+        let header = &mut buf[used..used + size_of::<Dirent>()];
+        header.as_mut_ptr().cast::<Dirent>().write_unaligned(dirent);
+        buf[used + size_of::<Dirent>()..][..name.len()].copy_from_slice(name);
+        used += rec_len;
+        count += 1;
+    }
+    if count == 0 && start < entries.len() {
+        // not even the next entry fits.
+        return Err(Errno::EINVAL);
+    }
+    UserSlice::new(args[1], used).write(&buf[..used])?;
+    file.seek(SeekFrom::Start(start + count))?;
+    Ok(used as u64)
+}
+
+/// the buffer is copied in, handed to the device and copied back out.
+pub unsafe fn ioctl(args: &Args) -> Result<u64> {
+    let file = file(args[0])?;
+    let len = args[3] as usize;
+    if len > MAX_IO_LEN {
+        return Err(Errno::EINVAL);
+    }
+    let user_buf = UserSlice::new(args[2], len);
+    let mut buf = user_buf.read_to_vec()?;
+    let ret = fs::ioctl(&file, args[1], &mut buf)?;
+    user_buf.write(&buf)?;
+    Ok(ret)
+}
@@ -0,0 +1,40 @@
+//! handle syscalls, working on the current process' handle table.
+
+use super::Args;
+use crate::mm::uaccess::UserPtr;
+use crate::process::handle::{HandleTable, Rights};
+use crate::process::{thread, ProcessPtr};
+use ::syscall as sc;
+use sc::handle::{Handle, HandleInfo};
+use sc::{Errno, Result};
+
+/// runs `f` on the current process' handle table. `f` must not touch
+/// userspace memory, which needs the process lock itself.
+pub(super) unsafe fn with_handles<T>(f: impl FnOnce(&mut HandleTable) -> Result<T>) -> Result<T> {
+    let proc = thread::cur_thread().get().get_proc();
+    let mut proc = proc.get_locked();
+    f(&mut proc.handles)
+}
+
+/// the process `handle` refers to, which needs `rights`.
+pub(super) unsafe fn process(handle: u64, rights: Rights) -> Result<ProcessPtr> {
+    with_handles(|handles| handles.process(handle as Handle, rights))
+}
+
+pub unsafe fn handle_close(args: &Args) -> Result<u64> {
+    let entry = with_handles(|handles| handles.close(args[0] as Handle))?;
+    drop(entry);
+    Ok(0)
+}
+
+pub unsafe fn handle_duplicate(args: &Args) -> Result<u64> {
+    let rights = u32::try_from(args[1]).map_err(|_| Errno::EINVAL)?;
+    let rights = Rights::from_raw(rights)?;
+    with_handles(|handles| handles.duplicate(args[0] as Handle, rights)).map(|handle| handle as u64)
+}
+
+pub unsafe fn handle_info(args: &Args) -> Result<u64> {
+    let info = with_handles(|handles| handles.entry(args[0] as Handle).map(|entry| entry.info()))?;
+    UserPtr::<HandleInfo>::new(args[1]).write(&info)?;
+    Ok(0)
+}
@@ -0,0 +1,31 @@
+//! port IO syscalls, letting drivers use the ports of their devices.
+
+use super::handle::{self, with_handles};
+use super::Args;
+use crate::arch::io_bitmap::{self, IoBitmap};
+use crate::process::handle::Rights;
+use crate::process::thread;
+use ::syscall::handle::Handle;
+use ::syscall::{Errno, Result};
+
+/// allows `args[3]` ports from `args[2]` in the process `args[0]` refers to,
+/// which have to lie in the ports `args[1]` refers to.
+pub unsafe fn ioport_grant(args: &Args) -> Result<u64> {
+    let process = handle::process(args[0], Rights::MANAGE)?;
+    let ports = with_handles(|handles| handles.io_ports(args[1] as Handle, Rights::MANAGE))?;
+    let first = u16::try_from(args[2]).map_err(|_| Errno::EINVAL)?;
+    let count = u16::try_from(args[3]).map_err(|_| Errno::EINVAL)?;
+    let end = first.checked_add(count).ok_or(Errno::EINVAL)?;
+    if count == 0 || first < ports.start || end > ports.end {
+        return Err(Errno::EINVAL);
+    }
+    let mut proc = process.get_locked();
+    proc.io_bitmap
+        .get_or_insert_with(IoBitmap::new)
+        .allow(first..end);
+    // the TSS has to see the grant before the caller returns to userspace.
+    if thread::cur_thread().get().get_proc().as_ptr() == process.as_ptr() {
+        io_bitmap::load(proc.io_bitmap.as_ref());
+    }
+    Ok(0)
+}
@@ -0,0 +1,242 @@
+//! IPC syscalls, sending and receiving over the current process' endpoint
+//! handles.
+
+use super::handle::with_handles;
+use super::{sleep, Args};
+use crate::ipc::{Endpoint, Message, MovedHandle, Payload, Server, MAX_GRANT_PAGES};
+use crate::mm::pmm::PAGE_SIZE;
+use crate::mm::uaccess::{UserPtr, UserSlice};
+use crate::mm::vmm::Flags;
+use crate::process::handle::{Entry, Object, Rights};
+use crate::process::thread;
+use crate::util::adr::VirtAdr;
+use ::syscall as sc;
+use alloc::string::String;
+use core::str;
+use sc::handle::{Handle, HANDLE_NONE};
+use sc::ipc::{
+    MAX_COPY_LEN, PAYLOAD_COPY, PAYLOAD_GRANT, PAYLOAD_GRANT_RW, PAYLOAD_NONE, TOKEN_NOTIFY,
+};
+use sc::{Errno, Result};
+
+/// longest endpoint name.
+const MAX_NAME_LEN: usize = 255;
+
+unsafe fn read_name(adr: u64, len: u64) -> Result<String> {
+    let len = len as usize;
+    if len > MAX_NAME_LEN {
+        return Err(Errno::ENAMETOOLONG);
+    }
+    let name = UserSlice::new(adr, len).read_to_vec()?;
+    match str::from_utf8(&name) {
+        Ok(name) if !name.is_empty() => Ok(name.into()),
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+/// takes the payload `msg` describes from the current process, along with
+/// its handle, which comes back if the message isn't delivered.
+unsafe fn take_message(msg: &sc::ipc::Message) -> Result<Message> {
+    let len = msg.payload_len as usize;
+    let payload = match msg.payload_kind {
+        PAYLOAD_NONE => Payload::None,
+        PAYLOAD_COPY if len > MAX_COPY_LEN => return Err(Errno::EMSGSIZE),
+        PAYLOAD_COPY => Payload::Copy(UserSlice::new(msg.payload, len).read_to_vec()?),
+        PAYLOAD_GRANT | PAYLOAD_GRANT_RW => {
+            grant(msg.payload, len, msg.payload_kind == PAYLOAD_GRANT_RW)?
+        }
+        _ => return Err(Errno::EINVAL),
+    };
+    let handle = match msg.handle {
+        HANDLE_NONE => None,
+        handle => {
+            let entry = with_handles(|handles| handles.take(handle))?;
+            let proc = thread::cur_thread().get().get_proc();
+            Some(MovedHandle::new(entry, proc, handle))
+        }
+    };
+    Ok(Message {
+        words: msg.words,
+        payload,
+        handle,
+    })
+}
+
+/// the pages holding `len` bytes at `adr`, pinned until the receiver is done
+/// with them. they have to be writable to be granted writable.
+unsafe fn grant(adr: u64, len: usize, writable: bool) -> Result<Payload> {
+    let offset = adr as usize % PAGE_SIZE;
+    if len == 0 {
+        return Err(Errno::EINVAL);
+    }
+    let count = offset
+        .checked_add(len)
+        .map(|end| end.div_ceil(PAGE_SIZE))
+        .filter(|&count| count <= MAX_GRANT_PAGES)
+        .ok_or(Errno::EMSGSIZE)?;
+    let first = adr - offset as u64;
+    let proc = thread::cur_thread().get().get_proc();
+    let mut proc = proc.get_locked();
+    let pages = (0..count)
+        .map(|i| {
+            let page = VirtAdr::new(first + (i * PAGE_SIZE) as u64);
+            proc.vmm.pin_user_page(page, writable).ok_or(Errno::EFAULT)
+        })
+        .collect::<Result<_>>()?;
+    Ok(Payload::Grant {
+        pages,
+        offset,
+        len,
+        writable,
+    })
+}
+
+/// hands `message` to the current process: a copied payload goes to `buf`,
+/// granted pages are mapped, a handle is added to its table, and `msg` is
+/// told where they ended up. `msg` is checked first, so nothing is mapped for
+/// a message which can't be stored. the handle goes back to the sender on
+/// failure.
+unsafe fn deliver(message: Message, msg: UserPtr<sc::ipc::Message>, buf: UserSlice) -> Result<()> {
+    msg.check_writable()?;
+    let (payload_kind, payload, payload_len) = match message.payload {
+        Payload::None => (PAYLOAD_NONE, 0, 0),
+        Payload::Copy(data) => {
+            buf.write(&data)?;
+            (PAYLOAD_COPY, buf.adr().adr(), data.len())
+        }
+        Payload::Grant {
+            pages,
+            offset,
+            len,
+            writable,
+        } => {
+            let mut flags = Flags::USER;
+            if writable {
+                flags |= Flags::RW;
+            }
+            let proc = thread::cur_thread().get().get_proc();
+            let virt = proc
+                .get_locked()
+                .vmm
+                .map_pages(pages, flags)
+                .map_err(|_| Errno::ENOMEM)?;
+            let kind = if writable {
+                PAYLOAD_GRANT_RW
+            } else {
+                PAYLOAD_GRANT
+            };
+            (kind, virt.adr() + offset as u64, len)
+        }
+    };
+    let handle = match &message.handle {
+        Some(moved) => with_handles(|handles| handles.alloc(moved.entry().clone()))?,
+        None => HANDLE_NONE,
+    };
+    let written = msg.write(&sc::ipc::Message {
+        words: message.words,
+        payload_kind,
+        _pad: 0,
+        payload,
+        payload_len: payload_len as u64,
+        handle,
+    });
+    if let Some(moved) = message.handle {
+        match written {
+            Ok(()) => moved.delivered(),
+            // `moved` puts the sender's entry back once dropped.
+            Err(_) => drop(with_handles(|handles| handles.close(handle))),
+        }
+    }
+    written
+}
+
+pub unsafe fn endpoint_create(args: &Args) -> Result<u64> {
+    let name = read_name(args[0], args[1])?;
+    let server = Object::Server(Server::new(Endpoint::create(&name)?));
+    let rights = Rights::SEND | Rights::RECEIVE | Rights::DUPLICATE | Rights::TRANSFER;
+    with_handles(|handles| handles.alloc(Entry::new(server, rights))).map(|handle| handle as u64)
+}
+
+/// opens the endpoint named `args[0]` to send to it. like files, names need
+/// no handle, see `crate::process::handle`. the handle only gets
+/// `Rights::SEND`, and the server decides what to do with what it receives.
+pub unsafe fn endpoint_open(args: &Args) -> Result<u64> {
+    let name = read_name(args[0], args[1])?;
+    let endpoint = Object::Endpoint(Endpoint::open(&name)?);
+    let rights = Rights::SEND | Rights::DUPLICATE | Rights::TRANSFER;
+    with_handles(|handles| handles.alloc(Entry::new(endpoint, rights))).map(|handle| handle as u64)
+}
+
+/// sends the message at `args[1]` and waits until it was received, or
+/// replied to if `reply_buf` takes the copied payload of the reply.
+unsafe fn send(args: &Args, reply_buf: Option<UserSlice>) -> Result<u64> {
+    let thread = thread::cur_thread();
+    let msg = UserPtr::<sc::ipc::Message>::new(args[1]);
+    let pending = thread.get_locked().ipc_call.take();
+    let call = match pending {
+        Some(call) => call,
+        None => {
+            let endpoint = with_handles(|handles| handles.endpoint(args[0] as Handle))?;
+            let message = take_message(&msg.read()?)?;
+            endpoint.send(message, reply_buf.as_ref().map(UserSlice::len))?
+        }
+    };
+    match call.finish() {
+        None => {
+            let ret = sleep(call.wait_queue());
+            thread.get_locked().ipc_call = Some(call);
+            Ok(ret)
+        }
+        Some(Err(errno)) => Err(errno),
+        Some(Ok(None)) => Ok(0),
+        Some(Ok(Some(reply))) => {
+            let buf = reply_buf.unwrap_or_else(|| UserSlice::new(0, 0));
+            deliver(reply, msg, buf).map(|_| 0)
+        }
+    }
+}
+
+pub unsafe fn ipc_send(args: &Args) -> Result<u64> {
+    send(args, None)
+}
+
+pub unsafe fn ipc_call(args: &Args) -> Result<u64> {
+    send(args, Some(UserSlice::new(args[2], args[3] as usize)))
+}
+
+/// waits for a message and returns the token to reply to it with.
+pub unsafe fn ipc_receive(args: &Args) -> Result<u64> {
+    let server = with_handles(|handles| handles.server(args[0] as Handle))?;
+    let buf = UserSlice::new(args[2], args[3] as usize);
+    let Some((token, message)) = server.endpoint().receive(buf.len()) else {
+        return Ok(sleep(server.endpoint().wait_queue()));
+    };
+    let bits = message.words[0];
+    if let Err(errno) = deliver(message, UserPtr::new(args[1]), buf) {
+        match token {
+            0 => {}
+            // the notification stays pending rather than getting lost.
+            TOKEN_NOTIFY => server.endpoint().notify(bits)?,
+            token => server.endpoint().reply(token, Err(errno))?,
+        }
+        return Err(errno);
+    }
+    Ok(token)
+}
+
+/// replies with the message at `args[2]`, or fails the call with the error
+/// in `args[3]` if it isn't 0.
+pub unsafe fn ipc_reply(args: &Args) -> Result<u64> {
+    let server = with_handles(|handles| handles.server(args[0] as Handle))?;
+    let reply = match args[3] {
+        0 => Ok(take_message(
+            &UserPtr::<sc::ipc::Message>::new(args[2]).read()?,
+        )?),
+        errno => {
+            let errno = u16::try_from(errno).ok().and_then(Errno::from_raw);
+            Err(errno.ok_or(Errno::EINVAL)?)
+        }
+    };
+    server.endpoint().reply(args[1], reply)?;
+    Ok(0)
+}
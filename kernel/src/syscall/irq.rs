@@ -0,0 +1,26 @@
+//! interrupt syscalls, letting drivers take the interrupts of their devices.
+
+use super::handle::with_handles;
+use super::Args;
+use crate::irq;
+use crate::process::handle::Rights;
+use ::syscall::handle::Handle;
+use ::syscall::Result;
+
+unsafe fn line(handle: u64) -> Result<u8> {
+    with_handles(|handles| handles.irq(handle as Handle, Rights::MANAGE))
+}
+
+/// binds the line of `args[0]` to the endpoint `args[1]` receives on, which
+/// gets the notification bits in `args[2]`.
+pub unsafe fn irq_bind(args: &Args) -> Result<u64> {
+    let line = line(args[0])?;
+    let server = with_handles(|handles| handles.server(args[1] as Handle))?;
+    irq::bind(line, server.endpoint().clone(), args[2])?;
+    Ok(0)
+}
+
+pub unsafe fn irq_ack(args: &Args) -> Result<u64> {
+    irq::ack(line(args[0])?)?;
+    Ok(0)
+}
@@ -0,0 +1,120 @@
+//! memory syscalls, mapping files, shared memory and memory objects into
+//! processes.
+
+use super::fs::{file, with_files};
+use super::handle::{process, with_handles};
+use super::Args;
+use crate::fs::{Access, OpenFile};
+use crate::mm::pmm::PAGE_SIZE;
+use crate::mm::shared::{SharedMemory, SharedNode};
+use crate::mm::uaccess::UserSlice;
+use crate::mm::vmm::{Flags, MapTy};
+use crate::process::handle::{Entry, Object, Rights};
+use ::syscall as sc;
+use alloc::sync::Arc;
+use core::str;
+use sc::handle::Handle;
+use sc::mm::{PROT_EXEC, PROT_MASK, PROT_WRITE};
+use sc::{Errno, Result};
+
+/// the largest mapping, 1GiB.
+const MAX_MAP_LEN: usize = 1 << 30;
+
+/// longest name of a shared memory object.
+const MAX_SHM_NAME_LEN: usize = 255;
+
+/// the flags mapping memory with the `PROT_*` flags in `prot` takes.
+fn flags(prot: u32) -> Flags {
+    let mut flags = Flags::USER;
+    if prot & PROT_WRITE != 0 {
+        flags |= Flags::RW;
+    }
+    if prot & PROT_EXEC != 0 {
+        flags |= Flags::EXECUTABLE;
+    }
+    flags
+}
+
+/// maps a file from the page cache, or a shared memory object, into the
+/// process `args[0]` refers to. nothing is mapped yet, the pages are filled
+/// in as they are touched.
+pub unsafe fn mmap(args: &Args) -> Result<u64> {
+    let proc = process(args[0], Rights::MAP)?;
+    let file = file(args[1])?;
+    let offset = args[2] as usize;
+    let len = args[3] as usize;
+    let prot = args[4] as u32;
+    if offset % PAGE_SIZE != 0 || len == 0 || len > MAX_MAP_LEN || prot & !PROT_MASK != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let writable = prot & PROT_WRITE != 0;
+    if !file.access().has(Access::READ) || (writable && !file.access().has(Access::WRITE)) {
+        return Err(Errno::EACCES);
+    }
+    let ty = match file.vnode().shared_memory() {
+        Some(object) if offset != 0 || len > object.size() => return Err(Errno::EINVAL),
+        Some(object) => MapTy::Shared { object },
+        None => {
+            let inode = file.cached_inode()?;
+            if writable && inode.read_only() {
+                return Err(Errno::EROFS);
+            }
+            MapTy::File { inode, offset }
+        }
+    };
+    let mapped = proc
+        .get_locked()
+        .vmm
+        .map(None, len.div_ceil(PAGE_SIZE), flags(prot), ty);
+    mapped.map(|adr| adr.adr()).map_err(|_| Errno::ENOMEM)
+}
+
+/// opens the shared memory object with the given name for reading and
+/// writing, creating it with `args[2]` bytes if there is none. like a file in
+/// a tmpfs, any process can open it by name, see `crate::process::handle`.
+/// memory only some processes may reach comes from `memory_create`.
+pub unsafe fn shm_open(args: &Args) -> Result<u64> {
+    let len = args[1] as usize;
+    if len > MAX_SHM_NAME_LEN {
+        return Err(Errno::ENAMETOOLONG);
+    }
+    let name = UserSlice::new(args[0], len).read_to_vec()?;
+    let name = str::from_utf8(&name).map_err(|_| Errno::EINVAL)?;
+    if name.is_empty() {
+        return Err(Errno::EINVAL);
+    }
+    let object = SharedMemory::open(name, args[2] as usize)?;
+    let file = OpenFile::new(
+        Arc::new(SharedNode::new(object)),
+        Access::READ | Access::WRITE,
+    );
+    with_files(|files| files.alloc(file)).map(|fd| fd as u64)
+}
+
+/// creates a memory object of `args[0]` bytes, which is only reachable
+/// through the returned handle.
+pub unsafe fn memory_create(args: &Args) -> Result<u64> {
+    let object = Object::Memory(SharedMemory::new(args[0] as usize)?);
+    let rights = Rights::READ | Rights::WRITE | Rights::MAP | Rights::DUPLICATE | Rights::TRANSFER;
+    with_handles(|handles| handles.alloc(Entry::new(object, rights))).map(|handle| handle as u64)
+}
+
+/// maps all of the memory object `args[1]` into the process `args[0]`.
+pub unsafe fn memory_map(args: &Args) -> Result<u64> {
+    let prot = args[2] as u32;
+    if prot & !PROT_MASK != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let mut rights = Rights::MAP | Rights::READ;
+    if prot & PROT_WRITE != 0 {
+        rights |= Rights::WRITE;
+    }
+    let proc = process(args[0], Rights::MAP)?;
+    let object = with_handles(|handles| handles.memory(args[1] as Handle, rights))?;
+    let pages = object.size() / PAGE_SIZE;
+    let mapped = proc
+        .get_locked()
+        .vmm
+        .map(None, pages, flags(prot), MapTy::Shared { object });
+    mapped.map(|adr| adr.adr()).map_err(|_| Errno::ENOMEM)
+}
@@ -0,0 +1,124 @@
+mod fs;
+mod handle;
+mod io;
+mod ipc;
+mod irq;
+mod mm;
+
+use crate::arch;
+use crate::mm::pmm;
+use crate::mm::uaccess::UserSlice;
+use crate::mm::vmm::Flags;
+use crate::mm::vmm::MapTy;
+use crate::process::handle::Rights;
+use crate::process::thread::WaitQueue;
+use ::syscall as sc;
+use core::str;
+use sc::{Errno, Result};
+
+/// Arguments of a syscall in the order they were passed in registers.
+pub type Args = [u64; 6];
+
+type Handler = unsafe fn(&Args) -> Result<u64>;
+
+const MAX_SYSCALLS: usize = 64;
+
+/// longest message `kprint` logs.
+const MAX_KPRINT_LEN: usize = 4096;
+
+static SYSCALL_TABLE: [Option<Handler>; MAX_SYSCALLS] = const {
+    let mut tbl: [Option<Handler>; MAX_SYSCALLS] = [None; MAX_SYSCALLS];
+    tbl[sc::SYSCALL_KPRINT as usize] = Some(kprint);
+    tbl[sc::SYSCALL_MALLOC as usize] = Some(malloc);
+    tbl[sc::SYSCALL_FREE as usize] = Some(free);
+    tbl[sc::SYSCALL_OPEN as usize] = Some(fs::open);
+    tbl[sc::SYSCALL_CLOSE as usize] = Some(fs::close);
+    tbl[sc::SYSCALL_READ as usize] = Some(fs::read);
+    tbl[sc::SYSCALL_WRITE as usize] = Some(fs::write);
+    tbl[sc::SYSCALL_LSEEK as usize] = Some(fs::lseek);
+    tbl[sc::SYSCALL_FSTAT as usize] = Some(fs::fstat);
+    tbl[sc::SYSCALL_DUP as usize] = Some(fs::dup);
+    tbl[sc::SYSCALL_DUP2 as usize] = Some(fs::dup2);
+    tbl[sc::SYSCALL_GETDENTS as usize] = Some(fs::getdents);
+    tbl[sc::SYSCALL_IOCTL as usize] = Some(fs::ioctl);
+    tbl[sc::SYSCALL_MMAP as usize] = Some(mm::mmap);
+    tbl[sc::SYSCALL_SHM_OPEN as usize] = Some(mm::shm_open);
+    tbl[sc::SYSCALL_PIPE as usize] = Some(fs::pipe);
+    tbl[sc::SYSCALL_HANDLE_CLOSE as usize] = Some(handle::handle_close);
+    tbl[sc::SYSCALL_ENDPOINT_CREATE as usize] = Some(ipc::endpoint_create);
+    tbl[sc::SYSCALL_ENDPOINT_OPEN as usize] = Some(ipc::endpoint_open);
+    tbl[sc::SYSCALL_IPC_SEND as usize] = Some(ipc::ipc_send);
+    tbl[sc::SYSCALL_IPC_CALL as usize] = Some(ipc::ipc_call);
+    tbl[sc::SYSCALL_IPC_RECEIVE as usize] = Some(ipc::ipc_receive);
+    tbl[sc::SYSCALL_IPC_REPLY as usize] = Some(ipc::ipc_reply);
+    tbl[sc::SYSCALL_HANDLE_DUPLICATE as usize] = Some(handle::handle_duplicate);
+    tbl[sc::SYSCALL_HANDLE_INFO as usize] = Some(handle::handle_info);
+    tbl[sc::SYSCALL_MEMORY_CREATE as usize] = Some(mm::memory_create);
+    tbl[sc::SYSCALL_MEMORY_MAP as usize] = Some(mm::memory_map);
+    tbl[sc::SYSCALL_IRQ_BIND as usize] = Some(irq::irq_bind);
+    tbl[sc::SYSCALL_IRQ_ACK as usize] = Some(irq::irq_ack);
+    tbl[sc::SYSCALL_IOPORT_GRANT as usize] = Some(io::ioport_grant);
+    tbl
+};
+
+/// dispatches `syscall` and returns the result encoded for `rax`.
+pub unsafe fn syscall(syscall: u64, args: Args) -> u64 {
+    let handler = SYSCALL_TABLE.get(syscall as usize).copied().flatten();
+    let result = match handler {
+        Some(handler) => handler(&args),
+        None => {
+            warn!("invalid syscall '{syscall}'");
+            Err(Errno::ENOSYS)
+        }
+    };
+    sc::encode(result)
+}
+
+/// makes the current syscall start over once it returns, after the other
+/// threads had their turn. returns what the syscall returns, which is
+/// discarded.
+fn restart() -> u64 {
+    arch::interrupt::restart_syscall();
+    0
+}
+
+/// makes the current syscall start over once `queue` is woken, which is how
+/// it blocks until what it waits for is ready.
+unsafe fn sleep(queue: &WaitQueue) -> u64 {
+    queue.wait();
+    restart()
+}
+
+unsafe fn kprint(args: &Args) -> Result<u64> {
+    let len = args[1] as usize;
+    if len > MAX_KPRINT_LEN {
+        return Err(Errno::EINVAL);
+    }
+    let msg = UserSlice::new(args[0], len).read_to_vec()?;
+    let str = str::from_utf8(&msg).map_err(|_| Errno::EINVAL)?;
+    info!("kprint: {str}");
+    Ok(0)
+}
+
+unsafe fn free(_args: &Args) -> Result<u64> {
+    Ok(0)
+}
+
+/// maps `args[1]` bytes into the process `args[0]` refers to.
+unsafe fn malloc(args: &Args) -> Result<u64> {
+    let proc = handle::process(args[0], Rights::MAP)?;
+    let len = args[1] as usize;
+    let pages = pages!(len);
+    proc.get_locked()
+        .vmm
+        .map(
+            None,
+            pages,
+            Flags::RW | Flags::USER,
+            MapTy::Phys {
+                adr: pmm::alloc_pages(pages).phys(),
+            },
+        )
+        .map(|adr| adr.adr())
+        .map_err(|_| Errno::ENOMEM)
+}
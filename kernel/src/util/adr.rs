@@ -122,3 +122,9 @@ impl core::fmt::Display for PhysAdr {
         f.write_fmt(format_args!("0x{:016x}", self.0))
     }
 }
+
+impl core::fmt::Debug for VirtAdr {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Display::fmt(self, f)
+    }
+}
@@ -1,5 +1,6 @@
 pub mod adr;
 pub mod locked;
+pub mod random;
 
 /// Delays roughly `amount` of cycles.
 #[inline(always)]
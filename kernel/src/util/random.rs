@@ -0,0 +1,95 @@
+//! Kernel pseudo random numbers.
+//!
+//! A splitmix64 generator seeded from the architecture's entropy source the
+//! first time it is used. It is fast and spreads values well, which is what
+//! address randomization needs, but it is not cryptographically secure, and
+//! its output would give away the kernel's layout. Randomness for userspace
+//! comes from `fill_bytes` instead.
+
+use crate::arch;
+use crate::util::locked::Locked;
+
+struct State {
+    seeded: bool,
+    val: u64,
+}
+
+static STATE: Locked<State> = Locked::new(State {
+    seeded: false,
+    val: 0,
+});
+
+pub fn next_u64() -> u64 {
+    let mut state = STATE.lock();
+    if !state.seeded {
+        state.val = arch::random::entropy();
+        state.seeded = true;
+    }
+    state.val = state.val.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = state.val;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// random value in `0..bound`, `bound` must not be zero.
+pub fn next_below(bound: u64) -> u64 {
+    debug_assert!(bound != 0);
+    next_u64() % bound
+}
+
+/// "expand 32-byte k", the first row of every ChaCha block.
+const CHACHA_CONSTANTS: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+
+fn quarter_round(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(16);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(12);
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(8);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(7);
+}
+
+/// block `counter` of the ChaCha20 stream for `key`, with a zero nonce.
+fn chacha20_block(key: &[u32; 8], counter: u64) -> [u8; 64] {
+    let mut state = [0; 16];
+    state[..4].copy_from_slice(&CHACHA_CONSTANTS);
+    state[4..12].copy_from_slice(key);
+    state[12] = counter as u32;
+    state[13] = (counter >> 32) as u32;
+    let mut x = state;
+    for _ in 0..10 {
+        quarter_round(&mut x, 0, 4, 8, 12);
+        quarter_round(&mut x, 1, 5, 9, 13);
+        quarter_round(&mut x, 2, 6, 10, 14);
+        quarter_round(&mut x, 3, 7, 11, 15);
+        quarter_round(&mut x, 0, 5, 10, 15);
+        quarter_round(&mut x, 1, 6, 11, 12);
+        quarter_round(&mut x, 2, 7, 8, 13);
+        quarter_round(&mut x, 3, 4, 9, 14);
+    }
+    let mut block = [0; 64];
+    for (i, word) in x.iter().enumerate() {
+        block[i * 4..][..4].copy_from_slice(&word.wrapping_add(state[i]).to_le_bytes());
+    }
+    block
+}
+
+/// fills `buf` with random bytes for userspace. they come from a ChaCha20
+/// stream keyed with fresh entropy on every call, so they tell nothing about
+/// `next_u64` or earlier calls. they are only as good as the entropy source,
+/// which on CPUs without rdrand or rdseed is just the time stamp counter.
+pub fn fill_bytes(buf: &mut [u8]) {
+    let mut key = [0; 8];
+    for pair in key.chunks_exact_mut(2) {
+        let seed = arch::random::entropy();
+        pair[0] = seed as u32;
+        pair[1] = (seed >> 32) as u32;
+    }
+    for (counter, chunk) in buf.chunks_mut(64).enumerate() {
+        let block = chacha20_block(&key, counter as u64);
+        chunk.copy_from_slice(&block[..chunk.len()]);
+    }
+}
@@ -1,88 +1,154 @@
-use core::mem::ManuallyDrop;
+use core::mem::MaybeUninit;
+use core::{ptr, slice};
 
+/// A fixed size queue of up to `SIZE` elements, which has to be a power of
+/// two.
 pub struct RingBuf<T, const SIZE: usize> {
-    buf: [ManuallyDrop<T>; SIZE],
+    buf: [MaybeUninit<T>; SIZE],
+    /// the positions only ever grow, wrapping around `usize`, and are taken
+    /// modulo `SIZE` to index `buf`. their difference is the length, so all
+    /// of `buf` can be used.
     reader: usize,
     writer: usize,
 }
 
 impl<T, const SIZE: usize> RingBuf<T, SIZE> {
-    const _ASSERT: () = if SIZE.is_power_of_two() {
-        ()
-    } else {
+    const ASSERT: () = if !SIZE.is_power_of_two() {
         panic!("SIZE must be a power of two")
     };
 
     pub const fn new() -> Self {
+        #[allow(clippy::let_unit_value)]
+        let _ = Self::ASSERT;
         Self {
-            buf: unsafe { core::mem::MaybeUninit::zeroed().assume_init() },
+            buf: unsafe { MaybeUninit::<[MaybeUninit<T>; SIZE]>::uninit().assume_init() },
             reader: 0,
             writer: 0,
         }
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.reader == self.writer
+    pub const fn capacity(&self) -> usize {
+        SIZE
     }
 
-    pub fn is_full(&self) -> bool {
-        self.next_writer() + 1 == self.reader
+    pub fn len(&self) -> usize {
+        self.writer.wrapping_sub(self.reader)
     }
 
-    pub fn len(&self) -> usize {
-        unimplemented!()
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
+    pub fn is_full(&self) -> bool {
+        self.len() == SIZE
+    }
+
+    /// returns `val` back if the buffer is full.
     pub fn push(&mut self, val: T) -> Result<(), T> {
-        if let Some(write) = self.step_writer(val) {
-            Err(write)
-        } else {
-            Ok(())
+        if self.is_full() {
+            return Err(val);
         }
+        self.buf[self.writer % SIZE].write(val);
+        self.writer = self.writer.wrapping_add(1);
+        Ok(())
     }
 
-    pub fn pop(&mut self) -> Result<T, ()> {
-        if let Some(read) = self.step_reader() {
-            Ok(read)
-        } else {
-            Err(())
+    pub fn pop(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
         }
+        let val = unsafe { self.buf[self.reader % SIZE].assume_init_read() };
+        self.reader = self.reader.wrapping_add(1);
+        Some(val)
     }
 
-    /// returns Some if value was not successfully pushed.
-    fn step_writer(&mut self, write: T) -> Option<T> {
-        if self.is_full() {
-            Some(write)
-        } else {
-            self.buf[self.writer] = ManuallyDrop::new(write);
-            self.writer = self.next_writer();
-            None
+    /// the queued elements in order, in two parts as they may wrap around the
+    /// end of the buffer. the second part is empty if they don't.
+    pub fn as_slices(&self) -> (&[T], &[T]) {
+        let start = self.reader % SIZE;
+        let first = self.len().min(SIZE - start);
+        unsafe {
+            (
+                slice::from_raw_parts(self.buf.as_ptr().add(start).cast(), first),
+                slice::from_raw_parts(self.buf.as_ptr().cast(), self.len() - first),
+            )
         }
     }
+}
 
-    fn step_reader(&mut self) -> Option<T> {
-        if self.is_empty() {
-            None
-        } else {
-            let index = self.reader;
-            self.reader = self.next_reader();
-            Some(unsafe { ManuallyDrop::take(&mut self.buf[index]) })
+impl<T: Copy, const SIZE: usize> RingBuf<T, SIZE> {
+    /// pushes as much of `vals` as fits and returns how many that were.
+    pub fn push_slice(&mut self, vals: &[T]) -> usize {
+        let count = vals.len().min(SIZE - self.len());
+        let start = self.writer % SIZE;
+        let first = count.min(SIZE - start);
+        unsafe {
+            let buf = self.buf.as_mut_ptr().cast::<T>();
+            ptr::copy_nonoverlapping(vals.as_ptr(), buf.add(start), first);
+            ptr::copy_nonoverlapping(vals.as_ptr().add(first), buf, count - first);
         }
+        self.writer = self.writer.wrapping_add(count);
+        count
     }
 
-    fn next_writer(&self) -> usize {
-        (self.writer + 1) % SIZE
+    /// pops as many elements as fit into `out` and returns how many that
+    /// were.
+    pub fn pop_into(&mut self, out: &mut [T]) -> usize {
+        let (first, second) = self.as_slices();
+        let from_first = first.len().min(out.len());
+        let from_second = second.len().min(out.len() - from_first);
+        out[..from_first].copy_from_slice(&first[..from_first]);
+        out[from_first..from_first + from_second].copy_from_slice(&second[..from_second]);
+        let count = from_first + from_second;
+        self.reader = self.reader.wrapping_add(count);
+        count
     }
+}
 
-    fn next_reader(&self) -> usize {
-        (self.reader + 1) % SIZE
+impl<T, const SIZE: usize> Default for RingBuf<T, SIZE> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl<T, const SIZE: usize> Drop for RingBuf<T, SIZE> {
     fn drop(&mut self) {
-        while let Some(read) = self.step_reader() {
-            drop(read)
+        while let Some(val) = self.pop() {
+            drop(val)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::RingBuf;
+
+    #[test]
+    fn fills_to_capacity() {
+        let mut ring = RingBuf::<u32, 4>::new();
+        for i in 0..4 {
+            assert_eq!(ring.len(), i as usize);
+            ring.push(i).unwrap();
+        }
+        assert!(ring.is_full());
+        assert_eq!(ring.push(4), Err(4));
+        for i in 0..4 {
+            assert_eq!(ring.pop(), Some(i));
+        }
+        assert!(ring.is_empty());
+        assert_eq!(ring.pop(), None);
+    }
+
+    #[test]
+    fn slices_wrap_around() {
+        let mut ring = RingBuf::<u8, 8>::new();
+        assert_eq!(ring.push_slice(&[0; 6]), 6);
+        assert_eq!(ring.pop_into(&mut [0; 5]), 5);
+        assert_eq!(ring.push_slice(&[1, 2, 3, 4, 5, 6, 7, 8, 9]), 7);
+        assert_eq!(ring.as_slices(), (&[0, 1, 2][..], &[3, 4, 5, 6, 7][..]));
+        let mut out = [0; 16];
+        assert_eq!(ring.pop_into(&mut out), 8);
+        assert_eq!(out[..8], [0, 1, 2, 3, 4, 5, 6, 7]);
+        assert!(ring.is_empty());
+    }
+}
@@ -359,6 +359,16 @@ impl Elf64 {
         self.header().e_phnum as usize
     }
 
+    /// offset of the program header table from the start of the file.
+    pub fn program_header_offset(&self) -> u64 {
+        self.header().e_phoff
+    }
+
+    /// size of one entry of the program header table.
+    pub fn program_header_size(&self) -> usize {
+        self.header().e_phentsize as usize
+    }
+
     pub fn program_headers(&self) -> &[Elf64Phdr] {
         unsafe { slice::from_raw_parts(self.program_headers_ptr(), self.program_header_count()) }
     }
@@ -0,0 +1,160 @@
+use crate::*;
+use alloc::collections::BTreeSet;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+struct PendingEntry {
+    path: String,
+    ty: EntryType,
+    mode: u16,
+    data: Vec<u8>,
+    align_shift: u8,
+}
+
+/// Collects entries and lays them out as an image.
+#[derive(Default)]
+pub struct Builder {
+    entries: Vec<PendingEntry>,
+    paths: BTreeSet<String>,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// adds a regular file with its data aligned to `1 << align_shift` bytes.
+    pub fn add_file(
+        &mut self,
+        path: &str,
+        mode: u16,
+        data: Vec<u8>,
+        align_shift: u8,
+    ) -> Result<()> {
+        self.add(path, EntryType::Regular, mode, data, align_shift)
+    }
+
+    pub fn add_dir(&mut self, path: &str, mode: u16) -> Result<()> {
+        self.add(path, EntryType::Directory, mode, Vec::new(), 0)
+    }
+
+    pub fn add_symlink(&mut self, path: &str, target: &str) -> Result<()> {
+        self.add(
+            path,
+            EntryType::Symlink,
+            0o777,
+            target.as_bytes().to_vec(),
+            0,
+        )
+    }
+
+    fn add(
+        &mut self,
+        path: &str,
+        ty: EntryType,
+        mode: u16,
+        data: Vec<u8>,
+        align_shift: u8,
+    ) -> Result<()> {
+        let index = self.entries.len();
+        if !is_valid_path(path) || path.len() > u32::MAX as usize {
+            return Err(Error::InvalidPath(index));
+        }
+        if !self.paths.insert(path.to_string()) {
+            return Err(Error::DuplicatePath(index));
+        }
+        self.entries.push(PendingEntry {
+            path: path.to_string(),
+            ty,
+            mode: mode & 0o7777,
+            data,
+            align_shift: align_shift.max(ALIGN_SHIFT_SMALL),
+        });
+        Ok(())
+    }
+
+    /// lays out the image, in the order the entries were added.
+    pub fn build(&self) -> Vec<u8> {
+        let table_end = HEADER_SIZE + self.entries.len() * ENTRY_SIZE;
+        let mut image = vec![0; table_end];
+        for entry in &self.entries {
+            image.extend_from_slice(entry.path.as_bytes());
+        }
+        let mut path_offset = table_end;
+        for (index, entry) in self.entries.iter().enumerate() {
+            let align = 1 << entry.align_shift;
+            image.resize((image.len() + align - 1) & !(align - 1), 0);
+            let data_offset = image.len();
+            image.extend_from_slice(&entry.data);
+
+            let record = &mut image[HEADER_SIZE + index * ENTRY_SIZE..][..ENTRY_SIZE];
+            let path = entry.path.as_bytes();
+            record[E_PATH_OFFSET..][..4].copy_from_slice(&(path_offset as u32).to_le_bytes());
+            record[E_PATH_LEN..][..4].copy_from_slice(&(path.len() as u32).to_le_bytes());
+            record[E_DATA_OFFSET..][..8].copy_from_slice(&(data_offset as u64).to_le_bytes());
+            record[E_DATA_LEN..][..8].copy_from_slice(&(entry.data.len() as u64).to_le_bytes());
+            record[E_MODE..][..2].copy_from_slice(&entry.mode.to_le_bytes());
+            record[E_TYPE] = entry.ty as u8;
+            record[E_ALIGN_SHIFT] = entry.align_shift;
+            let crc = entry_crc(path, &entry.data);
+            record[E_CRC..][..4].copy_from_slice(&crc.to_le_bytes());
+            path_offset += path.len();
+        }
+
+        let length = image.len() as u64;
+        let header = &mut image[..HEADER_SIZE];
+        header[H_MAGIC..][..MAGIC.len()].copy_from_slice(&MAGIC);
+        header[H_VERSION..][..2].copy_from_slice(&VERSION.to_le_bytes());
+        header[H_ENTRY_COUNT..][..4].copy_from_slice(&(self.entries.len() as u32).to_le_bytes());
+        header[H_LENGTH..][..8].copy_from_slice(&length.to_le_bytes());
+        let mut crc = Crc32::new();
+        crc.update(&image[..H_TABLE_CRC]);
+        crc.update(&image[HEADER_SIZE..table_end]);
+        image[H_TABLE_CRC..][..4].copy_from_slice(&crc.finish().to_le_bytes());
+        image
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_duplicate_and_invalid_paths() {
+        let mut builder = Builder::new();
+        builder.add_dir("bin", 0o755).unwrap();
+        assert_eq!(
+            builder.add_file("bin", 0o644, Vec::new(), 0),
+            Err(Error::DuplicatePath(1))
+        );
+        assert_eq!(builder.add_dir("", 0o755), Err(Error::InvalidPath(1)));
+        assert_eq!(builder.add_dir("/bin", 0o755), Err(Error::InvalidPath(1)));
+        assert_eq!(builder.add_dir("a//b", 0o755), Err(Error::InvalidPath(1)));
+        assert_eq!(builder.add_dir("a/../b", 0o755), Err(Error::InvalidPath(1)));
+        assert_eq!(builder.len(), 1);
+    }
+
+    #[test]
+    fn aligns_data() {
+        let mut builder = Builder::new();
+        builder.add_file("a", 0o644, vec![1; 3], 0).unwrap();
+        builder
+            .add_file("b", 0o755, vec![2; 5], ALIGN_SHIFT_PAGE)
+            .unwrap();
+        let image = builder.build();
+        let initrd = Initrd::parse(&image).unwrap();
+        let a = initrd.find("a").unwrap();
+        let b = initrd.find("b").unwrap();
+        assert_eq!(a.data_offset() % (1 << ALIGN_SHIFT_SMALL), 0);
+        assert_eq!(b.data_offset() % (1 << ALIGN_SHIFT_PAGE), 0);
+        assert_eq!(image.len(), b.data_offset() + 5);
+    }
+}
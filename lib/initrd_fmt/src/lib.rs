@@ -0,0 +1,151 @@
+//! The initrd image format, shared by the kernel and `tools/initrd`.
+//!
+//! All integers are little endian. An image is laid out as
+//!
+//! ```text
+//! header | entry table | paths | data
+//! ```
+//!
+//! The header is `HEADER_SIZE` bytes and is followed by `entry_count`
+//! records of `ENTRY_SIZE` bytes. Every record points at its path, a
+//! relative `/` separated path without `.` or `..` components, and at its
+//! data. The data of a symlink is its target, directories have none. Parent
+//! directories that have no entry of their own are implied.
+//!
+//! Data is aligned to `1 << align_shift` bytes relative to the start of the
+//! image, so an image loaded at a page boundary can map page aligned files
+//! in place.
+
+#![no_std]
+#![feature(error_in_core)]
+
+#[macro_use]
+extern crate alloc;
+
+mod build;
+mod crc32;
+mod parse;
+
+use core::fmt::Display;
+
+pub use build::Builder;
+pub use crc32::{crc32, Crc32};
+pub use parse::{Entries, Entry, Initrd};
+
+pub const MAGIC: [u8; 8] = *b"ACORNRD\0";
+pub const VERSION: u16 = 2;
+
+pub const HEADER_SIZE: usize = 32;
+pub const ENTRY_SIZE: usize = 32;
+
+/// alignment for data which is only read.
+pub const ALIGN_SHIFT_SMALL: u8 = 3;
+/// alignment for data which may be mapped, like executables.
+pub const ALIGN_SHIFT_PAGE: u8 = 12;
+
+// header field offsets.
+const H_MAGIC: usize = 0;
+const H_VERSION: usize = 8;
+const H_FLAGS: usize = 10;
+const H_ENTRY_COUNT: usize = 12;
+const H_LENGTH: usize = 16;
+/// covers the header up to this field and the entry table.
+const H_TABLE_CRC: usize = 24;
+
+// entry field offsets.
+const E_PATH_OFFSET: usize = 0;
+const E_PATH_LEN: usize = 4;
+const E_DATA_OFFSET: usize = 8;
+const E_DATA_LEN: usize = 16;
+const E_MODE: usize = 24;
+const E_TYPE: usize = 26;
+const E_ALIGN_SHIFT: usize = 27;
+/// covers the entry's path and data.
+const E_CRC: usize = 28;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum EntryType {
+    Regular = 1,
+    Directory = 2,
+    Symlink = 3,
+}
+
+impl EntryType {
+    pub const fn from_raw(raw: u8) -> Option<Self> {
+        match raw {
+            1 => Some(Self::Regular),
+            2 => Some(Self::Directory),
+            3 => Some(Self::Symlink),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// the image is shorter than its header or the length it claims.
+    Truncated(usize),
+    InvalidMagic,
+    UnsupportedVersion(u16),
+    /// an offset in the header or the entry table points outside the image.
+    OutOfBounds(usize),
+    InvalidPath(usize),
+    DuplicatePath(usize),
+    InvalidType(usize, u8),
+    Misaligned(usize),
+    TableChecksum,
+    EntryChecksum(usize),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::Truncated(len) => write!(f, "image truncated to {len} bytes"),
+            Error::InvalidMagic => write!(f, "invalid magic"),
+            Error::UnsupportedVersion(version) => write!(f, "unsupported version {version}"),
+            Error::OutOfBounds(index) => write!(f, "entry {index} is out of bounds"),
+            Error::InvalidPath(index) => write!(f, "entry {index} has an invalid path"),
+            Error::DuplicatePath(index) => write!(f, "entry {index} has a duplicate path"),
+            Error::InvalidType(index, ty) => write!(f, "entry {index} has invalid type {ty}"),
+            Error::Misaligned(index) => write!(f, "entry {index} has misaligned data"),
+            Error::TableChecksum => write!(f, "header checksum mismatch"),
+            Error::EntryChecksum(index) => write!(f, "entry {index} checksum mismatch"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// whether `path` is a valid entry path.
+pub fn is_valid_path(path: &str) -> bool {
+    !path.is_empty()
+        && path
+            .split('/')
+            .all(|name| !name.is_empty() && name != "." && name != "..")
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut raw = [0; 4];
+    raw.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(raw)
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut raw = [0; 8];
+    raw.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(raw)
+}
+
+fn entry_crc(path: &[u8], data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(path);
+    crc.update(data);
+    crc.finish()
+}
@@ -0,0 +1,399 @@
+use crate::*;
+use alloc::collections::BTreeSet;
+use core::str;
+
+/// largest supported data alignment, as a shift.
+const MAX_ALIGN_SHIFT: u8 = 16;
+
+/// A parsed image. Every offset has been checked by `parse`, so accessing
+/// entries can't go out of bounds.
+#[derive(Clone, Copy)]
+pub struct Initrd<'a> {
+    /// the image up to the length in its header.
+    image: &'a [u8],
+    count: usize,
+}
+
+impl<'a> Initrd<'a> {
+    /// checks the header and the bounds, alignment, path and type of every
+    /// entry. checksums are only checked by `verify`.
+    pub fn parse(image: &'a [u8]) -> Result<Self> {
+        if image.len() < HEADER_SIZE {
+            return Err(Error::Truncated(image.len()));
+        }
+        if image[H_MAGIC..H_MAGIC + MAGIC.len()] != MAGIC {
+            return Err(Error::InvalidMagic);
+        }
+        let version = read_u16(image, H_VERSION);
+        if version != VERSION || read_u16(image, H_FLAGS) != 0 {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let length = read_u64(image, H_LENGTH);
+        if length > image.len() as u64 {
+            return Err(Error::Truncated(image.len()));
+        }
+        let image = &image[..length as usize];
+        let count = read_u32(image, H_ENTRY_COUNT) as usize;
+        let table_end = count
+            .checked_mul(ENTRY_SIZE)
+            .and_then(|len| len.checked_add(HEADER_SIZE));
+        match table_end {
+            Some(end) if end <= image.len() => {}
+            _ => return Err(Error::Truncated(image.len())),
+        }
+
+        let initrd = Self { image, count };
+        let mut paths = BTreeSet::new();
+        for index in 0..count {
+            let entry = initrd.parse_entry(index)?;
+            if !paths.insert(entry.path) {
+                return Err(Error::DuplicatePath(index));
+            }
+        }
+        Ok(initrd)
+    }
+
+    /// checks the header and entry checksums.
+    pub fn verify(&self) -> Result<()> {
+        let mut crc = Crc32::new();
+        crc.update(&self.image[..H_TABLE_CRC]);
+        crc.update(&self.image[HEADER_SIZE..self.table_end()]);
+        if crc.finish() != read_u32(self.image, H_TABLE_CRC) {
+            return Err(Error::TableChecksum);
+        }
+        for entry in self.entries() {
+            entry.verify()?;
+        }
+        Ok(())
+    }
+
+    /// the image without any padding after it.
+    pub fn image(&self) -> &'a [u8] {
+        self.image
+    }
+
+    /// number of entries.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn entry(&self, index: usize) -> Option<Entry<'a>> {
+        if index < self.count {
+            Some(
+                self.parse_entry(index)
+                    .expect("entries are checked by parse"),
+            )
+        } else {
+            None
+        }
+    }
+
+    pub fn entries(&self) -> Entries<'a> {
+        Entries {
+            initrd: *self,
+            index: 0,
+        }
+    }
+
+    pub fn find(&self, path: &str) -> Option<Entry<'a>> {
+        self.entries().find(|entry| entry.path == path)
+    }
+
+    fn table_end(&self) -> usize {
+        HEADER_SIZE + self.count * ENTRY_SIZE
+    }
+
+    fn parse_entry(&self, index: usize) -> Result<Entry<'a>> {
+        let record = &self.image[HEADER_SIZE + index * ENTRY_SIZE..][..ENTRY_SIZE];
+        let range = |offset: u64, len: u64| {
+            let end = offset.checked_add(len)?;
+            (end <= self.image.len() as u64).then_some(offset as usize..end as usize)
+        };
+
+        let path_range = range(
+            read_u32(record, E_PATH_OFFSET) as u64,
+            read_u32(record, E_PATH_LEN) as u64,
+        )
+        .ok_or(Error::OutOfBounds(index))?;
+        let path = str::from_utf8(&self.image[path_range])
+            .ok()
+            .filter(|path| is_valid_path(path))
+            .ok_or(Error::InvalidPath(index))?;
+
+        let data_offset = read_u64(record, E_DATA_OFFSET);
+        let data_range =
+            range(data_offset, read_u64(record, E_DATA_LEN)).ok_or(Error::OutOfBounds(index))?;
+        let align_shift = record[E_ALIGN_SHIFT];
+        if align_shift > MAX_ALIGN_SHIFT || data_offset % (1 << align_shift) != 0 {
+            return Err(Error::Misaligned(index));
+        }
+
+        let ty =
+            EntryType::from_raw(record[E_TYPE]).ok_or(Error::InvalidType(index, record[E_TYPE]))?;
+        if ty == EntryType::Directory && !data_range.is_empty() {
+            return Err(Error::InvalidType(index, record[E_TYPE]));
+        }
+
+        Ok(Entry {
+            index,
+            path,
+            data_offset: data_range.start,
+            data: &self.image[data_range],
+            ty,
+            mode: read_u16(record, E_MODE) & 0o7777,
+            crc: read_u32(record, E_CRC),
+        })
+    }
+}
+
+/// A file, directory or symlink in an image.
+#[derive(Clone, Copy)]
+pub struct Entry<'a> {
+    index: usize,
+    path: &'a str,
+    data_offset: usize,
+    data: &'a [u8],
+    ty: EntryType,
+    mode: u16,
+    crc: u32,
+}
+
+impl<'a> Entry<'a> {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// relative path, without a leading `/`.
+    pub fn path(&self) -> &'a str {
+        self.path
+    }
+
+    /// the last component of the path.
+    pub fn name(&self) -> &'a str {
+        self.path.rsplit('/').next().unwrap_or(self.path)
+    }
+
+    /// the parent directory's path, empty for entries in the root.
+    pub fn parent(&self) -> &'a str {
+        self.path.rsplit_once('/').map_or("", |(parent, _)| parent)
+    }
+
+    pub fn ty(&self) -> EntryType {
+        self.ty
+    }
+
+    /// permission bits, like the lower 12 bits of a unix mode.
+    pub fn mode(&self) -> u16 {
+        self.mode
+    }
+
+    /// contents of a file or the target of a symlink.
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// offset of the data from the start of the image.
+    pub fn data_offset(&self) -> usize {
+        self.data_offset
+    }
+
+    pub fn verify(&self) -> Result<()> {
+        if entry_crc(self.path.as_bytes(), self.data) == self.crc {
+            Ok(())
+        } else {
+            Err(Error::EntryChecksum(self.index))
+        }
+    }
+}
+
+pub struct Entries<'a> {
+    initrd: Initrd<'a>,
+    index: usize,
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Entry<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.initrd.entry(self.index)?;
+        self.index += 1;
+        Some(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn image() -> Vec<u8> {
+        let mut builder = Builder::new();
+        builder.add_dir("bin", 0o755).unwrap();
+        builder
+            .add_file("bin/init", 0o755, b"\x7fELF".to_vec(), ALIGN_SHIFT_PAGE)
+            .unwrap();
+        builder.add_symlink("init", "bin/init").unwrap();
+        builder
+            .add_file("etc/motd", 0o10644, b"hi\n".to_vec(), 0)
+            .unwrap();
+        builder.build()
+    }
+
+    fn set_u32(image: &mut [u8], offset: usize, value: u32) {
+        image[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// rewrites the table checksum after the table was changed.
+    fn fix_table_crc(image: &mut [u8]) {
+        let count = read_u32(image, H_ENTRY_COUNT) as usize;
+        let mut crc = Crc32::new();
+        crc.update(&image[..H_TABLE_CRC]);
+        crc.update(&image[HEADER_SIZE..HEADER_SIZE + count * ENTRY_SIZE]);
+        let crc = crc.finish();
+        set_u32(image, H_TABLE_CRC, crc);
+    }
+
+    #[test]
+    fn round_trip() {
+        let image = image();
+        let initrd = Initrd::parse(&image).unwrap();
+        initrd.verify().unwrap();
+        assert_eq!(initrd.len(), 4);
+
+        let entries: Vec<_> = initrd.entries().map(|entry| entry.path()).collect();
+        assert_eq!(entries, ["bin", "bin/init", "init", "etc/motd"]);
+
+        let bin = initrd.entry(0).unwrap();
+        assert_eq!(bin.ty(), EntryType::Directory);
+        assert_eq!(bin.mode(), 0o755);
+        assert!(bin.data().is_empty());
+
+        let init = initrd.find("bin/init").unwrap();
+        assert_eq!(init.ty(), EntryType::Regular);
+        assert_eq!(init.data(), b"\x7fELF");
+        assert_eq!(init.name(), "init");
+        assert_eq!(init.parent(), "bin");
+
+        let link = initrd.find("init").unwrap();
+        assert_eq!(link.ty(), EntryType::Symlink);
+        assert_eq!(link.data(), b"bin/init");
+        assert_eq!(link.parent(), "");
+
+        let motd = initrd.find("etc/motd").unwrap();
+        assert_eq!(motd.mode(), 0o644);
+        assert_eq!(motd.data(), b"hi\n");
+        assert!(initrd.find("etc").is_none());
+        assert!(initrd.entry(4).is_none());
+    }
+
+    #[test]
+    fn padding_after_the_image_is_ignored() {
+        let mut image = image();
+        let len = image.len();
+        image.resize(len + 4096, 0xff);
+        let initrd = Initrd::parse(&image).unwrap();
+        initrd.verify().unwrap();
+        assert_eq!(initrd.image().len(), len);
+    }
+
+    #[test]
+    fn detects_data_corruption() {
+        let mut image = image();
+        let offset = Initrd::parse(&image)
+            .unwrap()
+            .find("etc/motd")
+            .unwrap()
+            .data_offset();
+        image[offset] ^= 1;
+        let initrd = Initrd::parse(&image).unwrap();
+        assert_eq!(initrd.verify(), Err(Error::EntryChecksum(3)));
+    }
+
+    #[test]
+    fn detects_table_corruption() {
+        let mut image = image();
+        image[HEADER_SIZE + E_MODE] ^= 1;
+        let initrd = Initrd::parse(&image).unwrap();
+        assert_eq!(initrd.verify(), Err(Error::TableChecksum));
+    }
+
+    #[test]
+    fn rejects_truncated_images() {
+        let image = image();
+        for len in [0, HEADER_SIZE - 1, HEADER_SIZE, image.len() - 1] {
+            assert_eq!(
+                Initrd::parse(&image[..len]).err(),
+                Some(Error::Truncated(len))
+            );
+        }
+
+        let mut image = image;
+        set_u32(&mut image, H_ENTRY_COUNT, u32::MAX);
+        assert_eq!(
+            Initrd::parse(&image).err(),
+            Some(Error::Truncated(image.len()))
+        );
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let mut image = image();
+        image[H_MAGIC] = b'X';
+        assert_eq!(Initrd::parse(&image).err(), Some(Error::InvalidMagic));
+
+        let mut image = self::image();
+        image[H_VERSION..H_VERSION + 2].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert_eq!(
+            Initrd::parse(&image).err(),
+            Some(Error::UnsupportedVersion(VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn rejects_out_of_bounds_entries() {
+        let mut image = image();
+        let len = image.len() as u32;
+        set_u32(&mut image, HEADER_SIZE + ENTRY_SIZE + E_PATH_OFFSET, len);
+        fix_table_crc(&mut image);
+        assert_eq!(Initrd::parse(&image).err(), Some(Error::OutOfBounds(1)));
+    }
+
+    #[test]
+    fn rejects_duplicate_paths() {
+        let mut image = image();
+        // point the path of the symlink at the one of the directory.
+        let path = read_u32(&image, HEADER_SIZE + E_PATH_OFFSET);
+        let len = read_u32(&image, HEADER_SIZE + E_PATH_LEN);
+        set_u32(
+            &mut image,
+            HEADER_SIZE + 2 * ENTRY_SIZE + E_PATH_OFFSET,
+            path,
+        );
+        set_u32(&mut image, HEADER_SIZE + 2 * ENTRY_SIZE + E_PATH_LEN, len);
+        fix_table_crc(&mut image);
+        assert_eq!(Initrd::parse(&image).err(), Some(Error::DuplicatePath(2)));
+    }
+
+    #[test]
+    fn rejects_invalid_types_and_alignment() {
+        let mut image = image();
+        image[HEADER_SIZE + E_TYPE] = 0;
+        assert_eq!(Initrd::parse(&image).err(), Some(Error::InvalidType(0, 0)));
+
+        // a directory with data.
+        let mut image = self::image();
+        image[HEADER_SIZE + 3 * ENTRY_SIZE + E_TYPE] = EntryType::Directory as u8;
+        assert_eq!(
+            Initrd::parse(&image).err(),
+            Some(Error::InvalidType(3, EntryType::Directory as u8))
+        );
+
+        let mut image = self::image();
+        image[HEADER_SIZE + ENTRY_SIZE + E_ALIGN_SHIFT] = MAX_ALIGN_SHIFT + 1;
+        assert_eq!(Initrd::parse(&image).err(), Some(Error::Misaligned(1)));
+    }
+}
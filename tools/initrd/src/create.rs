@@ -0,0 +1,111 @@
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path};
+
+use initrd_fmt::{Builder, ALIGN_SHIFT_PAGE, ALIGN_SHIFT_SMALL};
+
+use crate::error::{Error, IoContext, Result};
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+
+/// packs `paths`, descending into directories. entries are named after the
+/// path they were found at, with `strip_prefix` removed.
+pub fn create(paths: &[impl AsRef<Path>], strip_prefix: Option<&Path>) -> Result<Builder> {
+    let mut builder = Builder::new();
+    for path in paths {
+        let path = path.as_ref();
+        let name = entry_path(path, strip_prefix)?;
+        add(&mut builder, path, &name)?;
+    }
+    Ok(builder)
+}
+
+/// the name of the entry for `path`, a relative path with `/` separators.
+fn entry_path(path: &Path, strip_prefix: Option<&Path>) -> Result<String> {
+    let stripped = match strip_prefix {
+        Some(prefix) => path
+            .strip_prefix(prefix)
+            .map_err(|_| Error::InvalidPath(path.into(), "doesn't start with the prefix"))?,
+        None => path,
+    };
+    let mut names = Vec::new();
+    for component in stripped.components() {
+        match component {
+            Component::Normal(name) => names.push(
+                name.to_str()
+                    .ok_or(Error::InvalidPath(path.into(), "not valid unicode"))?,
+            ),
+            Component::ParentDir => {
+                return Err(Error::InvalidPath(path.into(), "contains '..'"));
+            }
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => {}
+        }
+    }
+    Ok(names.join("/"))
+}
+
+fn add(builder: &mut Builder, path: &Path, name: &str) -> Result<()> {
+    let metadata = fs::symlink_metadata(path).at(path)?;
+    let mode = (metadata.permissions().mode() & 0o7777) as u16;
+    let file_type = metadata.file_type();
+    if file_type.is_dir() {
+        return add_dir(builder, path, name, mode);
+    }
+    if name.is_empty() {
+        return Err(Error::InvalidPath(path.into(), "files need a name"));
+    }
+    let added = if file_type.is_symlink() {
+        let target = fs::read_link(path).at(path)?;
+        let target = target.to_str().ok_or(Error::InvalidPath(
+            path.into(),
+            "link target is not valid unicode",
+        ))?;
+        builder.add_symlink(name, target)
+    } else if file_type.is_file() {
+        let data = fs::read(path).at(path)?;
+        // executables are page aligned, so they can be mapped in place.
+        let align_shift = if data.starts_with(ELF_MAGIC) {
+            ALIGN_SHIFT_PAGE
+        } else {
+            ALIGN_SHIFT_SMALL
+        };
+        builder.add_file(name, mode, data, align_shift)
+    } else {
+        return Err(Error::InvalidPath(
+            path.into(),
+            "not a file, directory or symlink",
+        ));
+    };
+    added.map_err(|err| Error::InvalidEntry(name.into(), err))
+}
+
+fn add_dir(builder: &mut Builder, path: &Path, name: &str, mode: u16) -> Result<()> {
+    // a directory which is the prefix itself has no name, only its contents
+    // are added.
+    if !name.is_empty() {
+        builder
+            .add_dir(name, mode)
+            .map_err(|err| Error::InvalidEntry(name.into(), err))?;
+    }
+    let mut children = fs::read_dir(path)
+        .at(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()
+        .at(path)?;
+    // sorted, so the same tree always gives the same image.
+    children.sort();
+    for child in children {
+        let child_name = child
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or(Error::InvalidPath(child.clone(), "not valid unicode"))?;
+        let child_name = if name.is_empty() {
+            child_name.to_string()
+        } else {
+            format!("{name}/{child_name}")
+        };
+        add(builder, &child, &child_name)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,54 @@
+use std::fmt::Display;
+use std::io;
+use std::path::PathBuf;
+
+/// exit code for io errors and bad arguments.
+pub const EXIT_FAILURE: u8 = 1;
+/// exit code for images which are malformed or fail their checksums.
+pub const EXIT_INVALID_IMAGE: u8 = 2;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(PathBuf, io::Error),
+    /// the image at the path is malformed or corrupted.
+    InvalidImage(PathBuf, initrd_fmt::Error),
+    /// a path which can't be put into an image.
+    InvalidPath(PathBuf, &'static str),
+    /// the entry with the path couldn't be added.
+    InvalidEntry(String, initrd_fmt::Error),
+}
+
+impl Error {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::InvalidImage(..) => EXIT_INVALID_IMAGE,
+            _ => EXIT_FAILURE,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(path, err) => write!(f, "'{}': {err}", path.display()),
+            Error::InvalidImage(path, err) => write!(f, "'{}': {err}", path.display()),
+            Error::InvalidPath(path, why) => write!(f, "'{}': {why}", path.display()),
+            Error::InvalidEntry(path, err) => write!(f, "'{path}': {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// attaches `path` to io errors.
+pub trait IoContext<T> {
+    fn at(self, path: impl Into<PathBuf>) -> Result<T>;
+}
+
+impl<T> IoContext<T> for io::Result<T> {
+    fn at(self, path: impl Into<PathBuf>) -> Result<T> {
+        self.map_err(|err| Error::Io(path.into(), err))
+    }
+}
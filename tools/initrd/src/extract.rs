@@ -0,0 +1,44 @@
+use std::fs;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::Path;
+
+use initrd_fmt::{EntryType, Initrd};
+
+use crate::error::{IoContext, Result};
+
+/// writes every entry of `initrd` below `output`.
+pub fn extract(initrd: &Initrd, output: &Path) -> Result<()> {
+    fs::create_dir_all(output).at(output)?;
+    // directories might not be writable, so their modes are set last.
+    let mut dirs = Vec::new();
+    for entry in initrd.entries() {
+        let path = output.join(entry.path());
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).at(parent)?;
+        }
+        match entry.ty() {
+            EntryType::Directory => {
+                fs::create_dir_all(&path).at(&path)?;
+                dirs.push((path, entry.mode()));
+            }
+            EntryType::Regular => {
+                fs::write(&path, entry.data()).at(&path)?;
+                let permissions = fs::Permissions::from_mode(entry.mode() as u32);
+                fs::set_permissions(&path, permissions).at(&path)?;
+            }
+            EntryType::Symlink => {
+                if fs::symlink_metadata(&path).is_ok() {
+                    fs::remove_file(&path).at(&path)?;
+                }
+                let target = String::from_utf8_lossy(entry.data());
+                symlink(target.as_ref(), &path).at(&path)?;
+            }
+        }
+    }
+    // children before their parents.
+    for (path, mode) in dirs.into_iter().rev() {
+        let permissions = fs::Permissions::from_mode(mode as u32);
+        fs::set_permissions(&path, permissions).at(&path)?;
+    }
+    Ok(())
+}
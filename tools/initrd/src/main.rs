@@ -1,108 +1,148 @@
-#![feature(vec_into_raw_parts)]
+mod create;
+mod error;
+mod extract;
+#[cfg(test)]
+mod tests;
 
-use std::collections::HashSet;
-use std::mem::size_of;
-use std::path::PathBuf;
-use std::{fs, slice};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
 use argh::FromArgs;
+use initrd_fmt::{Entry, EntryType, Initrd};
+
+use error::{Error, IoContext, Result};
 
 #[derive(FromArgs, Debug)]
-/// Utility for creating initrd.
+/// Utility for creating and inspecting initrd images.
 struct Args {
+    #[argh(subcommand)]
+    command: Command,
+}
+
+#[derive(FromArgs, Debug)]
+#[argh(subcommand)]
+enum Command {
+    Create(CreateArgs),
+    List(ListArgs),
+    Extract(ExtractArgs),
+    Verify(VerifyArgs),
+}
+
+#[derive(FromArgs, Debug)]
+/// Pack files and directories into an image, descending into directories.
+#[argh(subcommand, name = "create")]
+struct CreateArgs {
     /// output file.
-    #[argh(option)]
+    #[argh(option, short = 'o')]
     output: PathBuf,
-    /// the files to be put into the initrd.
+    /// prefix removed from the paths before they are stored.
+    #[argh(option)]
+    strip_prefix: Option<PathBuf>,
+    /// the files and directories to be put into the initrd.
     #[argh(positional, greedy)]
-    files: Vec<PathBuf>,
+    paths: Vec<PathBuf>,
 }
 
-fn main() {
-    let Args { files, output } = argh::from_env();
+#[derive(FromArgs, Debug)]
+/// List the entries of an image.
+#[argh(subcommand, name = "list")]
+struct ListArgs {
+    /// the image.
+    #[argh(positional)]
+    image: PathBuf,
+}
 
-    #[repr(C, packed)]
-    struct InitrdHeader {
-        entries: u64,
-        /// length in bytes for the whole initrd.
-        length: u64,
-    }
-    const MAX_NAME_LEN: usize = 32;
-    #[repr(C, packed)]
-    struct InitrdEntryHeader {
-        start: u64,
-        /// The entry's size in bytes, excluding the header.
-        length: u64,
-        name: [u8; MAX_NAME_LEN],
-    }
+#[derive(FromArgs, Debug)]
+/// Write the entries of an image to a directory.
+#[argh(subcommand, name = "extract")]
+struct ExtractArgs {
+    /// directory to extract to, the current one by default.
+    #[argh(option, short = 'o', default = "PathBuf::from(\".\")")]
+    output: PathBuf,
+    /// the image.
+    #[argh(positional)]
+    image: PathBuf,
+}
+
+#[derive(FromArgs, Debug)]
+/// Check the structure and checksums of an image.
+#[argh(subcommand, name = "verify")]
+struct VerifyArgs {
+    /// the image.
+    #[argh(positional)]
+    image: PathBuf,
+}
 
-    let mut entries = 0;
-    let mut length = size_of::<InitrdHeader>() as u64;
-    let mut header_length = length;
-
-    let mut initrd_entry_headers = vec![];
-    let mut file_vec: Vec<u8> = vec![];
-
-    let mut names = HashSet::new();
-
-    for file in files {
-        let metadata =
-            fs::metadata(&file).expect(&format!("file '{}' doesn't exist", file.display()));
-        entries += 1;
-        length += metadata.len();
-        header_length += size_of::<InitrdEntryHeader>() as u64;
-        let name = file
-            .file_name()
-            .expect(&format!(
-                "'{}' is not a valid path to a file",
-                file.display()
-            ))
-            .to_str()
-            .expect(&format!(
-                "filename in path '{}' is not valid unicode",
-                file.display()
-            ));
-        let mut name_array = [0; 32];
-        for (i, byte) in name.bytes().enumerate() {
-            assert!(i < name.len(), "filename too long '{name}'");
-            name_array[i] = byte;
+fn main() -> ExitCode {
+    let Args { command } = argh::from_env();
+    let result = match command {
+        Command::Create(args) => create(args),
+        Command::List(args) => list(args),
+        Command::Extract(args) => extract(args),
+        Command::Verify(args) => verify(args),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("initrd: {err}");
+            ExitCode::from(err.exit_code())
         }
-        assert!(
-            !names.contains(&name_array),
-            "file with the same name already exists within the initrd"
-        );
-        names.insert(name_array);
-        file_vec.extend_from_slice(&fs::read(file).expect("failed to read file"));
-        initrd_entry_headers.push(InitrdEntryHeader {
-            start: u64::MAX,
-            length: metadata.len(),
-            name: name_array,
-        })
-    }
-    let initrd_header = InitrdHeader { entries, length };
-    let mut start_pos = header_length;
-    for entry in &mut initrd_entry_headers {
-        entry.start = start_pos;
-        start_pos += entry.length;
     }
-    let mut byte_vec = vec![];
-    unsafe {
-        byte_vec.extend_from_slice(slice::from_raw_parts(
-            &initrd_header as *const _ as *const u8,
-            size_of::<InitrdHeader>(),
-        ));
-        let (ptr, len, _) = initrd_entry_headers.into_raw_parts();
-        if !ptr.is_null() {
-            byte_vec.extend_from_slice(slice::from_raw_parts(
-                ptr as *const u8,
-                len * size_of::<InitrdEntryHeader>(),
-            ));
-        }
-        byte_vec.extend_from_slice(&file_vec);
+}
+
+fn create(args: CreateArgs) -> Result<()> {
+    let builder = create::create(&args.paths, args.strip_prefix.as_deref())?;
+    fs::write(&args.output, builder.build()).at(&args.output)
+}
+
+fn list(args: ListArgs) -> Result<()> {
+    let image = fs::read(&args.image).at(&args.image)?;
+    let initrd = parse(&image, &args.image)?;
+    for entry in initrd.entries() {
+        println!("{}", list_line(&entry));
     }
+    Ok(())
+}
 
-    fs::write(&output, byte_vec).expect(&format!(
-        "failed to write to output file {}",
-        output.display()
-    ))
+fn extract(args: ExtractArgs) -> Result<()> {
+    let image = fs::read(&args.image).at(&args.image)?;
+    let initrd = parse(&image, &args.image)?;
+    extract::extract(&initrd, &args.output)
+}
+
+fn verify(args: VerifyArgs) -> Result<()> {
+    let image = fs::read(&args.image).at(&args.image)?;
+    let initrd = parse(&image, &args.image)?;
+    println!("{}: {} entries, ok", args.image.display(), initrd.len());
+    Ok(())
+}
+
+/// parses `image` and checks its checksums.
+fn parse<'a>(image: &'a [u8], path: &Path) -> Result<Initrd<'a>> {
+    Initrd::parse(image)
+        .and_then(|initrd| initrd.verify().map(|_| initrd))
+        .map_err(|err| Error::InvalidImage(path.into(), err))
+}
+
+/// an `ls -l` like line for `entry`.
+fn list_line(entry: &Entry) -> String {
+    let ty = match entry.ty() {
+        EntryType::Regular => '-',
+        EntryType::Directory => 'd',
+        EntryType::Symlink => 'l',
+    };
+    let mut perms = String::with_capacity(9);
+    for shift in [6, 3, 0] {
+        let bits = entry.mode() >> shift;
+        perms.push(if bits & 4 != 0 { 'r' } else { '-' });
+        perms.push(if bits & 2 != 0 { 'w' } else { '-' });
+        perms.push(if bits & 1 != 0 { 'x' } else { '-' });
+    }
+    let mut line = format!("{ty}{perms} {:>10} {}", entry.data().len(), entry.path());
+    if entry.ty() == EntryType::Symlink {
+        line.push_str(" -> ");
+        line.push_str(&String::from_utf8_lossy(entry.data()));
+    }
+    line
 }
@@ -0,0 +1,197 @@
+use std::fs;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use initrd_fmt::{EntryType, Initrd, ALIGN_SHIFT_PAGE};
+
+use crate::error::Error;
+use crate::{create, extract, list_line};
+
+/// a directory below the system's temporary one, removed when dropped.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new() -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("initrd-test-{}-{n}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+const LONG_NAME: &str =
+    "a-file-name-which-is-much-longer-than-the-thirty-two-bytes-the-old-format-allowed.txt";
+
+/// a small tree with nested directories, a long name, an executable and a
+/// symlink.
+fn sample_tree(root: &Path) {
+    fs::create_dir_all(root.join("bin")).unwrap();
+    fs::create_dir_all(root.join("etc/nested/deeper")).unwrap();
+    fs::write(root.join("bin/init"), b"\x7fELF\x02\x01\x01").unwrap();
+    fs::set_permissions(root.join("bin/init"), fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(root.join("etc/hostname"), b"acorn\n").unwrap();
+    fs::set_permissions(root.join("etc/hostname"), fs::Permissions::from_mode(0o600)).unwrap();
+    fs::write(root.join("etc/nested/deeper").join(LONG_NAME), b"deep").unwrap();
+    symlink("bin", root.join("sbin")).unwrap();
+}
+
+#[test]
+fn create_and_parse_round_trip() {
+    let dir = TempDir::new();
+    let root = dir.path().join("root");
+    sample_tree(&root);
+
+    let image = create::create(&[&root], Some(&root)).unwrap().build();
+    let initrd = Initrd::parse(&image).unwrap();
+    initrd.verify().unwrap();
+
+    let paths: Vec<_> = initrd.entries().map(|entry| entry.path()).collect();
+    assert_eq!(
+        paths,
+        [
+            "bin",
+            "bin/init",
+            "etc",
+            "etc/hostname",
+            "etc/nested",
+            "etc/nested/deeper",
+            &format!("etc/nested/deeper/{LONG_NAME}"),
+            "sbin",
+        ]
+    );
+
+    let init = initrd.find("bin/init").unwrap();
+    assert_eq!(init.ty(), EntryType::Regular);
+    assert_eq!(init.mode(), 0o755);
+    assert_eq!(init.data(), b"\x7fELF\x02\x01\x01");
+    assert_eq!(init.data_offset() % (1 << ALIGN_SHIFT_PAGE), 0);
+
+    assert_eq!(initrd.find("etc/hostname").unwrap().mode(), 0o600);
+    let sbin = initrd.find("sbin").unwrap();
+    assert_eq!(sbin.ty(), EntryType::Symlink);
+    assert_eq!(sbin.data(), b"bin");
+}
+
+#[test]
+fn extract_round_trip() {
+    let dir = TempDir::new();
+    let root = dir.path().join("root");
+    sample_tree(&root);
+    let image = create::create(&[&root], Some(&root)).unwrap().build();
+
+    let out = dir.path().join("out");
+    extract::extract(&Initrd::parse(&image).unwrap(), &out).unwrap();
+    assert_eq!(fs::read(out.join("etc/hostname")).unwrap(), b"acorn\n");
+    assert_eq!(
+        fs::read(out.join("etc/nested/deeper").join(LONG_NAME)).unwrap(),
+        b"deep"
+    );
+    let mode = fs::metadata(out.join("bin/init"))
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o7777, 0o755);
+    assert_eq!(fs::read_link(out.join("sbin")).unwrap(), Path::new("bin"));
+
+    // packing the extracted tree again gives the same image.
+    let again = create::create(&[&out], Some(&out)).unwrap().build();
+    assert_eq!(image, again);
+}
+
+#[test]
+fn paths_without_prefix_keep_their_directories() {
+    let dir = TempDir::new();
+    let root = dir.path().join("root");
+    sample_tree(&root);
+
+    let image = create::create(&[root.join("etc/hostname")], Some(dir.path()))
+        .unwrap()
+        .build();
+    let initrd = Initrd::parse(&image).unwrap();
+    assert_eq!(initrd.len(), 1);
+    assert_eq!(initrd.entry(0).unwrap().path(), "root/etc/hostname");
+}
+
+#[test]
+fn rejects_paths_outside_the_prefix() {
+    let dir = TempDir::new();
+    let root = dir.path().join("root");
+    sample_tree(&root);
+    let err = create::create(&[root.join("etc")], Some(&root.join("bin")))
+        .err()
+        .expect("create should fail");
+    assert!(matches!(err, Error::InvalidPath(..)));
+}
+
+#[test]
+fn rejects_duplicate_entries() {
+    let dir = TempDir::new();
+    let root = dir.path().join("root");
+    sample_tree(&root);
+    let hostname = root.join("etc/hostname");
+    let err = create::create(&[&hostname, &hostname], Some(&root))
+        .err()
+        .expect("create should fail");
+    assert!(matches!(
+        err,
+        Error::InvalidEntry(_, initrd_fmt::Error::DuplicatePath(_))
+    ));
+}
+
+#[test]
+fn verify_detects_corruption() {
+    let dir = TempDir::new();
+    let root = dir.path().join("root");
+    sample_tree(&root);
+    let mut image = create::create(&[&root], Some(&root)).unwrap().build();
+
+    let offset = Initrd::parse(&image)
+        .unwrap()
+        .find("etc/hostname")
+        .unwrap()
+        .data_offset();
+    image[offset] ^= 0xFF;
+    let initrd = Initrd::parse(&image).unwrap();
+    let index = initrd.find("etc/hostname").unwrap().index();
+    assert_eq!(
+        initrd.verify(),
+        Err(initrd_fmt::Error::EntryChecksum(index))
+    );
+
+    image.truncate(image.len() - 1);
+    assert!(matches!(
+        Initrd::parse(&image).err(),
+        Some(initrd_fmt::Error::Truncated(_))
+    ));
+}
+
+#[test]
+fn list_lines() {
+    let dir = TempDir::new();
+    let root = dir.path().join("root");
+    sample_tree(&root);
+    let image = create::create(&[&root], Some(&root)).unwrap().build();
+    let initrd = Initrd::parse(&image).unwrap();
+
+    assert_eq!(
+        list_line(&initrd.find("bin/init").unwrap()),
+        "-rwxr-xr-x          7 bin/init"
+    );
+    assert_eq!(
+        list_line(&initrd.find("sbin").unwrap()),
+        "lrwxrwxrwx          3 sbin -> bin"
+    );
+}
@@ -4,13 +4,87 @@
 #[macro_use]
 extern crate librs;
 
+use librs::collections::VecDeque;
+use librs::handle::{Handle, OwnedHandle, HANDLE_GRANTED};
+use librs::ipc::{Event, Protocol, Server};
+use librs::irq::Irq;
+use librs::port::Ports;
+use librs::Errno;
+
+/// the endpoint clients read scancodes from.
+const ENDPOINT: &str = "ps2";
+/// the notification bit of the keyboard interrupt.
+const KEYBOARD_BIT: u64 = 1 << 0;
+/// scancodes kept for clients, older ones are dropped first.
+const MAX_QUEUED: usize = 256;
+
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+
+/// the output buffer holds a byte for us to read.
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+/// the byte in the output buffer came from the second (mouse) port.
+const STATUS_AUX: u8 = 1 << 5;
+
+/// Hands out the keyboard's raw set 1 scancodes one per call, or fails with
+/// `EAGAIN` if none are queued.
+struct Keyboard;
+
+impl Protocol for Keyboard {
+    type Request = ();
+    type Reply = u64;
+}
+
+/// moves the scancodes waiting in the controller to `queue`. bytes from the
+/// mouse port are left alone.
+fn drain(data: &Ports, status: &Ports, queue: &mut VecDeque<u8>) {
+    loop {
+        let flags = status.in8(STATUS_PORT);
+        if flags & STATUS_OUTPUT_FULL == 0 || flags & STATUS_AUX != 0 {
+            break;
+        }
+        if queue.len() == MAX_QUEUED {
+            queue.pop_front();
+        }
+        queue.push_back(data.in8(DATA_PORT));
+    }
+}
+
+/// grants this process `port`, which the granted `handle` refers to.
+fn grant(handle: Handle, port: u16) -> Ports {
+    let handle = OwnedHandle::from_raw(handle);
+    Ports::grant(&handle, port..port + 1)
+        .unwrap_or_else(|e| panic!("failed to grant port {port:#x}: {e}"))
+}
+
 #[no_mangle]
 extern "C" fn _start() -> ! {
     println!("starting the ps2 driver");
-    let mut string = librs::string::String::new();
-    for i in 0..10 {
-        string.push_str(&format!("[{i}] "));
+    // the kernel grants the keyboard's interrupt line, then the controller's
+    // data and status ports.
+    let irq = Irq::from_handle(OwnedHandle::from_raw(HANDLE_GRANTED));
+    let data = grant(HANDLE_GRANTED + 1, DATA_PORT);
+    let status = grant(HANDLE_GRANTED + 2, STATUS_PORT);
+    let server = Server::<Keyboard>::create(ENDPOINT).expect("failed to create endpoint");
+    irq.bind(&server, KEYBOARD_BIT)
+        .expect("failed to bind the keyboard interrupt");
+    let mut queue = VecDeque::new();
+    let mut buf = [0; 0];
+    loop {
+        let result = match server.receive(&mut buf) {
+            Ok(Event::Notification(bits)) if bits & KEYBOARD_BIT != 0 => {
+                drain(&data, &status, &mut queue);
+                irq.ack()
+            }
+            Ok(Event::Notification(_)) => Ok(()),
+            Ok(Event::Request(request)) => match queue.pop_front() {
+                Some(scancode) => request.reply(&(scancode as u64)),
+                None => request.fail(Errno::EAGAIN),
+            },
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            eprintln!("ps2: {e}");
+        }
     }
-    println!("constructed string: {string}");
-    loop {}
 }
@@ -0,0 +1,121 @@
+//! files opened through the kernel's VFS.
+
+use crate::io::{Read, Write};
+use syscall::fs::{Fd, Stat, O_RDONLY, O_RDWR, O_WRONLY, SEEK_CUR, SEEK_END, SEEK_SET};
+use syscall::handle::HANDLE_SELF;
+use syscall::Result;
+
+pub use syscall::fs::{Dirent, Dirents};
+
+#[derive(Debug, Clone, Copy)]
+pub enum SeekFrom {
+    Start(u64),
+    Current(i64),
+    End(i64),
+}
+
+/// An open file descriptor, closed when dropped.
+#[derive(Debug)]
+pub struct File {
+    fd: Fd,
+}
+
+impl File {
+    /// opens `path` for reading.
+    pub fn open(path: impl AsRef<str>) -> Result<Self> {
+        Self::open_with(path, O_RDONLY)
+    }
+
+    /// opens `path` for reading and writing.
+    pub fn open_rw(path: impl AsRef<str>) -> Result<Self> {
+        Self::open_with(path, O_RDWR)
+    }
+
+    /// opens `path` for writing only.
+    pub fn open_wo(path: impl AsRef<str>) -> Result<Self> {
+        Self::open_with(path, O_WRONLY)
+    }
+
+    /// opens the shared memory object `name`, creating it with `len` bytes
+    /// if there is none. map it to share memory with other processes.
+    pub fn open_shared(name: impl AsRef<str>, len: usize) -> Result<Self> {
+        syscall::shm_open(name, len).map(|fd| Self { fd })
+    }
+
+    /// creates a pipe and returns its read and its write end.
+    pub fn pipe() -> Result<(Self, Self)> {
+        syscall::pipe().map(|(reader, writer)| (Self { fd: reader }, Self { fd: writer }))
+    }
+
+    fn open_with(path: impl AsRef<str>, flags: u32) -> Result<Self> {
+        syscall::open(path, flags).map(|fd| Self { fd })
+    }
+
+    /// takes ownership of `fd`, which is closed when the file is dropped.
+    pub fn from_raw_fd(fd: Fd) -> Self {
+        Self { fd }
+    }
+
+    pub fn as_raw_fd(&self) -> Fd {
+        self.fd
+    }
+
+    /// gives up ownership without closing the descriptor.
+    pub fn into_raw_fd(self) -> Fd {
+        let fd = self.fd;
+        core::mem::forget(self);
+        fd
+    }
+
+    pub fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let (offset, whence) = match pos {
+            SeekFrom::Start(offset) => (offset as i64, SEEK_SET),
+            SeekFrom::Current(offset) => (offset, SEEK_CUR),
+            SeekFrom::End(offset) => (offset, SEEK_END),
+        };
+        syscall::lseek(self.fd, offset, whence)
+    }
+
+    pub fn stat(&self) -> Result<Stat> {
+        syscall::fstat(self.fd)
+    }
+
+    /// a second handle to the same open file, sharing its offset.
+    pub fn try_clone(&self) -> Result<Self> {
+        syscall::dup(self.fd).map(|fd| Self { fd })
+    }
+
+    /// fills `buf` with the next directory entries, see `Dirents`.
+    pub fn read_dir(&mut self, buf: &mut [u8]) -> Result<usize> {
+        syscall::getdents(self.fd, buf)
+    }
+
+    /// sends a device specific request, see `syscall::ioctl`.
+    pub fn ioctl(&mut self, request: u64, buf: &mut [u8]) -> Result<u64> {
+        syscall::ioctl(self.fd, request, buf)
+    }
+
+    /// maps `len` bytes from the page aligned `offset` into memory, see
+    /// `syscall::mmap`. the mapping outlives the file.
+    pub fn map(&self, offset: u64, len: usize, prot: u32) -> Result<*mut u8> {
+        syscall::mmap(HANDLE_SELF, self.fd, offset, len, prot)
+    }
+}
+
+impl Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        syscall::read(self.fd, buf)
+    }
+}
+
+impl Write for File {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        syscall::write(self.fd, buf)
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        let _ = syscall::close(self.fd);
+    }
+}
@@ -1,4 +1,5 @@
 use spin::Mutex;
+use syscall::handle::HANDLE_SELF;
 
 struct GlobalAlloc {
     lock: Mutex<()>,
@@ -7,7 +8,10 @@ struct GlobalAlloc {
 unsafe impl core::alloc::GlobalAlloc for GlobalAlloc {
     unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
         let _lock = self.lock.lock();
-        let ptr = syscall::malloc(layout.size() + layout.align());
+        let ptr = match syscall::malloc(HANDLE_SELF, layout.size() + layout.align()) {
+            Ok(ptr) => ptr,
+            Err(_) => return core::ptr::null_mut(),
+        };
         let mask = layout.align() as u64 - 1;
         let adr = (ptr as u64 + mask) & !mask;
         adr as *mut _
@@ -16,7 +20,7 @@ unsafe impl core::alloc::GlobalAlloc for GlobalAlloc {
     unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
         let _lock = self.lock.lock();
         debug_assert!(ptr as u64 & (layout.align() as u64 - 1) == 0);
-        syscall::free(ptr, layout.size())
+        let _ = syscall::free(ptr, layout.size());
     }
 }
 
@@ -0,0 +1,73 @@
+//! handles to kernel objects, see `syscall::handle`.
+
+use core::mem;
+use syscall::Result;
+
+pub use syscall::handle::*;
+
+/// A handle the process owns, closed when dropped.
+#[derive(Debug)]
+pub struct OwnedHandle(Handle);
+
+impl OwnedHandle {
+    /// takes ownership of `handle`, which is closed with the returned value.
+    pub fn from_raw(handle: Handle) -> Self {
+        Self(handle)
+    }
+
+    pub fn raw(&self) -> Handle {
+        self.0
+    }
+
+    /// gives up ownership without closing the handle.
+    pub fn into_raw(self) -> Handle {
+        let handle = self.0;
+        mem::forget(self);
+        handle
+    }
+
+    /// a new handle to the same object with the `RIGHT_*` flags in `rights`,
+    /// which can't be more than this one has.
+    pub fn duplicate(&self, rights: u32) -> Result<Self> {
+        syscall::handle_duplicate(self.0, rights).map(Self)
+    }
+
+    pub fn info(&self) -> Result<HandleInfo> {
+        syscall::handle_info(self.0)
+    }
+}
+
+impl Drop for OwnedHandle {
+    fn drop(&mut self) {
+        let _ = syscall::handle_close(self.0);
+    }
+}
+
+/// Memory which can be mapped into several processes by passing its handle
+/// around.
+#[derive(Debug)]
+pub struct Memory(OwnedHandle);
+
+impl Memory {
+    pub fn create(len: usize) -> Result<Self> {
+        syscall::memory_create(len).map(|handle| Self(OwnedHandle(handle)))
+    }
+
+    pub fn from_handle(handle: OwnedHandle) -> Self {
+        Self(handle)
+    }
+
+    pub fn handle(&self) -> &OwnedHandle {
+        &self.0
+    }
+
+    pub fn into_handle(self) -> OwnedHandle {
+        self.0
+    }
+
+    /// maps all of the memory into this process with the `syscall::mm::PROT_*`
+    /// flags in `prot`. the mapping outlives the handle.
+    pub fn map(&self, prot: u32) -> Result<*mut u8> {
+        syscall::memory_map(HANDLE_SELF, self.0.raw(), prot)
+    }
+}
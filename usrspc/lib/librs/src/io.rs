@@ -0,0 +1,52 @@
+//! reading and writing through file descriptors.
+
+use core::fmt;
+use syscall::fs::{STDERR, STDOUT};
+use syscall::{Errno, Result};
+
+pub trait Read {
+    /// returns the number of bytes read, which is zero at the end of the file.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+}
+
+pub trait Write {
+    /// returns the number of bytes written, which may be less than
+    /// `buf.len()`.
+    fn write(&mut self, buf: &[u8]) -> Result<usize>;
+
+    fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match self.write(buf)? {
+                0 => return Err(Errno::EIO),
+                written => buf = &buf[written..],
+            }
+        }
+        Ok(())
+    }
+}
+
+/// writes to a descriptor the process doesn't own, like stdout.
+struct FdWriter(syscall::fs::Fd);
+
+impl fmt::Write for FdWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut buf = s.as_bytes();
+        while !buf.is_empty() {
+            match syscall::write(self.0, buf) {
+                Ok(0) | Err(_) => return Err(fmt::Error),
+                Ok(written) => buf = &buf[written..],
+            }
+        }
+        Ok(())
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    let _ = fmt::Write::write_fmt(&mut FdWriter(STDOUT), args);
+}
+
+#[doc(hidden)]
+pub fn _eprint(args: fmt::Arguments) {
+    let _ = fmt::Write::write_fmt(&mut FdWriter(STDERR), args);
+}
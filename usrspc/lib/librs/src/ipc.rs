@@ -0,0 +1,320 @@
+//! typed message passing over kernel endpoints.
+//!
+//! A protocol names the request and reply types a server understands, both
+//! of which fit into the words of a message. Larger data goes along as a
+//! payload, either copied or granted, and a message can move a handle to
+//! the other side.
+
+use crate::handle::{OwnedHandle, HANDLE_NONE};
+use core::marker::PhantomData;
+use core::slice;
+use syscall::ipc::{
+    Message, MSG_WORDS, PAYLOAD_COPY, PAYLOAD_GRANT, PAYLOAD_GRANT_RW, PAYLOAD_NONE, TOKEN_NOTIFY,
+};
+use syscall::{Errno, Result};
+
+pub use syscall::ipc::MAX_COPY_LEN;
+
+/// A value sent in the words of a message.
+pub trait Words: Sized {
+    fn to_words(&self) -> [u64; MSG_WORDS];
+
+    /// returns `None` if `words` don't hold a valid value.
+    fn from_words(words: [u64; MSG_WORDS]) -> Option<Self>;
+}
+
+impl Words for () {
+    fn to_words(&self) -> [u64; MSG_WORDS] {
+        [0; MSG_WORDS]
+    }
+
+    fn from_words(_: [u64; MSG_WORDS]) -> Option<Self> {
+        Some(())
+    }
+}
+
+impl Words for u64 {
+    fn to_words(&self) -> [u64; MSG_WORDS] {
+        let mut words = [0; MSG_WORDS];
+        words[0] = *self;
+        words
+    }
+
+    fn from_words(words: [u64; MSG_WORDS]) -> Option<Self> {
+        Some(words[0])
+    }
+}
+
+impl Words for [u64; MSG_WORDS] {
+    fn to_words(&self) -> [u64; MSG_WORDS] {
+        *self
+    }
+
+    fn from_words(words: [u64; MSG_WORDS]) -> Option<Self> {
+        Some(words)
+    }
+}
+
+/// The messages a client and server exchange.
+pub trait Protocol {
+    type Request: Words;
+    type Reply: Words;
+}
+
+/// Data sent along with a message.
+pub enum Payload<'a> {
+    None,
+    /// copied into the receiver's buffer, up to `MAX_COPY_LEN` bytes.
+    Copy(&'a [u8]),
+    /// the pages holding the data are mapped into the receiver, which can
+    /// read them.
+    Grant(&'a [u8]),
+    /// like `Grant`, but the receiver can write to them as well.
+    GrantMut(&'a mut [u8]),
+}
+
+impl Payload<'_> {
+    /// a message of `words` carrying the payload, and `handle`, which the
+    /// kernel takes even if the message is never received.
+    fn message(&self, words: [u64; MSG_WORDS], handle: Option<OwnedHandle>) -> Message {
+        let handle = handle.map_or(HANDLE_NONE, OwnedHandle::into_raw);
+        let (payload_kind, data) = match self {
+            Self::None => {
+                return Message {
+                    handle,
+                    ..Message::new(words)
+                }
+            }
+            Self::Copy(data) => (PAYLOAD_COPY, *data),
+            Self::Grant(data) => (PAYLOAD_GRANT, *data),
+            Self::GrantMut(data) => (PAYLOAD_GRANT_RW, &**data),
+        };
+        Message {
+            words,
+            payload_kind,
+            _pad: 0,
+            payload: data.as_ptr() as u64,
+            payload_len: data.len() as u64,
+            handle,
+        }
+    }
+}
+
+/// the handle a received message carries.
+fn received_handle(msg: &Message) -> Option<OwnedHandle> {
+    (msg.handle != HANDLE_NONE).then(|| OwnedHandle::from_raw(msg.handle))
+}
+
+/// Data that came along with a message.
+pub enum Received<'a> {
+    None,
+    /// the part of the receive buffer it was copied to.
+    Copy(&'a [u8]),
+    /// granted pages, which stay mapped.
+    Grant(&'static [u8]),
+    GrantMut(&'static mut [u8]),
+}
+
+impl<'a> Received<'a> {
+    fn new(msg: &Message, buf: &'a [u8]) -> Result<Self> {
+        let data = msg.payload as *mut u8;
+        let len = msg.payload_len as usize;
+        Ok(match msg.payload_kind {
+            PAYLOAD_NONE => Self::None,
+            PAYLOAD_COPY => Self::Copy(&buf[..len]),
+            PAYLOAD_GRANT => Self::Grant(unsafe { slice::from_raw_parts(data, len) }),
+            PAYLOAD_GRANT_RW => Self::GrantMut(unsafe { slice::from_raw_parts_mut(data, len) }),
+            _ => return Err(Errno::EBADMSG),
+        })
+    }
+}
+
+/// A reply along with what came with it.
+pub struct Reply<'a, P: Protocol> {
+    pub reply: P::Reply,
+    pub payload: Received<'a>,
+    pub handle: Option<OwnedHandle>,
+}
+
+/// A connection to a server, closed when dropped.
+#[derive(Debug)]
+pub struct Client<P: Protocol> {
+    handle: OwnedHandle,
+    protocol: PhantomData<P>,
+}
+
+impl<P: Protocol> Client<P> {
+    pub fn connect(name: impl AsRef<str>) -> Result<Self> {
+        syscall::endpoint_open(name).map(|handle| Self::from_handle(OwnedHandle::from_raw(handle)))
+    }
+
+    /// a client of the endpoint `handle` refers to, which may have been
+    /// received from another process.
+    pub fn from_handle(handle: OwnedHandle) -> Self {
+        Self {
+            handle,
+            protocol: PhantomData,
+        }
+    }
+
+    pub fn handle(&self) -> &OwnedHandle {
+        &self.handle
+    }
+
+    /// sends `request` and waits for the reply.
+    pub fn call(&self, request: &P::Request) -> Result<P::Reply> {
+        self.call_with(request, Payload::None, None, &mut [])
+            .map(|reply| reply.reply)
+    }
+
+    /// sends `request` with `payload` and `handle` and waits for the reply,
+    /// whose copied payload goes to `buf`.
+    pub fn call_with<'a>(
+        &self,
+        request: &P::Request,
+        payload: Payload,
+        handle: Option<OwnedHandle>,
+        buf: &'a mut [u8],
+    ) -> Result<Reply<'a, P>> {
+        let mut msg = payload.message(request.to_words(), handle);
+        syscall::ipc_call(self.handle.raw(), &mut msg, buf)?;
+        let handle = received_handle(&msg);
+        Ok(Reply {
+            reply: P::Reply::from_words(msg.words).ok_or(Errno::EBADMSG)?,
+            payload: Received::new(&msg, buf)?,
+            handle,
+        })
+    }
+
+    /// sends `request` without waiting for a reply, only until the server
+    /// received it.
+    pub fn send(
+        &self,
+        request: &P::Request,
+        payload: Payload,
+        handle: Option<OwnedHandle>,
+    ) -> Result<()> {
+        let msg = payload.message(request.to_words(), handle);
+        syscall::ipc_send(self.handle.raw(), &msg)
+    }
+}
+
+/// An endpoint registered under a name, closed when dropped.
+#[derive(Debug)]
+pub struct Server<P: Protocol> {
+    handle: OwnedHandle,
+    protocol: PhantomData<P>,
+}
+
+impl<P: Protocol> Server<P> {
+    pub fn create(name: impl AsRef<str>) -> Result<Self> {
+        Ok(Self {
+            handle: OwnedHandle::from_raw(syscall::endpoint_create(name)?),
+            protocol: PhantomData,
+        })
+    }
+
+    pub fn handle(&self) -> &OwnedHandle {
+        &self.handle
+    }
+
+    /// waits for the next request, whose copied payload goes to `buf`, or
+    /// notification. requests which aren't valid `P::Request`s fail with
+    /// `EBADMSG`.
+    pub fn receive<'a>(&'a self, buf: &'a mut [u8]) -> Result<Event<'a, P>> {
+        let (token, request, handle, msg) = loop {
+            let mut msg = Message::default();
+            let token = syscall::ipc_receive(self.handle.raw(), &mut msg, buf)?;
+            if token == TOKEN_NOTIFY {
+                return Ok(Event::Notification(msg.words[0]));
+            }
+            let handle = received_handle(&msg);
+            let valid = msg.payload_kind <= PAYLOAD_GRANT_RW;
+            match P::Request::from_words(msg.words) {
+                Some(request) if valid => break (token, request, handle, msg),
+                _ if token != 0 => {
+                    syscall::ipc_reply(self.handle.raw(), token, Err(Errno::EBADMSG))?
+                }
+                _ => {}
+            }
+        };
+        Ok(Event::Request(Incoming {
+            server: self,
+            token,
+            request,
+            payload: Received::new(&msg, buf)?,
+            handle,
+        }))
+    }
+}
+
+/// What a server received.
+pub enum Event<'a, P: Protocol> {
+    Request(Incoming<'a, P>),
+    /// the notification bits set since the last one, like those of an
+    /// interrupt bound with `Irq::bind`.
+    Notification(u64),
+}
+
+/// A received request. calls are failed with `EPIPE` if it is dropped
+/// without a reply.
+pub struct Incoming<'a, P: Protocol> {
+    server: &'a Server<P>,
+    /// 0 for sends, and once replied.
+    token: u64,
+    request: P::Request,
+    payload: Received<'a>,
+    handle: Option<OwnedHandle>,
+}
+
+impl<'a, P: Protocol> Incoming<'a, P> {
+    pub fn request(&self) -> &P::Request {
+        &self.request
+    }
+
+    pub fn payload(&mut self) -> &mut Received<'a> {
+        &mut self.payload
+    }
+
+    /// the handle the request carries, which is closed with the request
+    /// unless it is taken.
+    pub fn take_handle(&mut self) -> Option<OwnedHandle> {
+        self.handle.take()
+    }
+
+    /// whether the client waits for a reply.
+    pub fn is_call(&self) -> bool {
+        self.token != 0
+    }
+
+    pub fn reply(self, reply: &P::Reply) -> Result<()> {
+        self.reply_with(reply, Payload::None, None)
+    }
+
+    pub fn reply_with(
+        mut self,
+        reply: &P::Reply,
+        payload: Payload,
+        handle: Option<OwnedHandle>,
+    ) -> Result<()> {
+        self.finish(Ok(&payload.message(reply.to_words(), handle)))
+    }
+
+    /// makes the client's call fail with `errno`.
+    pub fn fail(mut self, errno: Errno) -> Result<()> {
+        self.finish(Err(errno))
+    }
+
+    fn finish(&mut self, reply: Result<&Message>) -> Result<()> {
+        match core::mem::take(&mut self.token) {
+            0 => Ok(()),
+            token => syscall::ipc_reply(self.server.handle.raw(), token, reply),
+        }
+    }
+}
+
+impl<P: Protocol> Drop for Incoming<'_, P> {
+    fn drop(&mut self) {
+        let _ = self.finish(Err(Errno::EPIPE));
+    }
+}
@@ -0,0 +1,30 @@
+//! interrupt lines, which the kernel grants to drivers.
+
+use crate::handle::OwnedHandle;
+use crate::ipc::{Protocol, Server};
+use syscall::Result;
+
+/// A device interrupt line. it stays masked after each interrupt until it is
+/// acknowledged.
+#[derive(Debug)]
+pub struct Irq(OwnedHandle);
+
+impl Irq {
+    pub fn from_handle(handle: OwnedHandle) -> Self {
+        Self(handle)
+    }
+
+    pub fn handle(&self) -> &OwnedHandle {
+        &self.0
+    }
+
+    /// makes each interrupt set `bits` in a notification `server` receives.
+    pub fn bind<P: Protocol>(&self, server: &Server<P>, bits: u64) -> Result<()> {
+        syscall::irq_bind(self.0.raw(), server.handle().raw(), bits)
+    }
+
+    /// unmasks the line once the device was serviced.
+    pub fn ack(&self) -> Result<()> {
+        syscall::irq_ack(self.0.raw())
+    }
+}
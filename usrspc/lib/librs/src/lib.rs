@@ -9,6 +9,7 @@ extern crate alloc as alloc_crate;
 
 pub use alloc_crate::borrow;
 pub use alloc_crate::boxed;
+pub use alloc_crate::collections;
 pub use alloc_crate::fmt;
 pub use alloc_crate::format;
 pub use alloc_crate::rc;
@@ -50,7 +51,17 @@ pub use core::u64;
 pub use core::u8;
 pub use core::usize;
 
+pub use syscall::Errno;
+
+pub mod fs;
+pub mod handle;
+pub mod io;
+pub mod ipc;
+pub mod irq;
+pub mod port;
+
 /// exported macros not found in `core` or `alloc`
+#[macro_use]
 mod macros;
 
 /// userspace global allocator.
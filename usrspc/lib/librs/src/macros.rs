@@ -1,9 +1,37 @@
+/// prints to stdout.
 #[macro_export]
 macro_rules! print {
-    ($fmt:literal $($tt:tt)*) => {};
+    ($($arg:tt)*) => {
+        $crate::io::_print(format_args!($($arg)*))
+    };
 }
 
+/// prints to stdout, with a newline.
 #[macro_export]
 macro_rules! println {
-    ($fmt:literal $($tt:tt)*) => {};
+    () => {
+        $crate::print!("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::io::_print(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+/// prints to stderr.
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => {
+        $crate::io::_eprint(format_args!($($arg)*))
+    };
+}
+
+/// prints to stderr, with a newline.
+#[macro_export]
+macro_rules! eprintln {
+    () => {
+        $crate::eprint!("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::io::_eprint(format_args!("{}\n", format_args!($($arg)*)))
+    };
 }
@@ -1,7 +1,7 @@
 use core::panic::PanicInfo;
 
 #[panic_handler]
-unsafe fn panic(_info: &PanicInfo) -> ! {
-    syscall::kprint("panicked!");
+unsafe fn panic(info: &PanicInfo) -> ! {
+    eprintln!("panicked: {info}");
     loop {}
 }
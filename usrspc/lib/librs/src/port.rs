@@ -0,0 +1,109 @@
+//! port IO, mirroring the kernel's `arch::port`. a process can only use the
+//! ports it was granted, see `syscall::ioport_grant`.
+
+use crate::handle::{OwnedHandle, HANDLE_SELF};
+use core::arch::asm;
+use core::ops::Range;
+use syscall::Result;
+
+/// Ports this process was granted, which panic when used outside of them.
+#[derive(Debug, Clone)]
+pub struct Ports(Range<u16>);
+
+impl Ports {
+    /// grants this process `ports`, which have to lie in those `handle`
+    /// refers to.
+    pub fn grant(handle: &OwnedHandle, ports: Range<u16>) -> Result<Self> {
+        let count = ports.end.saturating_sub(ports.start);
+        syscall::ioport_grant(HANDLE_SELF, handle.raw(), ports.start, count)?;
+        Ok(Self(ports))
+    }
+
+    pub fn range(&self) -> Range<u16> {
+        self.0.clone()
+    }
+
+    /// panics unless the `len` ports from `port` were granted.
+    fn check(&self, port: u16, len: u16) {
+        let granted = port >= self.0.start && port as u32 + len as u32 <= self.0.end as u32;
+        assert!(granted, "port {port:#x} was not granted");
+    }
+
+    pub fn out8(&self, port: u16, v: u8) {
+        self.check(port, 1);
+        unsafe {
+            asm! {
+                "out dx, al",
+                in("al") v,
+                in("dx") port,
+                options(nostack)
+            };
+        }
+    }
+
+    pub fn out16(&self, port: u16, v: u16) {
+        self.check(port, 2);
+        unsafe {
+            asm! {
+                "out dx, ax",
+                in("ax") v,
+                in("dx") port,
+                options(nostack)
+            };
+        }
+    }
+
+    pub fn out32(&self, port: u16, v: u32) {
+        self.check(port, 4);
+        unsafe {
+            asm! {
+                "out dx, eax",
+                in("eax") v,
+                in("dx") port,
+                options(nostack)
+            };
+        }
+    }
+
+    pub fn in8(&self, port: u16) -> u8 {
+        self.check(port, 1);
+        let out: u8;
+        unsafe {
+            asm! {
+                "in al, dx",
+                out("al") out,
+                in("dx") port,
+                options(nostack)
+            };
+        }
+        out
+    }
+
+    pub fn in16(&self, port: u16) -> u16 {
+        self.check(port, 2);
+        let out: u16;
+        unsafe {
+            asm! {
+                "in ax, dx",
+                out("ax") out,
+                in("dx") port,
+                options(nostack)
+            };
+        }
+        out
+    }
+
+    pub fn in32(&self, port: u16) -> u32 {
+        self.check(port, 4);
+        let out: u32;
+        unsafe {
+            asm! {
+                "in eax, dx",
+                out("eax") out,
+                in("dx") port,
+                options(nostack)
+            };
+        }
+        out
+    }
+}